@@ -1,7 +1,7 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 use sloth_lang_core::*;
 #[inline]
-fn fib_bench0() {
+fn fib_bench0(optimize: bool) {
     let prog = r#"
        func fib(n){
            if (n == 1 or n == 0) {
@@ -10,29 +10,70 @@ fn fib_bench0() {
                return fib(n - 1) + fib(n -2);
            }
        }
-       print(fib(20));
+       var res = fib(20);
     "#;
-    run_string(prog).unwrap();
+    run_script(prog, "", false, false, optimize).unwrap();
 }
 #[inline]
-fn fib_bench1() {
+fn fib_bench1(optimize: bool) {
     let prog = r#"
        func fib(n){
-            var arr = Array(1,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0);
+            var arr = [1,1,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0];
             var i = 2;
             while(i < n){
-                assign arr[i] = arr[i-1] + arr[i-2];
-                assign i = i + 1;
+                arr[i] = arr[i-1] + arr[i-2];
+                i = i + 1;
             }
-            return arr[19]; 
+            return arr[19];
        }
-       print(fib(20));
+       var res = fib(20);
     "#;
-    run_string(prog).unwrap();
+    run_script(prog, "", false, false, optimize).unwrap();
+}
+#[inline]
+fn const_bench(optimize: bool) {
+    let prog = r#"
+       var seconds = 0;
+       var i = 0;
+       while (i < 1000) {
+           seconds = seconds + 60 * 60 * 24 - -1;
+           i = i + 1;
+       }
+    "#;
+    run_script(prog, "", false, false, optimize).unwrap();
+}
+#[inline]
+fn loop_bench(optimize: bool) {
+    let prog = r#"
+       func sum(n) {
+           var s = 0;
+           var i = 0;
+           while (i < n) {
+               s = s + i;
+               i = i + 1;
+           }
+           return s;
+       }
+       var res = sum(10000);
+    "#;
+    run_script(prog, "", false, false, optimize).unwrap();
 }
 fn criterion_benchmark(c: &mut Criterion) {
-    c.bench_function("fib 20", |b| b.iter(fib_bench0));
-    c.bench_function("fib 20_iter", |b| b.iter(fib_bench1));
+    for (name, optimize) in [("", false), (" -O", true)] {
+        let optimize = black_box(optimize);
+        c.bench_function(&format!("fib 20{name}"), |b| {
+            b.iter(|| fib_bench0(optimize))
+        });
+        c.bench_function(&format!("fib 20_iter{name}"), |b| {
+            b.iter(|| fib_bench1(optimize))
+        });
+        c.bench_function(&format!("constants 1000{name}"), |b| {
+            b.iter(|| const_bench(optimize))
+        });
+        c.bench_function(&format!("sum 10000{name}"), |b| {
+            b.iter(|| loop_bench(optimize))
+        });
+    }
 }
 
 criterion_group!(benches, criterion_benchmark);
@@ -1,7 +1,12 @@
+pub mod ast;
+pub mod codegen;
+pub mod optimizer;
 pub mod parser;
 pub mod scanner;
+pub mod symbols;
 
 use crate::interned_string::IString;
+use std::fmt;
 #[derive(Clone, Debug, PartialEq)]
 pub enum Token {
     Number(f64),
@@ -33,6 +38,7 @@ pub enum Token {
     Var,
     If,
     Else,
+    Match,
 
     LParen,
     RParen,
@@ -59,6 +65,8 @@ pub enum Token {
 
     Return,
     Except,
+    Try,
+    Catch,
 
     PipeOp,    // |>
     AddAssign, // +=
@@ -76,14 +84,18 @@ pub enum Token {
     DotsEq,   //..=
     ThreeDots, //...
 
+    Coalesce,        // ??
+    QuestionDot,     // ?.
+    QuestionBracket, // ?[
+    FatArrow,        // =>
+
     InterplotBegin, // special token ${
     InterplotEnd,   // special token }
 }
 
 impl Token {
     pub fn is_assign(&self) -> bool {
-        *self == Token::Equal
-            // || self.is_op_assign()
+        *self == Token::Equal || self.is_op_assign()
     }
 
     pub fn is_op_assign(&self) -> bool {
@@ -94,3 +106,78 @@ impl Token {
             || *self == Token::ModAssign
     }
 }
+
+/// tokens in diagnostics, as they are written in the source
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            Token::Number(x) => return write!(f, "number `{x}`"),
+            Token::String(s) => return write!(f, "string {:?}", s.get_inner()),
+            Token::Symbol(s) => return write!(f, "identifier `{}`", s.get_inner()),
+            Token::Array => return write!(f, "array"),
+            Token::True => "true",
+            Token::False => "false",
+            Token::Dict => "@",
+            Token::Stick => "|",
+            Token::Nil => "nil",
+            Token::And => "and",
+            Token::Or => "or",
+            Token::Not => "not",
+            Token::Add => "+",
+            Token::Sub => "-",
+            Token::LSlash => "/",
+            Token::Mod | Token::Percent => "%",
+            Token::Star => "*",
+            Token::Dot => ".",
+            Token::Question => "?",
+            Token::While => "while",
+            Token::For => "for",
+            Token::Break => "break",
+            Token::Continue => "continue",
+            Token::Var => "var",
+            Token::If => "if",
+            Token::Else => "else",
+            Token::Match => "match",
+            Token::LParen => "(",
+            Token::RParen => ")",
+            Token::LBracket => "[",
+            Token::RBracket => "]",
+            Token::LBrace => "{",
+            Token::RBrace | Token::InterplotEnd => "}",
+            Token::Comma => ",",
+            Token::LArrow => "<",
+            Token::RArrow => ">",
+            Token::Semicolon => ";",
+            Token::Colon => ":",
+            Token::Equal => "=",
+            Token::EEqual => "==",
+            Token::NotEqual => "!=",
+            Token::Le => "<=",
+            Token::Ge => ">=",
+            Token::Function => "func",
+            Token::Return => "return",
+            Token::Except => "except",
+            Token::Try => "try",
+            Token::Catch => "catch",
+            Token::PipeOp => "|>",
+            Token::AddAssign => "+=",
+            Token::SubAssign => "-=",
+            Token::MulAssign => "*=",
+            Token::DivAssign => "/=",
+            Token::ModAssign => "%=",
+            Token::Class => "class",
+            Token::Super => "super",
+            Token::This => "this",
+            Token::Is => "is",
+            Token::Dots => "..",
+            Token::DotsEq => "..=",
+            Token::ThreeDots => "...",
+            Token::Coalesce => "??",
+            Token::QuestionDot => "?.",
+            Token::QuestionBracket => "?[",
+            Token::FatArrow => "=>",
+            Token::InterplotBegin => "${",
+        };
+        write!(f, "`{text}`")
+    }
+}
@@ -0,0 +1,441 @@
+//! syntax tree built by `ParserCtx` and lowered to bytecode by `CodeGen`.
+//! positions are (line, col) of the scanner, like `Chunk.cood`.
+
+/// where a node is: (line, col) of the token it is named after and its length
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    pub pos: (usize, usize),
+    pub len: usize,
+}
+
+/// a name, `this` included
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ident {
+    pub name: String,
+    pub span: Span,
+}
+
+/// `// ...` or `/* ... */`, with the markers
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    pub text: String,
+    pub span: Span,
+}
+
+impl Comment {
+    /// line the comment ends on
+    pub fn end_line(&self) -> usize {
+        self.span.pos.0 + self.text.matches('\n').count()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    pub stmts: Vec<Stmt>,
+    /// position of the last token
+    pub end: (usize, usize),
+    /// in source order, the scanner skips them otherwise
+    pub comments: Vec<Comment>,
+}
+
+/// `{ ... }`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub stmts: Vec<Stmt>,
+    pub lbrace: (usize, usize),
+    pub rbrace: (usize, usize),
+}
+
+/// parameters and body of functions, methods and lambdas
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub params: Vec<Ident>,
+    /// `...` after the parameters
+    pub variadic: bool,
+    /// position of the `)` or `|` after the parameters
+    pub close: (usize, usize),
+    pub body: Block,
+}
+
+/// `func name(params) { ... }`, also a method of a class
+#[derive(Debug, Clone, PartialEq)]
+pub struct FuncDecl {
+    pub name: Ident,
+    pub func: Function,
+    pub lparen: (usize, usize),
+}
+
+/// `class Name : Super { methods }`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassDecl {
+    pub name: Ident,
+    pub superclass: Option<Ident>,
+    pub methods: Vec<FuncDecl>,
+    pub rbrace: (usize, usize),
+}
+
+/// `pattern if guard => { body }` of a `match`
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub guard: Option<Expr>,
+    /// position of `=>`
+    pub arrow: (usize, usize),
+    pub body: Block,
+}
+
+/// what an arm of a `match` accepts, names in it are bound for the guard and the body
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    /// `_`, anything
+    Wildcard(Span),
+    /// anything, bound to the name
+    Bind(Ident),
+    /// a number, string, bool or nil, equal values of the same type
+    Literal(Expr),
+    /// `lo..hi` or `lo..=hi` of numbers, the numbers in the range
+    Range { lo: Expr, hi: Expr, closed: bool },
+    /// `[items, ...rest]`, arrays of that many items, at least if there is a rest.
+    /// `rest` is a `Bind` or a `Wildcard`
+    Array {
+        items: Vec<Pattern>,
+        rest: Option<Box<Pattern>>,
+        span: Span,
+        close: (usize, usize),
+    },
+    /// `@("key": pattern)`, dicts and errors with the keys
+    Dict {
+        entries: Vec<(Expr, Pattern)>,
+        span: Span,
+        close: (usize, usize),
+    },
+    /// `Class(field, field: pattern)`, instances of the class with the fields.
+    /// a lone field is bound to its name
+    Class {
+        class: Ident,
+        fields: Vec<(Ident, Pattern)>,
+        close: (usize, usize),
+    },
+    /// `name is Class`, instances of the class, `binding` is a `Bind` or a `Wildcard`
+    Is { binding: Box<Pattern>, class: Ident },
+}
+
+/// `end` of statements with branches is the position of the token after them
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stmt {
+    /// `var name = init;`
+    Var {
+        name: Ident,
+        init: Option<Expr>,
+        semi: (usize, usize),
+    },
+    /// `target = value;`, the target is a name, an index or a field.
+    /// `target op= value;` has `op`, `span` is the one of `=` or `op=`
+    Assign {
+        target: Expr,
+        op: Option<BinaryOp>,
+        value: Expr,
+        span: Span,
+        semi: (usize, usize),
+    },
+    /// `expr;`
+    Expr {
+        expr: Expr,
+        semi: (usize, usize),
+    },
+    Block(Block),
+    /// `if (cond) then else otherwise`
+    If {
+        cond: Expr,
+        then: Box<Stmt>,
+        otherwise: Option<Box<Stmt>>,
+        rparen: (usize, usize),
+        /// the token after `then`, `else` if there is one
+        then_end: (usize, usize),
+        end: (usize, usize),
+    },
+    /// `while (cond) { ... }`
+    While {
+        cond: Expr,
+        body: Block,
+        rparen: (usize, usize),
+        end: (usize, usize),
+    },
+    /// `for (var var : iter) { ... }`
+    For {
+        var: Ident,
+        iter: Expr,
+        body: Block,
+        end: (usize, usize),
+    },
+    Func(FuncDecl),
+    Class(ClassDecl),
+    /// `return value;`
+    Return {
+        value: Option<Expr>,
+        span: Span,
+        semi: (usize, usize),
+    },
+    /// `except value;`
+    Except {
+        value: Option<Expr>,
+        span: Span,
+        semi: (usize, usize),
+    },
+    /// `try { body } catch (var) { handler }`
+    TryCatch {
+        body: Block,
+        catch: Span,
+        var: Option<Ident>,
+        handler: Block,
+        end: (usize, usize),
+    },
+    /// `match (subject) { arms }`, the first arm that matches is run
+    Match {
+        subject: Expr,
+        arms: Vec<MatchArm>,
+        lbrace: (usize, usize),
+        rbrace: (usize, usize),
+    },
+    Break(Span),
+    Continue(Span),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    /// `-`
+    Neg,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Or,
+    /// `..`
+    Range,
+    /// `..=`
+    RangeClosed,
+    /// `|>`
+    Pipe,
+    Is,
+    /// `??`, the right side is only evaluated if the left one is nil
+    Coalesce,
+}
+
+/// piece of an interpolated string
+#[derive(Debug, Clone, PartialEq)]
+pub enum StrPart {
+    Str(String, Span),
+    /// `${expr}`, `start` is the position of the first token of `expr`
+    Expr {
+        expr: Expr,
+        open: Span,
+        start: (usize, usize),
+        close: Span,
+    },
+}
+
+/// `span` of operators, calls, indexes and fields is the one of their token,
+/// `close` is the position of the closing bracket
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Name(Ident),
+    /// `super.method`, `span` of `super`
+    Super {
+        method: Ident,
+        span: Span,
+    },
+    Number(f64, Span),
+    String(String, Span),
+    /// `"a${b}c"`, parts start and end with a string
+    Interpolation(Vec<StrPart>),
+    Bool(bool, Span),
+    Nil(Span),
+    /// `[items]`
+    Array {
+        items: Vec<Expr>,
+        span: Span,
+        close: (usize, usize),
+    },
+    /// `@(key: value)`, keys are strings
+    Dict {
+        entries: Vec<(Expr, Expr)>,
+        span: Span,
+        close: (usize, usize),
+    },
+    Unary {
+        op: UnaryOp,
+        operand: Box<Expr>,
+        span: Span,
+    },
+    Binary {
+        op: BinaryOp,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+        span: Span,
+    },
+    /// `callee(args)`
+    Call {
+        callee: Box<Expr>,
+        args: Vec<Expr>,
+        span: Span,
+        close: (usize, usize),
+    },
+    /// `target[index]`, `target?[index]` is nil if the target is
+    Index {
+        target: Box<Expr>,
+        index: Box<Expr>,
+        optional: bool,
+        span: Span,
+        close: (usize, usize),
+    },
+    /// `target.name`, `target?.name` is nil if the target is
+    Field {
+        target: Box<Expr>,
+        name: Ident,
+        optional: bool,
+        span: Span,
+    },
+    /// `cond ? then : otherwise`, `span` of `?`
+    Conditional {
+        cond: Box<Expr>,
+        then: Box<Expr>,
+        otherwise: Box<Expr>,
+        span: Span,
+    },
+    /// `try call`, evaluates to an error value if the call fails
+    Try {
+        call: Box<Expr>,
+        span: Span,
+    },
+    /// `|params| { ... }`
+    Lambda {
+        func: Function,
+        span: Span,
+    },
+}
+
+impl Stmt {
+    /// position of the first token, declarations and loops start at
+    /// their name or condition instead of the keyword
+    pub fn start(&self) -> (usize, usize) {
+        match self {
+            Stmt::Var { name, .. } => name.span.pos,
+            Stmt::Assign { target, .. } => target.start(),
+            Stmt::Expr { expr, .. } => expr.start(),
+            Stmt::Block(block) => block.lbrace,
+            Stmt::If { cond, .. } | Stmt::While { cond, .. } => cond.start(),
+            Stmt::Match { subject, .. } => subject.start(),
+            Stmt::For { var, .. } => var.span.pos,
+            Stmt::Func(decl) => decl.name.span.pos,
+            Stmt::Class(decl) => decl.name.span.pos,
+            Stmt::Return { span, .. } | Stmt::Except { span, .. } => span.pos,
+            Stmt::TryCatch { body, .. } => body.lbrace,
+            Stmt::Break(span) | Stmt::Continue(span) => span.pos,
+        }
+    }
+
+    /// position of the last token, the keyword for `break` and `continue`
+    pub fn end(&self) -> (usize, usize) {
+        match self {
+            Stmt::Var { semi, .. }
+            | Stmt::Assign { semi, .. }
+            | Stmt::Expr { semi, .. }
+            | Stmt::Return { semi, .. }
+            | Stmt::Except { semi, .. } => *semi,
+            Stmt::Block(block) => block.rbrace,
+            Stmt::If {
+                then, otherwise, ..
+            } => otherwise.as_ref().unwrap_or(then).end(),
+            Stmt::While { body, .. } | Stmt::For { body, .. } => body.rbrace,
+            Stmt::Func(decl) => decl.func.body.rbrace,
+            Stmt::Class(decl) => decl.rbrace,
+            Stmt::TryCatch { handler, .. } => handler.rbrace,
+            Stmt::Match { rbrace, .. } => *rbrace,
+            Stmt::Break(span) | Stmt::Continue(span) => span.pos,
+        }
+    }
+}
+
+impl Expr {
+    /// position of the first token, a `(` around the expression is left out
+    pub fn start(&self) -> (usize, usize) {
+        match self {
+            Expr::Name(ident) => ident.span.pos,
+            Expr::Number(_, span)
+            | Expr::String(_, span)
+            | Expr::Bool(_, span)
+            | Expr::Nil(span)
+            | Expr::Super { span, .. }
+            | Expr::Array { span, .. }
+            | Expr::Dict { span, .. }
+            | Expr::Unary { span, .. }
+            | Expr::Try { span, .. }
+            | Expr::Lambda { span, .. } => span.pos,
+            Expr::Interpolation(parts) => match &parts[0] {
+                StrPart::Str(_, span) => span.pos,
+                StrPart::Expr { open, .. } => open.pos,
+            },
+            Expr::Binary { lhs, .. } => lhs.start(),
+            Expr::Conditional { cond, .. } => cond.start(),
+            Expr::Call { callee, .. } => callee.start(),
+            Expr::Index { target, .. } | Expr::Field { target, .. } => target.start(),
+        }
+    }
+
+    /// position of the last token, the last `}` of interpolations
+    pub fn end(&self) -> (usize, usize) {
+        match self {
+            Expr::Name(ident) => ident.span.pos,
+            Expr::Number(_, span)
+            | Expr::String(_, span)
+            | Expr::Bool(_, span)
+            | Expr::Nil(span) => span.pos,
+            Expr::Super { method, .. } => method.span.pos,
+            Expr::Interpolation(parts) => parts
+                .iter()
+                .rev()
+                .find_map(|part| match part {
+                    StrPart::Expr { close, .. } => Some(close.pos),
+                    StrPart::Str(..) => None,
+                })
+                .unwrap_or_else(|| self.start()),
+            Expr::Array { close, .. }
+            | Expr::Dict { close, .. }
+            | Expr::Call { close, .. }
+            | Expr::Index { close, .. } => *close,
+            Expr::Unary { operand, .. } => operand.end(),
+            Expr::Binary { rhs, .. } => rhs.end(),
+            Expr::Conditional { otherwise, .. } => otherwise.end(),
+            Expr::Field { name, .. } => name.span.pos,
+            Expr::Try { call, .. } => call.end(),
+            Expr::Lambda { func, .. } => func.body.rbrace,
+        }
+    }
+}
+
+impl Pattern {
+    /// position of the first token
+    pub fn start(&self) -> (usize, usize) {
+        match self {
+            Pattern::Wildcard(span) | Pattern::Array { span, .. } | Pattern::Dict { span, .. } => {
+                span.pos
+            }
+            Pattern::Bind(ident) | Pattern::Class { class: ident, .. } => ident.span.pos,
+            Pattern::Literal(expr) | Pattern::Range { lo: expr, .. } => expr.start(),
+            Pattern::Is { binding, .. } => binding.start(),
+        }
+    }
+}
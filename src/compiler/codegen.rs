@@ -0,0 +1,1284 @@
+use std::collections::HashMap;
+
+use crate::compiler::ast::*;
+use crate::compiler::optimizer::optimize;
+use crate::compiler::symbols::{SymbolDef, SymbolIndex, SymbolKind, SymbolRef};
+use crate::interned_string::{IString, StringPool};
+use crate::{Chunk, Diagnostic, Instr, LocalVar, Unboxed, UpValueDecl, Value};
+
+#[derive(PartialEq)]
+enum VarLoc {
+    Local(usize),
+    UpValue(usize),
+    Global(usize),
+    ThisRef,
+    NotFound,
+}
+/// lowers the syntax tree built by `ParserCtx` to `Chunk`s
+pub struct CodeGen<'a> {
+    chunk: Vec<Chunk>, // stack of chunks
+
+    func_ctx_stack: Vec<FuncCtx>,
+
+    /// every toplevel symbols are exported
+    exported_symbols: Vec<IString>,
+    string_pool: &'a mut StringPool,
+    depth: usize,
+
+    method_ctx: bool,
+    /// names declared and used, for editors
+    symbols: SymbolIndex,
+    /// first declaration of every global
+    global_defs: HashMap<String, usize>,
+    /// run `optimizer::optimize` in `finish`
+    optimize: bool,
+}
+
+pub struct CodeGenResult {
+    pub chunk: Chunk,
+    pub exported_symbols: Vec<IString>,
+    pub symbols: SymbolIndex,
+}
+#[derive(Debug, Default)]
+struct FuncCtx {
+    block_ctx_stack: Vec<BlockCtx>,
+    loop_ctx: bool,
+    loop_ctx_stack: Vec<LoopCtx>,
+    /// number of `try` blocks the code being generated is in
+    try_depth: usize,
+}
+#[derive(Debug, Default)]
+struct BlockCtx {
+    symbol_table: HashMap<String, usize>,
+    /// some local is captured by a closure, its upvalue is closed when
+    /// the block is left
+    captured: bool,
+    /// index of locals declared in this block in `Chunk.locals`
+    locals: Vec<usize>,
+    /// index in `SymbolIndex.defs` of every symbol
+    defs: HashMap<String, usize>,
+}
+#[derive(Debug, Default)]
+struct LoopCtx {
+    continue_patch_point: Vec<usize>,
+    break_patch_point: Vec<usize>,
+    /// index of the block of the loop body in `block_ctx_stack`
+    body_block: usize,
+    /// `try_depth` around the loop, the `try` blocks entered inside are
+    /// left by `break` and `continue`
+    try_depth: usize,
+}
+fn error_at(span: Span, msg: &str) -> Diagnostic {
+    Diagnostic::error(span.pos, msg).with_span(span.len)
+}
+impl<'a> CodeGen<'a> {
+    pub fn new(string_pool: &'a mut StringPool) -> CodeGen<'a> {
+        CodeGen {
+            chunk: vec![Chunk {
+                name: "<main>".to_owned(),
+                ..Chunk::default()
+            }],
+            func_ctx_stack: vec![FuncCtx {
+                block_ctx_stack: vec![BlockCtx::default()],
+                loop_ctx: false,
+                loop_ctx_stack: Vec::new(),
+                try_depth: 0,
+            }],
+            exported_symbols: Vec::new(),
+            string_pool,
+            depth: 0,
+
+            method_ctx: false,
+            symbols: SymbolIndex::default(),
+            global_defs: HashMap::new(),
+            optimize: false,
+        }
+    }
+
+    /// file name recorded in every chunk, shown in stack traces
+    pub fn set_file(&mut self, file: &str) {
+        self.chunk[0].file = file.to_owned();
+    }
+
+    /// optimize the chunk returned by `finish`, see `optimizer::optimize`
+    pub fn set_optimize(&mut self, optimize: bool) {
+        self.optimize = optimize;
+    }
+
+    pub fn finish(mut self) -> CodeGenResult {
+        self.end_locals_scope();
+        // globals may be used before they are declared
+        for r in self.symbols.refs.iter_mut() {
+            if r.def.is_none() {
+                r.def = self.global_defs.get(&r.name).copied();
+            }
+        }
+        let mut chunk = self.chunk.pop().unwrap();
+        if self.optimize {
+            optimize(&mut chunk, self.string_pool);
+        }
+        CodeGenResult {
+            chunk,
+            exported_symbols: self.exported_symbols,
+            symbols: self.symbols,
+        }
+    }
+    pub fn gen_prog(&mut self, prog: &Program) -> Result<(), Diagnostic> {
+        for stmt in prog.stmts.iter() {
+            self.gen_stmt(stmt)?;
+        }
+        self.emit(Instr::Return, prog.end);
+        Ok(())
+    }
+    /// compile one input of the REPL.
+    /// unlike `gen_prog`, the value of the last statement, if it is an
+    /// expression, is returned from the chunk.
+    pub fn gen_incremental(&mut self, prog: &Program) -> Result<(), Diagnostic> {
+        let (last, init) = match prog.stmts.split_last() {
+            Some((Stmt::Expr { expr, semi }, init)) => (Some((expr, *semi)), init),
+            _ => (None, &prog.stmts[..]),
+        };
+        for stmt in init.iter() {
+            self.gen_stmt(stmt)?;
+        }
+        if let Some((expr, semi)) = last {
+            // return the value instead of popping it
+            self.gen_expr(expr)?;
+            self.emit(Instr::Return, semi);
+        } else {
+            self.emit(Instr::Return, prog.end);
+        }
+        Ok(())
+    }
+    /// compile code evaluated by the debugger in a paused frame.
+    /// the code becomes a closure, names in `scope` are its upvalues
+    /// and other names are globals.
+    pub fn gen_debug_eval(
+        &mut self,
+        prog: &Program,
+        scope: &[IString],
+        method: bool,
+    ) -> Result<Chunk, Diagnostic> {
+        self.open_env();
+        for (i, name) in scope.iter().enumerate() {
+            self.chunk[self.depth]
+                .upvalues
+                .push(UpValueDecl::Ref(i, name.clone()));
+        }
+        self.method_ctx = method;
+        self.gen_incremental(prog)?;
+        let mut chunk = self.close_env();
+        chunk.name = "<eval>".to_owned();
+        Ok(chunk)
+    }
+    fn gen_stmts(&mut self, stmts: &[Stmt]) -> Result<(), Diagnostic> {
+        for stmt in stmts.iter() {
+            self.gen_stmt(stmt)?;
+        }
+        Ok(())
+    }
+    fn gen_stmt(&mut self, stmt: &Stmt) -> Result<(), Diagnostic> {
+        match stmt {
+            Stmt::Var { name, init, semi } => {
+                if let Some(init) = init {
+                    self.gen_expr(init)?;
+                    self.add_local(name, SymbolKind::Variable)?;
+                    self.emit_set_symbol(name, *semi)?;
+                } else {
+                    self.add_local(name, SymbolKind::Variable)?;
+                }
+            }
+            Stmt::Assign {
+                target,
+                op,
+                value,
+                span,
+                ..
+            } => self.gen_assign(target, *op, value, *span)?,
+            Stmt::Expr { expr, semi } => {
+                self.gen_expr(expr)?;
+                self.emit(Instr::Pop, *semi);
+            }
+            Stmt::Block(block) => {
+                self.open_block();
+                self.gen_stmts(&block.stmts)?;
+                self.close_block(block.rbrace);
+            }
+            Stmt::If {
+                cond,
+                then,
+                otherwise,
+                rparen,
+                then_end,
+                end,
+            } => {
+                self.gen_expr(cond)?;
+                let patch_point = self.chunk[self.depth].bytecodes.len();
+                // jump to FALSE branch, patched once it is compiled
+                self.emit(Instr::Nop, *rparen);
+                self.gen_stmt(then)?;
+                let patch_point2 = self.chunk[self.depth].bytecodes.len();
+                // jump to the end of if statement, only if there is an else clause
+                self.emit(Instr::Nop, *then_end);
+                self.chunk[self.depth].bytecodes[patch_point] =
+                    Instr::JumpIfNot((self.chunk[self.depth].bytecodes.len() - patch_point) as i32);
+                if let Some(otherwise) = otherwise {
+                    self.gen_stmt(otherwise)?;
+                    self.chunk[self.depth].bytecodes[patch_point2] =
+                        Instr::Jump((self.chunk[self.depth].bytecodes.len() - patch_point2) as i32);
+                }
+                // just leave the value on the stack, clean it at last
+                self.emit(Instr::Pop, *end);
+            }
+            Stmt::While {
+                cond,
+                body,
+                rparen,
+                end,
+            } => self.gen_while(cond, body, *rparen, *end)?,
+            Stmt::For {
+                var,
+                iter,
+                body,
+                end,
+            } => self.gen_for(var, iter, body, *end)?,
+            Stmt::Func(decl) => {
+                let def = self.add_local(&decl.name, SymbolKind::Function)?;
+                self.open_env();
+                self.gen_function(&decl.func)?;
+                self.end_def(def, decl.func.body.rbrace);
+                let mut chunk = self.close_env();
+                chunk.name = decl.name.name.clone();
+                self.chunk.last_mut().unwrap().chunks.push(chunk);
+                self.emit(
+                    Instr::LoadChunk(self.chunk.last().unwrap().chunks.len() - 1),
+                    decl.lparen,
+                );
+                self.emit_set_symbol(&decl.name, decl.lparen)?;
+            }
+            Stmt::Class(class) => self.gen_class(class)?,
+            Stmt::Return { value, span, .. } => {
+                if let Some(value) = value {
+                    self.gen_expr(value)?;
+                }
+                self.emit(Instr::Return, span.pos);
+            }
+            Stmt::Except { value, span, .. } => {
+                if let Some(value) = value {
+                    self.gen_expr(value)?;
+                } else {
+                    self.emit(Instr::PushNil, span.pos);
+                }
+                self.emit(Instr::Except, span.pos);
+            }
+            Stmt::TryCatch {
+                body,
+                catch,
+                var,
+                handler,
+                end,
+            } => self.gen_try_catch(body, *catch, var.as_ref(), handler, *end)?,
+            Stmt::Match {
+                subject,
+                arms,
+                rbrace,
+                ..
+            } => self.gen_match(subject, arms, *rbrace)?,
+            Stmt::Break(span) | Stmt::Continue(span) => {
+                let is_break = matches!(stmt, Stmt::Break(_));
+                if !self.func_ctx_stack.last().unwrap().loop_ctx {
+                    let msg = if is_break {
+                        "break can ONLY be used inside loops"
+                    } else {
+                        "continue can ONLY be used inside loops"
+                    };
+                    return Err(error_at(*span, msg));
+                }
+                // leaving the iteration, like reaching the end of the body
+                let func_ctx = self.func_ctx_stack.last().unwrap();
+                let loop_ctx = func_ctx.loop_ctx_stack.last().unwrap();
+                let body_block = loop_ctx.body_block;
+                for _ in loop_ctx.try_depth..func_ctx.try_depth {
+                    self.emit(Instr::PopHandler, span.pos);
+                }
+                if let Some(slot) = self.captured_slot(body_block) {
+                    self.emit(Instr::CloseUpValues(slot), span.pos);
+                }
+                // jump patched at the end of the loop
+                let point = self.chunk[self.depth].bytecodes.len();
+                self.emit(Instr::Nop, span.pos);
+                let loop_ctx = self
+                    .func_ctx_stack
+                    .last_mut()
+                    .unwrap()
+                    .loop_ctx_stack
+                    .last_mut()
+                    .unwrap();
+                if is_break {
+                    loop_ctx.break_patch_point.push(point);
+                } else {
+                    loop_ctx.continue_patch_point.push(point);
+                }
+            }
+        }
+        Ok(())
+    }
+    fn gen_class(&mut self, class: &ClassDecl) -> Result<(), Diagnostic> {
+        let class_def = self.add_local(&class.name, SymbolKind::Class)?;
+        self.emit(Instr::InitClass, class.name.span.pos);
+        if let Some(superclass) = &class.superclass {
+            self.record_ref(superclass);
+            self.emit_get_symbol(&superclass.name, superclass.span.pos)?;
+            self.emit(Instr::ClassExtend, superclass.span.pos);
+        }
+        self.method_ctx = true;
+        for method in class.methods.iter() {
+            let method_def = self.define(&method.name, SymbolKind::Method);
+            self.symbols.defs[method_def].container = Some(class_def);
+            let name = self.string_pool.creat_istring(&method.name.name);
+            self.load_value(Value::string(name), method.lparen);
+
+            self.open_env();
+            self.gen_function(&method.func)?;
+            let rbrace = method.func.body.rbrace;
+            if method.name.name == "__init__" {
+                // __init__() method implicitly return this
+                self.emit(Instr::GetThis, rbrace);
+                self.emit(Instr::Return, rbrace);
+            }
+            self.end_def(method_def, rbrace);
+            let mut chunk = self.close_env();
+            chunk.name = format!("{}.{}", class.name.name, method.name.name);
+            self.chunk.last_mut().unwrap().chunks.push(chunk);
+            self.emit(
+                Instr::LoadChunk(self.chunk.last().unwrap().chunks.len() - 1),
+                method.lparen,
+            );
+            self.emit(Instr::AddMethod, method.lparen);
+        }
+        self.method_ctx = false;
+        // klass on the top of stack
+        self.emit_set_symbol(&class.name, class.rbrace)?;
+        self.end_def(class_def, class.rbrace);
+        Ok(())
+    }
+    /// parameters and body, in the env of the function opened by the caller
+    fn gen_function(&mut self, func: &Function) -> Result<(), Diagnostic> {
+        for param in func.params.iter() {
+            self.add_local(param, SymbolKind::Parameter)?;
+        }
+        let chunk = &mut self.chunk[self.depth];
+        chunk.parameter_num = func.params.len();
+        chunk.is_va = func.variadic;
+        self.gen_stmts(&func.body.stmts)
+    }
+    fn gen_for(
+        &mut self,
+        var: &Ident,
+        iter: &Expr,
+        body: &Block,
+        end: (usize, usize),
+    ) -> Result<(), Diagnostic> {
+        self.open_block();
+        self.add_local(var, SymbolKind::Variable)?;
+        let iter_var_slot = self.chunk[self.depth].num_locals - 1;
+        self.gen_expr(iter)?;
+        self.emit(Instr::Iterator, body.lbrace);
+        let loop_start_point = self.chunk[self.depth].bytecodes.len();
+        self.emit(Instr::Next, body.lbrace);
+        let backpatch_point = self.chunk[self.depth].bytecodes.len();
+        self.emit(Instr::Nop, body.lbrace);
+        self.emit(Instr::SetLocal(iter_var_slot), body.lbrace);
+
+        self.gen_stmts(&body.stmts)?;
+        // every iteration has its own loop variable for closures
+        self.close_block(body.rbrace);
+        self.emit(
+            Instr::Jump(loop_start_point as i32 - self.chunk[self.depth].bytecodes.len() as i32),
+            end,
+        );
+        self.chunk[self.depth].bytecodes[backpatch_point] = Instr::JumpIfNot(
+            self.chunk[self.depth].bytecodes.len() as i32 - backpatch_point as i32,
+        );
+        // pop Nil
+        self.emit(Instr::Pop, end);
+        // pop iterator
+        self.emit(Instr::Pop, end);
+        Ok(())
+    }
+    fn gen_while(
+        &mut self,
+        cond: &Expr,
+        body: &Block,
+        rparen: (usize, usize),
+        end: (usize, usize),
+    ) -> Result<(), Diagnostic> {
+        let jumpback_point = self.chunk[self.depth].bytecodes.len();
+        self.gen_expr(cond)?;
+        let patch_point = self.chunk[self.depth].bytecodes.len();
+        self.emit(Instr::Nop, rparen); // to be patched
+        self.emit(Instr::Pop, rparen);
+        self.open_block();
+        let func_ctx = self.func_ctx_stack.last_mut().unwrap();
+        func_ctx.loop_ctx = true;
+        func_ctx.loop_ctx_stack.push(LoopCtx {
+            body_block: func_ctx.block_ctx_stack.len() - 1,
+            try_depth: func_ctx.try_depth,
+            ..LoopCtx::default()
+        });
+        self.gen_stmts(&body.stmts)?;
+        let func_ctx = self.func_ctx_stack.last_mut().unwrap();
+        let cur_loop_ctx = func_ctx.loop_ctx_stack.pop().unwrap();
+        func_ctx.loop_ctx = false;
+        // patch continue points, jump to loop header
+        for continue_point in cur_loop_ctx.continue_patch_point.iter() {
+            self.chunk[self.depth].bytecodes[*continue_point] =
+                Instr::Jump(jumpback_point as i32 - *continue_point as i32);
+        }
+        self.close_block(body.rbrace);
+        self.emit(
+            Instr::Jump(jumpback_point as i32 - self.chunk[self.depth].bytecodes.len() as i32),
+            body.rbrace,
+        );
+        self.chunk[self.depth].bytecodes[patch_point] =
+            Instr::JumpIfNot(self.chunk[self.depth].bytecodes.len() as i32 - patch_point as i32);
+        self.emit(Instr::Pop, end);
+        // patch break points, jump to end of loop
+        for break_point in cur_loop_ctx.break_patch_point.iter() {
+            self.chunk[self.depth].bytecodes[*break_point] =
+                Instr::Jump(self.chunk[self.depth].bytecodes.len() as i32 - *break_point as i32);
+        }
+        Ok(())
+    }
+    /// the protected block is compiled inline between `PushHandler` and
+    /// `PopHandler`, an error raised inside jumps to the catch block with
+    /// the error value on the stack
+    fn gen_try_catch(
+        &mut self,
+        body: &Block,
+        catch: Span,
+        var: Option<&Ident>,
+        handler: &Block,
+        end: (usize, usize),
+    ) -> Result<(), Diagnostic> {
+        let handler_point = self.emit_nop(body.lbrace);
+        self.func_ctx_stack[self.depth].try_depth += 1;
+        self.open_block();
+        self.gen_stmts(&body.stmts)?;
+        let block = self.func_ctx_stack[self.depth].block_ctx_stack.len() - 1;
+        let captured = self.captured_slot(block);
+        self.close_block(body.rbrace);
+        self.func_ctx_stack[self.depth].try_depth -= 1;
+        self.emit(Instr::PopHandler, body.rbrace);
+        let end_point = self.emit_nop(body.rbrace);
+        self.patch_jump(handler_point, Instr::PushHandler);
+        // locals of the protected block are left by the error as well
+        if let Some(slot) = captured {
+            self.emit(Instr::CloseUpValues(slot), catch.pos);
+        }
+        self.open_block();
+        if let Some(var) = var {
+            self.add_local(var, SymbolKind::Variable)?;
+            let err_var_slot = self.chunk[self.depth].num_locals - 1;
+            self.emit(Instr::SetLocal(err_var_slot), handler.lbrace);
+        } else {
+            self.emit(Instr::Pop, handler.lbrace);
+        }
+        self.gen_stmts(&handler.stmts)?;
+        self.close_block(handler.rbrace);
+        self.emit(Instr::Nop, end);
+        self.patch_jump(end_point, Instr::Jump);
+        Ok(())
+    }
+    /// the subject stays on the stack while the arms are tried in order.
+    /// a failed test jumps to the `Pop`s of what its arm pushed above the
+    /// subject, then the next arm is tried
+    fn gen_match(
+        &mut self,
+        subject: &Expr,
+        arms: &[MatchArm],
+        rbrace: (usize, usize),
+    ) -> Result<(), Diagnostic> {
+        self.gen_expr(subject)?;
+        let mut end_points = Vec::new();
+        for arm in arms.iter() {
+            self.open_block();
+            // jumps of failed tests, by the number of values above the subject
+            let mut fail_points = Vec::new();
+            self.gen_pattern(&arm.pattern, 0, &mut fail_points)?;
+            if let Some(guard) = &arm.guard {
+                self.gen_expr(guard)?;
+                self.emit_test(0, arm.arrow, &mut fail_points);
+            }
+            self.gen_stmts(&arm.body.stmts)?;
+            // a guard may capture the bindings even if the arm is not run
+            let block = self.func_ctx_stack[self.depth].block_ctx_stack.len() - 1;
+            let captured = self.captured_slot(block);
+            self.close_block(arm.body.rbrace);
+            end_points.push(self.emit_nop(arm.body.rbrace));
+            for points in fail_points.iter().rev() {
+                for point in points.iter() {
+                    self.patch_jump(*point, Instr::JumpIfNot);
+                }
+                self.emit(Instr::Pop, arm.arrow);
+            }
+            if let (Some(slot), false) = (captured, fail_points.is_empty()) {
+                self.emit(Instr::CloseUpValues(slot), arm.arrow);
+            }
+        }
+        for point in end_points.iter() {
+            self.patch_jump(*point, Instr::Jump);
+        }
+        // pop the subject
+        self.emit(Instr::Pop, rbrace);
+        Ok(())
+    }
+    /// tests of `pattern` on the value on the top of stack, which is
+    /// `depth` values above the subject. the value is left on the stack
+    fn gen_pattern(
+        &mut self,
+        pattern: &Pattern,
+        depth: usize,
+        fail_points: &mut Vec<Vec<usize>>,
+    ) -> Result<(), Diagnostic> {
+        let pos = pattern.start();
+        match pattern {
+            Pattern::Wildcard(_) => {}
+            Pattern::Bind(name) => {
+                self.emit(Instr::Dup(1), pos);
+                self.add_local(name, SymbolKind::Variable)?;
+                self.emit_set_symbol(name, pos)?;
+            }
+            Pattern::Literal(literal) => {
+                self.gen_expr(literal)?;
+                self.emit(Instr::MatchEq, pos);
+                self.emit_test(depth, pos, fail_points);
+            }
+            Pattern::Range { lo, hi, closed } => {
+                self.gen_expr(lo)?;
+                self.gen_expr(hi)?;
+                let test = if *closed {
+                    Instr::MatchRangeClosed
+                } else {
+                    Instr::MatchRange
+                };
+                self.emit(test, pos);
+                self.emit_test(depth, pos, fail_points);
+            }
+            Pattern::Array { items, rest, .. } => {
+                let len = items.len() as u32;
+                self.emit(Instr::MatchArray(len, rest.is_some() as u32), pos);
+                self.emit_test(depth, pos, fail_points);
+                for (i, item) in items.iter().enumerate() {
+                    if matches!(item, Pattern::Wildcard(_)) {
+                        continue;
+                    }
+                    let item_pos = item.start();
+                    self.emit(Instr::Dup(1), item_pos);
+                    self.load_value(Value::number(i as f64), item_pos);
+                    self.emit(Instr::GetCollection(0), item_pos);
+                    self.gen_pattern(item, depth + 1, fail_points)?;
+                    self.emit(Instr::Pop, item_pos);
+                }
+                if let Some(Pattern::Bind(name)) = rest.as_deref() {
+                    self.emit(Instr::Dup(1), name.span.pos);
+                    self.emit(Instr::ArraySlice(items.len()), name.span.pos);
+                    self.add_local(name, SymbolKind::Variable)?;
+                    self.emit_set_symbol(name, name.span.pos)?;
+                }
+            }
+            Pattern::Dict { entries, .. } => {
+                for (key, value) in entries.iter() {
+                    let Expr::String(key, span) = key else {
+                        unreachable!("dict pattern keys checked by the parser");
+                    };
+                    self.gen_field_pattern(key, value, span.pos, depth, fail_points)?;
+                }
+            }
+            Pattern::Class { class, fields, .. } => {
+                self.gen_class_test(class, depth, fail_points)?;
+                for (field, value) in fields.iter() {
+                    self.gen_field_pattern(&field.name, value, field.span.pos, depth, fail_points)?;
+                }
+            }
+            Pattern::Is { binding, class } => {
+                self.gen_class_test(class, depth, fail_points)?;
+                self.gen_pattern(binding, depth, fail_points)?;
+            }
+        }
+        Ok(())
+    }
+    /// the key or field `name` of the value on the top of stack matches `pattern`
+    fn gen_field_pattern(
+        &mut self,
+        name: &str,
+        pattern: &Pattern,
+        pos: (usize, usize),
+        depth: usize,
+        fail_points: &mut Vec<Vec<usize>>,
+    ) -> Result<(), Diagnostic> {
+        let idx = self.push_unique_string(name);
+        self.emit(Instr::HasField(idx), pos);
+        self.emit_test(depth, pos, fail_points);
+        if !matches!(pattern, Pattern::Wildcard(_)) {
+            self.emit(Instr::Dup(1), pos);
+            self.emit(Instr::GetField(idx), pos);
+            self.gen_pattern(pattern, depth + 1, fail_points)?;
+            self.emit(Instr::Pop, pos);
+        }
+        Ok(())
+    }
+    /// the value on the top of stack is an instance of `class`
+    fn gen_class_test(
+        &mut self,
+        class: &Ident,
+        depth: usize,
+        fail_points: &mut Vec<Vec<usize>>,
+    ) -> Result<(), Diagnostic> {
+        self.emit(Instr::Dup(1), class.span.pos);
+        self.gen_expr(&Expr::Name(class.clone()))?;
+        self.emit(Instr::ClassIs, class.span.pos);
+        self.emit_test(depth, class.span.pos, fail_points);
+        Ok(())
+    }
+    /// jump away if the bool on the top of stack is false, pop it otherwise
+    fn emit_test(&mut self, depth: usize, cood: (usize, usize), fail_points: &mut Vec<Vec<usize>>) {
+        if fail_points.len() <= depth {
+            fail_points.resize_with(depth + 1, Vec::new);
+        }
+        let point = self.emit_nop(cood);
+        fail_points[depth].push(point);
+        self.emit(Instr::Pop, cood);
+    }
+    /// `target = value` or `target op= value`, the object and the index of
+    /// the target are evaluated once
+    fn gen_assign(
+        &mut self,
+        target: &Expr,
+        op: Option<BinaryOp>,
+        value: &Expr,
+        span: Span,
+    ) -> Result<(), Diagnostic> {
+        match target {
+            Expr::Name(name) => {
+                let (get, set) = match self.resolve_name(name)? {
+                    VarLoc::Local(x) => (Instr::GetLocal(x), Instr::SetLocal(x)),
+                    VarLoc::UpValue(x) => (Instr::GetUpValue(x), Instr::SetUpValue(x)),
+                    VarLoc::Global(x) => (Instr::GetGlobal(x), Instr::SetGlobal(x)),
+                    _ => return Err(error_at(name.span, "set `this` is invalid")),
+                };
+                if op.is_some() {
+                    self.emit(get, name.span.pos);
+                }
+                self.gen_operand(op, value, span)?;
+                self.emit(set, name.span.pos);
+            }
+            Expr::Index {
+                target,
+                index,
+                span: index_span,
+                ..
+            } => {
+                self.gen_expr(target)?;
+                self.gen_expr(index)?;
+                if op.is_some() {
+                    self.emit(Instr::Dup(2), index_span.pos);
+                    self.emit(Instr::GetCollection(0), index_span.pos);
+                }
+                self.gen_operand(op, value, span)?;
+                self.emit(Instr::SetCollection(0), index_span.pos);
+            }
+            Expr::Field {
+                target,
+                name,
+                span: field_span,
+                ..
+            } => {
+                self.gen_expr(target)?;
+                let idx = self.push_unique_string(&name.name);
+                if op.is_some() {
+                    self.emit(Instr::Dup(1), field_span.pos);
+                    self.emit(Instr::GetField(idx), field_span.pos);
+                }
+                self.gen_operand(op, value, span)?;
+                self.emit(Instr::SetField(idx), field_span.pos);
+            }
+            _ => unreachable!("assignment target checked by the parser"),
+        }
+        Ok(())
+    }
+    /// value to assign, combined by `op` with the old one on the stack
+    fn gen_operand(
+        &mut self,
+        op: Option<BinaryOp>,
+        value: &Expr,
+        span: Span,
+    ) -> Result<(), Diagnostic> {
+        self.gen_expr(value)?;
+        if let Some(op) = op {
+            let instr = match op {
+                BinaryOp::Add => Instr::Add,
+                BinaryOp::Sub => Instr::Sub,
+                BinaryOp::Mul => Instr::Mul,
+                BinaryOp::Div => Instr::Div,
+                BinaryOp::Mod => Instr::Mod,
+                _ => unreachable!("only arithmetic compound assignment is parsed"),
+            };
+            self.emit(instr, span.pos);
+        }
+        Ok(())
+    }
+    fn gen_expr(&mut self, expr: &Expr) -> Result<(), Diagnostic> {
+        match expr {
+            Expr::Name(name) => {
+                let instr = match self.resolve_name(name)? {
+                    VarLoc::Local(x) => Instr::GetLocal(x),
+                    VarLoc::UpValue(x) => Instr::GetUpValue(x),
+                    VarLoc::Global(x) => Instr::GetGlobal(x),
+                    _ => Instr::GetThis,
+                };
+                self.emit(instr, name.span.pos);
+            }
+            Expr::Super { method, span } => {
+                if !self.method_ctx {
+                    return Err(error_at(*span, "`super` can NOT be used outside methods"));
+                }
+                let pos = method.span.pos;
+                self.emit(Instr::GetThis, pos);
+                let name = self.string_pool.creat_istring(&method.name);
+                self.load_value(Value::string(name), pos);
+                self.emit(Instr::GetSuperMethod, pos);
+            }
+            Expr::Number(x, span) => self.load_value(Value::number(*x), span.pos),
+            Expr::String(s, span) => {
+                let s = self.string_pool.creat_istring(s);
+                self.load_value(Value::string(s), span.pos);
+            }
+            Expr::Interpolation(parts) => self.gen_interpolation(parts)?,
+            Expr::Bool(true, span) => self.emit(Instr::LoadTrue, span.pos),
+            Expr::Bool(false, span) => self.emit(Instr::LoadFalse, span.pos),
+            Expr::Nil(span) => self.emit(Instr::PushNil, span.pos),
+            Expr::Array { items, span, .. } => {
+                for item in items.iter() {
+                    self.gen_expr(item)?;
+                }
+                self.emit(Instr::InitArray(items.len()), span.pos);
+            }
+            Expr::Dict { entries, span, .. } => {
+                for (key, value) in entries.iter() {
+                    self.gen_expr(key)?;
+                    self.gen_expr(value)?;
+                }
+                self.emit(Instr::InitDict(entries.len()), span.pos);
+            }
+            Expr::Unary { op, operand, span } => {
+                self.gen_expr(operand)?;
+                let instr = match op {
+                    UnaryOp::Neg => Instr::Negate,
+                    UnaryOp::Not => Instr::Not,
+                };
+                self.emit(instr, span.pos);
+            }
+            Expr::Binary {
+                op: op @ (BinaryOp::And | BinaryOp::Or),
+                lhs,
+                rhs,
+                span,
+            } => {
+                self.gen_expr(lhs)?;
+                let backpatch_point = self.chunk[self.depth].bytecodes.len();
+                self.emit(Instr::Nop, span.pos);
+                // discard left value bool
+                self.emit(Instr::Pop, span.pos);
+                self.gen_expr(rhs)?;
+                let offset = (self.chunk[self.depth].bytecodes.len() - backpatch_point) as i32;
+                self.chunk[self.depth].bytecodes[backpatch_point] = if *op == BinaryOp::And {
+                    Instr::JumpIfNot(offset)
+                } else {
+                    Instr::JumpIfTrue(offset)
+                };
+            }
+            Expr::Binary {
+                op: BinaryOp::Coalesce,
+                lhs,
+                rhs,
+                span,
+            } => {
+                self.gen_expr(lhs)?;
+                let backpatch_point = self.emit_nop(span.pos);
+                // discard left value nil
+                self.emit(Instr::Pop, span.pos);
+                self.gen_expr(rhs)?;
+                self.patch_jump(backpatch_point, Instr::JumpIfNotNil);
+            }
+            Expr::Conditional {
+                cond,
+                then,
+                otherwise,
+                span,
+            } => {
+                self.gen_expr(cond)?;
+                let else_point = self.emit_nop(span.pos);
+                self.emit(Instr::Pop, span.pos);
+                self.gen_expr(then)?;
+                let end_point = self.emit_nop(span.pos);
+                self.patch_jump(else_point, Instr::JumpIfNot);
+                self.emit(Instr::Pop, span.pos);
+                self.gen_expr(otherwise)?;
+                self.patch_jump(end_point, Instr::Jump);
+            }
+            Expr::Binary { op, lhs, rhs, span } => {
+                self.gen_expr(lhs)?;
+                self.gen_expr(rhs)?;
+                let instr = match op {
+                    BinaryOp::Add => Instr::Add,
+                    BinaryOp::Sub => Instr::Sub,
+                    BinaryOp::Mul => Instr::Mul,
+                    BinaryOp::Div => Instr::Div,
+                    BinaryOp::Mod => Instr::Mod,
+                    BinaryOp::Lt => Instr::Lt,
+                    BinaryOp::Gt => Instr::Gt,
+                    BinaryOp::Le => Instr::Le,
+                    BinaryOp::Ge => Instr::Ge,
+                    BinaryOp::Eq => Instr::Eq,
+                    BinaryOp::Ne => Instr::Ne,
+                    BinaryOp::Range => Instr::MakeRange,
+                    BinaryOp::RangeClosed => Instr::MakeRangeClosed,
+                    BinaryOp::Is => Instr::ClassIs,
+                    BinaryOp::Pipe => {
+                        // `para |> functor` => `functor(para)`
+                        self.emit(Instr::Swap2, span.pos);
+                        Instr::Call(1)
+                    }
+                    BinaryOp::And | BinaryOp::Or | BinaryOp::Coalesce => unreachable!(),
+                };
+                self.emit(instr, span.pos);
+            }
+            Expr::Call { .. } | Expr::Index { .. } | Expr::Field { .. } => {
+                let mut nil_points = Vec::new();
+                self.gen_postfix(expr, &mut nil_points)?;
+                for point in nil_points {
+                    self.patch_jump(point, Instr::JumpIfNil);
+                }
+            }
+            Expr::Try { call, .. } => {
+                // `try f(args)` evaluates to the return value, or an error value if anything goes wrong
+                let Expr::Call {
+                    callee, args, span, ..
+                } = call.as_ref()
+                else {
+                    unreachable!("`try` operand checked by the parser");
+                };
+                self.gen_call(callee, args)?;
+                self.emit(Instr::TryCall(args.len()), span.pos);
+            }
+            Expr::Lambda { func, span } => {
+                self.open_env();
+                self.gen_function(func)?;
+                let mut chunk = self.close_env();
+                chunk.name = "<lambda>".to_owned();
+                self.chunk.last_mut().unwrap().chunks.push(chunk);
+                // evaluate to a closure
+                self.emit(
+                    Instr::LoadChunk(self.chunk.last().unwrap().chunks.len() - 1),
+                    span.pos,
+                );
+            }
+        }
+        Ok(())
+    }
+    /// a chain of calls, indexes and fields. `?.` and `?[` on nil skip the
+    /// rest of the chain, the placeholders of their jumps are left in `nil_points`
+    fn gen_postfix(&mut self, expr: &Expr, nil_points: &mut Vec<usize>) -> Result<(), Diagnostic> {
+        match expr {
+            Expr::Call {
+                callee, args, span, ..
+            } => {
+                if let Expr::Field {
+                    target,
+                    name,
+                    optional,
+                    span,
+                } = callee.as_ref()
+                {
+                    // `target.name(args)`, methods are called without binding them
+                    self.gen_postfix(target, nil_points)?;
+                    if *optional {
+                        nil_points.push(self.emit_nop(span.pos));
+                    }
+                    for arg in args.iter() {
+                        self.gen_expr(arg)?;
+                    }
+                    let idx = self.push_unique_string(&name.name);
+                    self.emit(Instr::Invoke(idx as u32, args.len() as u32), span.pos);
+                } else {
+                    self.gen_postfix(callee, nil_points)?;
+                    for arg in args.iter() {
+                        self.gen_expr(arg)?;
+                    }
+                    self.emit(Instr::Call(args.len()), span.pos);
+                }
+            }
+            Expr::Index {
+                target,
+                index,
+                optional,
+                span,
+                ..
+            } => {
+                self.gen_postfix(target, nil_points)?;
+                if *optional {
+                    nil_points.push(self.emit_nop(span.pos));
+                }
+                self.gen_expr(index)?;
+                self.emit(Instr::GetCollection(0), span.pos);
+            }
+            Expr::Field {
+                target,
+                name,
+                optional,
+                span,
+            } => {
+                self.gen_postfix(target, nil_points)?;
+                if *optional {
+                    nil_points.push(self.emit_nop(span.pos));
+                }
+                let idx = self.push_unique_string(&name.name);
+                self.emit(Instr::GetField(idx), span.pos);
+            }
+            _ => self.gen_expr(expr)?,
+        }
+        Ok(())
+    }
+    /// placeholder of a jump patched by `patch_jump`, returns its pc
+    fn emit_nop(&mut self, cood: (usize, usize)) -> usize {
+        self.emit(Instr::Nop, cood);
+        self.chunk[self.depth].bytecodes.len() - 1
+    }
+    /// make the placeholder at `point` a jump to the next instruction
+    fn patch_jump(&mut self, point: usize, jump: fn(i32) -> Instr) {
+        let code = &mut self.chunk[self.depth].bytecodes;
+        code[point] = jump((code.len() - point) as i32);
+    }
+    /// callee and arguments of a call
+    fn gen_call(&mut self, callee: &Expr, args: &[Expr]) -> Result<(), Diagnostic> {
+        self.gen_expr(callee)?;
+        for arg in args.iter() {
+            self.gen_expr(arg)?;
+        }
+        Ok(())
+    }
+    /// `"a${b}c"` is `"a" + string(b) + "c"`
+    fn gen_interpolation(&mut self, parts: &[StrPart]) -> Result<(), Diagnostic> {
+        let mut add_at = None;
+        for part in parts.iter() {
+            match part {
+                StrPart::Str(s, span) => {
+                    let s = self.string_pool.creat_istring(s);
+                    self.load_value(Value::string(s), span.pos);
+                }
+                StrPart::Expr {
+                    expr,
+                    open,
+                    start,
+                    close,
+                } => {
+                    // require builtin string function
+                    self.emit_get_symbol("string", *start)?;
+                    self.gen_expr(expr)?;
+                    self.emit(Instr::Call(1), close.pos);
+                    self.emit(Instr::Add, open.pos);
+                    add_at = Some(close.pos);
+                    continue;
+                }
+            }
+            if let Some(pos) = add_at.take() {
+                self.emit(Instr::Add, pos);
+            }
+        }
+        Ok(())
+    }
+    #[inline]
+    fn emit_get_symbol(&mut self, symbol: &str, cood: (usize, usize)) -> Result<(), Diagnostic> {
+        let instr = match self.resolve(symbol, self.depth) {
+            VarLoc::ThisRef => Instr::GetThis,
+            VarLoc::Local(x) => Instr::GetLocal(x),
+            VarLoc::UpValue(x) => Instr::GetUpValue(x),
+            VarLoc::Global(x) => Instr::GetGlobal(x),
+            VarLoc::NotFound => {
+                return Err(Diagnostic::error(
+                    cood,
+                    format!("symbol not found:{}", symbol),
+                ));
+            }
+        };
+        self.emit(instr, cood);
+        Ok(())
+    }
+    #[inline]
+    fn emit_set_symbol(&mut self, symbol: &Ident, cood: (usize, usize)) -> Result<(), Diagnostic> {
+        let instr = match self.resolve(&symbol.name, self.depth) {
+            VarLoc::ThisRef => {
+                return Err(error_at(symbol.span, "set `this` is invalid"));
+            }
+            VarLoc::Local(x) => Instr::SetLocal(x),
+            VarLoc::UpValue(x) => Instr::SetUpValue(x),
+            VarLoc::Global(x) => Instr::SetGlobal(x),
+            VarLoc::NotFound => {
+                return Err(error_at(
+                    symbol.span,
+                    &format!("symbol not found:{}", symbol.name),
+                ));
+            }
+        };
+        self.emit(instr, cood);
+        Ok(())
+    }
+    /// where a name that is read or assigned is, records the use
+    fn resolve_name(&mut self, name: &Ident) -> Result<VarLoc, Diagnostic> {
+        if !self.method_ctx && name.name == "this" {
+            return Err(error_at(
+                name.span,
+                "`this` can NOT be used outside methods",
+            ));
+        }
+        self.record_ref(name);
+        match self.resolve(&name.name, self.depth) {
+            VarLoc::NotFound => Err(error_at(name.span, "symbol not found")),
+            loc => Ok(loc),
+        }
+    }
+    /// open function level env
+    #[inline]
+    fn open_env(&mut self) {
+        self.chunk.push(Chunk::default());
+        self.func_ctx_stack.push(FuncCtx::default());
+        self.func_ctx_stack
+            .last_mut()
+            .unwrap()
+            .block_ctx_stack
+            .push(BlockCtx::default());
+        self.depth += 1;
+    }
+    /// close function level env
+    #[inline]
+    fn close_env(&mut self) -> Chunk {
+        self.emit(Instr::Return, (0, 0));
+        self.end_locals_scope();
+        self.depth -= 1;
+        self.func_ctx_stack.pop();
+        let mut chunk = self.chunk.pop().unwrap();
+        chunk.file = self.chunk[0].file.clone();
+        chunk
+    }
+    /// open block level env
+    #[inline]
+    fn open_block(&mut self) {
+        self.func_ctx_stack
+            .last_mut()
+            .unwrap()
+            .block_ctx_stack
+            .push(BlockCtx::default());
+    }
+    /// close block level env, upvalues of its locals are closed at `cood`
+    #[inline]
+    fn close_block(&mut self, cood: (usize, usize)) {
+        let depth = self.func_ctx_stack.last().unwrap().block_ctx_stack.len() - 1;
+        if let Some(slot) = self.captured_slot(depth) {
+            self.emit(Instr::CloseUpValues(slot), cood);
+        }
+        let block = self
+            .func_ctx_stack
+            .last_mut()
+            .unwrap()
+            .block_ctx_stack
+            .pop()
+            .unwrap();
+        let end = self.chunk[self.depth].bytecodes.len();
+        for idx in block.locals {
+            self.chunk[self.depth].locals[idx].end = end;
+        }
+    }
+    /// lowest slot of the locals in the blocks from `block` inwards, if one
+    /// of them is captured
+    fn captured_slot(&self, block: usize) -> Option<usize> {
+        let blocks = &self.func_ctx_stack[self.depth].block_ctx_stack[block..];
+        if !blocks.iter().any(|b| b.captured) {
+            return None;
+        }
+        let chunk = &self.chunk[self.depth];
+        blocks
+            .iter()
+            .flat_map(|b| b.locals.iter())
+            .map(|idx| chunk.locals[*idx].slot)
+            .min()
+    }
+    /// locals of the function body are visible till its end
+    fn end_locals_scope(&mut self) {
+        let chunk = &mut self.chunk[self.depth];
+        let end = chunk.bytecodes.len();
+        for local in chunk.locals.iter_mut() {
+            if local.end == usize::MAX {
+                local.end = end;
+            }
+        }
+    }
+    /// returns the index of `symbol` in `SymbolIndex.defs`
+    fn add_local(&mut self, symbol: &Ident, kind: SymbolKind) -> Result<usize, Diagnostic> {
+        let def = self.define(symbol, kind);
+        if self.depth == 0 && self.func_ctx_stack[self.depth].block_ctx_stack.len() == 1 {
+            // outermost scope => Global
+            let s = self.string_pool.creat_istring(&symbol.name);
+            self.exported_symbols.push(s);
+            self.global_defs.entry(symbol.name.clone()).or_insert(def);
+            return Ok(def);
+        }
+        let num_locals = self.chunk[self.depth].num_locals;
+        let block = self.func_ctx_stack[self.depth]
+            .block_ctx_stack
+            .last_mut()
+            .unwrap();
+        if block.symbol_table.contains_key(&symbol.name) {
+            return Err(error_at(symbol.span, "redeclaration of symbol"));
+        }
+        block.symbol_table.insert(symbol.name.clone(), num_locals);
+        block.defs.insert(symbol.name.clone(), def);
+        let chunk = &mut self.chunk[self.depth];
+        chunk.locals.push(LocalVar {
+            name: symbol.name.clone(),
+            slot: num_locals,
+            start: chunk.bytecodes.len(),
+            end: usize::MAX,
+        });
+        block.locals.push(chunk.locals.len() - 1);
+        chunk.num_locals += 1;
+        Ok(def)
+    }
+    fn define(&mut self, symbol: &Ident, kind: SymbolKind) -> usize {
+        let Span { pos, len } = symbol.span;
+        self.symbols.defs.push(SymbolDef {
+            name: symbol.name.clone(),
+            kind,
+            pos,
+            span: len,
+            end: (pos.0, pos.1 + len),
+            container: None,
+        });
+        self.symbols.defs.len() - 1
+    }
+    /// the declaration `def` ends at its closing `}` at `rbrace`
+    fn end_def(&mut self, def: usize, rbrace: (usize, usize)) {
+        let (line, col) = rbrace;
+        self.symbols.defs[def].end = (line, col + 1);
+    }
+    /// record a use of `symbol`, resolved like `resolve` except that
+    /// globals are resolved in `finish`
+    fn record_ref(&mut self, symbol: &Ident) {
+        if symbol.name == "this" {
+            return;
+        }
+        let def = self
+            .func_ctx_stack
+            .iter()
+            .rev()
+            .flat_map(|func_ctx| func_ctx.block_ctx_stack.iter().rev())
+            .find_map(|block| block.defs.get(&symbol.name).copied());
+        self.symbols.refs.push(SymbolRef {
+            name: symbol.name.clone(),
+            pos: symbol.span.pos,
+            span: symbol.span.len,
+            def,
+        });
+    }
+    #[inline]
+    fn emit(&mut self, instr: Instr, cood: (usize, usize)) {
+        self.chunk[self.depth].bytecodes.push(instr);
+        self.chunk[self.depth].cood.push(cood);
+    }
+    #[inline]
+    fn push_constant(&mut self, c: Value) -> usize {
+        self.chunk[self.depth].constants.push(c);
+        self.chunk[self.depth].constants.len() - 1
+    }
+    fn push_unique_string(&mut self, s: &str) -> usize {
+        for (i, c) in self.chunk[self.depth].constants.iter().enumerate() {
+            if let Unboxed::String(s1) = c.unbox() {
+                if s == s1.get_inner() {
+                    return i;
+                }
+            }
+        }
+        let s = self.string_pool.creat_istring(s);
+        self.push_constant(Value::string(s))
+    }
+    #[inline]
+    fn load_value(&mut self, v: Value, cood: (usize, usize)) {
+        if let Some(x) = self.chunk[self.depth]
+            .constants
+            .iter()
+            .position(|val| val == &v)
+        {
+            self.emit(Instr::Load(x), cood);
+        } else {
+            let idx = self.push_constant(v);
+            self.emit(Instr::Load(idx), cood);
+        }
+    }
+
+    fn resolve(&mut self, symbol: &str, depth: usize) -> VarLoc {
+        if symbol == "this" {
+            if !self.method_ctx {
+                return VarLoc::NotFound;
+            }
+            return VarLoc::ThisRef;
+        }
+        // from innermost Block to outermost Block of current Function
+        for ctx in self.func_ctx_stack[depth].block_ctx_stack.iter().rev() {
+            if let Some(x) = ctx.symbol_table.get(symbol) {
+                return VarLoc::Local(*x);
+            }
+        }
+        if depth == 0 {
+            // stacktop = GLOBAL[constant[idx]]
+            let idx = self.push_unique_string(symbol);
+            return VarLoc::Global(idx);
+        }
+        if let Some(x) = self.chunk[depth].upvalues.iter().position(|upv| match upv {
+            UpValueDecl::Ref(_, s) => s.get_inner() == symbol,
+            UpValueDecl::RefUpValue(_, s) => s.get_inner() == symbol,
+        }) {
+            return VarLoc::UpValue(x);
+        }
+        let ret = self.resolve(symbol, depth - 1);
+        match ret {
+            VarLoc::Local(x) => {
+                // local variable of outer function
+                // add to upvalue list of this function
+                for ctx in self.func_ctx_stack[depth - 1]
+                    .block_ctx_stack
+                    .iter_mut()
+                    .rev()
+                {
+                    if ctx.symbol_table.contains_key(symbol) {
+                        ctx.captured = true;
+                        break;
+                    }
+                }
+                let s = self.string_pool.creat_istring(symbol);
+                self.chunk[depth].upvalues.push(UpValueDecl::Ref(x, s));
+                VarLoc::UpValue(self.chunk[depth].upvalues.len() - 1)
+            }
+            VarLoc::UpValue(x) => {
+                // upvalue of outer function
+                let s = self.string_pool.creat_istring(symbol);
+                self.chunk[depth]
+                    .upvalues
+                    .push(UpValueDecl::RefUpValue(x, s));
+                VarLoc::UpValue(self.chunk[depth].upvalues.len() - 1)
+            }
+            // Global variable
+            VarLoc::Global(x) => VarLoc::Global(x),
+            _ => VarLoc::NotFound,
+        }
+    }
+}
@@ -0,0 +1,222 @@
+//! optional pass over the bytecode of `CodeGen`, enabled by `-O`.
+//! instructions are only removed or replaced by cheaper ones doing the same,
+//! `Chunk.cood` and `Chunk.locals` follow the instructions they describe.
+
+use crate::interned_string::StringPool;
+use crate::{Chunk, Instr, Unboxed, Value};
+
+/// optimize `chunk` and the chunks of its functions:
+/// - constant arithmetic and string concatenation are folded
+/// - `Nop`s and code after `Return`, `Except` or `Jump` are removed
+/// - jumps to jumps go straight to the last one's target
+/// - values pushed and popped right away are not pushed
+pub fn optimize(chunk: &mut Chunk, string_pool: &mut StringPool) {
+    for sub in chunk.chunks.iter_mut() {
+        optimize(sub, string_pool);
+    }
+    loop {
+        let len = chunk.bytecodes.len();
+        thread_jumps(chunk);
+        rewrite(chunk, string_pool);
+        if chunk.bytecodes.len() == len {
+            break;
+        }
+    }
+}
+
+/// where the jump at `pc` goes
+fn jump_target(pc: usize, instr: Instr) -> Option<usize> {
+    match instr {
+        Instr::Jump(x)
+        | Instr::JumpIfNot(x)
+        | Instr::JumpIfTrue(x)
+        | Instr::JumpIfNil(x)
+        | Instr::JumpIfNotNil(x)
+        | Instr::PushHandler(x) => Some((pc as i32 + x) as usize),
+        _ => None,
+    }
+}
+
+fn with_offset(instr: Instr, offset: i32) -> Instr {
+    match instr {
+        Instr::Jump(_) => Instr::Jump(offset),
+        Instr::JumpIfNot(_) => Instr::JumpIfNot(offset),
+        Instr::JumpIfTrue(_) => Instr::JumpIfTrue(offset),
+        Instr::JumpIfNil(_) => Instr::JumpIfNil(offset),
+        Instr::JumpIfNotNil(_) => Instr::JumpIfNotNil(offset),
+        Instr::PushHandler(_) => Instr::PushHandler(offset),
+        _ => instr,
+    }
+}
+
+/// conditional jumps leave the condition on the stack, so one landing on
+/// another jump knows which way that one goes
+fn thread_jumps(chunk: &mut Chunk) {
+    let code = &mut chunk.bytecodes;
+    for pc in 0..code.len() {
+        let Some(mut target) = jump_target(pc, code[pc]) else {
+            continue;
+        };
+        // bounded, `while (true) {}` jumps to itself
+        for _ in 0..code.len() {
+            while matches!(code.get(target), Some(Instr::Nop)) {
+                target += 1;
+            }
+            let Some(&next) = code.get(target) else {
+                break;
+            };
+            target = match (code[pc], next) {
+                (_, Instr::Jump(x))
+                | (Instr::JumpIfNot(_), Instr::JumpIfNot(x))
+                | (Instr::JumpIfTrue(_), Instr::JumpIfTrue(x))
+                | (Instr::JumpIfNil(_), Instr::JumpIfNil(x))
+                | (Instr::JumpIfNotNil(_), Instr::JumpIfNotNil(x)) => (target as i32 + x) as usize,
+                (Instr::JumpIfNot(_), Instr::JumpIfTrue(_))
+                | (Instr::JumpIfTrue(_), Instr::JumpIfNot(_))
+                | (Instr::JumpIfNil(_), Instr::JumpIfNotNil(_))
+                | (Instr::JumpIfNotNil(_), Instr::JumpIfNil(_)) => target + 1,
+                _ => break,
+            };
+        }
+        code[pc] = with_offset(code[pc], target as i32 - pc as i32);
+    }
+}
+
+/// one pass of folding and removing, then jumps are patched
+fn rewrite(chunk: &mut Chunk, string_pool: &mut StringPool) {
+    let code = std::mem::take(&mut chunk.bytecodes);
+    let cood = std::mem::take(&mut chunk.cood);
+    let mut is_target = vec![false; code.len() + 1];
+    for (pc, instr) in code.iter().enumerate() {
+        if let Some(target) = jump_target(pc, *instr) {
+            is_target[target] = true;
+        }
+    }
+    // new pc of every instruction, removed ones get the one of what follows
+    let mut map = Vec::with_capacity(code.len() + 1);
+    // instruction, position and old target of jumps
+    let mut out: Vec<(Instr, (usize, usize), Option<usize>)> = Vec::with_capacity(code.len());
+    // what is emitted before may be jumped over, so it is kept
+    let mut barrier = 0;
+    let mut dead = false;
+    for (pc, &instr) in code.iter().enumerate() {
+        if is_target[pc] {
+            barrier = out.len();
+            dead = false;
+        }
+        map.push(out.len());
+        if dead {
+            continue;
+        }
+        let top = out.len().checked_sub(1).filter(|top| *top >= barrier);
+        let top2 = out.len().checked_sub(2).filter(|top2| *top2 >= barrier);
+        match instr {
+            Instr::Nop => continue,
+            Instr::Jump(_) => {
+                let target = jump_target(pc, instr).unwrap();
+                if target > pc && code[pc + 1..target].iter().all(|i| matches!(i, Instr::Nop)) {
+                    continue;
+                }
+                dead = true;
+            }
+            Instr::Return | Instr::Except => dead = true,
+            Instr::Add | Instr::Sub | Instr::Mul | Instr::Div | Instr::Mod => {
+                if let (Some(top2), Some(top)) = (top2, top) {
+                    if let (Instr::Load(a), Instr::Load(b)) = (out[top2].0, out[top].0) {
+                        let (a, b) = (&chunk.constants[a], &chunk.constants[b]);
+                        if let Some(v) = fold(instr, a, b, string_pool) {
+                            let pos = out[top2].1;
+                            out.truncate(top2);
+                            out.push((Instr::Load(constant(chunk, v)), pos, None));
+                            continue;
+                        }
+                    }
+                }
+            }
+            Instr::Negate => {
+                if let Some(top) = top {
+                    if let Instr::Load(a) = out[top].0 {
+                        if let Some(a) = chunk.constants[a].as_number() {
+                            let idx = constant(chunk, Value::number(-a));
+                            out[top].0 = Instr::Load(idx);
+                            continue;
+                        }
+                    }
+                }
+            }
+            Instr::Pop => {
+                let pure = top.is_some_and(|top| {
+                    matches!(
+                        out[top].0,
+                        Instr::Load(_)
+                            | Instr::LoadChunk(_)
+                            | Instr::PushNil
+                            | Instr::LoadTrue
+                            | Instr::LoadFalse
+                            | Instr::GetLocal(_)
+                            | Instr::GetUpValue(_)
+                    )
+                });
+                if pure {
+                    out.pop();
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        out.push((instr, cood[pc], jump_target(pc, instr)));
+    }
+    map.push(out.len());
+    // folding may have removed what an old pc pointed to
+    for pc in (0..code.len()).rev() {
+        map[pc] = map[pc].min(map[pc + 1]);
+    }
+    chunk.bytecodes.reserve(out.len());
+    chunk.cood.reserve(out.len());
+    for (pc, (instr, pos, target)) in out.into_iter().enumerate() {
+        let instr = match target {
+            Some(target) => with_offset(instr, map[target] as i32 - pc as i32),
+            None => instr,
+        };
+        chunk.bytecodes.push(instr);
+        chunk.cood.push(pos);
+    }
+    for local in chunk.locals.iter_mut() {
+        local.start = map[local.start.min(code.len())];
+        local.end = map[local.end.min(code.len())];
+    }
+}
+
+/// `a op b` computed at compile time, `None` if it is not a constant or raises
+fn fold(op: Instr, a: &Value, b: &Value, string_pool: &mut StringPool) -> Option<Value> {
+    let res = match (op, a.unbox(), b.unbox()) {
+        (Instr::Add, Unboxed::String(a), Unboxed::String(b)) => {
+            let s = a.get_inner().to_owned() + b.get_inner();
+            return Some(Value::string(string_pool.creat_istring(&s)));
+        }
+        (Instr::Add, Unboxed::Number(a), Unboxed::Number(b)) => a + b,
+        (Instr::Sub, Unboxed::Number(a), Unboxed::Number(b)) => a - b,
+        (Instr::Mul, Unboxed::Number(a), Unboxed::Number(b)) => a * b,
+        // like the vm, small divisors are a division by zero
+        (Instr::Div, Unboxed::Number(a), Unboxed::Number(b)) if b >= 1e-5 => a / b,
+        (Instr::Mod, Unboxed::Number(a), Unboxed::Number(b)) if b >= 1e-5 => a % b,
+        _ => return None,
+    };
+    Some(Value::number(res))
+}
+
+/// index of `v` in the constants of `chunk`, added if missing
+fn constant(chunk: &mut Chunk, v: Value) -> usize {
+    let found = chunk
+        .constants
+        .iter()
+        .position(|c| match (c.as_number(), v.as_number()) {
+            // `0 == -0`, but they print differently
+            (Some(a), Some(b)) => a.to_bits() == b.to_bits(),
+            _ => c == &v,
+        });
+    found.unwrap_or_else(|| {
+        chunk.constants.push(v);
+        chunk.constants.len() - 1
+    })
+}
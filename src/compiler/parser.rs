@@ -1,12 +1,12 @@
-use interned_string::{IString, StringPool};
-
+use crate::compiler::ast::*;
 use crate::compiler::scanner::ScannerResult;
 use crate::compiler::Token;
-use crate::*;
-use std::collections::{HashMap, HashSet};
+use crate::Diagnostic;
 #[derive(Clone, Copy, PartialEq, PartialOrd)]
 pub enum PrattPrecedence {
     Lowest,
+    Ternary,
+    Coalesce,
     PipeOp,
     IsOp,
     Range,
@@ -43,179 +43,156 @@ fn get_precedence(token: &Token) -> PrattPrecedence {
         Token::Dots | Token::DotsEq => PrattPrecedence::Range,
         Token::Is => PrattPrecedence::IsOp,
         Token::PipeOp => PrattPrecedence::PipeOp,
+        Token::Coalesce => PrattPrecedence::Coalesce,
+        Token::Question => PrattPrecedence::Ternary,
         _ => PrattPrecedence::None,
     }
 }
-#[derive(PartialEq)]
-enum VarLoc {
-    Local(usize),
-    UpValue(usize),
-    Global(usize),
-    ThisRef,
-    NotFound,
+fn binary_op(token: &Token) -> Option<BinaryOp> {
+    let op = match token {
+        Token::Add => BinaryOp::Add,
+        Token::Sub => BinaryOp::Sub,
+        Token::Star => BinaryOp::Mul,
+        Token::LSlash => BinaryOp::Div,
+        Token::Percent => BinaryOp::Mod,
+        Token::LArrow => BinaryOp::Lt,
+        Token::RArrow => BinaryOp::Gt,
+        Token::Le => BinaryOp::Le,
+        Token::Ge => BinaryOp::Ge,
+        Token::EEqual => BinaryOp::Eq,
+        Token::NotEqual => BinaryOp::Ne,
+        Token::And => BinaryOp::And,
+        Token::Or => BinaryOp::Or,
+        Token::Dots => BinaryOp::Range,
+        Token::DotsEq => BinaryOp::RangeClosed,
+        Token::PipeOp => BinaryOp::Pipe,
+        Token::Is => BinaryOp::Is,
+        Token::Coalesce => BinaryOp::Coalesce,
+        _ => return None,
+    };
+    Some(op)
+}
+/// operator of `op=`
+fn compound_op(token: &Token) -> Option<BinaryOp> {
+    let op = match token {
+        Token::AddAssign => BinaryOp::Add,
+        Token::SubAssign => BinaryOp::Sub,
+        Token::MulAssign => BinaryOp::Mul,
+        Token::DivAssign => BinaryOp::Div,
+        Token::ModAssign => BinaryOp::Mod,
+        _ => return None,
+    };
+    Some(op)
+}
+/// whether `expr` can be assigned to, a name followed by indexes and fields
+fn is_target(expr: &Expr) -> bool {
+    match expr {
+        Expr::Name(_) => true,
+        Expr::Index {
+            target,
+            optional: false,
+            ..
+        }
+        | Expr::Field {
+            target,
+            optional: false,
+            ..
+        } => is_target(target),
+        _ => false,
+    }
 }
-pub struct ParserCtx<'a> {
+/// `_` matches anything, other names bind what they match
+fn binding(name: Ident) -> Pattern {
+    if name.name == "_" {
+        Pattern::Wildcard(name.span)
+    } else {
+        Pattern::Bind(name)
+    }
+}
+/// builds the syntax tree from the tokens of the scanner, see `CodeGen`
+pub struct ParserCtx {
     ptr: usize,
     len: usize,
     token_cood: Vec<(usize, usize)>,
+    token_span: Vec<usize>,
     tokens: Vec<Token>,
-    pub chunk: Vec<Chunk>, // stack of chunks
-
-    func_ctx_stack: Vec<FuncCtx>,
-
-    /// actually foregin(builtin) functions)
-    global_symbol: HashMap<String, usize>,
-    /// every toplevel symbols are exported
-    exported_symbols: Vec<IString>,
-    string_pool: &'a mut StringPool,
-    depth: usize,
-
-    method_ctx: bool,
-}
-
-pub struct ParserResult {
-    pub chunk: Chunk,
-    pub exported_symbols: Vec<IString>,
-}
-#[derive(Debug, Default)]
-struct FuncCtx {
-    block_ctx_stack: Vec<BlockCtx>,
-    num_locals: usize,
-    loop_ctx: bool,
-    loop_ctx_stack: Vec<LoopCtx>,
-}
-#[derive(Debug, Default)]
-struct BlockCtx {
-    symbol_table: HashMap<IString, usize>,
-    /// map[local_slot, is_captured]
-    symbol_captured: Vec<bool>,
+    comments: Vec<Comment>,
 }
-#[derive(Debug, Default)]
-struct LoopCtx {
-    continue_patch_point: Vec<usize>,
-    break_patch_point: Vec<usize>,
-}
-impl<'a> ParserCtx<'a> {
-    pub fn new(
-        scanner_result: ScannerResult,
-        global_symbol: HashMap<String, usize>,
-        string_pool: &'a mut StringPool,
-    ) -> ParserCtx<'a> {
+impl ParserCtx {
+    pub fn new(scanner_result: ScannerResult) -> ParserCtx {
         ParserCtx {
             ptr: 0,
             len: scanner_result.tokens.len(),
-            chunk: vec![Chunk::default()],
             tokens: scanner_result.tokens,
             token_cood: scanner_result.cood,
-            func_ctx_stack: vec![FuncCtx {
-                block_ctx_stack: vec![BlockCtx::default()],
-                num_locals: 0,
-                loop_ctx: false,
-                loop_ctx_stack: Vec::new(),
-            }],
-            global_symbol,
-            exported_symbols: Vec::new(),
-            string_pool,
-            //num_upvalues: vec![0],
-            depth: 0,
-
-            method_ctx: false,
+            token_span: scanner_result.span,
+            comments: scanner_result.comments,
         }
     }
 
-    pub fn finish(mut self) -> ParserResult {
-        ParserResult {
-            chunk: self.chunk.pop().unwrap(),
-            exported_symbols: self.exported_symbols,
-        }
-    }
-    pub fn parse_prog(&mut self) -> Result<(), String> {
+    pub fn parse_prog(&mut self) -> Result<Program, Diagnostic> {
         if let Some(tk) = self.tokens.last() {
             if tk != &Token::Semicolon && tk != &Token::RBrace {
-                return Err("incomplete program".to_owned());
+                return Err(Diagnostic::error(
+                    self.token_cood.last().copied().unwrap_or((1, 1)),
+                    "incomplete program",
+                )
+                .with_note("program should end with `;` or `}`"));
             }
         }
-        loop {
-            if self.peek() == None {
-                break;
+        let mut stmts = Vec::new();
+        while self.peek().is_some() {
+            stmts.push(self.parse()?);
+        }
+        Ok(Program {
+            stmts,
+            end: self.get_cood(),
+            comments: std::mem::take(&mut self.comments),
+        })
+    }
+    /// parse one input of the REPL, the trailing `;` may be left out
+    pub fn parse_incremental(&mut self) -> Result<Program, Diagnostic> {
+        if let Some(tk) = self.tokens.last() {
+            if tk != &Token::Semicolon && tk != &Token::RBrace {
+                self.tokens.push(Token::Semicolon);
+                self.token_cood
+                    .push(self.token_cood.last().copied().unwrap_or((1, 1)));
+                self.token_span.push(1);
+                self.len += 1;
             }
-            self.parse()?;
         }
-        self.emit(Instr::Return);
-        Ok(())
+        self.parse_prog()
     }
-    fn parse(&mut self) -> Result<(), String> {
-        let tok = if let Some(tok) = self.peek() {
-            tok
-        } else {
-            return Ok(());
-        };
+    fn parse(&mut self) -> Result<Stmt, Diagnostic> {
+        let tok = self.peek_not_eof()?;
         match tok {
-            Token::Break => {
-                if !self.func_ctx_stack.last().unwrap().loop_ctx {
-                    return Err(self.parser_err_str("break can ONLY be used inside loops"));
-                } else {
-                    self.advance();
-                    self.advance();
-                    self.emit(Instr::Nop);
-                    self.func_ctx_stack
-                        .last_mut()
-                        .unwrap()
-                        .loop_ctx_stack
-                        .last_mut()
-                        .unwrap()
-                        .break_patch_point
-                        .push(self.chunk[self.depth].bytecodes.len());
-                }
-            }
-            Token::Continue => {
-                if !self.func_ctx_stack.last().unwrap().loop_ctx {
-                    return Err(self.parser_err_str("continue can ONLY be used inside loops"));
+            Token::Break | Token::Continue => {
+                let span = self.span();
+                self.advance();
+                self.consume(Token::Semicolon)?;
+                if tok == Token::Break {
+                    Ok(Stmt::Break(span))
                 } else {
-                    self.advance();
-                    self.advance();
-                    self.emit(Instr::Nop);
-                    self.func_ctx_stack
-                        .last_mut()
-                        .unwrap()
-                        .loop_ctx_stack
-                        .last_mut()
-                        .unwrap()
-                        .continue_patch_point
-                        .push(self.chunk[self.depth].bytecodes.len());
+                    Ok(Stmt::Continue(span))
                 }
             }
-            Token::Var => {
-                self.parse_decl()?;
-            }
-            Token::LBrace => {
-                self.parse_block()?;
-            }
-            Token::If => {
-                self.parse_if()?;
-            }
-            Token::While => {
-                self.parse_while()?;
-            }
-            Token::For => {
-                self.parse_for()?;
-            }
+            Token::Var => self.parse_decl(),
+            Token::LBrace => Ok(Stmt::Block(self.parse_block()?)),
+            Token::If => self.parse_if(),
+            Token::While => self.parse_while(),
+            Token::For => self.parse_for(),
+            Token::Match => self.parse_match(),
             Token::Function => {
-                self.parse_func_decl()?;
-            }
-            Token::Return => {
-                self.parse_return()?;
-            }
-            Token::Symbol(_) => {
-                self.parse_assign_or_rval_expr()?;
-                // self.emit(Instr::Pop);
-                self.consume(Token::Semicolon)?;
-            }
-            Token::Class => {
-                self.parse_class_decl()?;
-            }
-
-            Token::LBracket
+                self.advance();
+                let name = self.parse_ident("invalid function declaration.")?;
+                Ok(Stmt::Func(self.parse_func(name)?))
+            }
+            Token::Return => self.parse_return(),
+            Token::Except => self.parse_except(),
+            Token::Try if self.peek2() == Some(Token::LBrace) => self.parse_try_catch(),
+            Token::Class => self.parse_class_decl(),
+            Token::Symbol(_)
+            | Token::LBracket
             | Token::LParen
             | Token::Dict
             | Token::Number(_)
@@ -227,867 +204,703 @@ impl<'a> ParserCtx<'a> {
             | Token::Sub
             | Token::Super
             | Token::Stick
-            | Token::This => {
-                self.parse_rval_expr(PrattPrecedence::Lowest)?;
-                self.emit(Instr::Pop);
-                self.consume(Token::Semicolon)?;
-            }
-            Token::RBrace => return Ok(()),
-            tk => {
-                return Err(self.parser_err_str(format!("unexpected token {tk:?}").as_ref()));
-            }
+            | Token::Try
+            | Token::This => self.parse_expr_stmt(),
+            tk => Err(self.parser_err(&format!("unexpected {tk}"))),
         }
-        Ok(())
     }
-    fn parse_class_decl(&mut self) -> Result<(), String> {
-        self.advance();
-        let class_name = if let Token::Symbol(class_name) = self.peek_not_eof()? {
-            self.add_local(&class_name)?;
-            class_name
-        } else {
-            return Err(self.parser_err_str("expect class name `Symbol`"));
-        };
-        self.advance();
-        self.emit(Instr::InitClass);
+    fn parse_class_decl(&mut self) -> Result<Stmt, Diagnostic> {
+        self.consume(Token::Class)?;
+        let name = self.parse_ident("expect class name `Symbol`")?;
         //:SuperClass
-        if let Token::Colon = self.peek_not_eof()? {
-            self.advance();
-            if let Token::Symbol(super_class_name) = self.peek_not_eof()? {
-                self.emit_get_symbol(&super_class_name, self.get_line())?;
-                self.emit(Instr::ClassExtend);
-            } else {
-                return Err(self.parser_err_str("expect superclass name `Symbol`"));
-            }
+        let mut superclass = None;
+        if Token::Colon == self.peek_not_eof()? {
             self.advance();
+            superclass = Some(self.parse_ident("expect superclass name `Symbol`")?);
         }
         self.consume(Token::LBrace)?;
-        self.method_ctx = true;
+        let mut methods = Vec::new();
         while let Token::Function = self.peek_not_eof()? {
             self.advance();
-            let method_name = if let Token::Symbol(method_name) = self.peek_not_eof()? {
-                method_name
-            } else {
-                return Err(self.parser_err_str("expect method name `Symbol`"));
-            };
+            let method_name = self.parse_ident("expect method name `Symbol`")?;
+            methods.push(self.parse_func(method_name)?);
+        }
+        let rbrace = self.get_cood();
+        self.consume(Token::RBrace)?;
+        Ok(Stmt::Class(ClassDecl {
+            name,
+            superclass,
+            methods,
+            rbrace,
+        }))
+    }
+    /// parameters and body of a function or a method called `name`
+    fn parse_func(&mut self, name: Ident) -> Result<FuncDecl, Diagnostic> {
+        let lparen = self.get_cood();
+        self.consume(Token::LParen)?;
+        let params = self.parse_params(Token::RParen, "expect RParen after parameter list")?;
+        // varidic parameters
+        let variadic = Token::ThreeDots == self.peek_not_eof()?;
+        if variadic {
             self.advance();
-            self.load_value(Value::String(method_name.clone()));
-
-            let line = self.get_line();
-            self.open_env();
-            self.consume(Token::LParen)?;
-            let mut para_num = 0;
-            while let Token::Symbol(s) = self.peek_not_eof()? {
-                self.advance();
-                self.add_local(&s)?;
-                para_num += 1;
-                match self.consume(Token::Comma) {
-                    Ok(()) => {}
-                    Err(_) => {
-                        if Token::RParen == self.peek_not_eof()? {
-                            //self.advance();
-                            break;
-                        } else {
-                            return Err(self.parser_err_str("expect RParen after parameter list"));
-                        }
-                    }
-                }
-            }
+        }
+        let close = self.get_cood();
+        self.consume(Token::RParen)?;
+        let body = self.parse_block()?;
+        Ok(FuncDecl {
+            name,
+            func: Function {
+                params,
+                variadic,
+                close,
+                body,
+            },
+            lparen,
+        })
+    }
+    /// names separated by `,`, stops before `close`
+    fn parse_params(&mut self, close: Token, msg: &str) -> Result<Vec<Ident>, Diagnostic> {
+        let mut params = Vec::new();
+        while let Token::Symbol(_) = self.peek_not_eof()? {
+            params.push(self.parse_ident(msg)?);
             let tk = self.peek_not_eof()?;
-            if tk == Token::ThreeDots {
-                // varidic parameters
+            if Token::Comma == tk {
                 self.advance();
-                self.chunk[self.depth].is_va = true;
-            }
-            self.consume(Token::RParen)?;
-            self.consume(Token::LBrace)?;
-            self.parse_stmt_list()?;
-            if method_name.get_inner() == "__init__" {
-                // __init__() method implicitly return this
-                self.emit(Instr::GetThis);
-                self.emit(Instr::Return);
+            } else if tk == close {
+                break;
+            } else {
+                return Err(self.parser_err(msg));
             }
-            self.consume(Token::RBrace)?;
-            let mut chunk = self.close_env();
-            chunk.parameter_num = para_num;
-            self.chunk.last_mut().unwrap().chunks.push(chunk);
-            self.emit(Instr::LoadChunk(
-                self.chunk.last().unwrap().chunks.len() - 1,
-            ));
-            self.emit(Instr::AddMethod);
         }
-        self.method_ctx = false;
-        // klass on the top of stack
-        self.emit_set_symbol(&class_name, self.get_line())?;
-        self.consume(Token::RBrace)?;
-        Ok(())
+        Ok(params)
     }
-    fn parse_for(&mut self) -> Result<(), String> {
-        self.advance();
+    fn parse_for(&mut self) -> Result<Stmt, Diagnostic> {
+        self.consume(Token::For)?;
         self.consume(Token::LParen)?;
-        self.open_block();
         self.consume(Token::Var)?;
-        if let Token::Symbol(iter_var) = self.peek_not_eof()? {
-            self.add_local(&iter_var)?;
-        } else {
-            return Err(self.parser_err_str("expect iterate variable `Symbol`"));
-        }
-        self.advance();
-        let iter_var_slot = self.chunk[self.depth].num_locals - 1;
+        let var = self.parse_ident("expect iterate variable `Symbol`")?;
         self.consume(Token::Colon)?;
-        self.parse_rval_expr(PrattPrecedence::Lowest)?;
+        let iter = self.parse_expr(PrattPrecedence::Lowest)?;
         self.consume(Token::RParen)?;
-        self.emit(Instr::Iterator);
-        let loop_start_point = self.chunk[self.depth].bytecodes.len();
-        self.emit(Instr::Next);
-        let backpatch_point = self.chunk[self.depth].bytecodes.len();
-        self.emit(Instr::Nop);
-        self.emit(Instr::SetLocal(iter_var_slot));
-        // self.emit(Instr::Pop);
-
-        self.consume(Token::LBrace)?;
-        self.parse_stmt_list()?;
-        self.consume(Token::RBrace)?;
-        self.emit(Instr::Jump(
-            loop_start_point as i32 - self.chunk[self.depth].bytecodes.len() as i32,
-        ));
-        self.chunk[self.depth].bytecodes[backpatch_point] = Instr::JumpIfNot(
-            self.chunk[self.depth].bytecodes.len() as i32 - backpatch_point as i32,
-        );
-        // pop Nil
-        self.emit(Instr::Pop);
-        // pop iterator
-        self.emit(Instr::Pop);
-        self.close_block();
-        Ok(())
-    }
-    fn parse_return(&mut self) -> Result<(), String> {
+        let body = self.parse_block()?;
+        Ok(Stmt::For {
+            var,
+            iter,
+            body,
+            end: self.get_cood(),
+        })
+    }
+    fn parse_return(&mut self) -> Result<Stmt, Diagnostic> {
+        let span = self.span();
         self.consume(Token::Return)?;
-        let tok = if let Some(tok) = self.peek() {
-            tok
-        } else {
-            return Ok(());
-        };
-        if Token::Semicolon == tok {
-            self.emit(Instr::Return);
-            self.advance();
-            Ok(())
-        } else {
-            let line = self.get_line();
-            self.parse_rval_expr(PrattPrecedence::Lowest)?;
-            self.emit_with_line(Instr::Return, line);
-            self.consume(Token::Semicolon)?;
-            Ok(())
+        let mut value = None;
+        if Token::Semicolon != self.peek_not_eof()? {
+            value = Some(self.parse_expr(PrattPrecedence::Lowest)?);
         }
+        let semi = self.get_cood();
+        self.consume(Token::Semicolon)?;
+        Ok(Stmt::Return { value, span, semi })
     }
-    #[inline]
-    fn parse_argument(&mut self) -> Result<usize, String> {
-        let mut argument_num = 0;
-        let tok = self.peek_not_eof()?;
-        if Token::RParen == tok || Token::RBracket == tok {
-            return Ok(0);
+    fn parse_except(&mut self) -> Result<Stmt, Diagnostic> {
+        let span = self.span();
+        self.consume(Token::Except)?;
+        let mut value = None;
+        if Token::Semicolon != self.peek_not_eof()? {
+            value = Some(self.parse_expr(PrattPrecedence::Lowest)?);
         }
-        loop {
-            self.parse_rval_expr(PrattPrecedence::Lowest)?;
-            argument_num += 1;
-            let tk = self.peek_not_eof()?;
-            if Token::Comma == tk {
-                self.advance();
-            } else if Token::RParen == tk || Token::RBracket == tk {
-                return Ok(argument_num);
-            } else {
-                return Err(self.parser_err_str("illegal argument list"));
-            }
+        let semi = self.get_cood();
+        self.consume(Token::Semicolon)?;
+        Ok(Stmt::Except { value, span, semi })
+    }
+    /// `try { ... } catch (e) { ... }`
+    fn parse_try_catch(&mut self) -> Result<Stmt, Diagnostic> {
+        self.consume(Token::Try)?;
+        let body = self.parse_block()?;
+        let catch = self.span();
+        self.consume(Token::Catch)?;
+        let mut var = None;
+        if Token::LParen == self.peek_not_eof()? {
+            self.advance();
+            var = Some(self.parse_ident("expect error variable `Symbol`")?);
+            self.consume(Token::RParen)?;
         }
-    }
-    #[inline]
-    fn emit_get_symbol(&mut self, symbol: &IString, line: usize) -> Result<(), String> {
-        match self.resolve(symbol, self.depth) {
-            VarLoc::ThisRef => {
-                self.emit_with_line(Instr::GetThis, line);
-            }
-            VarLoc::Local(x) => {
-                self.emit_with_line(Instr::GetLocal(x), line);
-            }
-            VarLoc::UpValue(x) => {
-                self.emit_with_line(Instr::GetUpValue(x), line);
+        let handler = self.parse_block()?;
+        Ok(Stmt::TryCatch {
+            body,
+            catch,
+            var,
+            handler,
+            end: self.get_cood(),
+        })
+    }
+    /// `match (subject) { pattern if guard => { ... } ... }`
+    fn parse_match(&mut self) -> Result<Stmt, Diagnostic> {
+        self.consume(Token::Match)?;
+        self.consume(Token::LParen)?;
+        let subject = self.parse_expr(PrattPrecedence::Lowest)?;
+        self.consume(Token::RParen)?;
+        let lbrace = self.get_cood();
+        self.consume(Token::LBrace)?;
+        let mut arms = Vec::new();
+        while Token::RBrace != self.peek_not_eof()? {
+            let pattern = self.parse_pattern()?;
+            let mut guard = None;
+            if Token::If == self.peek_not_eof()? {
+                self.advance();
+                guard = Some(self.parse_expr(PrattPrecedence::Lowest)?);
+            }
+            let arrow = self.get_cood();
+            self.consume(Token::FatArrow)?;
+            let body = self.parse_block()?;
+            arms.push(MatchArm {
+                pattern,
+                guard,
+                arrow,
+                body,
+            });
+        }
+        let rbrace = self.get_cood();
+        self.consume(Token::RBrace)?;
+        Ok(Stmt::Match {
+            subject,
+            arms,
+            lbrace,
+            rbrace,
+        })
+    }
+    fn parse_pattern(&mut self) -> Result<Pattern, Diagnostic> {
+        let span = self.span();
+        match self.peek_not_eof()? {
+            Token::Symbol(_) => {
+                let name = self.parse_ident("expect a pattern")?;
+                match self.peek_not_eof()? {
+                    Token::LParen => self.parse_class_pattern(name),
+                    Token::Is => {
+                        self.advance();
+                        let class = self.parse_ident("expect class name after `is`")?;
+                        Ok(Pattern::Is {
+                            binding: Box::new(binding(name)),
+                            class,
+                        })
+                    }
+                    _ => Ok(binding(name)),
+                }
             }
-            VarLoc::Global(x) => {
-                self.emit_with_line(Instr::GetGlobal(x), line);
+            Token::LBracket => {
+                self.advance();
+                let mut items = Vec::new();
+                let mut rest = None;
+                while Token::RBracket != self.peek_not_eof()? {
+                    if Token::ThreeDots == self.peek_not_eof()? {
+                        // the rest is the last item
+                        let dots = self.span();
+                        self.advance();
+                        rest = Some(Box::new(match self.peek_not_eof()? {
+                            Token::Symbol(_) => binding(self.parse_ident("expect rest name")?),
+                            _ => Pattern::Wildcard(dots),
+                        }));
+                        break;
+                    }
+                    items.push(self.parse_pattern()?);
+                    if Token::Comma != self.peek_not_eof()? {
+                        break;
+                    }
+                    self.advance();
+                }
+                let close = self.get_cood();
+                self.consume(Token::RBracket)?;
+                Ok(Pattern::Array {
+                    items,
+                    rest,
+                    span,
+                    close,
+                })
+            }
+            Token::Dict => {
+                self.advance();
+                self.consume(Token::LParen)?;
+                let mut entries = Vec::new();
+                while Token::RParen != self.peek_not_eof()? {
+                    if !entries.is_empty() {
+                        self.consume(Token::Comma)?;
+                    }
+                    let key = if let Token::String(s) = self.peek_not_eof()? {
+                        Expr::String(s.get_inner().to_owned(), self.span())
+                    } else {
+                        return Err(self.parser_err("dict pattern keys are strings"));
+                    };
+                    self.advance();
+                    self.consume(Token::Colon)?;
+                    entries.push((key, self.parse_pattern()?));
+                }
+                if entries.is_empty() {
+                    return Err(self.parser_err("dict pattern expects at least one key"));
+                }
+                let close = self.get_cood();
+                self.consume(Token::RParen)?;
+                Ok(Pattern::Dict {
+                    entries,
+                    span,
+                    close,
+                })
             }
-            VarLoc::NotFound => {
-                return Err(self.parser_err_str((format!("symbol not found:{}", symbol)).as_str()));
+            _ => {
+                let lo = self.parse_literal()?;
+                let tk = self.peek_not_eof()?;
+                if tk != Token::Dots && tk != Token::DotsEq {
+                    return Ok(Pattern::Literal(lo));
+                }
+                self.advance();
+                let hi = self.parse_literal()?;
+                if !matches!((&lo, &hi), (Expr::Number(..), Expr::Number(..))) {
+                    return Err(
+                        Diagnostic::error(lo.start(), "range pattern expects numbers")
+                            .with_span(span.len),
+                    );
+                }
+                Ok(Pattern::Range {
+                    lo,
+                    hi,
+                    closed: tk == Token::DotsEq,
+                })
             }
         }
-        Ok(())
     }
-    #[inline]
-    fn emit_set_symbol(&mut self, symbol: &IString, line: usize) -> Result<(), String> {
-        match self.resolve(symbol, self.depth) {
-            VarLoc::ThisRef => {
-                return Err(self.parser_err_str("set `this` is invalid"));
+    /// fields of `Class(field, field: pattern)`
+    fn parse_class_pattern(&mut self, class: Ident) -> Result<Pattern, Diagnostic> {
+        self.consume(Token::LParen)?;
+        let mut fields = Vec::new();
+        while Token::RParen != self.peek_not_eof()? {
+            if !fields.is_empty() {
+                self.consume(Token::Comma)?;
             }
-            VarLoc::Local(x) => {
-                self.emit_with_line(Instr::SetLocal(x), line);
+            let field = self.parse_ident("expect field name in class pattern")?;
+            let pattern = if Token::Colon == self.peek_not_eof()? {
+                self.advance();
+                self.parse_pattern()?
+            } else {
+                Pattern::Bind(field.clone())
+            };
+            fields.push((field, pattern));
+        }
+        let close = self.get_cood();
+        self.consume(Token::RParen)?;
+        Ok(Pattern::Class {
+            class,
+            fields,
+            close,
+        })
+    }
+    /// number, string, bool or nil of a pattern, `-` is part of the number
+    fn parse_literal(&mut self) -> Result<Expr, Diagnostic> {
+        let span = self.span();
+        let expr = match self.peek_not_eof()? {
+            Token::Sub => {
+                self.advance();
+                let Token::Number(x) = self.peek_not_eof()? else {
+                    return Err(self.parser_err("expect a number after `-` in pattern"));
+                };
+                let len = span.len + self.span().len;
+                self.advance();
+                Expr::Number(-x, Span { pos: span.pos, len })
             }
-            VarLoc::UpValue(x) => {
-                self.emit_with_line(Instr::SetUpValue(x), line);
+            Token::Number(x) => {
+                self.advance();
+                Expr::Number(x, span)
+            }
+            Token::String(_) => match self.parse_string()? {
+                Expr::Interpolation(_) => {
+                    return Err(Diagnostic::error(
+                        span.pos,
+                        "string pattern can not be interpolated",
+                    )
+                    .with_span(span.len))
+                }
+                expr => expr,
+            },
+            tk @ (Token::True | Token::False) => {
+                self.advance();
+                Expr::Bool(tk == Token::True, span)
             }
-            VarLoc::Global(x) => {
-                self.emit_with_line(Instr::SetGlobal(x), line);
+            Token::Nil => {
+                self.advance();
+                Expr::Nil(span)
             }
-            VarLoc::NotFound => {
-                return Err(self.parser_err_str((format!("symbol not found:{}", symbol)).as_str()));
+            tk => {
+                return Err(self.parser_err(&format!("unexpected {tk}, expect a pattern")))
             }
-        }
-        Ok(())
+        };
+        Ok(expr)
     }
-    fn parse_func_decl(&mut self) -> Result<(), String> {
-        self.consume(Token::Function)?;
-        let symbol;
-        if let Token::Symbol(s) = self.peek_not_eof()? {
-            symbol = s;
-        } else {
-            return Err(self.parser_err_str("invalid function declaration."));
+    #[inline]
+    fn parse_argument(&mut self) -> Result<Vec<Expr>, Diagnostic> {
+        let mut args = Vec::new();
+        let tok = self.peek_not_eof()?;
+        if Token::RParen == tok || Token::RBracket == tok {
+            return Ok(args);
         }
-        self.advance();
-        let line = self.get_line();
-        self.add_local(&symbol)?;
-        self.open_env();
-        self.consume(Token::LParen)?;
-        let mut para_num = 0;
-        while let Token::Symbol(s) = self.peek_not_eof()? {
-            self.advance();
-            self.add_local(&s)?;
-            para_num += 1;
-            match self.consume(Token::Comma) {
-                Ok(()) => {}
-                Err(_) => {
-                    if Token::RParen == self.peek_not_eof()? {
-                        //self.advance();
-                        break;
-                    } else {
-                        return Err(self.parser_err_str("expect RParen after parameter list"));
-                    }
-                }
+        loop {
+            args.push(self.parse_expr(PrattPrecedence::Lowest)?);
+            let tk = self.peek_not_eof()?;
+            if Token::Comma == tk {
+                self.advance();
+            } else if Token::RParen == tk || Token::RBracket == tk {
+                return Ok(args);
+            } else {
+                return Err(self.parser_err("illegal argument list"));
             }
         }
-        let tk = self.peek_not_eof()?;
-        if tk == Token::ThreeDots {
-            // varidic parameters
-            self.advance();
-            self.chunk[self.depth].is_va = true;
-        }
-        self.consume(Token::RParen)?;
-        self.consume(Token::LBrace)?;
-        self.parse_stmt_list()?;
-        self.consume(Token::RBrace)?;
-        let mut chunk = self.close_env();
-        chunk.parameter_num = para_num;
-        self.chunk.last_mut().unwrap().chunks.push(chunk);
-        self.emit(Instr::LoadChunk(
-            self.chunk.last().unwrap().chunks.len() - 1,
-        ));
-        self.emit_set_symbol(&symbol, line)?;
-        // self.emit(Instr::Pop);
-        Ok(())
     }
-    fn parse_decl(&mut self) -> Result<(), String> {
+    fn parse_decl(&mut self) -> Result<Stmt, Diagnostic> {
         self.consume(Token::Var)?;
-        let symbol;
-        if let Token::Symbol(s) = self.peek_not_eof()? {
-            symbol = s;
-        } else {
-            return Err(self.parser_err_str("invalid declaration statement"));
-        }
-        self.advance();
-        if let Some(tok) = self.peek() {
-            //declaration with assignment
-            if Token::Equal == tok {
-                self.advance();
-                self.parse_rval_expr(PrattPrecedence::Lowest)?;
-                self.add_local(&symbol)?;
-                self.emit_set_symbol(&symbol, self.get_line())?;
-                // self.emit(Instr::Pop);
-                self.consume(Token::Semicolon)?;
-                return Ok(());
-            }
+        let name = self.parse_ident("invalid declaration statement")?;
+        let mut init = None;
+        //declaration with assignment
+        if Some(Token::Equal) == self.peek() {
+            self.advance();
+            init = Some(self.parse_expr(PrattPrecedence::Lowest)?);
         }
-        self.add_local(&symbol)?;
+        let semi = self.get_cood();
         self.consume(Token::Semicolon)?;
-        Ok(())
+        Ok(Stmt::Var { name, init, semi })
     }
-    fn parse_while(&mut self) -> Result<(), String> {
+    fn parse_while(&mut self) -> Result<Stmt, Diagnostic> {
         self.consume(Token::While)?;
         self.consume(Token::LParen)?;
-        let jumpback_point = self.chunk[self.depth].bytecodes.len();
-        self.parse_rval_expr(PrattPrecedence::Lowest)?;
-        let patch_point = self.chunk[self.depth].bytecodes.len();
-        self.emit(Instr::Nop); // to be patched
-        self.emit(Instr::Pop);
+        let cond = self.parse_expr(PrattPrecedence::Lowest)?;
+        let rparen = self.get_cood();
         self.consume(Token::RParen)?;
+        let body = self.parse_block()?;
+        Ok(Stmt::While {
+            cond,
+            body,
+            rparen,
+            end: self.get_cood(),
+        })
+    }
+    fn parse_block(&mut self) -> Result<Block, Diagnostic> {
+        let lbrace = self.get_cood();
         self.consume(Token::LBrace)?;
-        self.open_block();
-        self.func_ctx_stack.last_mut().unwrap().loop_ctx = true;
-        self.func_ctx_stack
-            .last_mut()
-            .unwrap()
-            .loop_ctx_stack
-            .push(LoopCtx::default());
-        self.parse_stmt_list()?;
-        let cur_loop_ctx = self
-            .func_ctx_stack
-            .last_mut()
-            .unwrap()
-            .loop_ctx_stack
-            .pop()
-            .unwrap();
-        // patch continue points, jump to loop header
-        for continue_point in cur_loop_ctx.continue_patch_point.iter() {
-            self.chunk[self.depth].bytecodes[*continue_point] =
-                Instr::Jump(jumpback_point as i32 - *continue_point as i32);
-        }
-        self.func_ctx_stack.last_mut().unwrap().loop_ctx = false;
-        self.emit(Instr::Jump(
-            jumpback_point as i32 - self.chunk[self.depth].bytecodes.len() as i32,
-        ));
-        self.close_block();
-        self.consume(Token::RBrace)?;
-        self.chunk[self.depth].bytecodes[patch_point] =
-            Instr::JumpIfNot(self.chunk[self.depth].bytecodes.len() as i32 - patch_point as i32);
-        self.emit(Instr::Pop);
-        // patch break points, jump to end of loop
-        for break_point in cur_loop_ctx.break_patch_point.iter() {
-            self.chunk[self.depth].bytecodes[*break_point] =
-                Instr::Jump(self.chunk[self.depth].bytecodes.len() as i32 - *break_point as i32);
-        }
-
-        Ok(())
-    }
-    fn parse_block(&mut self) -> Result<(), String> {
-        self.consume(Token::LBrace)?;
-        self.open_block();
-        loop {
-            let tk = self.peek_not_eof()?;
-            if tk == Token::RBrace {
-                break;
-            }
-            self.parse()?;
+        let mut stmts = Vec::new();
+        while Token::RBrace != self.peek_not_eof()? {
+            stmts.push(self.parse()?);
         }
-        self.close_block();
+        let rbrace = self.get_cood();
         self.consume(Token::RBrace)?;
-        Ok(())
+        Ok(Block {
+            stmts,
+            lbrace,
+            rbrace,
+        })
     }
-
-    fn parse_stmt_list(&mut self) -> Result<(), String> {
-        loop {
-            let tk = self.peek_not_eof()?;
-            if tk == Token::RBrace {
-                break;
-            }
-            self.parse()?;
-        }
-        Ok(())
-    }
-    fn parse_if(&mut self) -> Result<(), String> {
+    fn parse_if(&mut self) -> Result<Stmt, Diagnostic> {
         self.consume(Token::If)?;
         self.consume(Token::LParen)?;
-        self.parse_rval_expr(PrattPrecedence::Lowest)?;
-        let patch_point = self.chunk[self.depth].bytecodes.len();
-        // emit an empty slot, jump to FALSE branch but FALSE branch is now not parsed.
-        self.emit(Instr::Nop);
-        // pop the bool value
-        // self.emit(Instr::Pop);
+        let cond = self.parse_expr(PrattPrecedence::Lowest)?;
+        let rparen = self.get_cood();
         self.consume(Token::RParen)?;
-        // self.consume(Token::LBrace)?;
-        // self.open_block();
-        self.parse()?;
-        let patch_point2 = self.chunk[self.depth].bytecodes.len();
-
-        // emit an empty slot, jump to end of if statement, but we still don't know if there
-        // is an else clause.
-        self.emit(Instr::Nop);
-        // self.close_block();
-        // self.consume(Token::RBrace)?;
-        self.chunk[self.depth].bytecodes[patch_point] =
-            Instr::JumpIfNot((self.chunk[self.depth].bytecodes.len() - patch_point) as i32);
-        if let Some(tok) = self.peek() {
-            if Token::Else == tok {
-                self.advance();
-                self.parse()?;
-                self.chunk[self.depth].bytecodes[patch_point2] =
-                    Instr::Jump((self.chunk[self.depth].bytecodes.len() - patch_point2) as i32);
-            }
-        }
-        // just leave the value on the stack, clean it at last
-        self.emit(Instr::Pop);
-        Ok(())
-    }
-    /// open function level env
-    #[inline]
-    fn open_env(&mut self) {
-        self.chunk.push(Chunk::default());
-        self.func_ctx_stack.push(FuncCtx::default());
-        self.func_ctx_stack
-            .last_mut()
-            .unwrap()
-            .block_ctx_stack
-            .push(BlockCtx::default());
-        self.depth += 1;
-    }
-    /// close function level env
-    #[inline]
-    fn close_env(&mut self) -> Chunk {
-        self.emit_with_line(Instr::Return, 0);
-        self.depth -= 1;
-        self.func_ctx_stack.pop();
-        self.chunk.pop().unwrap()
-    }
-    /// open block level env
-    #[inline]
-    fn open_block(&mut self) {
-        self.func_ctx_stack
-            .last_mut()
-            .unwrap()
-            .block_ctx_stack
-            .push(BlockCtx::default());
-    }
-    /// close block level env
-    #[inline]
-    fn close_block(&mut self) {
-        self.func_ctx_stack
-            .last_mut()
-            .unwrap()
-            .block_ctx_stack
-            .pop();
-    }
-
-    fn add_local(&mut self, symbol: &IString) -> Result<(), String> {
-        if self.depth == 0 && self.func_ctx_stack[self.depth].block_ctx_stack.len() == 1 {
-            // outermost scope => Global
-            let s = symbol.clone();
-            self.exported_symbols.push(s);
-            return Ok(());
-        }
-        if self.func_ctx_stack[self.depth]
-            .block_ctx_stack
-            .last()
-            .unwrap()
-            .symbol_table
-            .get(symbol)
-            != None
-        {
-            return Err(self.parser_err_str("redeclaration of symbol"));
-        }
-        let num_locals = self.chunk[self.depth].num_locals;
-        self.func_ctx_stack[self.depth]
-            .block_ctx_stack
-            .last_mut()
-            .unwrap()
-            .symbol_table
-            .insert(symbol.clone(), num_locals);
-        self.func_ctx_stack[self.depth]
-            .block_ctx_stack
-            .last_mut()
-            .unwrap()
-            .symbol_captured
-            .push(false);
-        self.chunk[self.depth].num_locals += 1;
-        Ok(())
-    }
-
-    fn parse_assign_or_rval_expr(&mut self) -> Result<(), String> {
-        if let Token::Symbol(s) = self.peek_not_eof()? {
-            if !self.method_ctx && s.get_inner() == "this" {
-                return Err(self.parser_err_str("`this` can NOT be used outside methods"));
-            }
-            match self.resolve(&s, self.depth) {
-                VarLoc::Local(x) => self.emit(Instr::GetLocal(x)),
-                VarLoc::UpValue(x) => self.emit(Instr::GetUpValue(x)),
-                VarLoc::Global(x) => self.emit(Instr::GetGlobal(x)),
-                VarLoc::ThisRef => self.emit(Instr::GetThis),
-                _ => {
-                    return Err(self.parser_err_str("symbol not found"));
-                }
-            }
+        let then = Box::new(self.parse()?);
+        let then_end = self.get_cood();
+        let mut otherwise = None;
+        if Some(Token::Else) == self.peek() {
             self.advance();
-            let mut is_assign = true;
-            while let Some(tk) = self.peek() {
-                if tk.is_assign() {
-                    break;
-                } else if tk == Token::LBracket {
-                    let line = self.get_line();
-                    self.advance();
-                    self.parse_rval_expr(PrattPrecedence::Lowest)?;
-                    self.consume(Token::RBracket)?;
-                    self.emit_with_line(Instr::GetCollection(0), line);
-                    continue;
-                } else if tk == Token::Dot {
-                    let line = self.get_line();
-                    self.advance();
-                    if let Token::Symbol(s) = self.peek_not_eof()? {
-                        self.load_value(Value::String(s));
-                        self.advance();
-                    } else {
-                        return Err(self.parser_err_str("invalid rval expr"));
-                    }
-                    self.emit_with_line(Instr::GetCollection(1), line);
-                    continue;
-                } else {
-                    is_assign = false;
-                    break;
-                }
-            }
-            if is_assign {
-                // consume '='
-                self.advance();
-                // change get operation to corresponging set operation
-                let last_instr = self.chunk[self.depth].bytecodes.pop().unwrap();
-                self.parse_rval_expr(PrattPrecedence::Lowest)?;
-                // now value is on the top of stack
-                let modified_instr = match last_instr {
-                    Instr::GetCollection(v) => {
-                        // idx already on stack[top-1]
-                        Instr::SetCollection(v)
-                    }
-                    Instr::GetLocal(x) => Instr::SetLocal(x),
-                    Instr::GetGlobal(x) => Instr::SetGlobal(x),
-                    Instr::GetUpValue(x) => Instr::SetUpValue(x),
-                    _ => {
-                        unreachable!()
-                    }
-                };
-                self.emit(modified_instr);
-            } else {
-                // already parsed a symbol
-                // no bracktrack
-                self.parse_rval_expr2(PrattPrecedence::Lowest, true)?;
-                self.emit(Instr::Pop);
-            }
-        } else {
-            self.parse_rval_expr(PrattPrecedence::Lowest)?;
-            self.emit(Instr::Pop);
-        }
-        Ok(())
-    }
-
-    pub fn parse_rval_expr(&mut self, prec: PrattPrecedence) -> Result<(), String> {
-        self.parse_rval_expr2(prec, false)
+            otherwise = Some(Box::new(self.parse()?));
+        }
+        Ok(Stmt::If {
+            cond,
+            then,
+            otherwise,
+            rparen,
+            then_end,
+            end: self.get_cood(),
+        })
+    }
+    /// `expr;`, or `target = value;` and `target op= value;` if it starts with a name
+    fn parse_expr_stmt(&mut self) -> Result<Stmt, Diagnostic> {
+        let maybe_assign = matches!(self.peek(), Some(Token::Symbol(_)));
+        let expr = self.parse_expr(PrattPrecedence::Lowest)?;
+        if maybe_assign && self.peek().is_some_and(|tk| tk.is_assign()) && is_target(&expr) {
+            let span = self.span();
+            let op = self.peek().and_then(|tk| compound_op(&tk));
+            // consume '=' or 'op='
+            self.advance();
+            let value = self.parse_expr(PrattPrecedence::Lowest)?;
+            let semi = self.get_cood();
+            self.consume(Token::Semicolon)?;
+            return Ok(Stmt::Assign {
+                target: expr,
+                op,
+                value,
+                span,
+                semi,
+            });
+        }
+        let semi = self.get_cood();
+        self.consume(Token::Semicolon)?;
+        Ok(Stmt::Expr { expr, semi })
     }
 
-    /// [`maybe_assign`] indicates that we have already parsed a symbol
-    pub fn parse_rval_expr2(
-        &mut self,
-        prec: PrattPrecedence,
-        maybe_assign: bool,
-    ) -> Result<(), String> {
+    pub fn parse_expr(&mut self, prec: PrattPrecedence) -> Result<Expr, Diagnostic> {
         // Pratt Parser
-        if !maybe_assign {
-            match self.peek_not_eof()? {
-                Token::Symbol(s) => {
-                    if !self.method_ctx && s.get_inner() == "this" {
-                        return Err(self.parser_err_str("`this` can NOT be used outside methods"));
-                    }
-                    match self.resolve(&s, self.depth) {
-                        VarLoc::Local(x) => self.emit(Instr::GetLocal(x)),
-                        VarLoc::UpValue(x) => self.emit(Instr::GetUpValue(x)),
-                        VarLoc::Global(x) => self.emit(Instr::GetGlobal(x)),
-                        VarLoc::ThisRef => self.emit(Instr::GetThis),
-                        _ => {
-                            return Err(self.parser_err_str("symbol not found"));
-                        }
-                    }
-                    self.advance();
-                }
-                Token::Super => {
-                    if !self.method_ctx {
-                        return Err(self.parser_err_str("`super` can NOT be used outside methods"));
-                    }
+        let mut expr = self.parse_prefix()?;
+        while let Some(tk) = self.peek() {
+            let span = self.span();
+            expr = match tk {
+                Token::LParen => {
+                    //call
                     self.advance();
-                    self.consume(Token::Dot)?;
-                    if let Token::Symbol(s) = self.peek_not_eof()? {
-                        self.emit(Instr::GetThis);
-                        self.load_value(Value::String(s));
-                        self.emit(Instr::GetSuperMethod);
-                        self.advance();
-                    } else {
-                        return Err(self.parser_err_str("expect symbol after `super.`"));
+                    let args = self.parse_argument()?;
+                    let close = self.get_cood();
+                    self.consume(Token::RParen)?;
+                    Expr::Call {
+                        callee: Box::new(expr),
+                        args,
+                        span,
+                        close,
                     }
                 }
-                Token::Number(x) => {
-                    self.load_value(Value::Number(x));
-                    self.advance();
-                }
-                Token::String(s) => {
-                    self.load_value(Value::String(s));
+                Token::LBracket | Token::QuestionBracket => {
                     self.advance();
+                    let index = self.parse_expr(PrattPrecedence::Lowest)?;
+                    let close = self.get_cood();
+                    self.consume(Token::RBracket)?;
+                    Expr::Index {
+                        target: Box::new(expr),
+                        index: Box::new(index),
+                        optional: tk == Token::QuestionBracket,
+                        span,
+                        close,
+                    }
                 }
-                Token::True => {
-                    self.emit(Instr::LoadTrue);
-                    self.advance();
-                }
-                Token::False => {
-                    self.emit(Instr::LoadFalse);
-                    self.advance();
-                }
-                Token::Nil => {
-                    self.emit(Instr::PushNil);
-                    self.advance();
-                }
-
-                Token::LBracket => {
-                    self.parse_array()?;
-                }
-                Token::Dict => {
-                    self.parse_dict()?;
-                }
-                Token::Sub => {
-                    let line = self.token_cood[self.ptr].0;
-                    self.advance();
-                    self.parse_rval_expr(PrattPrecedence::Unary)?;
-                    self.emit_with_line(Instr::Negate, line);
-                }
-                Token::Not => {
-                    let line = self.token_cood[self.ptr].0;
-                    self.advance();
-                    self.parse_rval_expr(PrattPrecedence::Unary)?;
-                    self.emit_with_line(Instr::Not, line);
-                }
-                Token::LParen => {
-                    self.advance();
-                    self.parse_rval_expr(PrattPrecedence::Lowest)?;
-                    self.consume(Token::RParen)?;
-                }
-                Token::InterplotBegin => {
+                Token::Dot | Token::QuestionDot => {
                     self.advance();
-                    // require builtin string function
-                    let f = self.string_pool.creat_istring("string");
-                    self.emit_get_symbol(&f, self.get_line())?;
-
-                    self.parse_rval_expr(PrattPrecedence::Lowest)?;
-                    self.consume(Token::InterplotEnd)?;
-                    // stack: [builtin string()] [rval value]
-                    self.emit(Instr::Call(1));
+                    let name = self.parse_ident("invalid rval expr")?;
+                    Expr::Field {
+                        target: Box::new(expr),
+                        name,
+                        optional: tk == Token::QuestionDot,
+                        span,
+                    }
                 }
-                Token::Stick => {
-                    // lambda
+                Token::Question => {
+                    if PrattPrecedence::Ternary <= prec {
+                        break;
+                    }
                     self.advance();
-                    self.open_env();
-                    let mut para_num = 0;
-                    while let Token::Symbol(s) = self.peek_not_eof()? {
-                        self.advance();
-                        self.add_local(&s)?;
-                        para_num += 1;
-                        match self.consume(Token::Comma) {
-                            Ok(()) => {}
-                            Err(_) => {
-                                if Token::Stick == self.peek_not_eof()? {
-                                    //self.advance();
-                                    break;
-                                } else {
-                                    return Err(self
-                                        .parser_err_str("expect `|` after lambda parameter list"));
-                                }
-                            }
-                        }
+                    let then = self.parse_expr(PrattPrecedence::Lowest)?;
+                    self.consume(Token::Colon)?;
+                    // right associative, `a ? b : c ? d : e`
+                    let otherwise = self.parse_expr(PrattPrecedence::Lowest)?;
+                    Expr::Conditional {
+                        cond: Box::new(expr),
+                        then: Box::new(then),
+                        otherwise: Box::new(otherwise),
+                        span,
                     }
-                    self.consume(Token::Stick)?;
-                    self.consume(Token::LBrace)?;
-                    self.parse_stmt_list()?;
-                    self.consume(Token::RBrace)?;
-                    let mut chunk = self.close_env();
-                    chunk.parameter_num = para_num;
-                    self.chunk.last_mut().unwrap().chunks.push(chunk);
-                    // evaluate to a closure
-                    self.emit(Instr::LoadChunk(
-                        self.chunk.last().unwrap().chunks.len() - 1,
-                    ));
                 }
                 _ => {
-                    //println!("{:?}", c);
-                    return Ok(());
-                    //unimplemented!();
+                    let Some(op) = binary_op(&tk) else {
+                        break;
+                    };
+                    let nprec = get_precedence(&tk);
+                    if nprec <= prec {
+                        break;
+                    }
+                    self.advance();
+                    let rhs = self.parse_expr(nprec)?;
+                    Expr::Binary {
+                        op,
+                        lhs: Box::new(expr),
+                        rhs: Box::new(rhs),
+                        span,
+                    }
                 }
-            }
+            };
         }
-        while let Some(tk) = self.peek() {
-            let line = self.get_line();
-            if tk == Token::Semicolon {
-                break;
+        Ok(expr)
+    }
+    fn parse_prefix(&mut self) -> Result<Expr, Diagnostic> {
+        let span = self.span();
+        let tk = self.peek_not_eof()?;
+        let expr = match tk {
+            Token::Symbol(s) => {
+                self.advance();
+                Expr::Name(Ident {
+                    name: s.get_inner().to_owned(),
+                    span,
+                })
             }
-            if tk == Token::LParen {
-                //call
-                let line = self.get_line();
+            Token::Super => {
                 self.advance();
-                let arg_num = self.parse_argument()?;
-                self.consume(Token::RParen)?;
-                self.emit_with_line(Instr::Call(arg_num), line);
-
-                continue;
+                self.consume(Token::Dot)?;
+                let method = self.parse_ident("expect symbol after `super.`")?;
+                Expr::Super { method, span }
             }
-            if tk == Token::LBracket {
-                let line = self.get_line();
+            Token::Number(x) => {
                 self.advance();
-                self.parse_rval_expr(PrattPrecedence::Lowest)?;
-                self.consume(Token::RBracket)?;
-                self.emit_with_line(Instr::GetCollection(0), line);
-                continue;
+                Expr::Number(x, span)
             }
-            if tk == Token::Dot {
-                let line = self.get_line();
+            Token::String(_) => self.parse_string()?,
+            Token::True | Token::False => {
                 self.advance();
-                if let Token::Symbol(s) = self.peek_not_eof()? {
-                    self.load_value(Value::String(s));
-                    self.advance();
-                } else {
-                    return Err(self.parser_err_str("invalid rval expr"));
-                }
-                self.emit_with_line(Instr::GetCollection(1), line);
-                continue;
+                Expr::Bool(tk == Token::True, span)
             }
-            let backpatch_point: usize = self.chunk[self.depth].bytecodes.len();
-            let nprec = get_precedence(&tk);
-            if nprec != PrattPrecedence::None {
-                if get_precedence(&tk) <= prec {
-                    break;
-                }
-                if tk == Token::And || tk == Token::Or {
-                    self.emit(Instr::Nop);
-                    // discard left value bool
-                    self.emit(Instr::Pop);
-                }
+            Token::Nil => {
                 self.advance();
-                self.parse_rval_expr(nprec)?;
-            } else {
-                return Ok(());
+                Expr::Nil(span)
             }
-            match tk {
-                Token::Add => {
-                    self.emit_with_line(Instr::Add, line);
-                }
-                Token::Sub => {
-                    self.emit_with_line(Instr::Sub, line);
-                }
-                Token::LSlash => {
-                    self.emit_with_line(Instr::Div, line);
-                }
-                Token::Percent => {
-                    self.emit_with_line(Instr::Mod, line);
-                }
-                Token::Star => {
-                    self.emit_with_line(Instr::Mul, line);
-                }
-                Token::LArrow => {
-                    self.emit_with_line(Instr::Lt, line);
-                }
-                Token::RArrow => {
-                    self.emit_with_line(Instr::Gt, line);
-                }
-                Token::Le => {
-                    self.emit_with_line(Instr::Le, line);
-                }
-                Token::Ge => {
-                    self.emit_with_line(Instr::Ge, line);
-                }
-                Token::EEqual => {
-                    self.emit_with_line(Instr::Eq, line);
-                }
-                Token::NotEqual => {
-                    self.emit_with_line(Instr::Ne, line);
-                }
-                Token::And => {
-                    let pos = self.chunk[self.depth].bytecodes.len();
-                    self.chunk[self.depth].bytecodes[backpatch_point] =
-                        Instr::JumpIfNot((pos - backpatch_point) as i32);
-                    self.chunk[self.depth].lines[backpatch_point] = line;
-                }
-                Token::Or => {
-                    let pos = self.chunk[self.depth].bytecodes.len();
-                    self.chunk[self.depth].bytecodes[backpatch_point] =
-                        Instr::JumpIfTrue((pos - backpatch_point) as i32);
-                    self.chunk[self.depth].lines[backpatch_point] = line;
-                }
-                Token::Dots => {
-                    self.emit_with_line(Instr::MakeRange, line);
+            Token::Try => {
+                self.advance();
+                let call = self.parse_expr(PrattPrecedence::Call)?;
+                if !matches!(call, Expr::Call { .. }) {
+                    return Err(self.parser_err("`try` expects a call expression"));
                 }
-                Token::DotsEq => {
-                    self.emit_with_line(Instr::MakeRangeClosed, line);
+                Expr::Try {
+                    call: Box::new(call),
+                    span,
                 }
-                Token::PipeOp => {
-                    // `para |> functor` => `functor(para)`
-                    self.emit_with_line(Instr::Swap2, line);
-                    self.emit_with_line(Instr::Call(1), line);
+            }
+            Token::LBracket => {
+                self.advance();
+                let items = self.parse_argument()?;
+                let close = self.get_cood();
+                self.consume(Token::RBracket)?;
+                Expr::Array { items, span, close }
+            }
+            Token::Dict => self.parse_dict()?,
+            Token::Sub | Token::Not => {
+                self.advance();
+                let operand = self.parse_expr(PrattPrecedence::Unary)?;
+                let op = if tk == Token::Sub {
+                    UnaryOp::Neg
+                } else {
+                    UnaryOp::Not
+                };
+                Expr::Unary {
+                    op,
+                    operand: Box::new(operand),
+                    span,
                 }
-                Token::Is => {
-                    self.emit_with_line(Instr::ClassIs, line);
+            }
+            Token::LParen => {
+                self.advance();
+                let expr = self.parse_expr(PrattPrecedence::Lowest)?;
+                self.consume(Token::RParen)?;
+                expr
+            }
+            Token::Stick => {
+                // lambda
+                self.advance();
+                let params =
+                    self.parse_params(Token::Stick, "expect `|` after lambda parameter list")?;
+                let close = self.get_cood();
+                self.consume(Token::Stick)?;
+                let body = self.parse_block()?;
+                Expr::Lambda {
+                    func: Function {
+                        params,
+                        variadic: false,
+                        close,
+                        body,
+                    },
+                    span,
                 }
-                _ => return Err(self.parser_err_str("infix operator required here.")),
             }
-        }
-        Ok(())
-    }
-    fn parse_array(&mut self) -> Result<(), String> {
-        self.consume(Token::LBracket)?;
-        let line = self.get_line();
-        let arg_num = self.parse_argument()?;
-        self.emit_with_line(Instr::InitArray(arg_num), line);
-        self.consume(Token::RBracket)?;
-        Ok(())
+            tk => {
+                return Err(
+                    self.parser_err(&format!("unexpected {tk}, expect an expression"))
+                )
+            }
+        };
+        Ok(expr)
     }
-    fn parse_dict(&mut self) -> Result<(), String> {
-        self.consume(Token::Dict)?;
-        let line = self.get_line();
-        self.consume(Token::LParen)?;
-        let mut num_arg = 0;
+    /// a string literal, the scanner turns `"a${b}c"` into `"a" + ${ b } + "c"`
+    fn parse_string(&mut self) -> Result<Expr, Diagnostic> {
+        let mut parts = Vec::new();
         loop {
-            let tk = self.peek_not_eof()?;
-            if let Token::String(s) = tk {
-                self.load_value(Value::String(s));
-            } else if Token::RParen == tk {
-                // empty Dict
-                self.advance();
-                self.emit_with_line(Instr::InitDict(num_arg), line);
-            } else {
-                return Err(self.parser_err_str("illegal dict initialization."));
-            }
+            let span = self.span();
+            let Token::String(s) = self.peek_not_eof()? else {
+                return Err(self.parser_err("expect the rest of the string after `}`"));
+            };
             self.advance();
-            self.consume(Token::Colon)?;
-            self.parse_rval_expr(PrattPrecedence::Lowest)?;
-            if Token::RParen == self.peek_not_eof()? {
-                num_arg += 1;
+            parts.push(StrPart::Str(s.get_inner().to_owned(), span));
+            if self.peek() != Some(Token::Add) || self.peek2() != Some(Token::InterplotBegin) {
                 break;
-            } else {
+            }
+            self.advance();
+            let open = self.span();
+            self.advance();
+            let start = self.get_cood();
+            let expr = self.parse_expr(PrattPrecedence::Lowest)?;
+            let close = self.span();
+            self.consume(Token::InterplotEnd)?;
+            self.consume(Token::Add)?;
+            parts.push(StrPart::Expr {
+                expr,
+                open,
+                start,
+                close,
+            });
+        }
+        if let [StrPart::Str(s, span)] = &parts[..] {
+            return Ok(Expr::String(s.clone(), *span));
+        }
+        Ok(Expr::Interpolation(parts))
+    }
+    fn parse_dict(&mut self) -> Result<Expr, Diagnostic> {
+        let span = self.span();
+        self.consume(Token::Dict)?;
+        self.consume(Token::LParen)?;
+        let mut entries = Vec::new();
+        while Token::RParen != self.peek_not_eof()? {
+            if !entries.is_empty() {
                 self.consume(Token::Comma)?;
-                num_arg += 1;
             }
+            let key = if let Token::String(s) = self.peek_not_eof()? {
+                Expr::String(s.get_inner().to_owned(), self.span())
+            } else {
+                return Err(self.parser_err("illegal dict initialization."));
+            };
+            self.advance();
+            self.consume(Token::Colon)?;
+            entries.push((key, self.parse_expr(PrattPrecedence::Lowest)?));
         }
+        let close = self.get_cood();
         self.consume(Token::RParen)?;
-        self.emit_with_line(Instr::InitDict(num_arg), line);
-        Ok(())
+        Ok(Expr::Dict {
+            entries,
+            span,
+            close,
+        })
+    }
+    /// a name, `msg` is the error if the current token is something else
+    fn parse_ident(&mut self, msg: &str) -> Result<Ident, Diagnostic> {
+        if let Token::Symbol(s) = self.peek_not_eof()? {
+            let span = self.span();
+            self.advance();
+            Ok(Ident {
+                name: s.get_inner().to_owned(),
+                span,
+            })
+        } else {
+            Err(self.parser_err(msg))
+        }
     }
 
-    fn prefix_symbol(&mut self, _prec: PrattPrecedence) -> Result<(), String> {
-        Ok(())
-    }
-    fn consume(&mut self, token: Token) -> Result<(), String> {
+    fn consume(&mut self, token: Token) -> Result<(), Diagnostic> {
         if let Some(look_tok) = self.peek() {
             if look_tok == token {
                 self.advance();
                 return Ok(());
             } else {
-                return Err(format!(
-                    "unexpected token in ({},{}), expect {:?}, get {:?}",
-                    self.token_cood[self.ptr].0, self.token_cood[self.ptr].1, token, look_tok,
-                ));
+                return Err(self.parser_err(&format!("expect {token}, found {look_tok}")));
             }
         }
 
-        return Err(format!(
-            "unexpected token in ({},{}), expect {:?}, get EOF",
-            self.token_cood[self.ptr].0, self.token_cood[self.ptr].1, token,
-        ));
+        Err(self.parser_err(&format!("expect {token}, found end of file")))
     }
-    fn parser_err_str(&self, s: &str) -> String {
-        format!("{} in {:?}", s, self.token_cood[self.ptr])
+    fn parser_err(&self, s: &str) -> Diagnostic {
+        Diagnostic::error(self.get_cood(), s).with_span(self.span().len)
     }
     #[inline]
     fn peek(&self) -> Option<Token> {
@@ -1098,9 +911,9 @@ impl<'a> ParserCtx<'a> {
         }
     }
 
-    fn peek_not_eof(&self) -> Result<Token, String> {
+    fn peek_not_eof(&self) -> Result<Token, Diagnostic> {
         if self.ptr >= self.len {
-            Err(self.parser_err_str("unexpected EOF"))
+            Err(self.parser_err("unexpected EOF"))
         } else {
             Ok(self.tokens[self.ptr].clone())
         }
@@ -1118,124 +931,19 @@ impl<'a> ParserCtx<'a> {
         self.ptr += 1;
     }
     #[inline]
-    fn emit(&mut self, instr: Instr) {
-        self.chunk[self.depth].bytecodes.push(instr);
-        if self.ptr >= self.len {
-            self.chunk[self.depth].lines.push(self.token_cood.last().unwrap().0);
-        } else {
-            self.chunk[self.depth]
-                .lines
-                .push(self.token_cood[self.ptr].0);
-        }
-    }
-    #[inline]
-    fn emit_with_line(&mut self, instr: Instr, line: usize) {
-        self.chunk[self.depth].bytecodes.push(instr);
-        self.chunk[self.depth].lines.push(line);
-    }
-    #[inline]
-    fn push_constant(&mut self, c: Value) -> usize {
-        self.chunk[self.depth].constants.push(c);
-        return self.chunk[self.depth].constants.len() - 1;
-    }
-    fn push_unique_number(&mut self, v: f64) -> usize {
-        for (i, c) in self.chunk[self.depth].constants.iter().enumerate() {
-            if let Value::Number(v1) = c {
-                if (v - v1).abs() < f64::EPSILON {
-                    return i;
-                }
-            }
-        }
-        self.chunk[self.depth].constants.push(Value::Number(v));
-        return self.chunk[self.depth].constants.len() - 1;
-    }
-
-    fn push_unique_string(&mut self, s: &IString) -> usize {
-        for (i, c) in self.chunk[self.depth].constants.iter().enumerate() {
-            if let Value::String(s1) = c {
-                if s == s1 {
-                    return i;
-                }
-            }
-        }
-        self.chunk[self.depth]
-            .constants
-            .push(Value::String(s.clone()));
-        return self.chunk[self.depth].constants.len() - 1;
-    }
-    #[inline]
-    fn load_value(&mut self, v: Value) {
-        if let Some(x) = self.chunk[self.depth]
-            .constants
-            .iter()
-            .position(|val| val == &v)
-        {
-            self.emit(Instr::Load(x));
+    fn get_cood(&self) -> (usize, usize) {
+        if self.ptr >= self.token_cood.len() {
+            self.token_cood.last().copied().unwrap_or((1, 1))
         } else {
-            let idx = self.push_constant(v);
-            self.emit(Instr::Load(idx));
-        }
-    }
-
-    fn resolve(&mut self, symbol: &IString, depth: usize) -> VarLoc {
-        if symbol.get_inner() == "this" {
-            if !self.method_ctx {
-                return VarLoc::NotFound;
-            }
-            return VarLoc::ThisRef;
-        }
-        // from innermost Block to outermost Block of current Function
-        for ctx in self.func_ctx_stack[depth].block_ctx_stack.iter().rev() {
-            if let Some(x) = ctx.symbol_table.get(symbol) {
-                return VarLoc::Local(*x);
-            }
-        }
-        if depth == 0 {
-            // stacktop = GLOBAL[constant[idx]]
-            let idx = self.push_unique_string(symbol);
-            return VarLoc::Global(idx);
-        }
-        if let Some(x) = self.chunk[depth].upvalues.iter().position(|upv| match upv {
-            UpValueDecl::Ref(_, s) => s == symbol,
-            UpValueDecl::RefUpValue(_, s) => s == symbol,
-        }) {
-            return VarLoc::UpValue(x);
-        }
-        let ret = self.resolve(symbol, depth - 1);
-        match ret {
-            VarLoc::Local(x) => {
-                // local variable of outer function
-                // add to upvalue list of this function
-                self.chunk[depth]
-                    .upvalues
-                    .push(UpValueDecl::Ref(x, symbol.clone()));
-                return VarLoc::UpValue(self.chunk[depth].upvalues.len() - 1);
-            }
-            VarLoc::UpValue(x) => {
-                // upvalue of outer function
-                self.chunk[depth]
-                    .upvalues
-                    .push(UpValueDecl::RefUpValue(x, symbol.clone()));
-                return VarLoc::UpValue(self.chunk[depth].upvalues.len() - 1);
-            }
-            VarLoc::Global(x) => {
-                // Global variable
-                return VarLoc::Global(x);
-            }
-            _ => {
-                return VarLoc::NotFound;
-            }
+            self.token_cood[self.ptr]
         }
     }
-
+    /// span of the current token
     #[inline]
-    fn get_line(&self) -> usize {
-        if self.ptr >= self.token_cood.len() {
-            self.token_cood.last().unwrap().0
-        } else {
-            self.token_cood[self.ptr].0
+    fn span(&self) -> Span {
+        Span {
+            pos: self.get_cood(),
+            len: self.token_span.get(self.ptr).copied().unwrap_or(1),
         }
     }
 }
-#[cfg(test)]
-mod test {}
@@ -1,21 +1,30 @@
+use super::ast::{Comment, Span};
 use super::*;
 use crate::interned_string::{IString, StringPool};
+use crate::Diagnostic;
 use std::collections::HashMap;
 pub struct ScannerCtx<'a> {
     src: Vec<char>,
     pub tokens: Vec<Token>,
     pub cood: Vec<(usize, usize)>, // row & col
+    /// length of every token in chars
+    pub span: Vec<usize>,
+    pub comments: Vec<Comment>,
     pub string_pool: &'a mut StringPool,
     unclosed_brace: Vec<usize>,
     ptr: usize,
     row: usize,
     col: usize,
     len: usize,
+    /// (ptr, row, col) where the token being scanned starts
+    tok_start: (usize, usize, usize),
 }
 
 pub struct ScannerResult {
     pub tokens: Vec<Token>,
     pub cood: Vec<(usize, usize)>,
+    pub span: Vec<usize>,
+    pub comments: Vec<Comment>,
 }
 impl<'a> ScannerCtx<'a> {
     pub fn new(src: &str, string_pool: &'a mut StringPool) -> ScannerCtx<'a> {
@@ -28,12 +37,15 @@ impl<'a> ScannerCtx<'a> {
             src: char_serial,
             tokens: Vec::new(),
             cood: Vec::new(),
+            span: Vec::new(),
+            comments: Vec::new(),
             string_pool: string_pool,
             unclosed_brace: Vec::new(),
             ptr: 0,
             row: 1,
             col: 1,
             len: t,
+            tok_start: (0, 1, 1),
         }
     }
 
@@ -41,9 +53,11 @@ impl<'a> ScannerCtx<'a> {
         ScannerResult {
             tokens: self.tokens,
             cood: self.cood,
+            span: self.span,
+            comments: self.comments,
         }
     }
-    pub fn parse(&mut self) -> Result<(), String> {
+    pub fn parse(&mut self) -> Result<(), Diagnostic> {
         let keyword_map: HashMap<&str, Token> = HashMap::from([
             ("and", Token::And),
             ("or", Token::Or),
@@ -55,10 +69,13 @@ impl<'a> ScannerCtx<'a> {
             ("var", Token::Var),
             ("if", Token::If),
             ("else", Token::Else),
+            ("match", Token::Match),
             ("func", Token::Function),
             ("nil", Token::Nil),
             ("return", Token::Return),
             ("except", Token::Except),
+            ("try", Token::Try),
+            ("catch", Token::Catch),
             ("class", Token::Class),
             ("super", Token::Super),
             ("break", Token::Break),
@@ -89,6 +106,7 @@ impl<'a> ScannerCtx<'a> {
             ('@', Token::Dict),
         ]);
         loop {
+            self.tok_start = (self.ptr, self.row, self.col);
             let nxt_c = if let Some(nxt_c) = self.peek() {
                 nxt_c
             } else {
@@ -103,6 +121,7 @@ impl<'a> ScannerCtx<'a> {
                             break;
                         }
                     }
+                    self.record_comment();
                 }
                 c if { c == '/' && self.peekn(2) == Some('*') } => {
                     self.advance()?;
@@ -121,6 +140,7 @@ impl<'a> ScannerCtx<'a> {
                             }
                         }
                     }
+                    self.record_comment();
                 }
                 c if { c.is_ascii_digit() } => {
                     let x = Token::Number(self.number()?);
@@ -153,61 +173,92 @@ impl<'a> ScannerCtx<'a> {
                         if let Some(ahead2) = self.peekn(3) {
                             if c == '.' && ahead == '.' && ahead2 == '=' {
                                 self.tokens.push(Token::DotsEq);
-                                self.record_pos();
                                 self.advance()?;
                                 self.advance()?;
                                 self.advance()?;
+                                self.record_pos();
                                 continue;
                             }
                             if c == '.' && ahead == '.' && ahead2 == '.' {
                                 self.tokens.push(Token::ThreeDots);
-                                self.record_pos();
                                 self.advance()?;
                                 self.advance()?;
                                 self.advance()?;
+                                self.record_pos();
                                 continue;
                             }
                         }
                         if c == '!' && ahead == '=' {
                             self.tokens.push(Token::NotEqual);
-                            self.record_pos();
                             self.advance()?;
                             self.advance()?;
+                            self.record_pos();
                             continue;
                         }
                         if c == '<' && ahead == '=' {
                             self.tokens.push(Token::Le);
-                            self.record_pos();
                             self.advance()?;
                             self.advance()?;
+                            self.record_pos();
                             continue;
                         }
                         if c == '>' && ahead == '=' {
                             self.tokens.push(Token::Ge);
-                            self.record_pos();
                             self.advance()?;
                             self.advance()?;
+                            self.record_pos();
                             continue;
                         }
                         if c == '=' && ahead == '=' {
                             self.tokens.push(Token::EEqual);
+                            self.advance()?;
+                            self.advance()?;
+                            self.record_pos();
+                            continue;
+                        }
+                        if c == '=' && ahead == '>' {
+                            self.tokens.push(Token::FatArrow);
+                            self.advance()?;
+                            self.advance()?;
+                            self.record_pos();
+                            continue;
+                        }
+                        if ahead == '=' && "+-*/%".contains(c) {
+                            self.tokens.push(match c {
+                                '+' => Token::AddAssign,
+                                '-' => Token::SubAssign,
+                                '*' => Token::MulAssign,
+                                '/' => Token::DivAssign,
+                                _ => Token::ModAssign,
+                            });
+                            self.advance()?;
+                            self.advance()?;
                             self.record_pos();
+                            continue;
+                        }
+                        if c == '?' && "?.[".contains(ahead) {
+                            self.tokens.push(match ahead {
+                                '?' => Token::Coalesce,
+                                '.' => Token::QuestionDot,
+                                _ => Token::QuestionBracket,
+                            });
                             self.advance()?;
                             self.advance()?;
+                            self.record_pos();
                             continue;
                         }
                         if c == '|' && ahead == '>' {
                             self.tokens.push(Token::PipeOp);
-                            self.record_pos();
                             self.advance()?;
                             self.advance()?;
+                            self.record_pos();
                             continue;
                         }
                         if c == '.' && ahead == '.' {
                             self.tokens.push(Token::Dots);
-                            self.record_pos();
                             self.advance()?;
                             self.advance()?;
+                            self.record_pos();
                             continue;
                         }
                     }
@@ -222,33 +273,29 @@ impl<'a> ScannerCtx<'a> {
                             }
                         }
                         self.tokens.push(t.clone());
-                        self.record_pos();
                         self.advance()?;
+                        self.record_pos();
                     } else {
-                        return Err(self.scanner_err_str(
-                            "this punctuation is not used but reserved for future",
-                        ));
+                        return Err(self
+                            .scanner_err("this punctuation is not used but reserved for future"));
                     }
                 }
                 c if { c.is_whitespace() } => {
                     self.advance()?;
                 }
                 _ => {
-                    return Err(self.scanner_err_str(
+                    return Err(self.scanner_err(
                     "although utf-8 is supported, I don't think it's fun to mix emoji or something else in code."));
                 }
             }
         }
     }
-    fn consume(&mut self, c: char) -> Result<(), String> {
+    fn consume(&mut self, c: char) -> Result<(), Diagnostic> {
         if self.peek() == Some(c) {
             self.advance()?;
             Ok(())
         } else {
-            Err(format!(
-                "unexpected character in ({},{})",
-                self.row, self.col
-            ))
+            Err(self.scanner_err(&format!("expect `{c}`")))
         }
     }
     #[inline]
@@ -268,19 +315,35 @@ impl<'a> ScannerCtx<'a> {
         }
     }
     #[inline]
-    fn advance(&mut self) -> Result<(), String> {
+    fn advance(&mut self) -> Result<(), Diagnostic> {
         if self.peek() == Some('\n') {
-            self.col = 1;
+            // col is bumped to 1 below
+            self.col = 0;
             self.row += 1;
         }
         self.ptr += 1;
         self.col += 1;
         Ok(())
     }
+    /// called right after a token is scanned
     fn record_pos(&mut self) {
-        self.cood.push((self.row, self.col));
+        let (start, row, col) = self.tok_start;
+        self.cood.push((row, col));
+        self.span.push(self.ptr - start);
+    }
+    /// called right after a comment is scanned
+    fn record_comment(&mut self) {
+        let (start, row, col) = self.tok_start;
+        let text: String = self.src[start..self.ptr].iter().collect();
+        self.comments.push(Comment {
+            text: text.trim_end().to_owned(),
+            span: Span {
+                pos: (row, col),
+                len: self.ptr - start,
+            },
+        });
     }
-    fn identifier(&mut self) -> Result<String, String> {
+    fn identifier(&mut self) -> Result<String, Diagnostic> {
         let mut ret = String::new();
         ret.push(self.peek().unwrap());
         self.advance()?;
@@ -302,7 +365,7 @@ impl<'a> ScannerCtx<'a> {
         }
         Ok(ret)
     }
-    fn number(&mut self) -> Result<f64, String> {
+    fn number(&mut self) -> Result<f64, Diagnostic> {
         let mut ret = String::new();
 
         loop {
@@ -332,10 +395,10 @@ impl<'a> ScannerCtx<'a> {
         let num = ret.parse::<f64>();
         match num {
             Ok(x) => Ok(x),
-            Err(_) => Err(self.scanner_err_str("illegal number format")),
+            Err(_) => Err(self.scanner_err("illegal number format")),
         }
     }
-    fn string(&mut self) -> Result<String, String> {
+    fn string(&mut self) -> Result<String, Diagnostic> {
         let mut ret = String::new();
         self.advance()?; // consume "
         loop {
@@ -385,11 +448,7 @@ impl<'a> ScannerCtx<'a> {
                                 '$' => '$',
                                 '{' => '{',
                                 '}' => '}',
-                                _ => {
-                                    return Err(
-                                        self.scanner_err_str("unsupported escaped character")
-                                    )
-                                }
+                                _ => return Err(self.scanner_err("unsupported escaped character")),
                             };
                             ret.push(escaped_ch);
                             self.advance()?;
@@ -401,15 +460,15 @@ impl<'a> ScannerCtx<'a> {
                     }
                 }
                 None => {
-                    return Err(self.scanner_err_str("unexpectd eof before the end of a string"));
+                    return Err(self.scanner_err("unexpectd eof before the end of a string"));
                 }
             }
         }
         Ok(ret)
     }
     #[inline]
-    fn scanner_err_str(&mut self, msg: &str) -> String {
-        format!("{} in ({},{})", msg, self.row, self.col)
+    fn scanner_err(&self, msg: &str) -> Diagnostic {
+        Diagnostic::error((self.row, self.col), msg)
     }
 }
 #[cfg(test)]
@@ -0,0 +1,73 @@
+/// what a name is declared as
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymbolKind {
+    Variable,
+    Parameter,
+    Function,
+    Class,
+    Method,
+}
+
+/// a declared name, positions are (line, col) of the scanner
+#[derive(Debug, Clone)]
+pub struct SymbolDef {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub pos: (usize, usize),
+    pub span: usize,
+    /// end of the whole declaration, the closing `}` of functions and classes
+    pub end: (usize, usize),
+    /// class of a method
+    pub container: Option<usize>,
+}
+
+/// a use of a name
+#[derive(Debug, Clone)]
+pub struct SymbolRef {
+    pub name: String,
+    pub pos: (usize, usize),
+    pub span: usize,
+    /// index in `SymbolIndex.defs`, `None` for globals declared elsewhere
+    pub def: Option<usize>,
+}
+
+/// declarations and uses of names in one file, collected by the parser
+#[derive(Debug, Clone, Default)]
+pub struct SymbolIndex {
+    pub defs: Vec<SymbolDef>,
+    pub refs: Vec<SymbolRef>,
+}
+
+fn covers(pos: (usize, usize), span: usize, line: usize, col: usize) -> bool {
+    pos.0 == line && pos.1 <= col && col <= pos.1 + span
+}
+
+impl SymbolIndex {
+    /// the declaration of the name at (line, col), `Err` with the name
+    /// if it is a global declared elsewhere
+    pub fn def_at(&self, line: usize, col: usize) -> Option<Result<usize, String>> {
+        if let Some(id) = self
+            .defs
+            .iter()
+            .position(|d| d.kind != SymbolKind::Method && covers(d.pos, d.span, line, col))
+        {
+            return Some(Ok(id));
+        }
+        let r = self
+            .refs
+            .iter()
+            .find(|r| covers(r.pos, r.span, line, col))?;
+        Some(r.def.ok_or_else(|| r.name.clone()))
+    }
+
+    /// uses of what `def_at` returned
+    pub fn refs_to<'a>(
+        &'a self,
+        target: &'a Result<usize, String>,
+    ) -> impl Iterator<Item = &'a SymbolRef> + 'a {
+        self.refs.iter().filter(move |r| match target {
+            Ok(id) => r.def == Some(*id),
+            Err(name) => r.def.is_none() && &r.name == name,
+        })
+    }
+}
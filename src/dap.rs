@@ -0,0 +1,386 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+use serde_json::{json, Value as Json};
+
+use crate::native::value_to_string;
+use crate::vm::Vm;
+use crate::wire::{read_message, write_message};
+use crate::{compile_vm, eval_err_diagnostic, DebugEvent, PauseReason, StepMode, Unboxed, Value};
+
+/// the only thread reported, frames of fibers waiting for the executing one
+/// are listed below its frames
+const THREAD_ID: i64 = 1;
+/// variables references are `frame * SCOPES + scope + 1`
+const SCOPES: usize = 3;
+const SCOPE_LOCALS: usize = 0;
+const SCOPE_UPVALUES: usize = 1;
+const SCOPE_GLOBALS: usize = 2;
+
+/// serve the Debug Adapter Protocol, requests are read from `input`
+/// on another thread so that `pause` reaches a running program.
+/// returns after `disconnect` or at the end of `input`.
+pub fn run_dap<R, W>(input: R, output: W) -> io::Result<()>
+where
+    R: BufRead + Send + 'static,
+    W: Write + 'static,
+{
+    let interrupt = Arc::new(AtomicBool::new(false));
+    let (tx, rx) = mpsc::channel();
+    let flag = interrupt.clone();
+    thread::spawn(move || {
+        let mut input = input;
+        while let Ok(Some(msg)) = read_message(&mut input) {
+            if msg["command"] == "pause" {
+                flag.store(true, Ordering::Relaxed);
+            }
+            if tx.send(msg).is_err() {
+                break;
+            }
+        }
+    });
+    let mut server = DapServer::new(output, interrupt);
+    for req in rx {
+        if !server.handle(&req)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// numbers outgoing messages, shared with the output hook of the vm
+struct Sender<W: Write> {
+    output: W,
+    seq: i64,
+}
+
+impl<W: Write> Sender<W> {
+    fn send(&mut self, mut msg: Json) -> io::Result<()> {
+        self.seq += 1;
+        msg["seq"] = json!(self.seq);
+        write_message(&mut self.output, &msg)
+    }
+
+    fn event(&mut self, event: &str, body: Json) -> io::Result<()> {
+        self.send(json!({"type": "event", "event": event, "body": body}))
+    }
+}
+
+struct DapServer<W: Write> {
+    sender: Rc<RefCell<Sender<W>>>,
+    /// `None` before `launch` and after the program exits
+    vm: Option<Box<Vm>>,
+    program: String,
+    src: String,
+    /// globals from the prelude and native modules, not listed
+    builtins: HashMap<String, Value>,
+    /// by file, kept to be set again on `launch`
+    breakpoints: HashMap<String, Vec<usize>>,
+    interrupt: Arc<AtomicBool>,
+    stop_on_entry: bool,
+    configured: bool,
+    started: bool,
+    paused: bool,
+}
+
+impl<W: Write + 'static> DapServer<W> {
+    fn new(output: W, interrupt: Arc<AtomicBool>) -> DapServer<W> {
+        DapServer {
+            sender: Rc::new(RefCell::new(Sender { output, seq: 0 })),
+            vm: None,
+            program: String::new(),
+            src: String::new(),
+            builtins: HashMap::new(),
+            breakpoints: HashMap::new(),
+            interrupt,
+            stop_on_entry: false,
+            configured: false,
+            started: false,
+            paused: false,
+        }
+    }
+
+    /// returns false once the client disconnects
+    fn handle(&mut self, req: &Json) -> io::Result<bool> {
+        let command = req["command"].as_str().unwrap_or_default();
+        let args = &req["arguments"];
+        let res = match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsEvaluateForHovers": true,
+            })),
+            "launch" => self.launch(args),
+            "setBreakpoints" => Ok(self.set_breakpoints(args)),
+            "configurationDone" | "pause" | "disconnect" | "terminate" => Ok(Json::Null),
+            "threads" => Ok(json!({"threads": [{"id": THREAD_ID, "name": "main"}]})),
+            "stackTrace" => self.stack_trace(args),
+            "scopes" => self.scopes(args),
+            "variables" => self.variables(args),
+            "evaluate" => self.evaluate(args),
+            "continue" => self
+                .paused_vm()
+                .map(|_| json!({"allThreadsContinued": true})),
+            "next" | "stepIn" | "stepOut" => self.paused_vm().map(|_| Json::Null),
+            _ => Err(format!("unsupported request `{command}`")),
+        };
+        let success = res.is_ok();
+        let mut response = json!({
+            "type": "response",
+            "request_seq": req["seq"],
+            "command": command,
+            "success": success,
+        });
+        match res {
+            Ok(Json::Null) => {}
+            Ok(body) => response["body"] = body,
+            Err(msg) => response["message"] = json!(msg),
+        }
+        self.sender.borrow_mut().send(response)?;
+        if !success {
+            return Ok(true);
+        }
+
+        match command {
+            "initialize" => self.sender.borrow_mut().event("initialized", json!({}))?,
+            "launch" | "configurationDone" => {
+                if command == "configurationDone" {
+                    self.configured = true;
+                }
+                if self.configured && self.vm.is_some() && !self.started {
+                    self.started = true;
+                    let mode = if self.stop_on_entry {
+                        StepMode::StepIn
+                    } else {
+                        StepMode::Continue
+                    };
+                    self.resume(mode)?;
+                }
+            }
+            "continue" => self.resume(StepMode::Continue)?,
+            "next" => self.resume(StepMode::StepOver)?,
+            "stepIn" => self.resume(StepMode::StepIn)?,
+            "stepOut" => self.resume(StepMode::StepOut)?,
+            // the program is paused or done by now
+            "pause" => self.interrupt.store(false, Ordering::Relaxed),
+            "disconnect" | "terminate" => return Ok(false),
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    fn launch(&mut self, args: &Json) -> Result<Json, String> {
+        if self.vm.is_some() || self.started {
+            return Err("already launched".to_owned());
+        }
+        let program = args["program"]
+            .as_str()
+            .ok_or("`program` is required to launch")?;
+        let src = std::fs::read_to_string(program)
+            .map_err(|e| format!("cannot read `{program}`: {e}"))?;
+        let mut vm = compile_vm(&src, program, false, false).map_err(|diag| diag.render(&src))?;
+        vm.enable_debugger(false);
+        vm.set_debug_interrupt(self.interrupt.clone());
+        let sender = self.sender.clone();
+        vm.set_output(Box::new(move |s| {
+            let body = json!({"category": "stdout", "output": s});
+            let _ = sender.borrow_mut().event("output", body);
+        }));
+        for (file, lines) in self.breakpoints.iter() {
+            vm.set_breakpoints(file, lines);
+        }
+        self.builtins = vm
+            .globals()
+            .map(|(k, v)| (k.get_inner().to_owned(), *v))
+            .collect();
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+        self.program = program.to_owned();
+        self.src = src;
+        self.vm = Some(vm);
+        Ok(Json::Null)
+    }
+
+    fn set_breakpoints(&mut self, args: &Json) -> Json {
+        let file = args["source"]["path"].as_str().unwrap_or_default();
+        let lines: Vec<usize> = args["breakpoints"]
+            .as_array()
+            .map(|bps| {
+                bps.iter()
+                    .filter_map(|bp| bp["line"].as_u64())
+                    .map(|line| line as usize)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let resolved = match self.vm.as_mut() {
+            Some(vm) => vm.set_breakpoints(file, &lines),
+            None => vec![None; lines.len()],
+        };
+        let breakpoints: Vec<Json> = lines
+            .iter()
+            .zip(resolved.iter())
+            .map(|(line, res)| json!({"verified": res.is_some(), "line": res.unwrap_or(*line)}))
+            .collect();
+        self.breakpoints.insert(file.to_owned(), lines);
+        json!({ "breakpoints": breakpoints })
+    }
+
+    fn paused_vm(&mut self) -> Result<&mut Vm, String> {
+        match self.vm.as_mut() {
+            Some(vm) if self.paused => Ok(vm),
+            _ => Err("the program is not paused".to_owned()),
+        }
+    }
+
+    fn stack_trace(&mut self, args: &Json) -> Result<Json, String> {
+        let vm = self.paused_vm()?;
+        let frames = vm.debug_frames();
+        let start = args["startFrame"].as_u64().unwrap_or(0) as usize;
+        let levels = match args["levels"].as_u64() {
+            Some(0) | None => frames.len(),
+            Some(n) => n as usize,
+        };
+        let cwd = vm.interpreter_cwd.clone();
+        let stack_frames: Vec<Json> = frames
+            .iter()
+            .enumerate()
+            .skip(start)
+            .take(levels)
+            .map(|(id, frame)| {
+                json!({
+                    "id": id,
+                    "name": frame.name,
+                    "source": source(&cwd, &frame.file),
+                    "line": frame.line,
+                    "column": 1,
+                })
+            })
+            .collect();
+        Ok(json!({"stackFrames": stack_frames, "totalFrames": frames.len()}))
+    }
+
+    fn scopes(&mut self, args: &Json) -> Result<Json, String> {
+        let frame = args["frameId"].as_u64().unwrap_or(0) as usize;
+        let vm = self.paused_vm()?;
+        if frame >= vm.debug_frames().len() {
+            return Err(format!("no frame {frame}"));
+        }
+        let scope = |name: &str, scope: usize| {
+            json!({
+                "name": name,
+                "variablesReference": frame * SCOPES + scope + 1,
+                "expensive": scope == SCOPE_GLOBALS,
+            })
+        };
+        Ok(json!({"scopes": [
+            scope("Locals", SCOPE_LOCALS),
+            scope("Upvalues", SCOPE_UPVALUES),
+            scope("Globals", SCOPE_GLOBALS),
+        ]}))
+    }
+
+    fn variables(&mut self, args: &Json) -> Result<Json, String> {
+        let reference = args["variablesReference"].as_u64().unwrap_or(0) as usize;
+        if reference == 0 {
+            return Err("bad variables reference".to_owned());
+        }
+        let (frame, scope) = ((reference - 1) / SCOPES, (reference - 1) % SCOPES);
+        let builtins = &self.builtins;
+        let vm = match self.vm.as_mut() {
+            Some(vm) if self.paused => vm,
+            _ => return Err("the program is not paused".to_owned()),
+        };
+        let vars = match scope {
+            SCOPE_LOCALS => vm.debug_locals(frame),
+            SCOPE_UPVALUES => vm.debug_upvalues(frame),
+            _ => {
+                let mut globals: Vec<(String, Value)> = vm
+                    .globals()
+                    .filter(|(k, v)| builtins.get(k.get_inner()) != Some(v))
+                    .map(|(k, v)| (k.get_inner().to_owned(), *v))
+                    .collect();
+                globals.sort_by(|a, b| a.0.cmp(&b.0));
+                globals
+            }
+        };
+        let variables: Vec<Json> = vars
+            .iter()
+            .map(|(name, val)| json!({"name": name, "value": show(val), "variablesReference": 0}))
+            .collect();
+        Ok(json!({ "variables": variables }))
+    }
+
+    fn evaluate(&mut self, args: &Json) -> Result<Json, String> {
+        let frame = args["frameId"].as_u64().unwrap_or(0) as usize;
+        let expr = args["expression"].as_str().unwrap_or_default();
+        let vm = self.paused_vm()?;
+        match vm.debug_eval(frame, expr) {
+            Ok(val) => Ok(json!({"result": show(&val), "variablesReference": 0})),
+            Err(err) => Err(format!("{}: {}", err.kind(), err.message())),
+        }
+    }
+
+    /// run till the next stop, and tell the client why it stopped
+    fn resume(&mut self, mode: StepMode) -> io::Result<()> {
+        let entry = mode == StepMode::StepIn && !self.paused;
+        self.paused = false;
+        let vm = self.vm.as_mut().unwrap();
+        let res = vm.debug_run(mode);
+        let mut sender = self.sender.borrow_mut();
+        match res {
+            Ok(DebugEvent::Paused(reason)) => {
+                self.paused = true;
+                let reason = match reason {
+                    _ if entry => "entry",
+                    PauseReason::Breakpoint => "breakpoint",
+                    PauseReason::Step => "step",
+                    PauseReason::Interrupt => "pause",
+                };
+                sender.event(
+                    "stopped",
+                    json!({"reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true}),
+                )
+            }
+            Ok(DebugEvent::Finished) => {
+                self.vm = None;
+                sender.event("exited", json!({"exitCode": 0}))?;
+                sender.event("terminated", json!({}))
+            }
+            Err(err) => {
+                let diag = eval_err_diagnostic(vm, err);
+                // error may be raised in an imported module
+                let src = if diag.file == self.program {
+                    self.src.clone()
+                } else {
+                    std::fs::read_to_string(vm.interpreter_cwd.join(&diag.file)).unwrap_or_default()
+                };
+                let body = json!({"category": "stderr", "output": diag.render(&src)});
+                sender.event("output", body)?;
+                self.vm = None;
+                sender.event("exited", json!({"exitCode": 1}))?;
+                sender.event("terminated", json!({}))
+            }
+        }
+    }
+}
+
+fn source(cwd: &Path, file: &str) -> Json {
+    let path = cwd.join(file);
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    json!({"name": name, "path": path.to_string_lossy()})
+}
+
+/// strings quoted, like the REPL echoes them
+fn show(val: &Value) -> String {
+    match val.unbox() {
+        Unboxed::String(s) => format!("{:?}", s.get_inner()),
+        _ => value_to_string(val),
+    }
+}
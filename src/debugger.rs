@@ -0,0 +1,73 @@
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+use crate::Fiber;
+
+/// how far `Vm::debug_run` goes before pausing again
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StepMode {
+    /// till a breakpoint
+    Continue,
+    /// till the next line, entering calls
+    StepIn,
+    /// till the next line of the current function or its callers
+    StepOver,
+    /// till the current function returns to a line of its caller
+    StepOut,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PauseReason {
+    Breakpoint,
+    Step,
+    /// asked to by setting the flag given to `Vm::set_debug_interrupt`
+    Interrupt,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DebugEvent {
+    Paused(PauseReason),
+    Finished,
+}
+
+/// debugger state kept by the vm, checked before every instruction
+pub(crate) struct DebugState {
+    /// (file, line)
+    pub breakpoints: Vec<(String, usize)>,
+    pub step: StepMode,
+    /// fiber and number of call frames where stepping started
+    pub step_fiber: *mut Fiber,
+    pub step_depth: usize,
+    pub paused: Option<PauseReason>,
+    /// may be set from another thread to pause a running vm
+    pub interrupt: Arc<AtomicBool>,
+}
+
+impl DebugState {
+    pub fn new(stop_on_entry: bool) -> DebugState {
+        DebugState {
+            breakpoints: Vec::new(),
+            step: if stop_on_entry {
+                StepMode::StepIn
+            } else {
+                StepMode::Continue
+            },
+            step_fiber: std::ptr::null_mut(),
+            step_depth: 0,
+            paused: None,
+            interrupt: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+/// clients may send absolute paths while chunks keep the path a script is imported by
+pub(crate) fn same_file(a: &str, b: &str) -> bool {
+    if a == b {
+        return true;
+    }
+    if a.is_empty() || b.is_empty() {
+        return false;
+    }
+    Path::new(a).ends_with(b) || Path::new(b).ends_with(a)
+}
@@ -0,0 +1,166 @@
+use std::fmt::{self, Display};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Note => write!(f, "note"),
+        }
+    }
+}
+
+/// error (or warning) reported by scanner, parser or vm,
+/// pointing at a position in the source code.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub file: String,
+    /// 1-based, 0 if the position is unknown
+    pub line: usize,
+    /// 1-based, counted in chars
+    pub col: usize,
+    /// number of chars underlined, starting at `col`
+    pub span: usize,
+    pub severity: Severity,
+    pub message: String,
+    pub notes: Vec<String>,
+    /// id of the lint rule that reported it
+    pub code: Option<&'static str>,
+}
+
+impl Diagnostic {
+    pub fn error(cood: (usize, usize), message: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            file: String::new(),
+            line: cood.0,
+            col: cood.1,
+            span: 1,
+            severity: Severity::Error,
+            message: message.into(),
+            notes: Vec::new(),
+            code: None,
+        }
+    }
+
+    pub fn warning(cood: (usize, usize), message: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::error(cood, message)
+        }
+    }
+
+    pub fn with_span(mut self, span: usize) -> Diagnostic {
+        self.span = span.max(1);
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Diagnostic {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_code(mut self, code: &'static str) -> Diagnostic {
+        self.code = Some(code);
+        self
+    }
+
+    pub fn with_file(mut self, file: impl Into<String>) -> Diagnostic {
+        self.file = file.into();
+        self
+    }
+
+    /// severity, with the rule id like `warning[shadowing]`
+    fn title(&self) -> String {
+        match &self.code {
+            Some(code) => format!("{}[{code}]", self.severity),
+            None => self.severity.to_string(),
+        }
+    }
+
+    /// render like
+    /// ```text
+    /// error: expect `)`, found `;`
+    ///  --> main.slt:3:12
+    ///   |
+    /// 3 |     print(a;
+    ///   |            ^
+    ///   = note: ...
+    /// ```
+    pub fn render(&self, src: &str) -> String {
+        let mut out = format!("{}: {}\n", self.title(), self.message);
+        let file = if self.file.is_empty() {
+            "<input>"
+        } else {
+            &self.file
+        };
+        let src_line = if self.line > 0 {
+            src.lines().nth(self.line - 1)
+        } else {
+            None
+        };
+        let src_line = if let Some(src_line) = src_line {
+            src_line
+        } else {
+            if self.line > 0 {
+                out.push_str(&format!(" --> {file}:{}:{}\n", self.line, self.col));
+            } else {
+                out.push_str(&format!(" --> {file}\n"));
+            }
+            for note in self.notes.iter() {
+                out.push_str(&format!("  = note: {note}\n"));
+            }
+            return out;
+        };
+        let gutter = " ".repeat(self.line.to_string().len());
+        out.push_str(&format!("{gutter}--> {file}:{}:{}\n", self.line, self.col));
+        out.push_str(&format!("{gutter} |\n"));
+        out.push_str(&format!("{} | {}\n", self.line, src_line.trim_end()));
+        // keep tabs so that the caret lines up with the source line
+        let padding: String = src_line
+            .chars()
+            .take(self.col.saturating_sub(1))
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        out.push_str(&format!(
+            "{gutter} | {padding}{}\n",
+            "^".repeat(self.span.max(1))
+        ));
+        for note in self.notes.iter() {
+            out.push_str(&format!("{gutter} = note: {note}\n"));
+        }
+        out
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.file.is_empty() {
+            write!(f, "{}:", self.file)?;
+        }
+        write!(
+            f,
+            "{}:{}: {}: {}",
+            self.line,
+            self.col,
+            self.title(),
+            self.message
+        )?;
+        for note in self.notes.iter() {
+            write!(f, "\n  = note: {note}")?;
+        }
+        Ok(())
+    }
+}
+
+impl From<Diagnostic> for String {
+    fn from(diag: Diagnostic) -> String {
+        diag.to_string()
+    }
+}
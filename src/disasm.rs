@@ -0,0 +1,160 @@
+use std::fmt::Write;
+
+use crate::{Chunk, Instr, Unboxed, UpValueDecl};
+
+/// human-readable listing of `chunk` and every function nested in it,
+/// one section per function in the order they appear in the source.
+/// sections are numbered so that `LoadChunk` can refer to them.
+pub fn disassemble(chunk: &Chunk) -> String {
+    let mut chunks = Vec::new();
+    collect_chunks(chunk, &mut chunks);
+    let mut out = String::new();
+    for (id, chunk) in chunks.iter().enumerate() {
+        if id > 0 {
+            out.push('\n');
+        }
+        disassemble_one(&mut out, id, chunk, &chunks);
+    }
+    out
+}
+
+fn collect_chunks<'a>(chunk: &'a Chunk, chunks: &mut Vec<&'a Chunk>) {
+    chunks.push(chunk);
+    for sub_chunk in chunk.chunks.iter() {
+        collect_chunks(sub_chunk, chunks);
+    }
+}
+
+fn disassemble_one(out: &mut String, id: usize, chunk: &Chunk, chunks: &[&Chunk]) {
+    let file = if chunk.file.is_empty() {
+        "<input>"
+    } else {
+        &chunk.file
+    };
+    let _ = writeln!(out, "== #{id} {} ({file}) ==", chunk.name);
+    let va = if chunk.is_va { " + ..." } else { "" };
+    let _ = writeln!(
+        out,
+        "arity: {}{va}, locals: {}",
+        chunk.parameter_num, chunk.num_locals
+    );
+    if !chunk.upvalues.is_empty() {
+        let _ = writeln!(out, "upvalues:");
+        for (i, upv) in chunk.upvalues.iter().enumerate() {
+            let (kind, idx, name) = match upv {
+                UpValueDecl::Ref(idx, name) => ("local", idx, name),
+                UpValueDecl::RefUpValue(idx, name) => ("upvalue", idx, name),
+            };
+            let _ = writeln!(out, "{i:>6}  {kind} {idx} `{name}`");
+        }
+    }
+    for (offset, instr) in chunk.bytecodes.iter().enumerate() {
+        let (line, col) = chunk.cood.get(offset).copied().unwrap_or_default();
+        let pos = format!("{line}:{col}");
+        let mnemonic = format!("{instr:?}");
+        let mnemonic = mnemonic.split('(').next().unwrap_or_default();
+        let (operand, comment) = operand(chunk, offset, instr, chunks);
+        let line = format!("{offset:04}  {pos:<8}  {mnemonic:<15} {operand:>5}");
+        if comment.is_empty() {
+            let _ = writeln!(out, "{}", line.trim_end());
+        } else {
+            let _ = writeln!(out, "{line}  ; {comment}");
+        }
+    }
+}
+
+/// operand of `instr`, and what it refers to
+fn operand(chunk: &Chunk, offset: usize, instr: &Instr, chunks: &[&Chunk]) -> (String, String) {
+    match *instr {
+        Instr::Load(x) | Instr::GetField(x) | Instr::SetField(x) | Instr::HasField(x) => {
+            (x.to_string(), constant(chunk, x))
+        }
+        Instr::Invoke(x, n) => {
+            let name = constant(chunk, x as usize);
+            (x.to_string(), format!("{name}, {n} args"))
+        }
+        Instr::GetGlobal(x) | Instr::SetGlobal(x) => {
+            let name = match chunk.constants.get(x).map(|v| v.unbox()) {
+                Some(Unboxed::String(s)) => s.get_inner().to_owned(),
+                _ => "<bad global>".to_owned(),
+            };
+            (x.to_string(), name)
+        }
+        Instr::GetUpValue(x) | Instr::SetUpValue(x) => {
+            let name = match chunk.upvalues.get(x) {
+                Some(UpValueDecl::Ref(_, name)) | Some(UpValueDecl::RefUpValue(_, name)) => {
+                    format!("`{name}`")
+                }
+                None => "<bad upvalue>".to_owned(),
+            };
+            (x.to_string(), name)
+        }
+        Instr::LoadChunk(x) => {
+            let target = match chunk.chunks.get(x) {
+                Some(sub_chunk) => chunks
+                    .iter()
+                    .position(|c| std::ptr::eq(*c, sub_chunk))
+                    .map(|id| format!("#{id} {}", sub_chunk.name))
+                    .unwrap_or_default(),
+                None => "<bad chunk>".to_owned(),
+            };
+            (x.to_string(), target)
+        }
+        Instr::GetCollection(x) | Instr::SetCollection(x) => {
+            let kind = if x == 0 { "[]" } else { "." };
+            (x.to_string(), kind.to_owned())
+        }
+        Instr::Jump(x)
+        | Instr::JumpIfNot(x)
+        | Instr::JumpIfTrue(x)
+        | Instr::JumpIfNil(x)
+        | Instr::JumpIfNotNil(x)
+        | Instr::PushHandler(x) => {
+            let target = offset as i64 + x as i64;
+            (format!("{x:+}"), format!("-> {target:04}"))
+        }
+        // the offset is the one of the `JumpIfNot` after it
+        Instr::LtJumpIfNot(x)
+        | Instr::GtJumpIfNot(x)
+        | Instr::LeJumpIfNot(x)
+        | Instr::GeJumpIfNot(x) => {
+            let target = offset as i64 + 1 + x as i64;
+            (format!("{x:+}"), format!("-> {target:04}"))
+        }
+        Instr::Call(x) | Instr::TryCall(x) => (x.to_string(), "args".to_owned()),
+        Instr::CloseUpValues(x) => (x.to_string(), "locals and above".to_owned()),
+        Instr::InitArray(x) => (x.to_string(), "elements".to_owned()),
+        Instr::Dup(x) => (x.to_string(), "values".to_owned()),
+        Instr::ArraySlice(x) => (x.to_string(), "elements skipped".to_owned()),
+        Instr::MatchArray(len, rest) => {
+            let len = if rest == 1 {
+                format!("{len}+")
+            } else {
+                len.to_string()
+            };
+            (len, "elements".to_owned())
+        }
+        Instr::InitDict(x) => (x.to_string(), "pairs".to_owned()),
+        Instr::GetLocal(x) | Instr::SetLocal(x) | Instr::GetLocalAdd(x) | Instr::IncLocal(x) => {
+            let name = chunk
+                .locals
+                .iter()
+                .rfind(|v| v.slot == x && v.start <= offset && offset < v.end)
+                .map(|v| v.name.clone())
+                .unwrap_or_default();
+            (x.to_string(), name)
+        }
+        _ => (String::new(), String::new()),
+    }
+}
+
+fn constant(chunk: &Chunk, idx: usize) -> String {
+    match chunk.constants.get(idx).map(|v| v.unbox()) {
+        Some(Unboxed::String(s)) => format!("{:?}", s.get_inner()),
+        Some(Unboxed::Number(x)) => x.to_string(),
+        Some(Unboxed::Bool(b)) => b.to_string(),
+        Some(Unboxed::Nil) => "nil".to_owned(),
+        Some(v) => format!("{v:?}"),
+        None => "<bad constant>".to_owned(),
+    }
+}
@@ -5,30 +5,37 @@ use std::ffi::{CStr, CString};
 
 use raylib::prelude::*;
 
-use crate::{interned_string::IString, vm::Vm, Value};
+use crate::{
+    interned_string::IString,
+    vm::{EvalError, Vm},
+    NativeResult, Value,
+};
 macro_rules! arity_assert {
     ($n:expr, $arg_num:expr) => {
         if $arg_num != $n {
-            panic!("arity check failed, passed {}, required {}", $n, $arg_num);
+            return Err(EvalError::CallError(format!(
+                "arity check failed, passed {}, required {}",
+                $arg_num, $n
+            )));
         }
     };
 }
 
 macro_rules! mf_entry {
     ($name:expr,$func:expr) => {
-        ($name.to_owned(), Value::NativeFunction($func as *mut u8))
+        ($name.to_owned(), Value::native_function($func as *mut u8))
     };
 }
 
 macro_rules! num_entry {
     ($name:expr, $num:expr) => {
-        ($name.to_owned(), Value::Number($num as f64))
+        ($name.to_owned(), Value::number($num as f64))
     };
 }
 
 macro_rules! export_enum {
     ($($k:ident = $v:expr),*) => {
-        vec![$((stringify!($k).to_owned(), Value::Number($v as f64))),*]
+        vec![$((stringify!($k).to_owned(), Value::number($v as f64))),*]
     };
 }
 struct DrawCtx {
@@ -40,12 +47,12 @@ fn to_c_str(s: &IString) -> CString {
     let c_str = s.get_inner().to_owned();
     CString::new(c_str).unwrap()
 }
-pub fn draw_create(vm: &mut Vm, arg_num: usize, _protected: bool) {
+pub fn draw_create(vm: &mut Vm, arg_num: usize, _protected: bool) -> NativeResult {
     arity_assert!(4, arg_num);
-    let map_size = vm.gets_number() as usize;
-    let t = vm.gets_string();
-    let h = vm.gets_number();
-    let w = vm.gets_number();
+    let map_size = vm.gets_number()? as usize;
+    let t = vm.gets_string()?;
+    let h = vm.gets_number()?;
+    let w = vm.gets_number()?;
     let _ = vm.get_stack().pop();
     let mut b_draw_ctx = Box::new(DrawCtx {
         wnd_title: to_c_str(&t),
@@ -74,56 +81,62 @@ pub fn draw_create(vm: &mut Vm, arg_num: usize, _protected: bool) {
         // memory management across ffi boundary is subtle
         raylib::ffi::InitWindow(w as i32, h as i32, b_draw_ctx.wnd_title.as_ptr());
         if !raylib::ffi::IsWindowReady() {
-            panic!("raylib backend create window failed.");
+            return Err(EvalError::Error(
+                "raylib backend create window failed.".to_owned(),
+            ));
         }
         raylib::ffi::SetTargetFPS(60);
         raylib::ffi::SetRandomSeed(168);
     }
     let p_draw_ctx = Box::into_raw(b_draw_ctx);
     vm.get_stack()
-        .push(Value::OpaqueData(p_draw_ctx as *mut u8));
+        .push(Value::opaque_data(p_draw_ctx as *mut u8));
+    Ok(())
 }
 
-pub fn draw_should_close(vm: &mut Vm, arg_num: usize, _protected: bool) {
+pub fn draw_should_close(vm: &mut Vm, arg_num: usize, _protected: bool) -> NativeResult {
     arity_assert!(1, arg_num);
     let _ = vm.get_stack().pop();
     let _ = vm.get_stack().pop();
     let b = unsafe { raylib::ffi::WindowShouldClose() };
-    vm.get_stack().push(Value::Bool(b));
+    vm.get_stack().push(Value::bool(b));
+    Ok(())
 }
 
-pub fn draw_set_block(vm: &mut Vm, arg_num: usize, _protected: bool) {
+pub fn draw_set_block(vm: &mut Vm, arg_num: usize, _protected: bool) -> NativeResult {
     arity_assert!(4, arg_num);
-    let color = vm.gets_number();
-    let j = vm.gets_number();
-    let i = vm.gets_number();
-    let op_data = vm.gets_opaque() as *mut DrawCtx;
+    let color = vm.gets_number()?;
+    let j = vm.gets_number()?;
+    let i = vm.gets_number()?;
+    let op_data = vm.gets_opaque()? as *mut DrawCtx;
     let _ = vm.get_stack().pop();
     unsafe {
         (*op_data).block_map[i as usize][j as usize] = color as u32;
     }
 
-    vm.get_stack().push(Value::Nil);
+    vm.get_stack().push(Value::NIL);
+    Ok(())
 }
 
-pub fn draw_get_block(vm: &mut Vm, arg_num: usize, _protected: bool) {
+pub fn draw_get_block(vm: &mut Vm, arg_num: usize, _protected: bool) -> NativeResult {
     arity_assert!(3, arg_num);
-    let j = vm.gets_number();
-    let i = vm.gets_number();
-    let op_data = vm.gets_opaque() as *mut DrawCtx;
+    let j = vm.gets_number()?;
+    let i = vm.gets_number()?;
+    let op_data = vm.gets_opaque()? as *mut DrawCtx;
     let _ = vm.get_stack().pop();
     unsafe {
         let color = (*op_data).block_map[i as usize][j as usize];
-        vm.get_stack().push(Value::Number(color as f64));
+        vm.get_stack().push(Value::number(color as f64));
     }
+    Ok(())
 }
 
-pub fn draw_set_camera_pos(vm: &mut Vm, arg_num: usize, _protected: bool) {
+pub fn draw_set_camera_pos(vm: &mut Vm, arg_num: usize, _protected: bool) -> NativeResult {
     arity_assert!(4, arg_num);
-    let z = vm.gets_number();
-    let y = vm.gets_number();
-    let x = vm.gets_number();
-    let op_data = vm.gets_opaque() as *mut DrawCtx;
+    let z = vm.gets_number()?;
+    let y = vm.gets_number()?;
+    let x = vm.gets_number()?;
+    let op_data = vm.gets_opaque()? as *mut DrawCtx;
     let _ = vm.get_stack().pop();
 
     unsafe {
@@ -134,33 +147,36 @@ pub fn draw_set_camera_pos(vm: &mut Vm, arg_num: usize, _protected: bool) {
         };
     }
 
-    vm.get_stack().push(Value::Nil);
+    vm.get_stack().push(Value::NIL);
+    Ok(())
 }
 
-pub fn draw_is_key_pressed(vm: &mut Vm, arg_num: usize, _protected: bool) {
+pub fn draw_is_key_pressed(vm: &mut Vm, arg_num: usize, _protected: bool) -> NativeResult {
     arity_assert!(2, arg_num);
-    let key = vm.gets_number();
-    let op_data = vm.gets_opaque() as *mut DrawCtx;
+    let key = vm.gets_number()?;
+    let op_data = vm.gets_opaque()? as *mut DrawCtx;
     let _ = vm.get_stack().pop();
     unsafe {
         let pressed = raylib::ffi::IsKeyDown(key as i32);
-        vm.get_stack().push(Value::Bool(pressed));
+        vm.get_stack().push(Value::bool(pressed));
     }
+    Ok(())
 }
 
-pub fn draw_get_frame_time(vm: &mut Vm, arg_num: usize, _protected: bool) {
+pub fn draw_get_frame_time(vm: &mut Vm, arg_num: usize, _protected: bool) -> NativeResult {
     arity_assert!(1, arg_num);
-    let op_data = vm.gets_opaque() as *mut DrawCtx;
+    let op_data = vm.gets_opaque()? as *mut DrawCtx;
     let _ = vm.get_stack().pop();
     unsafe {
         let frame_time = raylib::ffi::GetFrameTime();
-        vm.get_stack().push(Value::Number(frame_time as f64));
+        vm.get_stack().push(Value::number(frame_time as f64));
     }
+    Ok(())
 }
 
-pub fn draw_render_blocks(vm: &mut Vm, arg_num: usize, _protected: bool) {
+pub fn draw_render_blocks(vm: &mut Vm, arg_num: usize, _protected: bool) -> NativeResult {
     arity_assert!(1, arg_num);
-    let op_data = vm.gets_opaque() as *mut DrawCtx;
+    let op_data = vm.gets_opaque()? as *mut DrawCtx;
     let _ = vm.get_stack().pop();
     unsafe {
         let len = (*op_data).block_map.len();
@@ -190,26 +206,29 @@ pub fn draw_render_blocks(vm: &mut Vm, arg_num: usize, _protected: bool) {
         raylib::ffi::DrawFPS(10, 10);
         raylib::ffi::EndDrawing();
     }
-    vm.get_stack().push(Value::Nil);
+    vm.get_stack().push(Value::NIL);
+    Ok(())
 }
 
-pub fn draw_destory(vm: &mut Vm, arg_num: usize, _protected: bool) {
+pub fn draw_destory(vm: &mut Vm, arg_num: usize, _protected: bool) -> NativeResult {
     arity_assert!(1, arg_num);
-    let op_data = vm.gets_opaque() as *mut DrawCtx;
+    let op_data = vm.gets_opaque()? as *mut DrawCtx;
     let _ = vm.get_stack().pop();
 
     unsafe {
         let _to_drop = Box::from_raw(op_data);
         raylib::ffi::CloseWindow();
     }
-    vm.get_stack().push(Value::Nil);
+    vm.get_stack().push(Value::NIL);
+    Ok(())
 }
 
-pub fn draw_random(vm: &mut Vm, arg_num: usize, _protected: bool) {
+pub fn draw_random(vm: &mut Vm, arg_num: usize, _protected: bool) -> NativeResult {
     arity_assert!(0, arg_num);
     let _ = vm.get_stack().pop();
     let v = unsafe { raylib::ffi::GetRandomValue(0, 65535) };
-    vm.get_stack().push(Value::Number((v as f64) / 65535.0));
+    vm.get_stack().push(Value::number((v as f64) / 65535.0));
+    Ok(())
 }
 pub fn module_export() -> (String, Vec<(String, Value)>) {
     let module_name = "draw".to_owned();
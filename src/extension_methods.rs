@@ -1,101 +1,145 @@
-use crate::{vm::Vm, Value};
+use crate::{
+    vm::{EvalError, Vm},
+    NativeResult, Unboxed, Value,
+};
 
 macro_rules! arity_assert {
     ($n:expr, $arg_num:expr) => {
         if $arg_num != $n {
-            panic!("arity check failed, passed {}, required {}", $n, $arg_num);
+            return Err(EvalError::CallError(format!(
+                "arity check failed, passed {}, required {}",
+                $arg_num, $n
+            )));
         }
     };
 }
 
 macro_rules! mf_entry {
     ($name:expr,$func:expr) => {
-        ($name.to_owned(), Value::NativeFunction($func as *mut u8))
+        ($name.to_owned(), Value::native_function($func as *mut u8))
     };
 }
 
-pub fn array_push(vm: &mut Vm, arg_num: usize, _protected: bool) {
+pub fn array_push(vm: &mut Vm, arg_num: usize, _protected: bool) -> NativeResult {
     arity_assert!(1, arg_num);
     let v = vm.get_stack().pop().unwrap();
     let _ = vm.get_stack().pop();
     // Array is hided under me
     let clct = vm.get_stack().pop().unwrap();
 
-    if let Value::Array(p_arr) = clct {
+    if let Unboxed::Array(p_arr) = clct.unbox() {
         unsafe {
             (*p_arr).array.push(v);
         }
     } else {
-        panic!("`array_push` can ONLY push to Array.");
+        return Err(EvalError::TypeError(
+            "`array_push` can ONLY push to Array.".to_owned(),
+        ));
     }
 
-    vm.get_stack().push(Value::Nil);
+    vm.get_stack().push(Value::NIL);
+    Ok(())
 }
 
-pub fn array_pop(vm: &mut Vm, arg_num: usize, _protected: bool) {
+pub fn array_pop(vm: &mut Vm, arg_num: usize, _protected: bool) -> NativeResult {
     arity_assert!(0, arg_num);
     let _ = vm.get_stack().pop();
     // Array is hided under me
     let clct = vm.get_stack().pop().unwrap();
 
-    if let Value::Array(p_arr) = clct {
+    if let Unboxed::Array(p_arr) = clct.unbox() {
         unsafe {
-            let v = (*p_arr).array.pop().unwrap();
-            vm.get_stack().push(v);
+            if let Some(v) = (*p_arr).array.pop() {
+                vm.get_stack().push(v);
+            } else {
+                return Err(EvalError::IndexOutOfBound(
+                    "`array_pop` on empty Array.".to_owned(),
+                ));
+            }
         }
     } else {
-        panic!("`array_push` can ONLY push to Array.");
+        return Err(EvalError::TypeError(
+            "`array_pop` can ONLY pop Array.".to_owned(),
+        ));
     }
+    Ok(())
 }
 
-pub fn array_pop_front(vm: &mut Vm, arg_num: usize, _protected: bool) {
+pub fn array_pop_front(vm: &mut Vm, arg_num: usize, _protected: bool) -> NativeResult {
     arity_assert!(0, arg_num);
     let _ = vm.get_stack().pop();
     // Array is hided under me
     let clct = vm.get_stack().pop().unwrap();
 
-    if let Value::Array(p_arr) = clct {
+    if let Unboxed::Array(p_arr) = clct.unbox() {
         unsafe {
+            if (*p_arr).array.is_empty() {
+                return Err(EvalError::IndexOutOfBound(
+                    "`array_pop_front` on empty Array.".to_owned(),
+                ));
+            }
             let v = (*p_arr).array.remove(0);
             vm.get_stack().push(v);
         }
     } else {
-        panic!("`array_pop_front` can ONLY pop Array.");
+        return Err(EvalError::TypeError(
+            "`array_pop_front` can ONLY pop Array.".to_owned(),
+        ));
     }
+    Ok(())
 }
 
-pub fn array_remove(vm: &mut Vm, arg_num: usize, _protected: bool) {
+pub fn array_remove(vm: &mut Vm, arg_num: usize, _protected: bool) -> NativeResult {
     arity_assert!(1, arg_num);
-    let idx = vm.gets_number();
+    let idx = vm.gets_number()?;
     let _ = vm.get_stack().pop();
     // Array is hided under me
     let clct = vm.get_stack().pop().unwrap();
 
-    if let Value::Array(p_arr) = clct {
+    if let Unboxed::Array(p_arr) = clct.unbox() {
         unsafe {
+            if idx < 0. || idx as usize >= (*p_arr).array.len() {
+                return Err(EvalError::IndexOutOfBound(format!(
+                    "`array_remove` index {} out of range, len: {}",
+                    idx,
+                    (*p_arr).array.len()
+                )));
+            }
             let v = (*p_arr).array.remove(idx as usize);
             vm.get_stack().push(v);
         }
     } else {
-        panic!("`array_pop_front` can ONLY remove from Array.");
+        return Err(EvalError::TypeError(
+            "`array_remove` can ONLY remove from Array.".to_owned(),
+        ));
     }
+    Ok(())
 }
 
-pub fn array_insert(vm: &mut Vm, arg_num: usize, _protected: bool) {
+pub fn array_insert(vm: &mut Vm, arg_num: usize, _protected: bool) -> NativeResult {
     arity_assert!(2, arg_num);
     let v = vm.get_stack().pop().unwrap();
-    let idx = vm.gets_number();
+    let idx = vm.gets_number()?;
     let _ = vm.get_stack().pop();
     // Array is hided under me
     let clct = vm.get_stack().pop().unwrap();
 
-    if let Value::Array(p_arr) = clct {
+    if let Unboxed::Array(p_arr) = clct.unbox() {
         unsafe {
+            if idx < 0. || idx as usize > (*p_arr).array.len() {
+                return Err(EvalError::IndexOutOfBound(format!(
+                    "`array_insert` index {} out of range, len: {}",
+                    idx,
+                    (*p_arr).array.len()
+                )));
+            }
             (*p_arr).array.insert(idx as usize, v);
         }
     } else {
-        panic!("`array_inser` can ONLY insert into Array.");
+        return Err(EvalError::TypeError(
+            "`array_insert` can ONLY insert into Array.".to_owned(),
+        ));
     }
-    vm.get_stack().push(Value::Nil);
+    vm.get_stack().push(Value::NIL);
+    Ok(())
 }
-
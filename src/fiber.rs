@@ -1,14 +1,17 @@
 use std::ptr::null_mut;
 
 use crate::{
-    vm::{CallFrame, Vm},
-    Fiber, FiberState, Value,
+    vm::{CallFrame, EvalError, Vm},
+    Fiber, FiberState, NativeResult, Unboxed, Value,
 };
 
 macro_rules! arity_assert {
     ($n:expr, $arg_num:expr) => {
         if $arg_num != $n {
-            panic!("arity check failed, passed {}, required {}", $n, $arg_num);
+            return Err(EvalError::CallError(format!(
+                "arity check failed, passed {}, required {}",
+                $arg_num, $n
+            )));
         }
     };
 }
@@ -16,7 +19,12 @@ macro_rules! arity_assert {
 // I don't know if fiber should be allowed to refer to value on the stack.
 // for now the behavior is undefined if a closure refering to unclosed upvalue
 // is called in another fiber.
-pub fn sloth_fiber_create(vm: &mut Vm, arg_num: usize, _protected: bool) {
+pub fn sloth_fiber_create(vm: &mut Vm, arg_num: usize, _protected: bool) -> NativeResult {
+    if arg_num == 0 {
+        return Err(EvalError::CallError(
+            "fiber.create take at least 1 argument: f: Closure.".to_owned(),
+        ));
+    }
     let mut args = Vec::new();
     let arg_cnt = arg_num - 1;
     for _ in 0..arg_cnt {
@@ -25,10 +33,12 @@ pub fn sloth_fiber_create(vm: &mut Vm, arg_num: usize, _protected: bool) {
     args.reverse();
     let closure = vm.get_stack().pop().unwrap();
     let _ = vm.get_stack().pop();
-    let p_closure = if let Value::Closure(p_closure) = closure {
+    let p_closure = if let Unboxed::Closure(p_closure) = closure.unbox() {
         p_closure
     } else {
-        panic!("creating Fiber with something not callable.");
+        return Err(EvalError::TypeError(
+            "creating Fiber with something not callable.".to_owned(),
+        ));
     };
 
     unsafe {
@@ -36,7 +46,10 @@ pub fn sloth_fiber_create(vm: &mut Vm, arg_num: usize, _protected: bool) {
             match &(**upv_obj).value {
                 crate::UpValue::Closed(_value) => {}
                 crate::UpValue::Ref(_) => {
-                    panic!("closure refering to unclosed UpValue CANNOT be used to create Fiber.");
+                    return Err(EvalError::CallError(
+                        "closure refering to unclosed UpValue CANNOT be used to create Fiber."
+                            .to_owned(),
+                    ));
                 }
             }
         }
@@ -49,96 +62,120 @@ pub fn sloth_fiber_create(vm: &mut Vm, arg_num: usize, _protected: bool) {
     if chunk.parameter_num != arg_cnt {
         if chunk.parameter_num < arg_cnt && chunk.is_va {
             for idx in chunk.parameter_num..args.len() {
-                packed_va_list.push(args[idx].clone());
+                packed_va_list.push(args[idx]);
             }
         } else {
-            panic!("cannot create fiber with closure and incorrect arglist.");
+            return Err(EvalError::CallError(
+                "cannot create fiber with closure and incorrect arglist.".to_owned(),
+            ));
         }
     }
     // dbg!(chunk.parameter_num);
     // dbg!(chunk.num_locals);
     for idx in 0..chunk.parameter_num {
-        stack.push(args[idx].clone());
+        stack.push(args[idx]);
     }
     for _ in 0..(chunk.num_locals - chunk.parameter_num) {
-        stack.push(Value::Nil);
+        stack.push(Value::NIL);
     }
     // initialize fiber
     let mut b_fiber = Box::new(Fiber {
         marked: false,
         call_frames: vec![CallFrame::new(0, p_closure, packed_va_list)],
         stack,
+        open_upvalues: Vec::new(),
         state: crate::FiberState::Initial,
         prev: null_mut() as *mut Fiber,
+        protected: false,
     });
     let p_fiber = b_fiber.as_mut() as *mut Fiber;
     vm.add_object(b_fiber);
-    vm.get_stack().push(Value::Fiber(p_fiber));
+    vm.get_stack().push(Value::fiber(p_fiber));
+    Ok(())
 }
 
-pub fn sloth_fiber_resume(vm: &mut Vm, arg_num: usize, _protected: bool) {
+pub fn sloth_fiber_resume(vm: &mut Vm, arg_num: usize, protected: bool) -> NativeResult {
+    if arg_num != 1 && arg_num != 2 {
+        return Err(EvalError::CallError(
+            "fiber.resume take 1 or 2 arguments: f: Fiber, val.".to_owned(),
+        ));
+    }
     let pass_val = if arg_num == 2 {
         vm.get_stack().pop().unwrap()
     } else {
-        Value::Nil
+        Value::NIL
     };
-    let fiber = if let Value::Fiber(f) = vm.get_stack().pop().unwrap() {
+    let fiber = if let Unboxed::Fiber(f) = vm.get_stack().pop().unwrap().unbox() {
         f
     } else {
-        panic!("can only resume Fiber");
+        return Err(EvalError::TypeError("can only resume Fiber".to_owned()));
     };
     let _ = vm.get_stack().pop();
     unsafe {
         if (*fiber).state != FiberState::Paused && (*fiber).state != FiberState::Initial {
-            panic!("ONLY Fiber in Paused Or Initial State can be resume.");
+            return Err(EvalError::CallError(
+                "ONLY Fiber in Paused Or Initial State can be resume.".to_owned(),
+            ));
         }
         (*vm.get_current_fiber()).state = FiberState::Waiting;
         if (*fiber).state == FiberState::Paused {
             (*fiber).stack.push(pass_val);
         }
         (*fiber).prev = vm.get_current_fiber();
+        (*fiber).protected = protected;
         if (*fiber).state == FiberState::Initial {
             vm.fiber_changed = true;
         }
         (*fiber).state = FiberState::Running;
         vm.set_fiber(fiber);
     }
+    Ok(())
 }
 
-pub fn sloth_fiber_yield(vm: &mut Vm, arg_num: usize, _protected: bool) {
+pub fn sloth_fiber_yield(vm: &mut Vm, arg_num: usize, _protected: bool) -> NativeResult {
+    if arg_num > 1 {
+        return Err(EvalError::CallError(
+            "fiber.yield take at most 1 argument.".to_owned(),
+        ));
+    }
     let pass_val = if arg_num == 1 {
         vm.get_stack().pop().unwrap()
     } else {
-        Value::Nil
+        Value::NIL
     };
     let _ = vm.get_stack().pop();
     unsafe {
-        (*vm.get_current_fiber()).state = FiberState::Paused;
         let prev = (*vm.get_current_fiber()).prev;
         if prev == null_mut() {
-            panic!("yield to nowhere");
+            return Err(EvalError::CallError("yield to nowhere".to_owned()));
         }
+        (*vm.get_current_fiber()).state = FiberState::Paused;
         (*prev).stack.push(pass_val);
         (*prev).state = FiberState::Running;
         vm.set_fiber(prev);
     }
+    Ok(())
 }
 
-pub fn sloth_fiber_transfer(vm: &mut Vm, arg_num: usize, _protected: bool) {
-    arity_assert!(0, arg_num);
-    let fiber = if let Value::Fiber(f) = vm.get_stack().pop().unwrap() {
+pub fn sloth_fiber_transfer(vm: &mut Vm, arg_num: usize, _protected: bool) -> NativeResult {
+    arity_assert!(1, arg_num);
+    let fiber = if let Unboxed::Fiber(f) = vm.get_stack().pop().unwrap().unbox() {
         f
     } else {
-        panic!("can only resume Fiber");
+        return Err(EvalError::TypeError(
+            "can only transfer to Fiber".to_owned(),
+        ));
     };
     let _ = vm.get_stack().pop();
     unsafe {
         if (*fiber).state != FiberState::Paused && (*fiber).state != FiberState::Initial {
-            panic!("ONLY Fiber in Paused Or Initial State can be transfered to.");
+            return Err(EvalError::CallError(
+                "ONLY Fiber in Paused Or Initial State can be transfered to.".to_owned(),
+            ));
         }
         (*vm.get_current_fiber()).state = FiberState::Paused;
         if (*fiber).state == FiberState::Paused {
-            (*fiber).stack.push(Value::Nil);
+            (*fiber).stack.push(Value::NIL);
         }
         if (*fiber).state == FiberState::Initial {
             vm.fiber_changed = true;
@@ -146,56 +183,58 @@ pub fn sloth_fiber_transfer(vm: &mut Vm, arg_num: usize, _protected: bool) {
         (*fiber).state = FiberState::Running;
         vm.set_fiber(fiber);
     }
+    Ok(())
 }
-pub fn sloth_fiber_set_error(vm: &mut Vm, arg_num: usize, _protected: bool) {
+pub fn sloth_fiber_set_error(vm: &mut Vm, arg_num: usize, _protected: bool) -> NativeResult {
+    arity_assert!(0, arg_num);
     let _ = vm.get_stack().pop();
     unsafe {
-        (*vm.get_current_fiber()).state = FiberState::Error;
         let prev = (*vm.get_current_fiber()).prev;
         if prev == null_mut() {
-            eprintln!("stack: {:?}", vm.get_stack());
-            eprintln!("call_frame: {:?}", vm.get_call_frame());
-            let pc = vm.get_call_frame().pc;
-            let instr = (*(*vm.get_call_frame().closure).chunk).bytecodes[pc];
-            eprintln!("instr: {:?}", instr);
-            panic!("fiber error occured but nowhere to go.");
+            return Err(EvalError::Error(
+                "fiber error occured but nowhere to go.".to_owned(),
+            ));
         }
+        (*vm.get_current_fiber()).state = FiberState::Error;
         (*prev).state = FiberState::Running;
         vm.set_fiber(prev);
-        vm.get_stack().push(Value::Nil);
+        vm.get_stack().push(Value::NIL);
     }
+    Ok(())
 }
 
-pub fn sloth_fiber_check(vm: &mut Vm, arg_num: usize, _protected: bool) {
+pub fn sloth_fiber_check(vm: &mut Vm, arg_num: usize, _protected: bool) -> NativeResult {
     arity_assert!(1, arg_num);
-    let fiber = if let Value::Fiber(f) = vm.get_stack().pop().unwrap() {
+    let fiber = if let Unboxed::Fiber(f) = vm.get_stack().pop().unwrap().unbox() {
         f
     } else {
-        panic!("not a Fiber");
+        return Err(EvalError::TypeError("not a Fiber".to_owned()));
     };
     let _ = vm.get_stack().pop();
     unsafe {
         let ok = (*fiber).state != FiberState::Error;
-        vm.get_stack().push(Value::Bool(ok));
+        vm.get_stack().push(Value::bool(ok));
     }
+    Ok(())
 }
 
-pub fn sloth_fiber_resumable(vm: &mut Vm, arg_num: usize, _protected: bool) {
+pub fn sloth_fiber_resumable(vm: &mut Vm, arg_num: usize, _protected: bool) -> NativeResult {
     arity_assert!(1, arg_num);
-    let fiber = if let Value::Fiber(f) = vm.get_stack().pop().unwrap() {
+    let fiber = if let Unboxed::Fiber(f) = vm.get_stack().pop().unwrap().unbox() {
         f
     } else {
-        panic!("not a Fiber");
+        return Err(EvalError::TypeError("not a Fiber".to_owned()));
     };
     let _ = vm.get_stack().pop();
     unsafe {
         let ok = (*fiber).state == FiberState::Paused || (*fiber).state == FiberState::Initial;
-        vm.get_stack().push(Value::Bool(ok));
+        vm.get_stack().push(Value::bool(ok));
     }
+    Ok(())
 }
 macro_rules! mf_entry {
     ($name:expr,$func:expr) => {
-        ($name.to_owned(), Value::NativeFunction($func as *mut u8))
+        ($name.to_owned(), Value::native_function($func as *mut u8))
     };
 }
 pub fn module_export() -> (String, Vec<(String, Value)>) {
@@ -0,0 +1,815 @@
+use std::fmt::Write;
+
+use crate::ast::*;
+use crate::compiler::parser::PrattPrecedence;
+use crate::{parse_program, Diagnostic};
+
+const INDENT: &str = "    ";
+
+type Pos = (usize, usize);
+
+/// `prog` reprinted in the canonical layout, comments are kept.
+/// a statement takes one line, blocks are indented by four spaces and
+/// at most one blank line from the source is kept between statements.
+/// brackets of calls, arrays and dicts are broken one item per line
+/// when their first item is on a line of its own in the source.
+pub fn format_program(prog: &str, file: &str) -> Result<String, Diagnostic> {
+    let program = parse_program(prog, file)?;
+    let mut printer = Printer {
+        src: prog.lines().collect(),
+        out: String::new(),
+        indent: 0,
+        comments: program.comments,
+        next_comment: 0,
+        line: 0,
+        fresh: true,
+    };
+    printer.items(
+        &program.stmts,
+        (usize::MAX, usize::MAX),
+        "",
+        stmt_bounds,
+        Printer::stmt,
+    );
+    let mut out = printer.out;
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    // never hand out a program that does not parse
+    if let Err(diag) = parse_program(&out, file) {
+        return Err(
+            Diagnostic::error((0, 0), "formatted program does not parse")
+                .with_file(file)
+                .with_note(diag.message),
+        );
+    }
+    Ok(out)
+}
+
+struct Printer<'a> {
+    /// lines of the source
+    src: Vec<&'a str>,
+    out: String,
+    indent: usize,
+    comments: Vec<Comment>,
+    /// first comment not printed yet
+    next_comment: usize,
+    /// source line of what was printed last
+    line: usize,
+    /// nothing printed since a bracket was opened
+    fresh: bool,
+}
+
+fn stmt_bounds(stmt: &Stmt) -> (Pos, Pos) {
+    (stmt.start(), stmt.end())
+}
+
+fn expr_bounds(expr: &Expr) -> (Pos, Pos) {
+    (expr.start(), expr.end())
+}
+
+fn entry_bounds((key, value): &(Expr, Expr)) -> (Pos, Pos) {
+    (key.start(), value.end())
+}
+
+fn arm_bounds(arm: &MatchArm) -> (Pos, Pos) {
+    (arm.pattern.start(), arm.body.rbrace)
+}
+
+fn method_bounds(method: &FuncDecl) -> (Pos, Pos) {
+    (method.name.span.pos, method.func.body.rbrace)
+}
+
+impl Printer<'_> {
+    /// start a line for what is on `line` of the source,
+    /// one blank line is kept if the source has some
+    fn begin_line(&mut self, line: usize) {
+        if !self.out.is_empty() {
+            self.out.push('\n');
+            if !self.fresh && line > self.line + 1 {
+                self.out.push('\n');
+            }
+        }
+        self.fresh = false;
+        self.out.push_str(&INDENT.repeat(self.indent));
+    }
+
+    /// comments before `before`, on lines of their own
+    fn leading(&mut self, before: Pos) {
+        while let Some(comment) = self.comments.get(self.next_comment) {
+            if comment.span.pos >= before {
+                break;
+            }
+            let (line, end_line) = (comment.span.pos.0, comment.end_line());
+            self.begin_line(line);
+            self.out.push_str(&self.comments[self.next_comment].text);
+            self.line = end_line;
+            self.next_comment += 1;
+        }
+    }
+
+    /// comments before `before` that start on the line printed last,
+    /// and those left inside an expression, at the end of the line
+    fn trailing(&mut self, before: Pos) {
+        while let Some(comment) = self.comments.get(self.next_comment) {
+            if comment.span.pos.0 > self.line || comment.span.pos >= before {
+                break;
+            }
+            self.line = comment.end_line();
+            self.out.push(' ');
+            self.out.push_str(&self.comments[self.next_comment].text);
+            self.next_comment += 1;
+        }
+    }
+
+    /// comments before `before` left between two tokens, where the printer is
+    fn inline(&mut self, before: Pos) {
+        while self.comment_before(before) {
+            self.inline_comment();
+        }
+    }
+
+    /// the next comment, where the printer is.
+    /// a line comment ends the line, what follows goes on the next one
+    fn inline_comment(&mut self) {
+        let comment = &self.comments[self.next_comment];
+        self.line = self.line.max(comment.end_line());
+        let before_token = self.out.ends_with([' ', '(', '[', '|']);
+        if !before_token {
+            self.out.push(' ');
+        }
+        self.out.push_str(&comment.text);
+        if comment.text.starts_with("//") {
+            self.out.push('\n');
+            self.out.push_str(&INDENT.repeat(self.indent + 1));
+        } else if before_token {
+            self.out.push(' ');
+        }
+        self.next_comment += 1;
+    }
+
+    /// `s` with its leading spaces, but after a space `inline` left
+    fn token(&mut self, s: &str) {
+        if self.out.ends_with(' ') {
+            self.out.push_str(s.trim_start());
+        } else {
+            self.out.push_str(s);
+        }
+    }
+
+    /// `sep` between two items on a line, comments before `next` stay on
+    /// their side of it. line comments go after it to keep it on their line
+    fn item_sep(&mut self, sep: &str, next: Pos) {
+        while let Some(comment) = self.comments.get(self.next_comment) {
+            if comment.span.pos >= next
+                || comment.text.starts_with("//")
+                || self.follows(sep, comment.span.pos)
+            {
+                break;
+            }
+            self.inline_comment();
+        }
+        self.token(sep);
+        self.inline(next);
+    }
+
+    /// whether `sep` is the last thing in the source before `pos`
+    fn follows(&self, sep: &str, (line, col): Pos) -> bool {
+        let sep = sep.trim();
+        let mut text: String = self.src[line - 1].chars().take(col - 1).collect();
+        for line in (0..line - 1).rev() {
+            if !text.trim_end().is_empty() {
+                break;
+            }
+            text = self.src[line].to_owned();
+        }
+        !sep.is_empty() && text.trim_end().ends_with(sep)
+    }
+
+    fn comment_before(&self, pos: Pos) -> bool {
+        self.comments
+            .get(self.next_comment)
+            .is_some_and(|comment| comment.span.pos < pos)
+    }
+
+    /// `items` one per line, followed by `sep` but the last one
+    fn items<T>(
+        &mut self,
+        items: &[T],
+        close: Pos,
+        sep: &str,
+        bounds: impl Fn(&T) -> (Pos, Pos),
+        print: impl Fn(&mut Self, &T),
+    ) {
+        for (i, item) in items.iter().enumerate() {
+            let (start, end) = bounds(item);
+            self.leading(start);
+            self.begin_line(start.0);
+            print(self, item);
+            if i + 1 < items.len() {
+                self.out.push_str(sep);
+            }
+            self.line = end.0;
+            self.trailing(items.get(i + 1).map_or(close, |next| bounds(next).0));
+        }
+        self.leading(close);
+    }
+
+    /// `items` indented between brackets written by the caller,
+    /// `open` and `close` are where the brackets are in the source
+    fn list<T>(
+        &mut self,
+        open: Pos,
+        items: &[T],
+        close: Pos,
+        sep: &str,
+        bounds: impl Fn(&T) -> (Pos, Pos),
+        print: impl Fn(&mut Self, &T),
+    ) {
+        self.line = open.0;
+        self.trailing(items.first().map_or(close, |item| bounds(item).0));
+        self.indent += 1;
+        self.fresh = true;
+        self.items(items, close, sep, bounds, print);
+        self.indent -= 1;
+        self.out.push('\n');
+        self.out.push_str(&INDENT.repeat(self.indent));
+        self.line = close.0;
+    }
+
+    fn block(&mut self, block: &Block) {
+        if block.stmts.is_empty() && !self.comment_before(block.rbrace) {
+            self.out.push_str("{}");
+            return;
+        }
+        self.out.push('{');
+        self.list(
+            block.lbrace,
+            &block.stmts,
+            block.rbrace,
+            "",
+            stmt_bounds,
+            Self::stmt,
+        );
+        self.out.push('}');
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Var { name, init, semi } => {
+                self.out.push_str("var ");
+                self.out.push_str(&name.name);
+                if let Some(init) = init {
+                    self.item_sep(" = ", init.start());
+                    self.expr(init);
+                }
+                self.inline(*semi);
+                self.out.push(';');
+            }
+            Stmt::Assign {
+                target,
+                op,
+                value,
+                span,
+                semi,
+            } => {
+                self.expr(target);
+                self.inline(span.pos);
+                if let Some(op) = op {
+                    self.token(binary_symbol(*op).trim_end());
+                    self.out.push_str("= ");
+                } else {
+                    self.token(" = ");
+                }
+                self.inline(value.start());
+                self.expr(value);
+                self.inline(*semi);
+                self.out.push(';');
+            }
+            Stmt::Expr { expr, semi } => {
+                self.expr(expr);
+                self.inline(*semi);
+                self.out.push(';');
+            }
+            Stmt::Block(block) => self.block(block),
+            Stmt::If {
+                cond,
+                then,
+                otherwise,
+                rparen,
+                then_end,
+                ..
+            } => {
+                self.out.push_str("if (");
+                self.inline(cond.start());
+                self.expr(cond);
+                self.inline(*rparen);
+                self.out.push(')');
+                self.item_sep(" ", then.start());
+                self.stmt(then);
+                if let Some(otherwise) = otherwise {
+                    self.inline(*then_end);
+                    self.token(" else ");
+                    self.inline(otherwise.start());
+                    self.stmt(otherwise);
+                }
+            }
+            Stmt::While {
+                cond, body, rparen, ..
+            } => {
+                self.out.push_str("while (");
+                self.inline(cond.start());
+                self.expr(cond);
+                self.inline(*rparen);
+                self.out.push(')');
+                self.item_sep(" ", body.lbrace);
+                self.block(body);
+            }
+            Stmt::For {
+                var, iter, body, ..
+            } => {
+                self.out.push_str("for (var ");
+                self.out.push_str(&var.name);
+                self.item_sep(": ", iter.start());
+                self.expr(iter);
+                self.inline(body.lbrace);
+                self.token(") ");
+                self.block(body);
+            }
+            Stmt::Func(decl) => self.func_decl(decl),
+            Stmt::Class(decl) => {
+                self.out.push_str("class ");
+                self.out.push_str(&decl.name.name);
+                if let Some(superclass) = &decl.superclass {
+                    self.out.push_str(": ");
+                    self.out.push_str(&superclass.name);
+                }
+                if decl.methods.is_empty() && !self.comment_before(decl.rbrace) {
+                    self.out.push_str(" {}");
+                    return;
+                }
+                self.out.push_str(" {");
+                self.list(
+                    decl.name.span.pos,
+                    &decl.methods,
+                    decl.rbrace,
+                    "",
+                    method_bounds,
+                    Self::func_decl,
+                );
+                self.out.push('}');
+            }
+            Stmt::Return { value, semi, .. } => self.keyword_stmt("return", value, *semi),
+            Stmt::Except { value, semi, .. } => self.keyword_stmt("except", value, *semi),
+            Stmt::TryCatch {
+                body,
+                catch,
+                var,
+                handler,
+                ..
+            } => {
+                self.out.push_str("try");
+                self.item_sep(" ", body.lbrace);
+                self.block(body);
+                self.inline(catch.pos);
+                self.token(" catch ");
+                if let Some(var) = var {
+                    self.out.push('(');
+                    self.inline(var.span.pos);
+                    self.out.push_str(&var.name);
+                    self.inline(handler.lbrace);
+                    self.token(") ");
+                } else {
+                    self.inline(handler.lbrace);
+                }
+                self.block(handler);
+            }
+            Stmt::Match {
+                subject,
+                arms,
+                lbrace,
+                rbrace,
+            } => {
+                self.out.push_str("match (");
+                self.inline(subject.start());
+                self.expr(subject);
+                self.inline(*lbrace);
+                self.out.push(')');
+                if arms.is_empty() && !self.comment_before(*rbrace) {
+                    self.out.push_str(" {}");
+                    return;
+                }
+                self.out.push_str(" {");
+                self.list(*lbrace, arms, *rbrace, "", arm_bounds, Self::arm);
+                self.out.push('}');
+            }
+            Stmt::Break(_) => self.out.push_str("break;"),
+            Stmt::Continue(_) => self.out.push_str("continue;"),
+        }
+    }
+
+    fn arm(&mut self, arm: &MatchArm) {
+        self.pattern(&arm.pattern);
+        if let Some(guard) = &arm.guard {
+            self.out.push_str(" if ");
+            self.expr(guard);
+        }
+        self.out.push_str(" => ");
+        self.block(&arm.body);
+    }
+
+    /// on one line, a field bound to its own name is written alone
+    fn pattern(&mut self, pattern: &Pattern) {
+        match pattern {
+            Pattern::Wildcard(_) => self.out.push('_'),
+            Pattern::Bind(name) => self.out.push_str(&name.name),
+            Pattern::Literal(literal) => self.expr(literal),
+            Pattern::Range { lo, hi, closed } => {
+                self.expr(lo);
+                self.out.push_str(if *closed { "..=" } else { ".." });
+                self.expr(hi);
+            }
+            Pattern::Array { items, rest, .. } => {
+                self.out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.pattern(item);
+                }
+                if let Some(rest) = rest {
+                    if !items.is_empty() {
+                        self.out.push_str(", ");
+                    }
+                    self.out.push_str("...");
+                    if let Pattern::Bind(name) = rest.as_ref() {
+                        self.out.push_str(&name.name);
+                    }
+                }
+                self.out.push(']');
+            }
+            Pattern::Dict { entries, .. } => {
+                self.out.push_str("@(");
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.expr(key);
+                    self.out.push_str(": ");
+                    self.pattern(value);
+                }
+                self.out.push(')');
+            }
+            Pattern::Class { class, fields, .. } => {
+                self.out.push_str(&class.name);
+                self.out.push('(');
+                for (i, (field, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        self.out.push_str(", ");
+                    }
+                    self.out.push_str(&field.name);
+                    if !matches!(value, Pattern::Bind(name) if name.name == field.name) {
+                        self.out.push_str(": ");
+                        self.pattern(value);
+                    }
+                }
+                self.out.push(')');
+            }
+            Pattern::Is { binding, class } => {
+                self.pattern(binding);
+                self.out.push_str(" is ");
+                self.out.push_str(&class.name);
+            }
+        }
+    }
+
+    /// `return` or `except`
+    fn keyword_stmt(&mut self, keyword: &str, value: &Option<Expr>, semi: Pos) {
+        self.out.push_str(keyword);
+        if let Some(value) = value {
+            self.item_sep(" ", value.start());
+            self.expr(value);
+        }
+        self.inline(semi);
+        self.out.push(';');
+    }
+
+    fn func_decl(&mut self, decl: &FuncDecl) {
+        self.out.push_str("func ");
+        self.out.push_str(&decl.name.name);
+        self.out.push('(');
+        self.params(&decl.func);
+        self.out.push(')');
+        self.item_sep(" ", decl.func.body.lbrace);
+        self.block(&decl.func.body);
+    }
+
+    /// comments among the parameters stay next to them, up to the closing `)` or `|`
+    fn params(&mut self, func: &Function) {
+        for (i, param) in func.params.iter().enumerate() {
+            if i > 0 {
+                self.item_sep(", ", param.span.pos);
+            } else {
+                self.inline(param.span.pos);
+            }
+            self.out.push_str(&param.name);
+        }
+        if func.variadic {
+            if !func.params.is_empty() {
+                self.token(", ");
+            }
+            self.out.push_str("...");
+        }
+        self.inline(func.close);
+    }
+
+    /// `items` between `open` and `close`, broken one per line
+    /// if the first one does not start on the line of `open_pos`
+    fn seq<T>(
+        &mut self,
+        (open, open_pos): (&str, Pos),
+        items: &[T],
+        (close, close_pos): (&str, Pos),
+        bounds: impl Fn(&T) -> (Pos, Pos),
+        print: impl Fn(&mut Self, &T),
+    ) {
+        self.out.push_str(open);
+        match items.first() {
+            Some(first) if bounds(first).0 .0 > open_pos.0 => {
+                self.list(open_pos, items, close_pos, ",", bounds, print);
+            }
+            _ => {
+                for (i, item) in items.iter().enumerate() {
+                    let start = bounds(item).0;
+                    if i > 0 {
+                        self.item_sep(", ", start);
+                    } else {
+                        self.inline(start);
+                    }
+                    print(self, item);
+                }
+                self.inline(close_pos);
+            }
+        }
+        self.out.push_str(close);
+    }
+
+    /// `expr`, in parentheses if `paren`
+    fn operand(&mut self, expr: &Expr, paren: bool) {
+        if paren {
+            self.out.push('(');
+        }
+        self.expr(expr);
+        if paren {
+            self.out.push(')');
+        }
+    }
+
+    /// target of a call, an index or a field
+    fn postfix_target(&mut self, target: &Expr) {
+        // `1.x` would be scanned as a number
+        let paren =
+            precedence(target) < PrattPrecedence::Call || matches!(target, Expr::Number(..));
+        self.operand(target, paren);
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Name(ident) => self.out.push_str(&ident.name),
+            Expr::Super { method, .. } => {
+                self.out.push_str("super.");
+                self.out.push_str(&method.name);
+            }
+            Expr::Number(x, _) => {
+                let _ = write!(self.out, "{x}");
+            }
+            Expr::String(s, _) => {
+                self.out.push('"');
+                escape(&mut self.out, s);
+                self.out.push('"');
+            }
+            Expr::Interpolation(parts) => {
+                self.out.push('"');
+                for part in parts {
+                    match part {
+                        StrPart::Str(s, _) => escape(&mut self.out, s),
+                        StrPart::Expr { expr, .. } => {
+                            self.out.push_str("${");
+                            self.expr(expr);
+                            self.out.push('}');
+                        }
+                    }
+                }
+                self.out.push('"');
+            }
+            Expr::Bool(b, _) => self.out.push_str(if *b { "true" } else { "false" }),
+            Expr::Nil(_) => self.out.push_str("nil"),
+            Expr::Array { items, span, close } => {
+                self.seq(
+                    ("[", span.pos),
+                    items,
+                    ("]", *close),
+                    expr_bounds,
+                    Self::expr,
+                );
+            }
+            Expr::Dict {
+                entries,
+                span,
+                close,
+            } => {
+                self.seq(
+                    ("@(", span.pos),
+                    entries,
+                    (")", *close),
+                    entry_bounds,
+                    |printer, (key, value)| {
+                        printer.expr(key);
+                        printer.out.push_str(": ");
+                        printer.expr(value);
+                    },
+                );
+            }
+            Expr::Unary { op, operand, .. } => {
+                self.out.push_str(match op {
+                    UnaryOp::Neg => "-",
+                    UnaryOp::Not => "not ",
+                });
+                self.operand(operand, precedence(operand) <= PrattPrecedence::Unary);
+            }
+            Expr::Binary {
+                op, lhs, rhs, span, ..
+            } => {
+                let prec = binary_precedence(*op);
+                self.operand(lhs, precedence(lhs) < prec);
+                self.inline(span.pos);
+                self.token(binary_symbol(*op));
+                self.inline(rhs.start());
+                self.operand(rhs, precedence(rhs) <= prec);
+            }
+            Expr::Call {
+                callee,
+                args,
+                span,
+                close,
+            } => {
+                self.postfix_target(callee);
+                self.seq(
+                    ("(", span.pos),
+                    args,
+                    (")", *close),
+                    expr_bounds,
+                    Self::expr,
+                );
+            }
+            Expr::Index {
+                target,
+                index,
+                optional,
+                ..
+            } => {
+                self.postfix_target(target);
+                self.out.push_str(if *optional { "?[" } else { "[" });
+                self.expr(index);
+                self.out.push(']');
+            }
+            Expr::Field {
+                target,
+                name,
+                optional,
+                ..
+            } => {
+                self.postfix_target(target);
+                self.out.push_str(if *optional { "?." } else { "." });
+                self.out.push_str(&name.name);
+            }
+            Expr::Conditional {
+                cond,
+                then,
+                otherwise,
+                ..
+            } => {
+                self.operand(cond, precedence(cond) <= PrattPrecedence::Ternary);
+                self.out.push_str(" ? ");
+                self.expr(then);
+                self.out.push_str(" : ");
+                self.operand(otherwise, precedence(otherwise) < PrattPrecedence::Ternary);
+            }
+            Expr::Try { call, .. } => {
+                self.out.push_str("try ");
+                self.expr(call);
+            }
+            Expr::Lambda { func, .. } => {
+                self.out.push('|');
+                self.params(func);
+                self.out.push('|');
+                self.item_sep(" ", func.body.lbrace);
+                if self.inline_body(&func.body) {
+                    self.inline_block(&func.body);
+                } else {
+                    self.block(&func.body);
+                }
+            }
+        }
+    }
+
+    /// lambdas written on one line stay on one line,
+    /// unless there are comments or blocks in them
+    fn inline_body(&self, body: &Block) -> bool {
+        let simple = body.stmts.iter().all(|stmt| {
+            matches!(
+                stmt,
+                Stmt::Var { .. }
+                    | Stmt::Assign { .. }
+                    | Stmt::Expr { .. }
+                    | Stmt::Return { .. }
+                    | Stmt::Except { .. }
+                    | Stmt::Break(_)
+                    | Stmt::Continue(_)
+            )
+        });
+        let commented = self.comments[self.next_comment..]
+            .iter()
+            .take_while(|comment| comment.span.pos < body.rbrace)
+            .any(|comment| comment.span.pos > body.lbrace);
+        body.lbrace.0 == body.rbrace.0 && simple && !commented
+    }
+
+    fn inline_block(&mut self, body: &Block) {
+        if body.stmts.is_empty() {
+            self.out.push_str("{}");
+            return;
+        }
+        self.out.push_str("{ ");
+        for stmt in body.stmts.iter() {
+            self.stmt(stmt);
+            self.out.push(' ');
+        }
+        self.out.push('}');
+    }
+}
+
+/// how tight `expr` binds, operands binding looser are put in parentheses
+fn precedence(expr: &Expr) -> PrattPrecedence {
+    match expr {
+        Expr::Binary { op, .. } => binary_precedence(*op),
+        Expr::Unary { .. } | Expr::Try { .. } | Expr::Lambda { .. } => PrattPrecedence::Unary,
+        Expr::Call { .. } | Expr::Index { .. } | Expr::Field { .. } => PrattPrecedence::Call,
+        Expr::Conditional { .. } => PrattPrecedence::Ternary,
+        _ => PrattPrecedence::Primary,
+    }
+}
+
+fn binary_precedence(op: BinaryOp) -> PrattPrecedence {
+    match op {
+        BinaryOp::Add | BinaryOp::Sub => PrattPrecedence::Term,
+        BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => PrattPrecedence::Factor,
+        BinaryOp::Lt | BinaryOp::Gt | BinaryOp::Le | BinaryOp::Ge => PrattPrecedence::Cmp,
+        BinaryOp::Eq | BinaryOp::Ne => PrattPrecedence::Equal,
+        BinaryOp::And => PrattPrecedence::And,
+        BinaryOp::Or => PrattPrecedence::Or,
+        BinaryOp::Range | BinaryOp::RangeClosed => PrattPrecedence::Range,
+        BinaryOp::Is => PrattPrecedence::IsOp,
+        BinaryOp::Coalesce => PrattPrecedence::Coalesce,
+        BinaryOp::Pipe => PrattPrecedence::PipeOp,
+    }
+}
+
+/// with the spaces around it, ranges have none
+fn binary_symbol(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add => " + ",
+        BinaryOp::Sub => " - ",
+        BinaryOp::Mul => " * ",
+        BinaryOp::Div => " / ",
+        BinaryOp::Mod => " % ",
+        BinaryOp::Lt => " < ",
+        BinaryOp::Gt => " > ",
+        BinaryOp::Le => " <= ",
+        BinaryOp::Ge => " >= ",
+        BinaryOp::Eq => " == ",
+        BinaryOp::Ne => " != ",
+        BinaryOp::And => " and ",
+        BinaryOp::Or => " or ",
+        BinaryOp::Range => "..",
+        BinaryOp::RangeClosed => "..=",
+        BinaryOp::Pipe => " |> ",
+        BinaryOp::Is => " is ",
+        BinaryOp::Coalesce => " ?? ",
+    }
+}
+
+/// `s` as the inside of a string literal
+fn escape(out: &mut String, s: &str) {
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            '$' => out.push_str("\\$"),
+            c => out.push(c),
+        }
+    }
+}
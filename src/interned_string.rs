@@ -1,9 +1,9 @@
-//use std::collections::HashMap;
+use std::collections::HashMap;
 use std::fmt::{self, Debug, Display};
-use std::hash::Hash;
+use std::hash::{BuildHasherDefault, Hash, Hasher};
 use std::ptr;
 
-#[derive(Eq, Hash)]
+#[derive(Eq)]
 pub struct IString {
     data: *mut StringPoolEntry,
 }
@@ -21,6 +21,11 @@ impl PartialEq for IString {
         self.data == other.data
     }
 }
+impl Hash for IString {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.get_hash());
+    }
+}
 impl Display for IString {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let s = unsafe { &(*self.data).data };
@@ -37,6 +42,7 @@ impl Debug for IString {
 
 impl Drop for IString {
     fn drop(&mut self) {
+        // entry is reclaimed by `StringPool::sweep` later
         unsafe {
             (*self.data).ref_count -= 1;
         }
@@ -47,48 +53,172 @@ impl IString {
     pub fn get_inner(&self) -> &str {
         unsafe { &(*self.data).data }
     }
+    /// hash of the content, computed once when interned
+    pub fn get_hash(&self) -> u64 {
+        unsafe { (*self.data).hash }
+    }
+    /// the entry, without holding it. `Value`s keep strings this way,
+    /// the gc marks what they refer to
+    pub(crate) fn as_raw(&self) -> *mut u8 {
+        self.data as *mut u8
+    }
+    /// # Safety
+    /// `raw` comes from `as_raw` and the entry has not been swept since
+    pub(crate) unsafe fn from_raw(raw: *mut u8) -> IString {
+        let data = raw as *mut StringPoolEntry;
+        (*data).ref_count += 1;
+        IString { data }
+    }
+    /// keep the entry through the next sweep even if nothing holds it
+    pub(crate) fn mark(&self) {
+        unsafe {
+            (*self.data).marked = true;
+        }
+    }
 }
 struct StringPoolEntry {
     data: String,
+    hash: u64,
     ref_count: usize,
+    /// reached by the gc since the last sweep
+    marked: bool,
+    /// next entry with the same hash
+    next: *mut StringPoolEntry,
+}
+
+/// FNV-1a, cheap for the short strings programs are made of
+fn hash_str(s: &str) -> u64 {
+    let mut h: u64 = 0xcbf29ce484222325;
+    for b in s.bytes() {
+        h ^= b as u64;
+        h = h.wrapping_mul(0x100000001b3);
+    }
+    h
+}
+
+/// keys are already hashes, pass them through
+#[derive(Default)]
+struct PassHasher(u64);
+impl Hasher for PassHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 = (self.0 << 8) | *b as u64;
+        }
+    }
+    fn write_u64(&mut self, i: u64) {
+        self.0 = i;
+    }
 }
+
+/// sweep once the pool holds this many entries
+const SWEEP_INITIAL_THRESHOLD: usize = 256;
+
 pub struct StringPool {
-    data: Vec<*mut StringPoolEntry>,
+    /// hash -> chain of entries
+    data: HashMap<u64, *mut StringPoolEntry, BuildHasherDefault<PassHasher>>,
+    len: usize,
+    next_sweep: usize,
 }
 
 impl StringPool {
     pub fn new() -> StringPool {
-        StringPool { data: Vec::new() }
+        StringPool {
+            data: HashMap::default(),
+            len: 0,
+            next_sweep: SWEEP_INITIAL_THRESHOLD,
+        }
     }
     pub fn creat_istring(&mut self, s: &str) -> IString {
-        for (index, ss_entry) in self.data.iter().enumerate() {
-            let ss = unsafe { &(**ss_entry).data };
-            if ss == s {
-                unsafe {
-                    (**ss_entry).ref_count += 1;
+        let hash = hash_str(s);
+        let head = self.data.get(&hash).copied().unwrap_or(ptr::null_mut());
+        let mut entry = head;
+        while !entry.is_null() {
+            unsafe {
+                if (*entry).data == s {
+                    // may revive an entry nobody refers to yet
+                    (*entry).ref_count += 1;
+                    return IString { data: entry };
                 }
-                return IString { data: *ss_entry };
+                entry = (*entry).next;
             }
         }
-        let entry = Box::new(StringPoolEntry {
+        let entry = Box::into_raw(Box::new(StringPoolEntry {
             data: s.to_owned(),
+            hash,
             ref_count: 1,
-        });
-        let entry = Box::into_raw(entry);
-        self.data.push(entry);
-
-        // clean up
-        for ss_entry in self.data.iter_mut() {
+            marked: false,
+            next: head,
+        }));
+        self.data.insert(hash, entry);
+        self.len += 1;
+        IString { data: entry }
+    }
+    /// free entries no `IString` refers to and not marked,
+    /// only the gc knows which strings values refer to
+    pub fn sweep(&mut self) {
+        let mut freed = 0;
+        self.data.retain(|_, head| {
+            let mut link: *mut *mut StringPoolEntry = head;
             unsafe {
-                if (**ss_entry).ref_count <= 0 {
-                    // let Box destruct it
-                    let _to_drop = Box::from_raw(*ss_entry);
-                    *ss_entry = ptr::null_mut() as *mut StringPoolEntry;
+                while !(*link).is_null() {
+                    let entry = *link;
+                    if (*entry).ref_count == 0 && !(*entry).marked {
+                        *link = (*entry).next;
+                        // let Box destruct it
+                        let _to_drop = Box::from_raw(entry);
+                        freed += 1;
+                    } else {
+                        (*entry).marked = false;
+                        link = &mut (*entry).next;
+                    }
                 }
             }
-        }
-        self.data
-            .retain(|e| *e != ptr::null_mut() as *mut StringPoolEntry);
-        IString { data: entry }
+            !head.is_null()
+        });
+        self.len -= freed;
+        self.next_sweep = (self.len * 2).max(SWEEP_INITIAL_THRESHOLD);
+    }
+    /// grown enough since the last sweep to collect
+    pub fn needs_sweep(&self) -> bool {
+        self.len >= self.next_sweep
+    }
+    /// number of interned strings, including unreferenced ones not swept yet
+    pub fn len(&self) -> usize {
+        self.len
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::StringPool;
+
+    #[test]
+    fn intern_and_sweep() {
+        let mut pool = StringPool::new();
+        let a = pool.creat_istring("hello");
+        let b = pool.creat_istring("hello");
+        assert_eq!(a, b);
+        assert_eq!(a.get_hash(), b.get_hash());
+        assert_ne!(a, pool.creat_istring("world"));
+        assert_eq!(pool.len(), 2);
+        // "world" is no longer referred to
+        pool.sweep();
+        assert_eq!(pool.len(), 1);
+        drop(a);
+        drop(b);
+        // revived before swept
+        let c = pool.creat_istring("hello");
+        pool.sweep();
+        assert_eq!(pool.len(), 1);
+        assert_eq!(c.get_inner(), "hello");
+        // marked entries survive one sweep
+        c.mark();
+        drop(c);
+        pool.sweep();
+        assert_eq!(pool.len(), 1);
+        pool.sweep();
+        assert_eq!(pool.len(), 0);
     }
 }
@@ -1,19 +1,42 @@
 mod compiler;
+mod dap;
+mod debugger;
+mod diagnostic;
+mod disasm;
 mod extension_methods;
 mod fiber;
+mod format;
 #[allow(dead_code)]
 mod interned_string;
+mod lint;
+mod lsp;
 mod native;
+mod repl;
+mod serialize;
 mod vec;
 mod draw;
 mod math;
 mod vm;
+mod wire;
 
 use std::collections::HashMap;
 use std::ffi::{c_char, CStr};
 use std::fmt::{self, Debug};
 use std::io::Write;
 
+pub use compiler::ast;
+pub use dap::run_dap;
+pub use debugger::{DebugEvent, PauseReason, StepMode};
+pub use diagnostic::{Diagnostic, Severity};
+pub use disasm::disassemble;
+pub use format::format_program;
+pub use lint::lint_program;
+pub use lsp::run_lsp;
+pub use native::value_to_string;
+pub use repl::{Repl, REPL_FILE};
+pub use wire::{read_message, write_message};
+
+use compiler::codegen::CodeGen;
 use compiler::parser::{self, ParserCtx};
 use compiler::scanner::{self, ScannerCtx};
 use interned_string::{IString, StringPool};
@@ -25,42 +48,42 @@ use vm::{CallFrame, Vm};
 
 macro_rules! mf_entry {
     ($name:expr,$func:expr) => {
-        ($name.to_owned(), Value::NativeFunction($func as *mut u8))
+        ($name.to_owned(), Value::native_function($func as *mut u8))
     };
 }
 pub fn prelude() -> Vec<(String, Value)> {
     vec![
         (
             "print".to_owned(),
-            Value::NativeFunction(sloth_print_val as *mut u8),
+            Value::native_function(sloth_print_val as *mut u8),
         ),
         (
             "import".to_owned(),
-            Value::NativeFunction(sloth_load_module as *mut u8),
+            Value::native_function(sloth_load_module as *mut u8),
         ),
         (
             "number".to_owned(),
-            Value::NativeFunction(sloth_to_number as *mut u8),
+            Value::native_function(sloth_to_number as *mut u8),
         ),
         (
             "string".to_owned(),
-            Value::NativeFunction(sloth_to_string as *mut u8),
+            Value::native_function(sloth_to_string as *mut u8),
         ),
         (
             "bool".to_owned(),
-            Value::NativeFunction(sloth_to_bool as *mut u8),
+            Value::native_function(sloth_to_bool as *mut u8),
         ),
         (
             "input".to_owned(),
-            Value::NativeFunction(sloth_input as *mut u8),
+            Value::native_function(sloth_input as *mut u8),
         ),
         (
             "type_string".to_owned(),
-            Value::NativeFunction(sloth_typeof as *mut u8),
+            Value::native_function(sloth_typeof as *mut u8),
         ),
         (
             "va_arg".to_owned(),
-            Value::NativeFunction(sloth_va_arg as *mut u8),
+            Value::native_function(sloth_va_arg as *mut u8),
         ),
         mf_entry!("__Array_push__", extension_methods::array_push),
         mf_entry!("__Array_pop__", extension_methods::array_pop),
@@ -92,58 +115,155 @@ pub extern "C" fn sloth_lang_run_string(prog: *const c_char) -> i32{
         return 1;
     }
 }
-pub fn run_string(prog: &str, only_compile: bool) -> Result<(), String> {
+pub fn run_string(prog: &str, only_compile: bool) -> Result<(), Diagnostic> {
     run_string_debug(prog, only_compile, false)
 }
-pub fn run_string_debug(prog: &str, only_compile: bool, debug: bool) -> Result<(), String> {
+pub fn run_string_debug(
+    prog: &str,
+    only_compile: bool,
+    debug: bool,
+) -> Result<(), Diagnostic> {
+    run_script(prog, "", only_compile, debug, false)
+}
+/// like `run_string_debug`, `file` is reported in diagnostics and stack traces.
+/// `optimize` runs the optimizer over the script and the modules it imports
+pub fn run_script(
+    prog: &str,
+    file: &str,
+    only_compile: bool,
+    debug: bool,
+    optimize: bool,
+) -> Result<(), Diagnostic> {
+    let mut vm = compile_vm(prog, file, debug, optimize)?;
+    if !only_compile {
+        if let Err(err) = vm.run() {
+            return Err(eval_err_diagnostic(&vm, err));
+        }
+    }
+    Ok(())
+}
+/// compile `prog` into a vm with native modules loaded, ready to `run`
+fn compile_vm(
+    prog: &str,
+    file: &str,
+    debug: bool,
+    optimize: bool,
+) -> Result<Box<Vm>, Diagnostic> {
     let mut string_pool = StringPool::new();
-    let mut scanner = ScannerCtx::new(prog, &mut string_pool);
-    scanner.parse()?;
+    let chunk = compile_chunk(prog, file, &mut string_pool, debug, optimize)?;
+    let mut vm = build_vm(chunk, string_pool, debug);
+    vm.set_optimize(optimize);
+    Ok(vm)
+}
+fn compile_chunk(
+    prog: &str,
+    file: &str,
+    string_pool: &mut StringPool,
+    debug: bool,
+    optimize: bool,
+) -> Result<Chunk, Diagnostic> {
+    let mut scanner = ScannerCtx::new(prog, string_pool);
+    scanner.parse().map_err(|diag| diag.with_file(file))?;
     let scanner_result = scanner.finish();
     if debug {
         eprintln!("{:?}", scanner_result.tokens);
     }
-    let mut parser = ParserCtx::new(scanner_result, HashMap::new(), &mut string_pool);
-    parser.parse_prog()?;
-    let parser_result = parser.finish();
+    let prog = ParserCtx::new(scanner_result)
+        .parse_prog()
+        .map_err(|diag| diag.with_file(file))?;
+    let mut codegen = CodeGen::new(string_pool);
+    codegen.set_file(file);
+    codegen.set_optimize(optimize);
+    codegen.gen_prog(&prog).map_err(|diag| diag.with_file(file))?;
+    let result = codegen.finish();
     if debug {
-        eprintln!("{:?}", parser_result.chunk);
+        eprint!("{}", disassemble(&result.chunk));
     }
+    Ok(result.chunk)
+}
+fn build_vm(chunk: Chunk, string_pool: StringPool, debug: bool) -> Box<Vm> {
     let _ = std::io::stdout().flush();
     let cwd = std::env::current_dir().unwrap();
     if debug {
         eprintln!("interpreter running in {cwd:?}");
     }
-    let mut vm = Box::new(Vm::new(
-        parser_result.chunk,
-        HashMap::new(),
-        string_pool,
-        debug,
-        cwd,
-    ));
+    let mut vm = Box::new(Vm::new(chunk, HashMap::new(), string_pool, debug, cwd));
     vm.load_native_module(None, prelude());
-    let (name, module) = fiber::module_export();
-    vm.load_native_module(Some(&name), module);
-    let (name, module) = vec::module_export();
-    vm.load_native_module(Some(&name), module);
-    let (name, module) = draw::module_export();
-    vm.load_native_module(Some(&name), module);
-    let (name, module) = math::module_export();
-    vm.load_native_module(Some(&name), module);
-    if !only_compile {
-        vm.run()?;
+    for (name, module) in native_modules() {
+        vm.load_native_module(Some(&name), module);
+    }
+    vm
+}
+/// `(name, members)` of modules loaded into every vm
+fn native_modules() -> Vec<(String, Vec<(String, Value)>)> {
+    vec![
+        fiber::module_export(),
+        vec::module_export(),
+        draw::module_export(),
+        math::module_export(),
+    ]
+}
+/// compile `prog` into the content of a `.sltc` file
+pub fn compile_to_bytecode(prog: &str, file: &str, optimize: bool) -> Result<Vec<u8>, Diagnostic> {
+    let mut string_pool = StringPool::new();
+    let chunk = compile_chunk(prog, file, &mut string_pool, false, optimize)?;
+    serialize::save_chunk(&chunk).map_err(|msg| Diagnostic::error((0, 0), msg).with_file(file))
+}
+/// listing of `prog` compiled, see `disassemble`
+pub fn disassemble_program(prog: &str, file: &str, optimize: bool) -> Result<String, Diagnostic> {
+    let mut string_pool = StringPool::new();
+    let chunk = compile_chunk(prog, file, &mut string_pool, false, optimize)?;
+    Ok(disassemble(&chunk))
+}
+/// syntax tree of `prog`, see `ast`
+pub fn parse_program(prog: &str, file: &str) -> Result<ast::Program, Diagnostic> {
+    let mut string_pool = StringPool::new();
+    let mut scanner = ScannerCtx::new(prog, &mut string_pool);
+    scanner.parse().map_err(|diag| diag.with_file(file))?;
+    let mut parser = ParserCtx::new(scanner.finish());
+    parser.parse_prog().map_err(|diag| diag.with_file(file))
+}
+/// like `run_script`, `bytecode` is the content of a `.sltc` file
+pub fn run_bytecode(bytecode: &[u8], file: &str, debug: bool) -> Result<(), Diagnostic> {
+    let mut string_pool = StringPool::new();
+    let chunk = serialize::load_chunk(bytecode, &mut string_pool)
+        .map_err(|msg| Diagnostic::error((0, 0), msg).with_file(file))?;
+    if debug {
+        eprint!("{}", disassemble(&chunk));
+    }
+    let mut vm = build_vm(chunk, string_pool, debug);
+    if let Err(err) = vm.run() {
+        return Err(eval_err_diagnostic(&vm, err));
     }
     Ok(())
 }
+/// `err` is returned by `vm.run()`, the vm still points at where it was raised
+fn eval_err_diagnostic(vm: &Vm, err: vm::EvalError) -> Diagnostic {
+    let mut diag = Diagnostic::error(vm.current_cood(), err.message())
+        .with_file(vm.current_file())
+        .with_note(format!("raised as {}", err.kind()));
+    if let Some(trace) = err.backtrace() {
+        for frame in trace.iter() {
+            diag = diag.with_note(format!("at {frame}"));
+        }
+    }
+    diag
+}
+
+/// a sloth value in one word. numbers are stored as they are, anything else
+/// is a NaN no arithmetic produces, carrying a tag and a pointer or a bool.
+/// match on `unbox()` to look inside
+#[derive(Clone, Copy)]
+pub struct Value(u64);
 
-#[derive(Debug, PartialEq, Clone)]
-pub enum Value {
+/// what a `Value` holds
+#[derive(Debug, Clone)]
+pub enum Unboxed {
     Nil,
     Bool(bool),
     Number(f64),
-    Range(f64, f64),
+    Range(*mut Range),
     String(IString),
-    //Symbol(IString),
     Array(*mut Array),
     Dictionary(*mut Dict),
     Error(*mut Dict),
@@ -157,19 +277,183 @@ pub enum Value {
     Klass(*mut Klass),
     Instance(*mut Instance),
 
-    StringIter(IString, usize),
-    ArrayIter(*mut Array, usize),
+    Iter(*mut Iter),
 }
 
+/// a quiet NaN with one more bit set, no NaN `Value::number` stores has it
+const QNAN: u64 = 0x7ffc_0000_0000_0000;
+/// pointers of user space fit in 47 bits
+const PAYLOAD: u64 = (1 << 47) - 1;
+const TAG_NIL: u64 = 0;
+const TAG_BOOL: u64 = 1;
+const TAG_STRING: u64 = 2;
+const TAG_ARRAY: u64 = 3;
+const TAG_DICTIONARY: u64 = 4;
+const TAG_ERROR: u64 = 5;
+const TAG_MODULE: u64 = 6;
+const TAG_CLOSURE: u64 = 7;
+const TAG_NATIVE_FUNCTION: u64 = 8;
+const TAG_OPAQUE_DATA: u64 = 9;
+const TAG_FIBER: u64 = 10;
+const TAG_KLASS: u64 = 11;
+const TAG_INSTANCE: u64 = 12;
+const TAG_RANGE: u64 = 13;
+const TAG_ITER: u64 = 14;
+
 impl Value {
+    pub const NIL: Value = Value::boxed(TAG_NIL, 0);
+    pub const TRUE: Value = Value::boxed(TAG_BOOL, 1);
+    pub const FALSE: Value = Value::boxed(TAG_BOOL, 0);
+
+    /// the tag is split between the sign bit and the 3 bits below `QNAN`
+    const fn boxed(tag: u64, payload: u64) -> Value {
+        Value(QNAN | (tag & 8) << 60 | (tag & 7) << 47 | payload)
+    }
+    fn pointer<T>(tag: u64, p: *mut T) -> Value {
+        debug_assert!(p as u64 & !PAYLOAD == 0, "pointer out of 47 bits");
+        Value::boxed(tag, p as u64)
+    }
+    /// `None` for numbers
+    #[inline(always)]
+    fn tag(self) -> Option<u64> {
+        if self.0 & QNAN == QNAN {
+            Some((self.0 >> 60) & 8 | (self.0 >> 47) & 7)
+        } else {
+            None
+        }
+    }
+    #[inline(always)]
+    pub fn number(x: f64) -> Value {
+        // NaNs with a payload would be taken for boxed values
+        if x.is_nan() {
+            Value(f64::NAN.to_bits())
+        } else {
+            Value(x.to_bits())
+        }
+    }
+    #[inline(always)]
+    pub fn bool(b: bool) -> Value {
+        Value::boxed(TAG_BOOL, b as u64)
+    }
+    /// the string is kept alive by the gc from now on, not by `s`
+    pub fn string(s: IString) -> Value {
+        Value::pointer(TAG_STRING, s.as_raw())
+    }
+    pub fn array(p: *mut Array) -> Value {
+        Value::pointer(TAG_ARRAY, p)
+    }
+    pub fn dictionary(p: *mut Dict) -> Value {
+        Value::pointer(TAG_DICTIONARY, p)
+    }
+    pub fn error(p: *mut Dict) -> Value {
+        Value::pointer(TAG_ERROR, p)
+    }
+    pub fn module(p: *mut Dict) -> Value {
+        Value::pointer(TAG_MODULE, p)
+    }
+    pub fn closure(p: *mut Closure) -> Value {
+        Value::pointer(TAG_CLOSURE, p)
+    }
+    pub fn native_function(p: *mut u8) -> Value {
+        Value::pointer(TAG_NATIVE_FUNCTION, p)
+    }
+    pub fn opaque_data(p: *mut u8) -> Value {
+        Value::pointer(TAG_OPAQUE_DATA, p)
+    }
+    pub fn fiber(p: *mut Fiber) -> Value {
+        Value::pointer(TAG_FIBER, p)
+    }
+    pub fn klass(p: *mut Klass) -> Value {
+        Value::pointer(TAG_KLASS, p)
+    }
+    pub fn instance(p: *mut Instance) -> Value {
+        Value::pointer(TAG_INSTANCE, p)
+    }
+    pub fn range(p: *mut Range) -> Value {
+        Value::pointer(TAG_RANGE, p)
+    }
+    pub fn iter(p: *mut Iter) -> Value {
+        Value::pointer(TAG_ITER, p)
+    }
+    #[inline(always)]
+    pub fn as_number(self) -> Option<f64> {
+        if self.0 & QNAN == QNAN {
+            None
+        } else {
+            Some(f64::from_bits(self.0))
+        }
+    }
+    pub fn is_nil(self) -> bool {
+        self.0 == Value::NIL.0
+    }
+    pub fn unbox(self) -> Unboxed {
+        let Some(tag) = self.tag() else {
+            return Unboxed::Number(f64::from_bits(self.0));
+        };
+        let payload = self.0 & PAYLOAD;
+        let p = payload as *mut u8;
+        match tag {
+            TAG_NIL => Unboxed::Nil,
+            TAG_BOOL => Unboxed::Bool(payload != 0),
+            TAG_STRING => Unboxed::String(unsafe { IString::from_raw(p) }),
+            TAG_ARRAY => Unboxed::Array(p.cast()),
+            TAG_DICTIONARY => Unboxed::Dictionary(p.cast()),
+            TAG_ERROR => Unboxed::Error(p.cast()),
+            TAG_MODULE => Unboxed::Module(p.cast()),
+            TAG_CLOSURE => Unboxed::Closure(p.cast()),
+            TAG_NATIVE_FUNCTION => Unboxed::NativeFunction(p),
+            TAG_OPAQUE_DATA => Unboxed::OpaqueData(p),
+            TAG_FIBER => Unboxed::Fiber(p.cast()),
+            TAG_KLASS => Unboxed::Klass(p.cast()),
+            TAG_INSTANCE => Unboxed::Instance(p.cast()),
+            TAG_RANGE => Unboxed::Range(p.cast()),
+            TAG_ITER => Unboxed::Iter(p.cast()),
+            _ => unreachable!("bad tag {tag}"),
+        }
+    }
     pub fn to_bool_v(&self) -> Value {
-        Value::Bool(self.to_bool())
+        Value::bool(self.to_bool())
     }
     pub fn to_bool(&self) -> bool {
-        match self {
-            Value::Nil => false,
-            Value::Bool(v) => *v,
-            _ => true,
+        self.0 != Value::NIL.0 && self.0 != Value::FALSE.0
+    }
+}
+impl From<Unboxed> for Value {
+    fn from(val: Unboxed) -> Value {
+        match val {
+            Unboxed::Nil => Value::NIL,
+            Unboxed::Bool(b) => Value::bool(b),
+            Unboxed::Number(x) => Value::number(x),
+            Unboxed::Range(p) => Value::range(p),
+            Unboxed::String(s) => Value::string(s),
+            Unboxed::Array(p) => Value::array(p),
+            Unboxed::Dictionary(p) => Value::dictionary(p),
+            Unboxed::Error(p) => Value::error(p),
+            Unboxed::Module(p) => Value::module(p),
+            Unboxed::Closure(p) => Value::closure(p),
+            Unboxed::NativeFunction(p) => Value::native_function(p),
+            Unboxed::OpaqueData(p) => Value::opaque_data(p),
+            Unboxed::Fiber(p) => Value::fiber(p),
+            Unboxed::Klass(p) => Value::klass(p),
+            Unboxed::Instance(p) => Value::instance(p),
+            Unboxed::Iter(p) => Value::iter(p),
+        }
+    }
+}
+/// numbers compare as numbers, anything else by identity
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        match (self.as_number(), other.as_number()) {
+            (Some(a), Some(b)) => a == b,
+            _ => self.0 == other.0,
+        }
+    }
+}
+impl Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.unbox() {
+            Unboxed::Range(r) => unsafe { write!(f, "Range({:?}, {:?})", (*r).start, (*r).end) },
+            v => v.fmt(f),
         }
     }
 }
@@ -178,8 +462,12 @@ trait GCObject {
     fn is_marked(&self) -> bool;
     fn mark(&mut self);
     fn demark(&mut self);
-    fn mark_children(&mut self);
+    /// mark the objects it references, pushing them to `gray`
+    fn mark_children(&mut self, gray: &mut Gray);
 }
+/// objects marked but whose children are not yet, drained by the vm
+/// so marking a long chain of objects does not grow the native stack
+type Gray = Vec<*mut dyn GCObject>;
 macro_rules! gcobject_header {
     () => {
         fn is_marked(&self) -> bool {
@@ -197,164 +485,161 @@ macro_rules! derive_gcobject {
     ($obj:ty) => {
         impl GCObject for $obj {
             gcobject_header!();
-            fn mark_children(&mut self) {}
+            fn mark_children(&mut self, _gray: &mut Gray) {}
         }
     };
 }
-macro_rules! mark_proc {
-    ($val:expr) => {
-        match $val {
-            Value::Array(p_arr) => {
-                unsafe {
-                    let arr = &mut **p_arr;
-                    if !arr.is_marked() {
-                        arr.mark();
-                        arr.mark_children();
-                    }
-                };
+/// mark `obj`, its children are marked once it is popped from `gray`
+fn mark_object(obj: *mut dyn GCObject, gray: &mut Gray) {
+    unsafe {
+        if !(*obj).is_marked() {
+            (*obj).mark();
+            gray.push(obj);
+        }
+    }
+}
+fn mark_value(val: &Value, gray: &mut Gray) {
+    match val.unbox() {
+        Unboxed::String(s) => s.mark(),
+        Unboxed::Array(p) => mark_object(p, gray),
+        Unboxed::Dictionary(p) | Unboxed::Error(p) | Unboxed::Module(p) => mark_object(p, gray),
+        Unboxed::Closure(p) => mark_object(p, gray),
+        Unboxed::Fiber(p) => mark_object(p, gray),
+        Unboxed::Klass(p) => mark_object(p, gray),
+        Unboxed::Instance(p) => mark_object(p, gray),
+        Unboxed::Range(p) => mark_object(p, gray),
+        Unboxed::Iter(p) => mark_object(p, gray),
+        Unboxed::Nil
+        | Unboxed::Bool(_)
+        | Unboxed::Number(_)
+        | Unboxed::NativeFunction(_)
+        | Unboxed::OpaqueData(_) => {}
+    }
+}
+/// constants of `chunk` and every nested chunk
+fn mark_chunk(chunk: &Chunk, gray: &mut Gray) {
+    for val in chunk.constants.iter() {
+        mark_value(val, gray);
+    }
+    // a freed shape could be allocated again at the same address
+    for cache in chunk.inline_caches.iter() {
+        match *cache {
+            InlineCache::Empty => {}
+            InlineCache::Field(shape, _) => mark_object(shape as *mut Shape, gray),
+            InlineCache::Method(shape, method) => {
+                mark_object(shape as *mut Shape, gray);
+                mark_object(method, gray);
             }
-            Value::Dictionary(p_dict) => {
-                unsafe {
-                    let dict = &mut **p_dict;
-                    if !dict.is_marked() {
-                        dict.mark();
-                        dict.mark_children();
-                    }
-                };
+            InlineCache::Transition(shape, next) => {
+                mark_object(shape as *mut Shape, gray);
+                mark_object(next, gray);
             }
-            Value::Closure(p_closure) => {
-                unsafe {
-                    let closure = &mut **p_closure;
-                    if !closure.is_marked() {
-                        closure.mark();
-                        closure.mark_children();
-                    }
-                };
-            }
-
-            _ => {}
         }
-    };
+    }
+    for chunk in chunk.chunks.iter() {
+        mark_chunk(chunk, gray);
+    }
 }
 derive_gcobject!(Matrix);
+impl GCObject for Shape {
+    gcobject_header!();
+    fn mark_children(&mut self, gray: &mut Gray) {
+        for next in self.transitions.values() {
+            mark_object(*next, gray);
+        }
+    }
+}
+derive_gcobject!(Range);
+impl GCObject for Iter {
+    gcobject_header!();
+    fn mark_children(&mut self, gray: &mut Gray) {
+        match &self.state {
+            IterState::Array(p, _) => mark_object(*p, gray),
+            IterState::Range(_, _) | IterState::String(_, _) => {}
+        }
+    }
+}
 impl GCObject for UpValueObject {
     gcobject_header!();
-    fn mark_children(&mut self) {
+    fn mark_children(&mut self, gray: &mut Gray) {
         match &self.value {
             UpValue::Ref(_) => {}
-            UpValue::Closed(val) => {
-                mark_proc!(val);
-            }
+            UpValue::Closed(val) => mark_value(val, gray),
         }
     }
 }
 
 impl GCObject for Array {
     gcobject_header!();
-    fn mark_children(&mut self) {
+    fn mark_children(&mut self, gray: &mut Gray) {
         for val in self.array.iter() {
-            mark_proc!(val);
+            mark_value(val, gray);
         }
     }
 }
 impl GCObject for Closure {
     gcobject_header!();
-    fn mark_children(&mut self) {
+    fn mark_children(&mut self, gray: &mut Gray) {
         for p_upv in self.upvalues.iter() {
-            unsafe {
-                let upv = *p_upv;
-                if !(*upv).is_marked() {
-                    (*upv).mark();
-                    (*upv).mark_children();
-                }
-            }
+            mark_object(*p_upv, gray);
         }
-        unsafe {
-            if let Some(has_this_ref) = self.this_ref {
-                if !(*has_this_ref).is_marked() {
-                    (*has_this_ref).mark();
-                    (*has_this_ref).mark_children();
-                }
-            }
+        if let Some(this_ref) = self.this_ref {
+            mark_object(this_ref, gray);
         }
     }
 }
 impl GCObject for Dict {
     gcobject_header!();
-    fn mark_children(&mut self) {
+    fn mark_children(&mut self, gray: &mut Gray) {
         for val in self.dict.values() {
-            mark_proc!(val);
+            mark_value(val, gray);
         }
     }
 }
 
 impl GCObject for Klass {
     gcobject_header!();
-    fn mark_children(&mut self) {
-        unsafe {
-            if !(*self.super_klass).is_marked() {
-                (*self.super_klass).mark();
-                (*self.super_klass).mark_children();
-            }
+    fn mark_children(&mut self, gray: &mut Gray) {
+        if !self.super_klass.is_null() {
+            mark_object(self.super_klass, gray);
         }
         for val in self.methods.values() {
-            mark_proc!(val);
+            mark_value(val, gray);
         }
+        mark_object(self.shape, gray);
     }
 }
 
 impl GCObject for Instance {
     gcobject_header!();
-    fn mark_children(&mut self) {
-        unsafe {
-            if !(*self.klass).is_marked() {
-                (*self.klass).mark();
-                (*self.klass).mark_children();
-            }
-        }
-        for val in self.fields.values() {
-            mark_proc!(val);
+    fn mark_children(&mut self, gray: &mut Gray) {
+        mark_object(self.klass, gray);
+        mark_object(self.shape, gray);
+        for val in self.fields.iter() {
+            mark_value(val, gray);
         }
     }
 }
 impl GCObject for Fiber {
     gcobject_header!();
-    fn mark_children(&mut self) {
-        for call_frame in self.call_frames.iter_mut() {
-            unsafe {
-                let closure = &mut (*call_frame.closure);
-                if !closure.is_marked() {
-                    closure.mark();
-                    closure.mark_children();
-                }
+    /// `prev` is not marked here, it may be stale once the fiber paused.
+    /// the vm marks the chain of waiting fibers itself.
+    fn mark_children(&mut self, gray: &mut Gray) {
+        for call_frame in self.call_frames.iter() {
+            mark_object(call_frame.closure, gray);
+            if let Some(this) = call_frame.this_ref {
+                mark_object(this, gray);
             }
-            for val in self.stack.iter_mut() {
-                match val {
-                    Value::Array(p_arr) => {
-                        unsafe {
-                            let arr = &mut **p_arr;
-                            arr.mark();
-                            arr.mark_children();
-                        };
-                    }
-                    Value::Dictionary(p_dict) => {
-                        unsafe {
-                            let dict = &mut **p_dict;
-                            dict.mark();
-                            dict.mark_children();
-                        };
-                    }
-                    Value::Closure(p_closure) => {
-                        unsafe {
-                            let closure = &mut **p_closure;
-                            closure.mark();
-                            closure.mark_children();
-                        };
-                    }
-                    _ => {}
-                }
+            for val in call_frame.va_args.iter() {
+                mark_value(val, gray);
             }
         }
+        for val in self.stack.iter() {
+            mark_value(val, gray);
+        }
+        for upv in self.open_upvalues.iter() {
+            mark_object(*upv, gray);
+        }
     }
 }
 #[derive(Debug)]
@@ -362,6 +647,27 @@ pub struct Array {
     pub marked: bool,
     pub array: Vec<Value>,
 }
+/// `a..b`, `a..=b` is made `a..b+1`
+#[derive(Debug)]
+pub struct Range {
+    pub marked: bool,
+    pub start: f64,
+    pub end: f64,
+}
+/// where a `for` loop is in what it iterates
+#[derive(Debug)]
+pub enum IterState {
+    /// next number and the end
+    Range(f64, f64),
+    /// byte offset of the next char
+    String(IString, usize),
+    Array(*mut Array, usize),
+}
+#[derive(Debug)]
+pub struct Iter {
+    pub marked: bool,
+    pub state: IterState,
+}
 #[derive(Debug)]
 pub struct Dict {
     pub marked: bool,
@@ -389,8 +695,13 @@ pub struct Fiber {
     pub marked: bool,
     pub call_frames: Vec<CallFrame>,
     pub stack: Vec<Value>,
+    /// upvalues still refering to `stack`, sorted by their slot
+    pub open_upvalues: Vec<*mut UpValueObject>,
     pub state: FiberState,
     pub prev: *mut Fiber,
+    /// resumed or imported by a protected call, errors nothing inside
+    /// catches are caught by it in `prev`
+    pub protected: bool,
 }
 
 #[derive(Debug)]
@@ -398,13 +709,48 @@ pub struct Klass {
     pub marked: bool,
     pub super_klass: *mut Klass,
     pub methods: HashMap<IString, Value>,
+    /// shape of its instances without fields
+    pub shape: *mut Shape,
 }
 
 #[derive(Debug)]
 pub struct Instance {
     pub marked: bool,
     pub klass: *mut Klass,
-    pub fields: HashMap<IString, Value>,
+    pub shape: *mut Shape,
+    /// values of the fields, at the slots given by `shape`
+    pub fields: Vec<Value>,
+}
+impl Instance {
+    pub fn field(&self, name: &IString) -> Option<Value> {
+        let slot = unsafe { (*self.shape).slots.get(name)? };
+        Some(self.fields[*slot])
+    }
+}
+
+/// names of the fields of an instance and their slots. instances of a
+/// class which got the same fields in the same order share one.
+/// managed by gc, kept by the class, its instances and inline caches
+#[derive(Debug, Default)]
+pub struct Shape {
+    pub marked: bool,
+    pub slots: HashMap<IString, usize>,
+    /// shape after adding a field
+    pub transitions: HashMap<IString, *mut Shape>,
+}
+
+/// what a `GetField`, `SetField` or `Invoke` found on its last run
+#[derive(Debug, Clone, Copy, Default)]
+pub enum InlineCache {
+    #[default]
+    Empty,
+    /// instances of the shape have the field at the slot
+    Field(*const Shape, usize),
+    /// instances of the shape have no such field, their class has the method
+    Method(*const Shape, *mut Closure),
+    /// instances of the first shape are of the second one with the field
+    /// added, at the last slot
+    Transition(*const Shape, *mut Shape),
 }
 
 #[derive(Debug, Clone)]
@@ -412,6 +758,15 @@ pub enum UpValueDecl {
     Ref(usize, IString),
     RefUpValue(usize, IString),
 }
+/// name of a local slot, and the range of bytecodes `[start, end)` it is visible to.
+/// used by the debugger, the vm itself only knows slots
+#[derive(Debug, Clone)]
+pub struct LocalVar {
+    pub name: String,
+    pub slot: usize,
+    pub start: usize,
+    pub end: usize,
+}
 #[derive(Debug)]
 pub enum UpValue {
     Closed(Value),
@@ -432,14 +787,25 @@ pub struct Matrix {
 #[derive(Default, Clone)]
 pub struct Chunk {
     pub bytecodes: Vec<Instr>,
-    pub lines: Vec<usize>,
+    /// (line, col) of every bytecode
+    pub cood: Vec<(usize, usize)>,
     pub constants: Vec<Value>,
     pub chunks: Vec<Chunk>,
     pub file: String,
+    /// function name, `Class.method` for methods
+    pub name: String,
     pub upvalues: Vec<UpValueDecl>,
+    pub locals: Vec<LocalVar>,
     pub parameter_num: usize,
     pub num_locals: usize,
     pub is_va: bool,
+    /// global slots of the name at each constant index used by
+    /// `GetGlobal`/`SetGlobal`, in the module and in the main module,
+    /// filled when the vm loads the chunk
+    pub global_slots: Vec<(usize, usize)>,
+    /// cache of the instruction at each pc, filled when the vm loads the
+    /// chunk if it has `GetField`, `SetField` or `Invoke`
+    pub inline_caches: Vec<InlineCache>,
 }
 impl PartialEq for Chunk {
     fn eq(&self, _other: &Self) -> bool {
@@ -448,9 +814,10 @@ impl PartialEq for Chunk {
 }
 impl Debug for Chunk {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f)?;
+        writeln!(f, "{} ({})", self.name, self.file)?;
         for i in 0..(self.bytecodes.len()) {
-            writeln!(f, "{:>15}   {:?}", self.lines[i], self.bytecodes[i])?;
+            let (line, col) = self.cood[i];
+            writeln!(f, "{:>15}   {:?}", format!("{line}:{col}"), self.bytecodes[i])?;
         }
         writeln!(f, "num_locals: {:?}", self.num_locals)?;
         writeln!(f, "constants: {:?}", self.constants)?;
@@ -485,6 +852,8 @@ pub enum Instr {
     SetLocal(usize),
     GetUpValue(usize),
     SetUpValue(usize),
+    /// close the upvalues of locals at or above the slot, leaving their block
+    CloseUpValues(usize),
     InitArray(usize), /*size of array*/
     InitDict(usize),
     PushNil,
@@ -494,6 +863,12 @@ pub enum Instr {
     /// 1 - .
     GetCollection(usize),
     SetCollection(usize),
+    /// `.` with the name of the field at the constant index
+    GetField(usize),
+    SetField(usize),
+    /// `receiver.name(args)` without binding the method,
+    /// name of the method at the constant index and number of arguments
+    Invoke(u32, u32),
 
     Add,
     Sub,
@@ -519,11 +894,41 @@ pub enum Instr {
 
     Pop,
     Swap2,       /*change top 2 value on the stack*/
+    /// push a copy of the top n values
+    Dup(usize),
     Call(usize), /*parameter num*/
 
+    /// same as `Call`, but errors raised inside evaluate to an error value
     TryCall(usize),
+    /// enter a `try` block, errors raised until the matching `PopHandler`
+    /// unwind the stack to its height here, push the error value and jump
+    /// by the offset to the `catch` block
+    PushHandler(i32),
+    /// leave the innermost `try` block of the frame
+    PopHandler,
+    /// push whether stack top is an error value
+    IsError,
+    /// pop a literal, push whether the value below is equal to it.
+    /// values of different types are not equal
+    MatchEq,
+    /// pop the end and the start of a range, push whether the value below
+    /// is a number in `[start, end)`
+    MatchRange,
+    /// like `MatchRange`, in `[start, end]`
+    MatchRangeClosed,
+    /// push whether stack top is an array of the length,
+    /// at least that long if the second operand is 1
+    MatchArray(u32, u32),
+    /// push whether stack top is a dict or an error with the key, or an
+    /// instance with the field, named by the constant at the index
+    HasField(usize),
+    /// replace the array on the top of stack by its elements from the index
+    ArraySlice(usize),
     JumpIfNot(i32),
     JumpIfTrue(i32),
+    /// jump if the stack top is nil, it is left on the stack like other jumps
+    JumpIfNil(i32),
+    JumpIfNotNil(i32),
     Jump(i32),
     Return,
     Except,
@@ -541,13 +946,82 @@ pub enum Instr {
     GetThis,
 
     UnpackVA,
+
+    /// written by the vm over the instructions above once their operands
+    /// were numbers, and back when they are not, see `Instr::generic`.
+    /// never compiled nor saved
+    AddNum,
+    SubNum,
+    MulNum,
+    LtNum,
+    GtNum,
+    LeNum,
+    GeNum,
+    /// `Lt` and the `JumpIfNot(x)` after it
+    LtJumpIfNot(i32),
+    GtJumpIfNot(i32),
+    LeJumpIfNot(i32),
+    GeJumpIfNot(i32),
+    /// `GetLocal(x)` and the `Add` after it
+    GetLocalAdd(usize),
+    /// `GetLocal(x) Load(c) Add SetLocal(x)`
+    IncLocal(usize),
 }
-type NativeFunction = fn(&mut Vm, usize, bool);
+impl Instr {
+    /// the instruction a quickened one was written over, the instructions
+    /// fused into it are still after it
+    pub fn generic(self) -> Instr {
+        match self {
+            Instr::AddNum => Instr::Add,
+            Instr::SubNum => Instr::Sub,
+            Instr::MulNum => Instr::Mul,
+            Instr::LtNum | Instr::LtJumpIfNot(_) => Instr::Lt,
+            Instr::GtNum | Instr::GtJumpIfNot(_) => Instr::Gt,
+            Instr::LeNum | Instr::LeJumpIfNot(_) => Instr::Le,
+            Instr::GeNum | Instr::GeJumpIfNot(_) => Instr::Ge,
+            Instr::GetLocalAdd(x) | Instr::IncLocal(x) => Instr::GetLocal(x),
+            _ => self,
+        }
+    }
+}
+/// natives pop their arguments and the callee, then push exactly one result on success.
+/// an `Err` is raised as a sloth runtime error and can be caught by `try`.
+pub type NativeResult = Result<(), vm::EvalError>;
+type NativeFunction = fn(&mut Vm, usize, bool) -> NativeResult;
 #[cfg(test)]
 mod test {
-    use crate::run_string_debug;
+    use crate::{
+        compile_to_bytecode, compile_vm, disassemble_program, eval_err_diagnostic, format_program,
+        lint_program, parse_program, run_bytecode, run_string_debug, serialize, Chunk, Instr,
+        StringPool, Unboxed, UpValueDecl,
+    };
 
     use super::run_string;
+    use crate::{Diagnostic, Vm};
+    use std::{cell::RefCell, rc::Rc};
+
+    /// what `vm` prints while it runs
+    fn capture(vm: &mut Vm) -> Result<String, Diagnostic> {
+        let out = Rc::new(RefCell::new(String::new()));
+        let sink = out.clone();
+        vm.set_output(Box::new(move |s| sink.borrow_mut().push_str(s)));
+        vm.run().map_err(|err| eval_err_diagnostic(vm, err))?;
+        let out = out.borrow().clone();
+        Ok(out)
+    }
+
+    /// what `src` prints
+    fn run_capture(src: &str) -> Result<String, Diagnostic> {
+        let mut vm = compile_vm(src, "prog.slt", false, false)?;
+        capture(&mut vm)
+    }
+
+    /// what `src` prints, compiled with the optimizer
+    fn run_capture_optimized(src: &str) -> Result<String, Diagnostic> {
+        let mut vm = compile_vm(src, "prog.slt", false, true)?;
+        capture(&mut vm)
+    }
+
     #[test]
     fn pipe_test() {
         let src = r#"
@@ -1113,6 +1587,1235 @@ mod test {
         println!("{res:?}");
     }
 
+    #[test]
+    fn try_catch() {
+        let src = r#"
+            func div(a, b) {
+                if (b == 0) {
+                    except "div by zero";
+                }
+                return a / b;
+            }
+            try {
+                print(div(1, 0));
+                print("not executed");
+            } catch (e) {
+                print(e.kind, e.info, e.line, "\n");
+            }
+            try {
+                var arr = [1, 2, 3];
+                print(arr[10]);
+            } catch (e) {
+                print(e.kind, e.msg, "\n");
+            }
+            var r = try div(4, 2);
+            var e = try div(4, 0);
+            print(r, e.kind, e.info, "\n");
+        "#;
+        let res = run_string(src, false);
+        println!("{res:?}");
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn try_control_flow() {
+        let src = r#"
+            func f() {
+                try {
+                    return 1;
+                } catch {}
+                return 2;
+            }
+            var n = 0;
+            func g() {
+                var i = 0;
+                while (i < 10) {
+                    i = i + 1;
+                    try {
+                        if (i == 2) {
+                            continue;
+                        }
+                        if (i == 5) {
+                            break;
+                        }
+                        n = n + 1;
+                    } catch {}
+                }
+                // the handlers left by `break` and `continue` catch nothing
+                except i;
+            }
+            func h() {
+                try {
+                    [].pop();
+                } catch (err) {
+                    return err.kind;
+                }
+            }
+            var e = try g();
+            if (f() != 1 or n != 3 or e.info != 5 or h() != "IndexOutOfBound") {
+                except "wrong control flow in try";
+            }
+            // closures capture the locals of the block which raised
+            var fs = [];
+            for (var i: 0..3) {
+                try {
+                    var j = i * 10;
+                    fs.push(|| { return j; });
+                    except "after capture";
+                } catch {}
+            }
+            if (fs[0]() + fs[1]() + fs[2]() != 30) {
+                except "locals of try block not closed";
+            }
+        "#;
+        let res = run_string(src, false);
+        assert!(res.is_ok(), "{res:?}");
+    }
+
+    #[test]
+    fn try_resume() {
+        let src = r#"
+            var f = fiber.create(|| {
+                fiber.yield(1);
+                except "in fiber";
+            });
+            var first = try fiber.resume(f);
+            var e = try fiber.resume(f);
+            if (first != 1 or e.info != "in fiber" or fiber.check(f)) {
+                except "error of resumed fiber not caught";
+            }
+            var g = fiber.create(|| { [].pop(); });
+            var caught = nil;
+            try {
+                fiber.resume(g);
+            } catch (err) {
+                caught = err.kind;
+            }
+            if (caught != "IndexOutOfBound") {
+                except "error of resumed fiber not caught by try block";
+            }
+        "#;
+        let res = run_string(src, false);
+        assert!(res.is_ok(), "{res:?}");
+    }
+
+    #[test]
+    fn uncaught_except() {
+        let src = r#"
+            func f() {
+                except "uncaught";
+            }
+            f();
+        "#;
+        let res = run_string(src, false);
+        println!("{res:?}");
+        assert_eq!(res.unwrap_err().message, "uncaught");
+        let diag = run_string("except [1, \"boom\"];", false).unwrap_err();
+        assert_eq!(diag.message, "[1,boom,]");
+    }
+
+    #[test]
+    fn native_error() {
+        let src = r#"
+            var e = try ord("ab");
+            var r = try [].pop();
+            var m = try import("not_exist.slt");
+            if (e.kind != "Error" or r.kind != "IndexOutOfBound" or m.kind != "Error") {
+                except "wrong error kind";
+            }
+        "#;
+        assert!(run_string(src, false).is_ok());
+        // `kind` of the error, and its message
+        let err = |src: &str| {
+            let diag = run_string(src, false).unwrap_err();
+            (diag.notes[0].clone(), diag.message)
+        };
+        let arity = |passed, required| {
+            (
+                "raised as CallError".to_owned(),
+                format!("arity check failed, passed {passed}, required {required}"),
+            )
+        };
+        assert_eq!(err("func f() { return number(); } f();"), arity(0, 1));
+        assert_eq!(err("func f() { return number(1, 2, 3); } f();"), arity(3, 1));
+        assert_eq!(err("bool();"), arity(0, 1));
+        assert_eq!(err("string(1, 2);"), arity(2, 1));
+        assert_eq!(err("type_string();"), arity(0, 1));
+        assert_eq!(err("input(1);"), arity(1, 0));
+        assert_eq!(err("func f() { return va_arg(1); } f();"), arity(1, 0));
+        assert_eq!(
+            err("ord(1);"),
+            (
+                "raised as TypeError".to_owned(),
+                "ord take 1 argument: s: String.".to_owned()
+            )
+        );
+        assert_eq!(
+            err("chr(\"a\");"),
+            (
+                "raised as TypeError".to_owned(),
+                "chr take 1 argument: v: Number.".to_owned()
+            )
+        );
+        // the caller's frame is intact after a caught error
+        let src = r#"
+            func f(x) {
+                var e = try number(1, 2, 3);
+                return [x, e.kind];
+            }
+            var r = f(7);
+            if (r[0] != 7 or r[1] != "CallError") {
+                except "frame corrupted";
+            }
+        "#;
+        assert!(run_string(src, false).is_ok());
+    }
+
+    #[test]
+    fn diagnostic_pos() {
+        let src = "var a = 1;\nprint(a;\n";
+        let res = run_string(src, false);
+        println!("{res:?}");
+        let diag = res.unwrap_err();
+        assert_eq!((diag.line, diag.col), (2, 8));
+        println!("{}", diag.render(src));
+
+        let src = "var a = 1;\nvar b = a + \"s\";\n";
+        let res = run_string(src, false);
+        println!("{res:?}");
+        let diag = res.unwrap_err();
+        assert_eq!((diag.line, diag.col), (2, 11));
+        println!("{}", diag.render(src));
+    }
+
+    #[test]
+    fn stack_trace() {
+        let src = r#"
+            func inner() {
+                return 1 + nil;
+            }
+            func outer() {
+                return inner();
+            }
+            var e = try outer();
+            print(e.trace, "\n");
+            outer();
+        "#;
+        let res = run_string(src, false);
+        println!("{res:?}");
+        let diag = res.unwrap_err();
+        assert_eq!(diag.line, 3);
+        assert_eq!(
+            diag.notes[1..],
+            [
+                "at inner (<input>:3)",
+                "at outer (<input>:6)",
+                "at <main> (<input>:10)"
+            ]
+        );
+    }
+
+    #[test]
+    fn gc_stress() {
+        let src = r#"
+            class Node {
+                func __init__(val, next) {
+                    this.val = val;
+                    this.next = next;
+                }
+            }
+            func counter() {
+                var cnt = 0;
+                return || {
+                    cnt = cnt + 1;
+                    return cnt;
+                };
+            }
+            var c = counter();
+            var list = nil;
+            var sum = 0;
+            for (var i: 0..200) {
+                list = Node(i, list);
+                var garbage = [i, @("k": [i]), || { return i; }];
+                var e = try [].pop();
+                sum = sum + garbage[0] + c() - i;
+            }
+            var f = fiber.create(|x| {
+                var acc = [];
+                var n = 0;
+                while (true) {
+                    acc.push(x);
+                    n = n + 1;
+                    x = fiber.yield(n);
+                }
+            }, 1);
+            for (var i: 0..50) {
+                fiber.resume(f, [i]);
+            }
+            var len = 0;
+            while (list) {
+                len = len + 1;
+                list = list.next;
+            }
+            if (sum != 20100 or len != 200 or c() != 201) {
+                except "gc freed live objects";
+            }
+        "#;
+        let mut vm = compile_vm(src, "", false, false).unwrap();
+        vm.set_gc_stress(true);
+        let res = vm.run().map_err(|err| eval_err_diagnostic(&vm, err));
+        println!("{res:?}");
+        assert!(res.is_ok());
+        println!("{} objects alive", vm.gc_object_count());
+        assert!(vm.gc_object_count() < 1000);
+    }
+
+    #[test]
+    fn gc_deep_chain() {
+        let src = r#"
+            var list = nil;
+            for (var i: 0..5000) {
+                list = [i, list];
+            }
+            var len = 0;
+            while (list) {
+                len = len + 1;
+                list = list[1];
+            }
+            if (len != 5000) {
+                except "gc freed live objects";
+            }
+        "#;
+        // marking must not recurse along the chain, a small stack would overflow
+        let handle = std::thread::Builder::new()
+            .stack_size(256 * 1024)
+            .spawn(move || {
+                let mut vm = compile_vm(src, "", false, false).unwrap();
+                vm.set_gc_stress(true);
+                vm.run().map_err(|err| eval_err_diagnostic(&vm, err))
+            })
+            .unwrap();
+        let res = handle.join().unwrap();
+        assert!(res.is_ok(), "{res:?}");
+    }
+
+    #[test]
+    fn gc_shapes() {
+        let src = r#"
+            func make(i) {
+                class P {
+                    func __init__(v) {
+                        if (v % 2 == 0) {
+                            this.a = v;
+                            this.b = -v;
+                        } else {
+                            this.b = -v;
+                            this.a = v;
+                        }
+                    }
+                    func sum() {
+                        return this.a + this.b + this.a;
+                    }
+                }
+                return P(i);
+            }
+            var total = 0;
+            for (var i: 0..300) {
+                total = total + make(i).sum();
+            }
+            if (total != 44850) {
+                except "stale inline cache";
+            }
+        "#;
+        // every call makes a class, its shapes go with it
+        let mut vm = compile_vm(src, "", false, false).unwrap();
+        vm.set_gc_stress(true);
+        let res = vm.run().map_err(|err| eval_err_diagnostic(&vm, err));
+        assert!(res.is_ok(), "{res:?}");
+        assert!(vm.gc_object_count() < 100, "{}", vm.gc_object_count());
+    }
+
+    #[test]
+    fn disasm() {
+        let src = r#"
+            var greeting = "hi";
+            func adder(x) {
+                return |y| { return x + y; };
+            }
+            while (greeting) {
+                greeting = nil;
+            }
+        "#;
+        let listing = disassemble_program(src, "prog.slt", false).unwrap();
+        println!("{listing}");
+        let sections: Vec<&str> = listing.lines().filter(|l| l.starts_with("==")).collect();
+        assert_eq!(
+            sections,
+            [
+                "== #0 <main> (prog.slt) ==",
+                "== #1 adder (prog.slt) ==",
+                "== #2 <lambda> (prog.slt) =="
+            ]
+        );
+        assert!(listing.contains("; \"hi\""));
+        assert!(listing.contains("SetGlobal           1  ; greeting"));
+        assert!(listing.contains("; #1 adder"));
+        assert!(listing.contains("local 0 `x`"));
+        assert!(listing.contains("GetUpValue          0  ; `x`"));
+        // the loop jumps back to its condition
+        assert!(listing.contains("Jump               -5  ; -> 0004"));
+    }
+
+    #[test]
+    fn optimize() {
+        let src = r#"
+            var day = 60 * 60 * 24;
+            var name = "sl" + "oth";
+            func sign(x) {
+                if (x > 0 and x < 100) {
+                    return 1;
+                } else {
+                    return -1;
+                }
+                print("never");
+            }
+            var i = 0;
+            while (i < 3) {
+                if (i == 1) { i = i + 1; continue; }
+                i = i + 1;
+            }
+            print(day, name, sign(5), sign(500), i, -(2 - 3) % 3);
+        "#;
+        let plain = disassemble_program(src, "prog.slt", false).unwrap();
+        let listing = disassemble_program(src, "prog.slt", true).unwrap();
+        println!("{listing}");
+        assert!(plain.contains("Nop"));
+        assert!(!listing.contains("Nop"));
+        assert!(!listing.contains("Mul"));
+        assert!(!listing.contains("\"never\""));
+        assert!(listing.contains("; 86400"));
+        assert!(listing.contains("; \"sloth\""));
+        assert!(listing.contains("; -1"));
+        // `and` jumps straight past the `if` when its left side is false
+        assert!(listing.contains("JumpIfNot          +8  ; -> 0011"));
+        assert_eq!(run_capture(src).unwrap(), "86400 sloth 1 -1 3 1 ");
+        assert_eq!(run_capture_optimized(src).unwrap(), "86400 sloth 1 -1 3 1 ");
+    }
+
+    #[test]
+    fn quickening() {
+        let src = r#"
+            func count(lo, hi) {
+                var n = 0;
+                while (lo < hi) {
+                    lo = lo + 1;
+                    n = n + 1;
+                }
+                return n;
+            }
+            func add(a, b) { return a + b; }
+            class V {
+                func __init__(x) { this.x = x; }
+                func __add__(o) { return V(this.x + o); }
+                func __lt__(o) { return this.x < o; }
+            }
+            print(count(0, 3), add(1, 2), add("a", "b"), add(V(1), 2).x);
+            print(count(V(0), 3), add(3, 4), count(1, 3));
+        "#;
+        let mut vm = compile_vm(src, "prog.slt", false, false).unwrap();
+        assert_eq!(capture(&mut vm).unwrap(), "3 3 ab 3 3 7 2 ");
+        let listing = crate::disassemble(vm.main_chunk());
+        println!("{listing}");
+        // numbers again after the instance, quickened again
+        assert!(listing.contains("LtJumpIfNot"));
+        assert!(listing.contains("IncLocal            0  ; lo"));
+        assert!(listing.contains("IncLocal            2  ; n"));
+        assert!(listing.contains("GetLocalAdd         1  ; b"));
+        // saved as they were compiled
+        let plain = disassemble_program(src, "prog.slt", false).unwrap();
+        let mut string_pool = crate::StringPool::new();
+        let bytecode = crate::serialize::save_chunk(vm.main_chunk()).unwrap();
+        let loaded = crate::serialize::load_chunk(&bytecode, &mut string_pool).unwrap();
+        assert_eq!(crate::disassemble(&loaded), plain);
+    }
+
+    #[test]
+    fn value_packing() {
+        use crate::{Unboxed, Value};
+        assert_eq!(std::mem::size_of::<Value>(), 8);
+        assert_eq!(Value::number(-0.5).as_number(), Some(-0.5));
+        assert!(Value::number(f64::NAN).as_number().unwrap().is_nan());
+        assert!(matches!(Value::number(f64::INFINITY).unbox(), Unboxed::Number(_)));
+        assert!(Value::NIL.is_nil() && !Value::NIL.to_bool() && Value::TRUE.to_bool());
+        assert!(Value::number(0.).to_bool());
+        let src = r#"
+            var r = 3..0;
+            var s = "";
+            for (var i: r) { s = s + string(i); }
+            for (var i: r) { s = s + string(i); }
+            var t = [];
+            for (var c: "aé😀") { t.push(c); }
+            var n = 0;
+            while (n < 2000) { var tmp = "x" + string(n); n = n + 1; }
+            print(s, t[0], t[1], t[2], type_string(r));
+        "#;
+        assert_eq!(run_capture(src).unwrap(), "321321 a é 😀 Range ");
+    }
+
+    #[test]
+    fn ast() {
+        use crate::ast::{BinaryOp, Expr, Stmt, StrPart};
+        let src = "var total = 1 + 2 * x;\nprint(\"total: ${total}\");\n";
+        let prog = parse_program(src, "prog.slt").unwrap();
+        println!("{prog:?}");
+        assert_eq!(prog.stmts.len(), 2);
+        let Stmt::Var {
+            name,
+            init: Some(Expr::Binary { op, rhs, span, .. }),
+            ..
+        } = &prog.stmts[0]
+        else {
+            panic!("not a declaration");
+        };
+        assert_eq!((name.name.as_str(), op, span.pos), ("total", &BinaryOp::Add, (1, 15)));
+        assert!(matches!(rhs.as_ref(), Expr::Binary { op: BinaryOp::Mul, .. }));
+        let Stmt::Expr {
+            expr: Expr::Call { args, .. },
+            ..
+        } = &prog.stmts[1]
+        else {
+            panic!("not a call");
+        };
+        let Expr::Interpolation(parts) = &args[0] else {
+            panic!("not interpolated");
+        };
+        assert!(matches!(&parts[0], StrPart::Str(s, _) if s == "total: "));
+        assert!(matches!(&parts[1], StrPart::Expr { expr: Expr::Name(id), .. } if id.name == "total"));
+        assert!(parse_program("var a = (1;", "prog.slt").is_err());
+    }
+
+    #[test]
+    fn format() {
+        let src = r#"
+// squares
+var  xs=[1,2,3] |> map(|x|{return x*x;}) ;   // inline lambda
+func  show(d){
+  for(var k : d){print("${k}: ${d[k]}\n");}
+    /* after
+       the loop */
+}
+
+
+var d = @("a":1,"b" : (1+2)*3);
+var l = |it| {
+  return -(-it);
+};
+show(
+  d, // why
+  "x"
+);
+"#;
+        let expected = r#"// squares
+var xs = [1, 2, 3] |> map(|x| { return x * x; }); // inline lambda
+func show(d) {
+    for (var k: d) {
+        print("${k}: ${d[k]}\n");
+    }
+    /* after
+       the loop */
+}
+
+var d = @("a": 1, "b": (1 + 2) * 3);
+var l = |it| {
+    return -(-it);
+};
+show(
+    d, // why
+    "x"
+);
+"#;
+        let formatted = format_program(src, "prog.slt").unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(format_program(&formatted, "prog.slt").unwrap(), formatted);
+        assert!(format_program("var a = ;", "prog.slt").is_err());
+    }
+
+    #[test]
+    fn format_inline_comments() {
+        let src = r#"func h(a /* c */, b, /* d */ c) /* e */ {
+    if (a) {
+        print(a);
+    } /* between */ else {
+        print(b /* f */ + c);
+    }
+    try { g(); } /* t */ catch (e) {}
+    return  /* r */ [a, /* i */ b];
+}
+var l = |x /* p */| { return x; };
+"#;
+        let expected = r#"func h(a /* c */, b, /* d */ c) /* e */ {
+    if (a) {
+        print(a);
+    } /* between */ else {
+        print(b /* f */ + c);
+    }
+    try {
+        g();
+    } /* t */ catch (e) {}
+    return /* r */ [a, /* i */ b];
+}
+var l = |x /* p */| { return x; };
+"#;
+        let formatted = format_program(src, "prog.slt").unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(format_program(&formatted, "prog.slt").unwrap(), formatted);
+
+        // a line comment ends the line, the rest of the statement follows it
+        let src = "if (a) {\n} // between\nelse {\n}\nfunc k(a, // first\n    b) {}\n";
+        let expected = "if (a) {} // between\n    else {}\nfunc k(a, // first\n    b) {}\n";
+        let formatted = format_program(src, "prog.slt").unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(format_program(&formatted, "prog.slt").unwrap(), formatted);
+    }
+
+    #[test]
+    fn lint() {
+        let src = r#"func area(w, h, unit) {
+    var scale = 2;
+    return w * h;
+    print(unit);
+}
+class Shape {
+    func __init__(name) { this.name = name; }
+    func describe() { return super.describe(); }
+}
+class Square : Shape {
+    func describe() {
+        var name = this.name;
+        return super.area() + name;
+    }
+}
+func main() {
+    var area = 1; // lint: allow(unused-variable)
+    total = area;
+    while (true) { break; var _i = 0; }
+    try { except "x"; } catch (e) { print(e); }
+    return [Shape(), Square("s"), area(1, 2)];
+}
+"#;
+        let warnings = lint_program(src, "lint.slt").unwrap();
+        let found: Vec<_> = warnings
+            .iter()
+            .map(|w| (w.code.unwrap(), w.line, w.col))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("unused-variable", 2, 9),
+                ("unreachable-code", 4, 5),
+                ("super-without-superclass", 8, 30),
+                ("unknown-super-method", 13, 22),
+                ("shadowing", 17, 9),
+                ("undeclared-global", 18, 5),
+                ("unreachable-code", 19, 31),
+                ("argument-count", 21, 13),
+            ]
+        );
+        assert!(warnings.iter().all(|w| w.file == "lint.slt"));
+        assert!(warnings[0].render(src).starts_with("warning[unused-variable]: "));
+        assert!(lint_program("var a = ;", "lint.slt").is_err());
+        let arity = |src| lint_program(src, "lint.slt").unwrap()[0].message.clone();
+        assert_eq!(
+            arity("func f(x) { return x; }\nf();"),
+            "`f` takes 1 argument but 0 are given"
+        );
+        assert_eq!(
+            arity("func f(x, y) { return x + y; }\nf(1);"),
+            "`f` takes 2 arguments but 1 is given"
+        );
+        let clean = "func twice(f, x) { return f(f(x)); }\nprint(twice(|x| { return x * 2; }, 1));";
+        assert_eq!(lint_program(clean, "lint.slt").unwrap(), Vec::new());
+    }
+
+    #[test]
+    fn repl() {
+        use crate::Repl;
+        let mut repl = Repl::new();
+        assert_eq!(repl.eval("var x = 40;"), Ok(None));
+        assert_eq!(repl.eval("x + 2"), Ok(Some("42".to_owned())));
+        assert_eq!(repl.eval("\"a\" + \"b\";"), Ok(Some("\"ab\"".to_owned())));
+        let src = "func counter() {\n    var n = 0;\n    return || { n = n + 1; return n; };\n}";
+        assert!(!Repl::is_complete("func counter() {\n    var n = 0;"));
+        assert!(Repl::is_complete(src));
+        assert_eq!(repl.eval(src), Ok(None));
+        assert_eq!(repl.eval("var c = counter(); c();"), Ok(Some("1".to_owned())));
+        // functions from earlier inputs survive later ones
+        assert_eq!(repl.eval("c()"), Ok(Some("2".to_owned())));
+
+        let res = repl.eval("x / nil");
+        println!("{res:?}");
+        assert!(res.is_err());
+        // a failed input leaves the globals usable
+        assert_eq!(repl.eval("x"), Ok(Some("40".to_owned())));
+
+        let globals = repl.run_command(":globals").unwrap().unwrap();
+        println!("{globals}");
+        assert!(globals.lines().any(|l| l == "x = 40"));
+        assert!(!globals.contains("print"));
+        let listing = repl.run_command(":dis x + 1").unwrap().unwrap();
+        assert!(listing.contains("GetGlobal") && listing.contains("Return"));
+        assert!(repl.run_command(":nope").is_err());
+    }
+
+    #[test]
+    fn debugger() {
+        use crate::{DebugEvent, PauseReason, StepMode, Value};
+        let src = [
+            "func add(a, b) {",
+            "    var sum = a + b;",
+            "    return sum;",
+            "}",
+            "",
+            "var x = 1;",
+            "var y = add(x, 2);",
+            "{",
+            "    var z = y * 2;",
+            "    print(z);",
+            "}",
+            "func make() {",
+            "    var n = 10;",
+            "    return || {",
+            "        n = n + 1;",
+            "        return n;",
+            "    };",
+            "}",
+            "var r = make()();",
+        ]
+        .join("\n");
+        let mut vm = compile_vm(&src, "dbg.slt", false, false).unwrap();
+        vm.enable_debugger(false);
+        // line 5 has no code
+        assert_eq!(
+            vm.set_breakpoints("dbg.slt", &[2, 5, 15, 40]),
+            [Some(2), Some(6), Some(15), None]
+        );
+        vm.set_breakpoints("/somewhere/dbg.slt", &[2, 15]);
+        let paused = DebugEvent::Paused(PauseReason::Breakpoint);
+        let stepped = DebugEvent::Paused(PauseReason::Step);
+
+        assert_eq!(vm.debug_run(StepMode::Continue).unwrap(), paused);
+        let frames = vm.debug_frames();
+        assert_eq!(
+            frames.iter().map(|f| (f.name.as_str(), f.line)).collect::<Vec<_>>(),
+            [("add", 2), ("<main>", 7)]
+        );
+        let locals = vm.debug_locals(0);
+        assert_eq!(
+            locals,
+            [("a".to_owned(), Value::number(1.)), ("b".to_owned(), Value::number(2.))]
+        );
+        assert_eq!(vm.debug_eval(0, "a * 10").unwrap(), Value::number(10.));
+        assert_eq!(vm.debug_eval(0, "b = 5;").unwrap(), Value::NIL);
+        assert!(vm.debug_eval(0, "(a").is_err());
+        assert!(vm.debug_eval(0, "a / nil").is_err());
+        assert_eq!(vm.debug_eval(0, "a").unwrap(), Value::number(1.));
+
+        assert_eq!(vm.debug_run(StepMode::StepOver).unwrap(), stepped);
+        assert_eq!(vm.debug_frames()[0].line, 3);
+        assert_eq!(vm.debug_locals(0)[2], ("sum".to_owned(), Value::number(6.)));
+
+        assert_eq!(vm.debug_run(StepMode::StepOut).unwrap(), stepped);
+        assert_eq!(vm.debug_frames().len(), 1);
+        assert_eq!(vm.debug_frames()[0].line, 9);
+        assert_eq!(vm.debug_eval(0, "y").unwrap(), Value::number(6.));
+        assert!(vm.debug_locals(0).is_empty());
+
+        assert_eq!(vm.debug_run(StepMode::StepIn).unwrap(), stepped);
+        assert_eq!(vm.debug_frames()[0].line, 10);
+        assert_eq!(vm.debug_locals(0), [("z".to_owned(), Value::number(12.))]);
+
+        assert_eq!(vm.debug_run(StepMode::Continue).unwrap(), paused);
+        assert_eq!(vm.debug_frames()[0].name, "<lambda>");
+        assert_eq!(vm.debug_upvalues(0), [("n".to_owned(), Value::number(10.))]);
+        vm.debug_eval(0, "n = 100;").unwrap();
+
+        assert_eq!(vm.debug_run(StepMode::Continue).unwrap(), DebugEvent::Finished);
+        let r = vm.globals().find(|(k, _)| k.get_inner() == "r");
+        assert_eq!(r.map(|(_, v)| v), Some(&Value::number(101.)));
+    }
+
+    #[test]
+    fn bytecode_round_trip() {
+        let src = r#"
+            class Counter {
+                func __init__(start) {
+                    this.n = start;
+                }
+                func inc() {
+                    this.n = this.n + 1;
+                    return this.n;
+                }
+            }
+            func adder(x) {
+                return |y| { return x + y; };
+            }
+            var c = Counter(-1.5);
+            c.inc();
+            var e = try [].pop();
+            var s = "sloth" + string(adder(2)(c.n));
+            if (s != "sloth1.5" or type_string(e) != "Err") {
+                except "bytecode changed meaning";
+            }
+            except "at the end";
+        "#;
+        let bytecode = compile_to_bytecode(src, "prog.slt", false).unwrap();
+        let res = run_bytecode(&bytecode, "prog.sltc", false);
+        println!("{res:?}");
+        let diag = res.unwrap_err();
+        assert_eq!((diag.file.as_str(), diag.line), ("prog.slt", 21));
+        assert!(diag.message.contains("at the end"));
+
+        let res = run_bytecode(&bytecode[..bytecode.len() - 1], "prog.sltc", false);
+        println!("{res:?}");
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn bytecode_corrupt() {
+        let src = r#"
+            func adder(x) {
+                return |y| { return x + y; };
+            }
+            var s = 0;
+            for (var i: 0..3) {
+                s = s + adder(i)(1);
+            }
+            var a = [s, 1];
+            var d = @("s": s);
+            match (a) { [x, ...r] => { s = r; } }
+        "#;
+        let bytecode = compile_to_bytecode(src, "prog.slt", false).unwrap();
+        for len in 0..bytecode.len() {
+            assert!(run_bytecode(&bytecode[..len], "prog.sltc", false).is_err());
+        }
+        let mut string_pool = StringPool::new();
+        let good = serialize::load_chunk(&bytecode, &mut string_pool).unwrap();
+        // every operand pointing past what the chunk has
+        let corrupted = |f: &dyn Fn(&mut Chunk)| {
+            let mut chunk = good.clone();
+            f(&mut chunk);
+            let bytes = serialize::save_chunk(&chunk).unwrap();
+            let res = serialize::load_chunk(&bytes, &mut StringPool::new());
+            println!("{res:?}");
+            res.unwrap_err()
+        };
+        let replace = |chunk: &mut Chunk, pred: fn(&Instr) -> bool, instr: Instr| {
+            let pc = chunk.bytecodes.iter().position(pred).unwrap();
+            chunk.bytecodes[pc] = instr;
+        };
+        corrupted(&|c| replace(c, |i| matches!(i, Instr::Load(_)), Instr::Load(99)));
+        corrupted(&|c| replace(c, |i| matches!(i, Instr::LoadChunk(_)), Instr::LoadChunk(9)));
+        corrupted(&|c| replace(c, |i| matches!(i, Instr::GetLocal(_)), Instr::GetLocal(99)));
+        corrupted(&|c| replace(c, |i| matches!(i, Instr::Jump(_)), Instr::Jump(999)));
+        corrupted(&|c| replace(c, |i| matches!(i, Instr::Jump(_)), Instr::Jump(-999)));
+        corrupted(&|c| {
+            c.bytecodes.push(Instr::Pop);
+            c.cood.push((0, 0));
+        });
+        corrupted(&|c| c.chunks[0].num_locals = 0);
+        corrupted(&|c| {
+            let inner = &mut c.chunks[0].chunks[0];
+            replace(inner, |i| matches!(i, Instr::GetUpValue(_)), Instr::GetUpValue(5))
+        });
+        corrupted(&|c| {
+            let name = c.chunks[0].chunks[0].upvalues[0].clone();
+            if let UpValueDecl::Ref(_, name) = name {
+                c.chunks[0].chunks[0].upvalues[0] = UpValueDecl::Ref(99, name);
+            }
+        });
+        // operands counting values on the stack load, but fail when run
+        let failing = |f: &dyn Fn(&mut Chunk)| {
+            let mut chunk = good.clone();
+            f(&mut chunk);
+            let bytes = serialize::save_chunk(&chunk).unwrap();
+            let res = run_bytecode(&bytes, "prog.sltc", false);
+            println!("{res:?}");
+            assert!(res.is_err());
+        };
+        failing(&|c| replace(c, |i| matches!(i, Instr::Call(_)), Instr::Call(99)));
+        failing(&|c| replace(c, |i| matches!(i, Instr::Call(_)), Instr::TryCall(usize::MAX)));
+        failing(&|c| replace(c, |i| matches!(i, Instr::InitArray(_)), Instr::InitArray(99)));
+        failing(&|c| replace(c, |i| matches!(i, Instr::InitDict(_)), Instr::InitDict(99)));
+        failing(&|c| replace(c, |i| matches!(i, Instr::InitDict(_)), Instr::Dup(99)));
+        failing(&|c| {
+            let name = c.constants.iter().position(|v| matches!(v.unbox(), Unboxed::String(_)));
+            replace(c, |i| matches!(i, Instr::Call(_)), Instr::Invoke(name.unwrap() as u32, 99))
+        });
+        failing(&|c| replace(c, |i| matches!(i, Instr::ArraySlice(_)), Instr::ArraySlice(99)));
+    }
+
+    #[test]
+    fn import_bytecode() {
+        let dir = std::env::temp_dir().join(format!("sloth_import_bytecode_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let module = r#"
+            var answer = 42;
+            func twice(x) {
+                return 2 * x;
+            }
+        "#;
+        let bytecode = compile_to_bytecode(module, "m.slt", false).unwrap();
+        // only the bytecode is there
+        std::fs::write(dir.join("m.sltc"), bytecode).unwrap();
+        let src = format!(
+            r#"
+            var m = import("{0}/m.slt");
+            var n = import("{0}/m.sltc");
+            if (m.twice(m.answer) != 84 or n.answer != 42) {{
+                except "wrong module";
+            }}
+            "#,
+            dir.display()
+        );
+        let res = run_string(&src, false);
+        println!("{res:?}");
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn global_slots() {
+        let dir = std::env::temp_dir().join(format!("sloth_global_slots_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let module = r#"
+            var scale = 2;
+            func area(r) {
+                return scale * r * r + offset;
+            }
+            func peek() {
+                return tail;
+            }
+            print("loaded");
+        "#;
+        std::fs::write(dir.join("m.slt"), module).unwrap();
+        let src = format!(
+            r#"
+            var scale = 100;
+            var offset = 1;
+            func late() {{
+                return area(1);
+            }}
+            var m = import("{0}/m.slt");
+            print(m.area(3));
+            as_glob(m);
+            var tail = "t";
+            print(late(), scale, m.peek());
+            "#,
+            dir.display()
+        );
+        let out = run_capture(&src);
+        std::fs::remove_dir_all(&dir).unwrap();
+        // module functions use the globals of their module, then of the main one
+        assert_eq!(out.unwrap(), "loaded 19 3 2 t ");
+        let err = run_string("func f() { return missing; } f();", false).unwrap_err();
+        assert!(err.message.contains("global variable `missing` not found"));
+    }
+
+    #[test]
+    fn inline_caches() {
+        let src = r#"
+            class Point {
+                func __init__(x, y) {
+                    if (x > 1) {
+                        this.y = y;
+                        this.x = x;
+                    } else {
+                        this.x = x;
+                        this.y = y;
+                    }
+                    this.scaled = |k| { return this.x * k; };
+                }
+                func sum() {
+                    return this.x + this.y;
+                }
+            }
+            var total = 0;
+            var points = [];
+            for (var i: 0..4) {
+                var p = Point(i, 10);
+                points.push(p);
+                total = total + p.sum() + p.scaled(2);
+            }
+            points[3].x = 100;
+            print(total, points.pop().sum(), points[0].y);
+        "#;
+        // objects built in different field orders share the call sites
+        assert_eq!(run_capture(src).unwrap(), "58 110 10 ");
+
+        let listing = disassemble_program(src, "prog.slt", false).unwrap();
+        assert!(listing.contains("Invoke"));
+        assert!(listing.contains("SetField"));
+        assert!(listing.contains("GetField"));
+        let err = run_string("class A {} var a = A(); a.missing();", false).unwrap_err();
+        assert!(err.message.contains("method not found"));
+    }
+
+    #[test]
+    fn open_upvalues() {
+        let src = r#"
+            func pair() {
+                var n = 0;
+                var inc = || { n = n + 1; return n; };
+                var get = || { return n; };
+                return [inc, get];
+            }
+            func deep(d) {
+                var x = d;
+                var c = || { return x; };
+                if (d == 0) {
+                    return c();
+                }
+                return deep(d - 1) + c();
+            }
+            var f = fiber.create(|k|{
+                var bump = || { k = k + 1; return k; };
+                fiber.yield(bump());
+                fiber.yield(bump());
+            }, 5);
+            print(fiber.resume(f));
+            var p = pair();
+            p[0]();
+            p[0]();
+            print(p[1](), deep(200), fiber.resume(f));
+        "#;
+        // closures capturing one local share it, returns in the main fiber
+        // leave the upvalues of the paused one open
+        assert_eq!(run_capture(src).unwrap(), "6 2 20100 7 ");
+    }
+
+    #[test]
+    fn loop_bindings() {
+        let src = r#"
+            var fs = [];
+            for (var i: 0..3) {
+                var sq = i * i;
+                fs.push(|| { return [i, sq]; });
+            }
+            var gs = [];
+            var j = 0;
+            while (j < 4) {
+                var k = j;
+                j = j + 1;
+                if (k == 1) {
+                    gs.push(|| { return k; });
+                    continue;
+                }
+                {
+                    var inner = k * 10;
+                    gs.push(|| { return inner; });
+                }
+                if (k == 3) {
+                    break;
+                }
+            }
+            func counters() {
+                var hs = [];
+                var n = 0;
+                while (n < 2) {
+                    var m = n;
+                    hs.push(|| { m = m + 100; return m; });
+                    n = n + 1;
+                }
+                return hs;
+            }
+            var hs = counters();
+            print(fs[0](), fs[2](), gs[0](), gs[1](), gs[2](), gs[3]());
+            print(hs[0](), hs[1](), hs[0]());
+        "#;
+        // every iteration captures its own variables
+        assert_eq!(run_capture(src).unwrap(), "[0,0,] [2,4,] 0 1 20 30 100 101 200 ");
+
+        let listing = disassemble_program("while (true) { var x = 1; }", "a.slt", false).unwrap();
+        assert!(!listing.contains("CloseUpValues"));
+    }
+
+    #[test]
+    fn compound_assignment() {
+        let src = r#"
+            class V {
+                func __init__(x) {
+                    this.x = x;
+                    this.score = 0;
+                }
+                func __add__(o) {
+                    return V(this.x + o.x);
+                }
+            }
+            class Store {
+                func __init__() {
+                    this.d = @("a": 1);
+                    this.reads = 0;
+                }
+                func __index__(k) {
+                    this.reads += 1;
+                    return this.d[k];
+                }
+                func __assign__(k, v) {
+                    this.d[k] = v;
+                }
+            }
+            var g = 10;
+            g -= 3;
+            g *= 2;
+            func f() {
+                var loc = 7;
+                loc %= 4;
+                var up = 1;
+                var inc = || { up += 10; return up; };
+                inc();
+                return [loc, inc()];
+            }
+            var arr = [1, 2, 3];
+            var calls = 0;
+            func idx() {
+                calls += 1;
+                return 1;
+            }
+            arr[idx()] += 40;
+            var d = @("k": 5);
+            d.k /= 2;
+            var v = V(1);
+            v += V(2);
+            v.score += 1;
+            var s = Store();
+            s["a"] += 5;
+            print(g, f(), arr, calls, d.k, v.x, v.score, s.d["a"], s.reads);
+        "#;
+        // the index is evaluated once, overloads are used
+        assert_eq!(run_capture(src).unwrap(), "14 [3,21,] [1,42,3,] 1 2.5 3 1 6 1 ");
+
+        let formatted = format_program("x+=1;\nthis.n  %=  [2][0];", "prog.slt").unwrap();
+        assert_eq!(formatted, "x += 1;\nthis.n %= [2][0];\n");
+        let err = run_string("var a = nil; a -= 1;", false).unwrap_err();
+        assert_eq!(err.col, 16);
+    }
+
+    #[test]
+    fn nil_operators() {
+        let src = r#"
+            var cfg = @("db": @("port": 5432, "hosts": ["a", "b"]), "debug": false);
+            var none = nil;
+            var calls = 0;
+            func side() {
+                calls = calls + 1;
+                return 0;
+            }
+            print(cfg?.db?.port, none?.db?.port, none?[side()], cfg.db?.hosts?[1]);
+            print(none?.f(side()), none ?? "dflt", cfg.debug ?? true, 1 ?? side(), calls);
+            var x = 5;
+            print(x > 3 ? x > 4 ? "big" : "mid" : "small", false ? 1 : nil ? 2 : 3);
+            print(none?.db.port, none?[side()].hosts[0], none?.f(side()).g().h, cfg?.db.port, calls);
+        "#;
+        // the right side of `??` and the rest of the chain after `?.` on nil are not evaluated
+        assert_eq!(
+            run_capture_optimized(src).unwrap(),
+            "5432 Nil Nil b Nil dflt false 1 0 big 3 Nil Nil Nil 5432 0 "
+        );
+
+        let formatted = format_program("var a=b?.c?[0]??(d?e:f);", "prog.slt").unwrap();
+        assert_eq!(formatted, "var a = b?.c?[0] ?? (d ? e : f);\n");
+        let err = run_string("var n = nil; print(n.port);", false).unwrap_err();
+        assert!(err.message.contains("can not be indexed"));
+        let err = run_string("var n = @(); n?.a = 1;", false).unwrap_err();
+        assert_eq!(err.message, "expect `;`, found `=`");
+        let err = run_string("var a = 1 n;", false).unwrap_err();
+        assert_eq!(err.message, "expect `;`, found identifier `n`");
+        let err = run_string("var s = \"a\" \"b\";", false).unwrap_err();
+        assert_eq!(err.message, "expect `;`, found string \"b\"");
+    }
+
+    #[test]
+    fn match_patterns() {
+        let src = r#"
+            class Point {
+                func __init__(x, y) {
+                    this.x = x;
+                    this.y = y;
+                }
+            }
+            func describe(v) {
+                match (v) {
+                    0 => { return "zero"; }
+                    -1..=9 => { return "digit"; }
+                    "hi" => { return "greeting"; }
+                    [] => { return "empty"; }
+                    [first, ...rest] => { return "${first}+${rest}"; }
+                    @("kind": "move", "dx": dx) => { return "move ${dx}"; }
+                    Point(x: 0, y) => { return "y axis ${y}"; }
+                    Point(x, y) if x == y => { return "diagonal ${x}"; }
+                    p is Point => { return "point ${p.x}"; }
+                    _ => { return "other"; }
+                }
+            }
+            for (var v: [0, 7, 10, "hi", [], [1, 2, 3], @("kind": "move", "dx": 2), Point(0, 4), Point(2, 2), Point(5, 1), @("kind": 1)]) {
+                print(describe(v));
+            }
+            var fs = [];
+            for (var pair: [[1, 2], [3, 4]]) {
+                match (pair) {
+                    [a, b] => { fs = fs + [|| { return a + b; }]; }
+                }
+            }
+            print(fs[0](), fs[1]());
+            func bucket(v) {
+                match (v) {
+                    0..10 => { return "low"; }
+                    10..=20 => { return "mid"; }
+                    _ => { return "out"; }
+                }
+            }
+            print(bucket(9.5), bucket(10), bucket(20), bucket(20.5), bucket(-0.5), bucket("a"));
+        "#;
+        let expected = "zero digit other greeting empty 1+[2,3,] move 2 y axis 4 diagonal 2 point 5 other 3 7 \
+            low mid mid out out out ";
+        // literals of other types do not match nor raise
+        assert_eq!(run_capture(src).unwrap(), expected);
+        assert_eq!(run_capture_optimized(src).unwrap(), expected);
+
+        let formatted =
+            format_program("match (v) { [a,...r] if a>0 => {} Point(x,y:_) => {} }", "prog.slt")
+                .unwrap();
+        assert_eq!(
+            formatted,
+            "match (v) {\n    [a, ...r] if a > 0 => {}\n    Point(x, y: _) => {}\n}\n"
+        );
+        let err = run_string("match (1) { [x, x] => {} }", false).unwrap_err();
+        assert!(err.message.contains("redeclaration"));
+        let err = run_string("match (1) { 1..\"a\" => {} }", false).unwrap_err();
+        assert!(err.message.contains("range pattern expects numbers"));
+
+        // fields are looked up on the instance, wherever they were assigned
+        let src = r#"
+            class P {
+                func __init__(x) {
+                    this.x = x;
+                }
+            }
+            var p = P(1);
+            p.extra = 5;
+            match (p) {
+                P(extra) => { print(extra); }
+            }
+            match (P(2)) {
+                P(extra) => { print("extra"); }
+                P(a, b) => { print("a b"); }
+                P(x) => { print(x); }
+            }
+        "#;
+        // a pattern of fields the instance lacks does not match
+        assert_eq!(run_capture(src).unwrap(), "5 2 ");
+    }
+
     #[test]
     fn example_for() {
         let src = r#"
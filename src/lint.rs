@@ -0,0 +1,555 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::*;
+use crate::compiler::codegen::CodeGen;
+use crate::compiler::symbols::{SymbolIndex, SymbolKind};
+use crate::interned_string::StringPool;
+use crate::{native_modules, parse_program, prelude, Diagnostic};
+
+/// warnings of `sloth lint` for `prog`, `Err` if it does not compile.
+/// every warning carries the id of its rule:
+/// - `unused-variable`, `unused-parameter`: locals never read
+/// - `shadowing`: a local hiding an outer declaration
+/// - `unreachable-code`: statements after `return`, `except`, `break` or `continue`
+/// - `undeclared-global`: assignment to a name declared nowhere
+/// - `argument-count`: call of a function of the file with a wrong number of arguments
+/// - `super-without-superclass`: `super` in a class that extends nothing
+/// - `unknown-super-method`: `super.method` that no superclass declares
+///
+/// names starting with `_` are never unused nor shadowing.
+/// `// lint: allow(rule, ...)` on the line of a warning or the line
+/// before it suppresses the warning.
+pub fn lint_program(prog: &str, file: &str) -> Result<Vec<Diagnostic>, Diagnostic> {
+    let program = parse_program(prog, file)?;
+    let mut string_pool = StringPool::new();
+    let mut codegen = CodeGen::new(&mut string_pool);
+    codegen.set_file(file);
+    codegen
+        .gen_prog(&program)
+        .map_err(|diag| diag.with_file(file))?;
+    let symbols = codegen.finish().symbols;
+    let warnings = lint(&program, &symbols);
+    Ok(warnings
+        .into_iter()
+        .map(|diag| diag.with_file(file))
+        .collect())
+}
+
+/// warnings for a program that compiled into `symbols`
+pub(crate) fn lint(program: &Program, symbols: &SymbolIndex) -> Vec<Diagnostic> {
+    let mut builtins: HashSet<String> = prelude().into_iter().map(|(name, _)| name).collect();
+    builtins.extend(native_modules().into_iter().map(|(name, _)| name));
+    let mut linter = Linter {
+        symbols,
+        defs: symbols
+            .defs
+            .iter()
+            .enumerate()
+            .map(|(id, def)| (def.pos, id))
+            .collect(),
+        refs: symbols
+            .refs
+            .iter()
+            .filter_map(|r| Some((r.pos, r.def?)))
+            .collect(),
+        builtins,
+        read: vec![false; symbols.defs.len()],
+        scopes: vec![Vec::new()],
+        locals: Vec::new(),
+        arities: HashMap::new(),
+        calls: Vec::new(),
+        assigned: HashSet::new(),
+        classes: HashMap::new(),
+        class: None,
+        supers: Vec::new(),
+        warnings: Vec::new(),
+    };
+    // globals are visible before they are declared
+    for stmt in program.stmts.iter() {
+        match stmt {
+            Stmt::Var { name, .. } => linter.declare(name),
+            Stmt::Func(FuncDecl { name, .. }) | Stmt::Class(ClassDecl { name, .. }) => {
+                linter.declare(name)
+            }
+            _ => {}
+        }
+    }
+    linter.stmts(&program.stmts);
+    linter.finish();
+    let allowed = allowed_rules(&program.comments);
+    let mut warnings: Vec<Diagnostic> = linter
+        .warnings
+        .into_iter()
+        .filter(|diag| {
+            let code = diag.code.unwrap_or_default();
+            !allowed.iter().any(|(line, rules)| {
+                (*line == diag.line || *line + 1 == diag.line) && rules.iter().any(|r| r == code)
+            })
+        })
+        .collect();
+    warnings.sort_by_key(|diag| (diag.line, diag.col));
+    warnings
+}
+
+/// rules listed by `lint: allow(...)` comments, by the line of the comment
+fn allowed_rules(comments: &[Comment]) -> Vec<(usize, Vec<String>)> {
+    let mut allowed = Vec::new();
+    for comment in comments {
+        let Some((_, rest)) = comment.text.split_once("lint: allow(") else {
+            continue;
+        };
+        let Some((rules, _)) = rest.split_once(')') else {
+            continue;
+        };
+        let rules = rules.split(',').map(|r| r.trim().to_owned()).collect();
+        allowed.push((comment.span.pos.0, rules));
+    }
+    allowed
+}
+
+/// control never reaches what comes after `stmt`
+fn terminates(stmt: &Stmt) -> bool {
+    match stmt {
+        Stmt::Return { .. } | Stmt::Except { .. } | Stmt::Break(_) | Stmt::Continue(_) => true,
+        Stmt::Block(block) => block.stmts.iter().any(terminates),
+        Stmt::If {
+            then,
+            otherwise: Some(otherwise),
+            ..
+        } => terminates(then) && terminates(otherwise),
+        Stmt::TryCatch { body, handler, .. } => {
+            body.stmts.iter().any(terminates) && handler.stmts.iter().any(terminates)
+        }
+        _ => false,
+    }
+}
+
+fn warning(rule: &'static str, span: Span, msg: String) -> Diagnostic {
+    Diagnostic::warning(span.pos, msg)
+        .with_span(span.len)
+        .with_code(rule)
+}
+
+#[derive(Clone, Copy)]
+struct ClassScope {
+    def: Option<usize>,
+    superclass: bool,
+}
+
+struct Linter<'a> {
+    symbols: &'a SymbolIndex,
+    /// index in `symbols.defs` by the position of the name
+    defs: HashMap<(usize, usize), usize>,
+    /// resolved uses, by position
+    refs: HashMap<(usize, usize), usize>,
+    /// prelude and native modules
+    builtins: HashSet<String>,
+    /// by index in `symbols.defs`
+    read: Vec<bool>,
+    /// names and defs visible from here, globals first
+    scopes: Vec<Vec<(String, usize)>>,
+    /// locals declared so far
+    locals: Vec<usize>,
+    /// parameter number and variadic of functions and constructors
+    arities: HashMap<usize, (usize, bool)>,
+    /// (callee, argument number, name at the call) of calls by name
+    calls: Vec<(usize, usize, Ident)>,
+    /// defs that are the target of an assignment
+    assigned: HashSet<usize>,
+    /// superclass and method names of classes
+    classes: HashMap<usize, (Option<Ident>, Vec<String>)>,
+    /// the innermost class around, `this` and `super` refer to it
+    class: Option<ClassScope>,
+    /// (class, method) of `super.method`
+    supers: Vec<(usize, Ident)>,
+    warnings: Vec<Diagnostic>,
+}
+
+impl Linter<'_> {
+    fn warn(&mut self, rule: &'static str, span: Span, msg: String) {
+        self.warnings.push(warning(rule, span, msg));
+    }
+
+    fn declare(&mut self, ident: &Ident) {
+        let Some(&def) = self.defs.get(&ident.span.pos) else {
+            return;
+        };
+        if self.scopes.len() > 1 {
+            let outer = self
+                .scopes
+                .iter()
+                .rev()
+                .flatten()
+                .find(|(name, _)| name == &ident.name)
+                .map(|(_, outer)| self.symbols.defs[*outer].pos);
+            if let Some((line, col)) = outer.filter(|_| !ident.name.starts_with('_')) {
+                let msg = format!("`{}` shadows an outer declaration", ident.name);
+                let note = format!("the outer `{}` is declared at {line}:{col}", ident.name);
+                let diag = warning("shadowing", ident.span, msg).with_note(note);
+                self.warnings.push(diag);
+            }
+            self.locals.push(def);
+        }
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .push((ident.name.clone(), def));
+    }
+
+    /// `stmts` in a scope of their own
+    fn scoped(&mut self, stmts: &[Stmt]) {
+        self.scopes.push(Vec::new());
+        self.stmts(stmts);
+        self.scopes.pop();
+    }
+
+    /// one warning for the statements after a terminating one
+    fn stmts(&mut self, stmts: &[Stmt]) {
+        let mut terminated = false;
+        let mut reported = false;
+        for stmt in stmts {
+            if terminated && !reported {
+                let span = Span {
+                    pos: stmt.start(),
+                    len: 1,
+                };
+                self.warn("unreachable-code", span, "unreachable statement".to_owned());
+                reported = true;
+            }
+            self.stmt(stmt);
+            terminated |= terminates(stmt);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Var { name, init, .. } => {
+                if let Some(init) = init {
+                    self.expr(init);
+                }
+                if self.scopes.len() > 1 {
+                    self.declare(name);
+                }
+            }
+            Stmt::Assign { target, value, .. } => {
+                match target {
+                    Expr::Name(ident) => self.assign(ident),
+                    _ => self.expr(target),
+                }
+                self.expr(value);
+            }
+            Stmt::Expr { expr, .. } => self.expr(expr),
+            Stmt::Block(block) => self.scoped(&block.stmts),
+            Stmt::If {
+                cond,
+                then,
+                otherwise,
+                ..
+            } => {
+                self.expr(cond);
+                self.stmt(then);
+                if let Some(otherwise) = otherwise {
+                    self.stmt(otherwise);
+                }
+            }
+            Stmt::While { cond, body, .. } => {
+                self.expr(cond);
+                self.scoped(&body.stmts);
+            }
+            Stmt::For {
+                var, iter, body, ..
+            } => {
+                self.expr(iter);
+                self.scopes.push(Vec::new());
+                self.declare(var);
+                self.stmts(&body.stmts);
+                self.scopes.pop();
+            }
+            Stmt::Func(decl) => {
+                if self.scopes.len() > 1 {
+                    self.declare(&decl.name);
+                }
+                if let Some(&def) = self.defs.get(&decl.name.span.pos) {
+                    let arity = (decl.func.params.len(), decl.func.variadic);
+                    self.arities.insert(def, arity);
+                }
+                self.function(&decl.func);
+            }
+            Stmt::Class(class) => self.class(class),
+            Stmt::Return { value, .. } | Stmt::Except { value, .. } => {
+                if let Some(value) = value {
+                    self.expr(value);
+                }
+            }
+            Stmt::TryCatch {
+                body, var, handler, ..
+            } => {
+                // the protected block shares the scope around it
+                self.stmts(&body.stmts);
+                self.scopes.push(Vec::new());
+                if let Some(var) = var {
+                    self.declare(var);
+                }
+                self.stmts(&handler.stmts);
+                self.scopes.pop();
+            }
+            Stmt::Match { subject, arms, .. } => {
+                self.expr(subject);
+                for arm in arms {
+                    self.scopes.push(Vec::new());
+                    self.pattern(&arm.pattern);
+                    if let Some(guard) = &arm.guard {
+                        self.expr(guard);
+                    }
+                    self.stmts(&arm.body.stmts);
+                    self.scopes.pop();
+                }
+            }
+            Stmt::Break(_) | Stmt::Continue(_) => {}
+        }
+    }
+
+    /// names bound by `pattern` and classes it refers to
+    fn pattern(&mut self, pattern: &Pattern) {
+        match pattern {
+            Pattern::Wildcard(_) | Pattern::Literal(_) | Pattern::Range { .. } => {}
+            Pattern::Bind(name) => self.declare(name),
+            Pattern::Array { items, rest, .. } => {
+                for item in items {
+                    self.pattern(item);
+                }
+                if let Some(rest) = rest {
+                    self.pattern(rest);
+                }
+            }
+            Pattern::Dict { entries, .. } => {
+                for (_, value) in entries {
+                    self.pattern(value);
+                }
+            }
+            Pattern::Class { class, fields, .. } => {
+                self.read(class);
+                for (_, value) in fields {
+                    self.pattern(value);
+                }
+            }
+            Pattern::Is { binding, class } => {
+                self.read(class);
+                self.pattern(binding);
+            }
+        }
+    }
+
+    fn class(&mut self, class: &ClassDecl) {
+        if self.scopes.len() > 1 {
+            self.declare(&class.name);
+        }
+        if let Some(superclass) = &class.superclass {
+            self.read(superclass);
+        }
+        let init = class.methods.iter().find(|m| m.name.name == "__init__");
+        if let (Some(init), Some(&def)) = (init, self.defs.get(&class.name.span.pos)) {
+            let arity = (init.func.params.len(), init.func.variadic);
+            self.arities.insert(def, arity);
+        }
+        let def = self.defs.get(&class.name.span.pos).copied();
+        if let Some(def) = def {
+            let methods = class.methods.iter().map(|m| m.name.name.clone()).collect();
+            self.classes
+                .insert(def, (class.superclass.clone(), methods));
+        }
+        let scope = ClassScope {
+            def,
+            superclass: class.superclass.is_some(),
+        };
+        let outer = self.class.replace(scope);
+        for method in class.methods.iter() {
+            self.function(&method.func);
+        }
+        self.class = outer;
+    }
+
+    fn function(&mut self, func: &Function) {
+        self.scopes.push(Vec::new());
+        for param in func.params.iter() {
+            self.declare(param);
+        }
+        self.stmts(&func.body.stmts);
+        self.scopes.pop();
+    }
+
+    fn read(&mut self, ident: &Ident) {
+        if let Some(&def) = self.refs.get(&ident.span.pos) {
+            self.read[def] = true;
+        }
+    }
+
+    fn assign(&mut self, ident: &Ident) {
+        match self.refs.get(&ident.span.pos) {
+            Some(&def) => {
+                self.assigned.insert(def);
+            }
+            None if !self.builtins.contains(&ident.name) => self.warn(
+                "undeclared-global",
+                ident.span,
+                format!("`{}` is assigned but declared nowhere", ident.name),
+            ),
+            None => {}
+        }
+    }
+
+    /// `callee` called by name with `args` arguments
+    fn call(&mut self, callee: &Expr, args: usize) {
+        if let Expr::Name(ident) = callee {
+            if let Some(&def) = self.refs.get(&ident.span.pos) {
+                self.calls.push((def, args, ident.clone()));
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Name(ident) => self.read(ident),
+            Expr::Super { method, span } => match self.class {
+                Some(ClassScope {
+                    superclass: false, ..
+                }) => {
+                    let msg = "`super` in a class without a superclass";
+                    self.warn("super-without-superclass", *span, msg.to_owned());
+                }
+                Some(ClassScope { def: Some(def), .. }) => {
+                    self.supers.push((def, method.clone()));
+                }
+                _ => {}
+            },
+            Expr::Number(..) | Expr::String(..) | Expr::Bool(..) | Expr::Nil(_) => {}
+            Expr::Interpolation(parts) => {
+                for part in parts {
+                    if let StrPart::Expr { expr, .. } = part {
+                        self.expr(expr);
+                    }
+                }
+            }
+            Expr::Array { items, .. } => {
+                for item in items {
+                    self.expr(item);
+                }
+            }
+            Expr::Dict { entries, .. } => {
+                for (key, value) in entries {
+                    self.expr(key);
+                    self.expr(value);
+                }
+            }
+            Expr::Unary { operand, .. } => self.expr(operand),
+            Expr::Binary { op, lhs, rhs, .. } => {
+                if *op == BinaryOp::Pipe {
+                    // `x |> f` is `f(x)`
+                    self.call(rhs, 1);
+                }
+                self.expr(lhs);
+                self.expr(rhs);
+            }
+            Expr::Call { callee, args, .. } => {
+                self.call(callee, args.len());
+                self.expr(callee);
+                for arg in args {
+                    self.expr(arg);
+                }
+            }
+            Expr::Index { target, index, .. } => {
+                self.expr(target);
+                self.expr(index);
+            }
+            Expr::Field { target, .. } => self.expr(target),
+            Expr::Conditional {
+                cond,
+                then,
+                otherwise,
+                ..
+            } => {
+                self.expr(cond);
+                self.expr(then);
+                self.expr(otherwise);
+            }
+            Expr::Try { call, .. } => self.expr(call),
+            Expr::Lambda { func, .. } => self.function(func),
+        }
+    }
+
+    /// warnings that need the whole program
+    fn finish(&mut self) {
+        for def in std::mem::take(&mut self.locals) {
+            let symbol = &self.symbols.defs[def];
+            if self.read[def] || symbol.name.starts_with('_') {
+                continue;
+            }
+            let (rule, what) = match symbol.kind {
+                SymbolKind::Parameter => ("unused-parameter", "parameter"),
+                SymbolKind::Function => ("unused-variable", "function"),
+                SymbolKind::Class => ("unused-variable", "class"),
+                _ => ("unused-variable", "variable"),
+            };
+            let span = Span {
+                pos: symbol.pos,
+                len: symbol.span,
+            };
+            let msg = format!("{what} `{}` is never read", symbol.name);
+            self.warn(rule, span, msg);
+        }
+        for (def, args, ident) in std::mem::take(&mut self.calls) {
+            let Some(&(params, variadic)) = self.arities.get(&def) else {
+                continue;
+            };
+            if self.rebound(def) || args == params || (variadic && args > params) {
+                continue;
+            }
+            let expected = if variadic {
+                format!("at least {params}")
+            } else {
+                params.to_string()
+            };
+            let plural = if params == 1 { "" } else { "s" };
+            let verb = if args == 1 { "is" } else { "are" };
+            let msg = format!(
+                "`{}` takes {expected} argument{plural} but {args} {verb} given",
+                ident.name
+            );
+            self.warn("argument-count", ident.span, msg);
+        }
+        for (class, method) in std::mem::take(&mut self.supers) {
+            if self.inherits(class, &method.name) == Some(false) {
+                let msg = format!("no superclass of this class has a method `{}`", method.name);
+                self.warn("unknown-super-method", method.span, msg);
+            }
+        }
+    }
+
+    /// the name of `def` may be bound to something else at runtime
+    fn rebound(&self, def: usize) -> bool {
+        let name = &self.symbols.defs[def].name;
+        self.assigned.contains(&def)
+            || self.symbols.defs.iter().filter(|d| &d.name == name).count() > 1
+    }
+
+    /// a superclass of `class` has `method`, `None` if one of them is
+    /// not a class of the program
+    fn inherits(&self, class: usize, method: &str) -> Option<bool> {
+        let mut class = class;
+        // bounded, the chain may loop through classes declared twice
+        for _ in 0..self.classes.len() {
+            let Some(superclass) = &self.classes[&class].0 else {
+                return Some(false);
+            };
+            class = *self.refs.get(&superclass.span.pos)?;
+            if self.rebound(class) {
+                return None;
+            }
+            let (_, methods) = self.classes.get(&class)?;
+            if methods.iter().any(|m| m == method) {
+                return Some(true);
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,378 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use serde_json::{json, Value as Json};
+
+use crate::compiler::codegen::CodeGen;
+use crate::compiler::parser::ParserCtx;
+use crate::compiler::scanner::ScannerCtx;
+use crate::compiler::symbols::{SymbolDef, SymbolIndex, SymbolKind};
+use crate::interned_string::StringPool;
+use crate::lint::lint;
+use crate::wire::{read_message, write_message};
+use crate::{native_modules, prelude, Diagnostic, Severity, Unboxed, Value};
+
+/// JSON-RPC error code of unknown methods
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// serve the Language Server Protocol till `exit` or the end of `input`
+pub fn run_lsp<R: BufRead, W: Write>(mut input: R, output: W) -> io::Result<()> {
+    let mut server = LspServer {
+        output,
+        docs: HashMap::new(),
+    };
+    while let Some(msg) = read_message(&mut input)? {
+        if !server.handle(&msg)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+struct Document {
+    text: String,
+    /// from the last version that parsed, kept while the text is broken
+    symbols: SymbolIndex,
+}
+
+struct LspServer<W: Write> {
+    output: W,
+    /// by uri
+    docs: HashMap<String, Document>,
+}
+
+impl<W: Write> LspServer<W> {
+    /// returns false on `exit`
+    fn handle(&mut self, msg: &Json) -> io::Result<bool> {
+        let method = msg["method"].as_str().unwrap_or_default();
+        let params = &msg["params"];
+        let result = match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "referencesProvider": true,
+                    "documentSymbolProvider": true,
+                    "completionProvider": {"triggerCharacters": ["."]},
+                },
+                "serverInfo": {"name": "sloth"},
+            })),
+            "shutdown" => Ok(Json::Null),
+            "exit" => return Ok(false),
+            "textDocument/didOpen" => {
+                let doc = &params["textDocument"];
+                self.update(
+                    doc["uri"].as_str().unwrap_or_default(),
+                    doc["text"].as_str(),
+                )?;
+                return Ok(true);
+            }
+            "textDocument/didChange" => {
+                // full sync, the last change is the whole text
+                let text = params["contentChanges"]
+                    .as_array()
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str());
+                self.update(
+                    params["textDocument"]["uri"].as_str().unwrap_or_default(),
+                    text,
+                )?;
+                return Ok(true);
+            }
+            "textDocument/didClose" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+                self.docs.remove(uri);
+                self.publish(uri, Vec::new())?;
+                return Ok(true);
+            }
+            "textDocument/definition" => Ok(self.definition(params)),
+            "textDocument/references" => Ok(self.references(params)),
+            "textDocument/documentSymbol" => Ok(self.document_symbols(params)),
+            "textDocument/completion" => Ok(self.completion(params)),
+            _ => Err(format!("unsupported method `{method}`")),
+        };
+        // notifications have no id and get no response
+        if msg.get("id").is_none() {
+            return Ok(true);
+        }
+        let mut response = json!({"jsonrpc": "2.0", "id": msg["id"]});
+        match result {
+            Ok(result) => response["result"] = result,
+            Err(message) => {
+                response["error"] = json!({"code": METHOD_NOT_FOUND, "message": message})
+            }
+        }
+        write_message(&mut self.output, &response)?;
+        Ok(true)
+    }
+
+    fn update(&mut self, uri: &str, text: Option<&str>) -> io::Result<()> {
+        let Some(text) = text else {
+            return Ok(());
+        };
+        let (diags, symbols) = analyze(text, uri);
+        let doc = self.docs.entry(uri.to_owned()).or_insert(Document {
+            text: String::new(),
+            symbols: SymbolIndex::default(),
+        });
+        doc.text = text.to_owned();
+        if let Some(symbols) = symbols {
+            doc.symbols = symbols;
+        }
+        let diags = diags.iter().map(|d| lsp_diagnostic(text, d)).collect();
+        self.publish(uri, diags)
+    }
+
+    fn publish(&mut self, uri: &str, diagnostics: Vec<Json>) -> io::Result<()> {
+        let msg = json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": {"uri": uri, "diagnostics": diagnostics},
+        });
+        write_message(&mut self.output, &msg)
+    }
+
+    /// document and the position of a request in scanner coordinates
+    fn doc_at<'a>(&'a self, params: &'a Json) -> Option<(&'a str, &'a Document, (usize, usize))> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let doc = self.docs.get(uri)?;
+        let pos = from_lsp(&doc.text, &params["position"]);
+        Some((uri, doc, pos))
+    }
+
+    fn definition(&self, params: &Json) -> Json {
+        let Some((uri, doc, (line, col))) = self.doc_at(params) else {
+            return Json::Null;
+        };
+        match doc.symbols.def_at(line, col) {
+            Some(Ok(id)) => {
+                let def = &doc.symbols.defs[id];
+                location(uri, &doc.text, def.pos, def.span)
+            }
+            _ => Json::Null,
+        }
+    }
+
+    fn references(&self, params: &Json) -> Json {
+        let Some((uri, doc, (line, col))) = self.doc_at(params) else {
+            return Json::Null;
+        };
+        let Some(target) = doc.symbols.def_at(line, col) else {
+            return json!([]);
+        };
+        let mut locations = Vec::new();
+        if let Ok(id) = target {
+            if params["context"]["includeDeclaration"].as_bool() == Some(true) {
+                let def = &doc.symbols.defs[id];
+                locations.push(location(uri, &doc.text, def.pos, def.span));
+            }
+        }
+        for r in doc.symbols.refs_to(&target) {
+            locations.push(location(uri, &doc.text, r.pos, r.span));
+        }
+        json!(locations)
+    }
+
+    fn document_symbols(&self, params: &Json) -> Json {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let Some(doc) = self.docs.get(uri) else {
+            return Json::Null;
+        };
+        let decls: Vec<&SymbolDef> = doc
+            .symbols
+            .defs
+            .iter()
+            .filter(|d| {
+                matches!(
+                    d.kind,
+                    SymbolKind::Function | SymbolKind::Class | SymbolKind::Method
+                )
+            })
+            .collect();
+        json!(symbol_tree(&doc.text, &decls, &mut 0, None))
+    }
+
+    fn completion(&self, params: &Json) -> Json {
+        let Some((_, doc, (line, col))) = self.doc_at(params) else {
+            return Json::Null;
+        };
+        let before: String = doc
+            .text
+            .lines()
+            .nth(line - 1)
+            .unwrap_or_default()
+            .chars()
+            .take(col - 1)
+            .collect();
+        let word_start = before
+            .trim_end_matches(|c: char| c.is_alphanumeric() || c == '_')
+            .len();
+        // `module.` completes members of a native module, unless shadowed
+        if let Some(receiver) = before[..word_start].strip_suffix('.') {
+            let receiver = receiver
+                .rsplit(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .next()
+                .unwrap_or_default();
+            let shadowed = doc.symbols.defs.iter().any(|d| d.name == receiver);
+            let members = native_modules()
+                .into_iter()
+                .find(|(name, _)| name == receiver && !shadowed)
+                .map(|(_, members)| members)
+                .unwrap_or_default();
+            let items: Vec<Json> = members
+                .iter()
+                .map(|(name, val)| completion_item(name, val))
+                .collect();
+            return json!(items);
+        }
+        let mut items: Vec<Json> = prelude()
+            .iter()
+            .map(|(name, val)| completion_item(name, val))
+            .collect();
+        for (name, _) in native_modules() {
+            items.push(json!({"label": name, "kind": 9}));
+        }
+        let mut seen = Vec::new();
+        for def in doc.symbols.defs.iter() {
+            if def.kind == SymbolKind::Method || seen.contains(&&def.name) {
+                continue;
+            }
+            seen.push(&def.name);
+            items.push(json!({"label": def.name, "kind": completion_kind(def.kind)}));
+        }
+        json!(items)
+    }
+}
+
+/// diagnostics of compiling and linting `text`, and the names in it if it compiles
+fn analyze(text: &str, file: &str) -> (Vec<Diagnostic>, Option<SymbolIndex>) {
+    let mut string_pool = StringPool::new();
+    let mut scanner = ScannerCtx::new(text, &mut string_pool);
+    if let Err(diag) = scanner.parse() {
+        return (vec![diag.with_file(file)], None);
+    }
+    let prog = match ParserCtx::new(scanner.finish()).parse_prog() {
+        Ok(prog) => prog,
+        Err(diag) => return (vec![diag.with_file(file)], None),
+    };
+    let mut codegen = CodeGen::new(&mut string_pool);
+    codegen.set_file(file);
+    match codegen.gen_prog(&prog) {
+        Ok(()) => {
+            let symbols = codegen.finish().symbols;
+            let warnings = lint(&prog, &symbols);
+            let warnings = warnings.into_iter().map(|diag| diag.with_file(file));
+            (warnings.collect(), Some(symbols))
+        }
+        Err(diag) => (vec![diag.with_file(file)], None),
+    }
+}
+
+/// functions and classes with what is declared inside of them,
+/// `decls` are in source order
+fn symbol_tree(
+    text: &str,
+    decls: &[&SymbolDef],
+    next: &mut usize,
+    end: Option<(usize, usize)>,
+) -> Vec<Json> {
+    let mut symbols = Vec::new();
+    while let Some(def) = decls.get(*next) {
+        if end.is_some_and(|end| def.pos >= end) {
+            break;
+        }
+        *next += 1;
+        let children = symbol_tree(text, decls, next, Some(def.end));
+        let kind = match def.kind {
+            SymbolKind::Class => 5,
+            SymbolKind::Method => 6,
+            _ => 12,
+        };
+        symbols.push(json!({
+            "name": def.name,
+            "kind": kind,
+            "range": {"start": to_lsp(text, def.pos), "end": to_lsp(text, def.end)},
+            "selectionRange": range(text, def.pos, def.span),
+            "children": children,
+        }));
+    }
+    symbols
+}
+
+fn completion_item(name: &str, val: &Value) -> Json {
+    let kind = match val.unbox() {
+        Unboxed::NativeFunction(_) => 3,
+        _ => 21,
+    };
+    json!({"label": name, "kind": kind})
+}
+
+fn completion_kind(kind: SymbolKind) -> i64 {
+    match kind {
+        SymbolKind::Function => 3,
+        SymbolKind::Class => 7,
+        SymbolKind::Method => 2,
+        SymbolKind::Variable | SymbolKind::Parameter => 6,
+    }
+}
+
+fn lsp_diagnostic(text: &str, diag: &Diagnostic) -> Json {
+    let severity = match diag.severity {
+        Severity::Error => 1,
+        Severity::Warning => 2,
+        Severity::Note => 3,
+    };
+    let mut message = diag.message.clone();
+    for note in diag.notes.iter() {
+        message.push('\n');
+        message.push_str(note);
+    }
+    let pos = (diag.line.max(1), diag.col.max(1));
+    let mut json = json!({
+        "range": range(text, pos, diag.span),
+        "severity": severity,
+        "source": "sloth",
+        "message": message,
+    });
+    if let Some(code) = &diag.code {
+        json["code"] = json!(code);
+    }
+    json
+}
+
+fn location(uri: &str, text: &str, pos: (usize, usize), span: usize) -> Json {
+    json!({"uri": uri, "range": range(text, pos, span)})
+}
+
+fn range(text: &str, (line, col): (usize, usize), span: usize) -> Json {
+    json!({"start": to_lsp(text, (line, col)), "end": to_lsp(text, (line, col + span))})
+}
+
+/// 1-based line and col counted in chars to a 0-based LSP position,
+/// whose character is counted in UTF-16 code units
+fn to_lsp(text: &str, (line, col): (usize, usize)) -> Json {
+    let character: usize = text
+        .lines()
+        .nth(line.saturating_sub(1))
+        .unwrap_or_default()
+        .chars()
+        .take(col.saturating_sub(1))
+        .map(char::len_utf16)
+        .sum();
+    json!({"line": line.saturating_sub(1), "character": character})
+}
+
+fn from_lsp(text: &str, pos: &Json) -> (usize, usize) {
+    let line = pos["line"].as_u64().unwrap_or(0) as usize;
+    let character = pos["character"].as_u64().unwrap_or(0) as usize;
+    let mut units = 0;
+    let mut col = 1;
+    for c in text.lines().nth(line).unwrap_or_default().chars() {
+        if units >= character {
+            break;
+        }
+        units += c.len_utf16();
+        col += 1;
+    }
+    (line + 1, col)
+}
@@ -1,28 +1,223 @@
 use sloth_lang_core::*;
 
-use std::{io::Read, path::PathBuf};
+use std::{
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
 
-use clap::{Arg, Parser};
+use clap::{Parser, Subcommand};
 
 #[derive(Parser)]
 #[command(version, about = "sloth-lang interpreter", long_about = None)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Cli {
-    /// script path, execution start here
-    script: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// script path, execution start here. start a REPL if not given
+    script: Option<PathBuf>,
     #[arg(short, long)]
     debug: bool,
+    /// compile the script to bytecode instead of running it
+    #[arg(short, long)]
+    compile: bool,
+    /// where compiled bytecode is written, `<script>.sltc` by default
+    #[arg(short, long, requires = "compile")]
+    output: Option<PathBuf>,
+    /// fold constants, remove dead code and thread jumps before running or compiling
+    #[arg(short = 'O', long)]
+    optimize: bool,
+}
+#[derive(Subcommand)]
+enum Command {
+    /// print the bytecode of every function in a script
+    Disasm {
+        script: PathBuf,
+        /// print the bytecode after the optimizer ran
+        #[arg(short = 'O', long)]
+        optimize: bool,
+    },
+    /// serve the Debug Adapter Protocol on stdin and stdout
+    Dap,
+    /// serve the Language Server Protocol on stdin and stdout
+    Lsp,
+    /// rewrite scripts in the canonical layout
+    Fmt {
+        #[arg(required = true)]
+        scripts: Vec<PathBuf>,
+        /// list the scripts that are not formatted instead of rewriting them
+        #[arg(long)]
+        check: bool,
+    },
+    /// report suspicious code in scripts
+    Lint {
+        #[arg(required = true)]
+        scripts: Vec<PathBuf>,
+    },
 }
 fn main() {
     let args = Cli::parse();
+    match args.command {
+        Some(Command::Disasm { script, optimize }) => disasm(&script, optimize),
+        Some(Command::Dap) => dap(),
+        Some(Command::Lsp) => lsp(),
+        Some(Command::Fmt { scripts, check }) => fmt(&scripts, check),
+        Some(Command::Lint { scripts }) => lint(&scripts),
+        None if args.script.is_none() => repl(),
+        None => run(args),
+    }
+}
+fn repl() {
+    let mut repl = Repl::new();
+    let stdin = std::io::stdin();
+    let mut input = String::new();
+    loop {
+        print!("{}", if input.is_empty() { "> " } else { "... " });
+        let _ = std::io::stdout().flush();
+        let mut line = String::new();
+        if stdin.read_line(&mut line).unwrap_or(0) == 0 {
+            // EOF
+            println!();
+            break;
+        }
+        if input.is_empty() && line.trim_start().starts_with(':') {
+            let res = repl.run_command(&line);
+            report(res, &line);
+            continue;
+        }
+        input.push_str(&line);
+        if !Repl::is_complete(&input) {
+            continue;
+        }
+        if !input.trim().is_empty() {
+            let res = repl.eval(&input);
+            report(res, &input);
+        }
+        input.clear();
+    }
+}
+fn report(res: Result<Option<String>, Diagnostic>, input: &str) {
+    match res {
+        Ok(Some(out)) => println!("{out}"),
+        Ok(None) => {}
+        Err(diag) => {
+            let src = if diag.file == REPL_FILE {
+                input.to_owned()
+            } else {
+                std::fs::read_to_string(&diag.file).unwrap_or_default()
+            };
+            eprint!("{}", diag.render(&src));
+        }
+    }
+}
+fn dap() {
+    let input = std::io::BufReader::new(std::io::stdin());
+    if let Err(e) = run_dap(input, std::io::stdout()) {
+        eprintln!("dap: {e}");
+        std::process::exit(1);
+    }
+}
+fn lsp() {
+    let stdin = std::io::stdin();
+    if let Err(e) = run_lsp(stdin.lock(), std::io::stdout()) {
+        eprintln!("lsp: {e}");
+        std::process::exit(1);
+    }
+}
+fn fmt(scripts: &[PathBuf], check: bool) {
+    let cwd = std::env::current_dir().unwrap();
+    let mut failed = false;
+    for script in scripts {
+        let path = cwd.join(script);
+        let buffer = std::fs::read_to_string(&path).unwrap();
+        match format_program(&buffer, &script.to_string_lossy()) {
+            Ok(formatted) if formatted == buffer => {}
+            Ok(_) if check => {
+                println!("{}", script.display());
+                failed = true;
+            }
+            Ok(formatted) => std::fs::write(&path, formatted).unwrap(),
+            Err(diag) => {
+                eprint!("{}", diag.render(&buffer));
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
+fn lint(scripts: &[PathBuf]) {
+    let cwd = std::env::current_dir().unwrap();
+    let mut failed = false;
+    for script in scripts {
+        let buffer = std::fs::read_to_string(cwd.join(script)).unwrap();
+        let diags = match lint_program(&buffer, &script.to_string_lossy()) {
+            Ok(warnings) => warnings,
+            Err(diag) => vec![diag],
+        };
+        for diag in diags.iter() {
+            eprint!("{}", diag.render(&buffer));
+        }
+        failed |= !diags.is_empty();
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
+fn disasm(script: &Path, optimize: bool) {
+    let cwd = std::env::current_dir().unwrap();
+    let buffer = std::fs::read_to_string(cwd.join(script)).unwrap();
+    match disassemble_program(&buffer, &script.to_string_lossy(), optimize) {
+        Ok(listing) => print!("{listing}"),
+        Err(diag) => {
+            eprint!("{}", diag.render(&buffer));
+            std::process::exit(1);
+        }
+    }
+}
+fn run(args: Cli) {
+    let script = args.script.unwrap();
     let cwd = std::env::current_dir().unwrap();
     let mut full_path = PathBuf::new();
     full_path.push(&cwd);
-    full_path.push(&args.script);
-    let mut file = std::fs::File::open(full_path).unwrap();
+    full_path.push(&script);
+    let file = script.to_string_lossy();
+    if script.extension().is_some_and(|ext| ext == "sltc") {
+        let bytecode = std::fs::read(&full_path).unwrap();
+        if let Err(diag) = run_bytecode(&bytecode, &file, args.debug) {
+            // the source may be kept next to the bytecode
+            let src = std::fs::read_to_string(cwd.join(&diag.file)).unwrap_or_default();
+            eprint!("{}", diag.render(&src));
+            std::process::exit(1);
+        }
+        return;
+    }
+    let mut script_file = std::fs::File::open(full_path).unwrap();
     let mut buffer = String::new();
-    file.read_to_string(&mut buffer).unwrap();
-    let res = run_string_debug(&buffer, false, args.debug);
+    script_file.read_to_string(&mut buffer).unwrap();
+    if args.compile {
+        let output = args.output.unwrap_or_else(|| script.with_extension("sltc"));
+        match compile_to_bytecode(&buffer, &file, args.optimize) {
+            Ok(bytecode) => std::fs::write(cwd.join(output), bytecode).unwrap(),
+            Err(diag) => {
+                eprint!("{}", diag.render(&buffer));
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    let res = run_script(&buffer, &file, false, args.debug, args.optimize);
     if args.debug {
         eprintln!("{res:?}");
     }
+    if let Err(diag) = res {
+        // error may be raised in an imported module
+        let src = if diag.file == file {
+            buffer
+        } else {
+            std::fs::read_to_string(cwd.join(&diag.file)).unwrap_or_default()
+        };
+        eprint!("{}", diag.render(&src));
+        std::process::exit(1);
+    }
 }
@@ -1,30 +1,38 @@
-use crate::{interned_string::IString, vm::Vm, Value};
+use crate::{
+    vm::{EvalError, Vm},
+    NativeResult, Value,
+};
 macro_rules! arity_assert {
     ($n:expr, $arg_num:expr) => {
         if $arg_num != $n {
-            panic!("arity check failed, passed {}, required {}", $n, $arg_num);
+            return Err(EvalError::CallError(format!(
+                "arity check failed, passed {}, required {}",
+                $arg_num, $n
+            )));
         }
     };
 }
 
 macro_rules! mf_entry {
     ($name:expr,$func:expr) => {
-        ($name.to_owned(), Value::NativeFunction($func as *mut u8))
+        ($name.to_owned(), Value::native_function($func as *mut u8))
     };
 }
 
-pub fn sloth_math_floor(vm: &mut Vm, arg_num: usize, _protected: bool) {
+pub fn sloth_math_floor(vm: &mut Vm, arg_num: usize, _protected: bool) -> NativeResult {
     arity_assert!(1, arg_num);
-    let v = vm.gets_number();
+    let v = vm.gets_number()?;
     let _ = vm.get_stack().pop();
-    vm.get_stack().push(Value::Number(v.floor()));
+    vm.get_stack().push(Value::number(v.floor()));
+    Ok(())
 }
 
-pub fn sloth_math_ceil(vm: &mut Vm, arg_num: usize, _protected: bool) {
+pub fn sloth_math_ceil(vm: &mut Vm, arg_num: usize, _protected: bool) -> NativeResult {
     arity_assert!(1, arg_num);
-    let v = vm.gets_number();
+    let v = vm.gets_number()?;
     let _ = vm.get_stack().pop();
-    vm.get_stack().push(Value::Number(v.ceil()));
+    vm.get_stack().push(Value::number(v.ceil()));
+    Ok(())
 }
 
 pub fn module_export() -> (String, Vec<(String, Value)>) {
@@ -1,93 +1,129 @@
+use crate::vm::EvalError;
 use crate::*;
 //type NativeFResult = Result<Value, String>;
 use std::fmt::Write;
-use std::{
-    collections::HashSet,
-    fs::File,
-    io::{self, Read},
-};
+use std::{collections::HashSet, fs, io, path::Path};
 
 
 macro_rules! arity_assert {
     ($n:expr, $arg_num:expr) => {
         if $arg_num != $n {
-            panic!("arity check failed, passed {}, required {}", $n, $arg_num);
+            return Err(EvalError::CallError(format!(
+                "arity check failed, passed {}, required {}",
+                $arg_num, $n
+            )));
         }
     };
 }
 
 macro_rules! mf_entry {
     ($name:expr,$func:expr) => {
-        ($name.to_owned(), Value::NativeFunction($func as *mut u8))
+        ($name.to_owned(), Value::native_function($func as *mut u8))
     };
 }
 
-pub fn sloth_typeof(vm: &mut Vm, _arg_num: usize, _protected: bool) {
-    let val = vm.get_stack().pop().unwrap_or(Value::Nil);
+pub fn sloth_typeof(vm: &mut Vm, arg_num: usize, _protected: bool) -> NativeResult {
+    arity_assert!(1, arg_num);
+    let val = vm.get_stack().pop().unwrap_or(Value::NIL);
+    // pop me
+    let _ = vm.get_stack().pop();
     macro_rules! vstr {
         ($s:expr) => {
             vm.make_managed_string($s)
         };
     }
-    let v = match val {
-        Value::Nil => vstr!("Nil"),
-        Value::Bool(_) => vstr!("Bool"),
-        Value::Number(_) => vstr!("Number"),
-        Value::String(_) => vstr!("String"),
-        Value::Array(_) => vstr!("Array"),
-        Value::Module(_) => vstr!("Module"),
-        Value::Dictionary(_) => vstr!("Dict"),
-        Value::Error(_) => vstr!("Err"),
-        Value::Closure(_) => vstr!("Closure"),
-        Value::NativeFunction(_) => vstr!("NativeFunction"),
-        Value::OpaqueData(_) => vstr!("OpaqueData"),
-        Value::Fiber(_) => vstr!("Fiber"),
-        Value::Range(_, _) => vstr!("Range"),
+    let v = match val.unbox() {
+        Unboxed::Nil => vstr!("Nil"),
+        Unboxed::Bool(_) => vstr!("Bool"),
+        Unboxed::Number(_) => vstr!("Number"),
+        Unboxed::String(_) => vstr!("String"),
+        Unboxed::Array(_) => vstr!("Array"),
+        Unboxed::Module(_) => vstr!("Module"),
+        Unboxed::Dictionary(_) => vstr!("Dict"),
+        Unboxed::Error(_) => vstr!("Err"),
+        Unboxed::Closure(_) => vstr!("Closure"),
+        Unboxed::NativeFunction(_) => vstr!("NativeFunction"),
+        Unboxed::OpaqueData(_) => vstr!("OpaqueData"),
+        Unboxed::Fiber(_) => vstr!("Fiber"),
+        Unboxed::Range(_) => vstr!("Range"),
         _ => vstr!("..."),
     };
-    vm.get_stack().push(Value::String(v));
+    vm.get_stack().push(Value::string(v));
+    Ok(())
 }
 
-pub fn sloth_add_glob(vm: &mut Vm, arg_num: usize, _protected: bool) {
+pub fn sloth_add_glob(vm: &mut Vm, arg_num: usize, _protected: bool) -> NativeResult {
     // add all kv to Global
     arity_assert!(1, arg_num);
-    let p_dict = if let Value::Dictionary(p_dict) = vm.get_stack().pop().unwrap() {
+    let p_dict = if let Unboxed::Dictionary(p_dict) = vm.get_stack().pop().unwrap().unbox() {
         p_dict
     } else {
-        panic!("add_glob take 1 argument: dict: Dict");
+        return Err(EvalError::TypeError(
+            "add_glob take 1 argument: dict: Dict".to_owned(),
+        ));
     };
     let _ = vm.get_stack().pop();
     unsafe {
         for (k,v) in (*p_dict).dict.iter() {
-            vm.get_current_glob().insert(k.clone(), v.clone());
+            vm.set_global(k.clone(), *v);
         }
     }
-    vm.get_stack().push(Value::Nil);
+    vm.get_stack().push(Value::NIL);
+    Ok(())
 }
 
-pub fn sloth_load_module(vm: &mut Vm, arg_num: usize, _protected: bool) {
-    if arg_num != 1 {
-        vm.get_stack().pop();
-        vm.get_stack().push(Value::Nil);
-    }
+pub fn sloth_load_module(vm: &mut Vm, arg_num: usize, protected: bool) -> NativeResult {
+    arity_assert!(1, arg_num);
     let path = vm.get_stack().pop().unwrap();
-    if let Value::String(path) = path {
+    if let Unboxed::String(path) = path.unbox() {
         vm.get_stack().pop();
-        vm.fiber_changed = true;
         let full_path = vm.interpreter_cwd.join(path.get_inner());
-        let mut file = File::open(&full_path).unwrap();
-        let mut buf = String::new();
-        file.read_to_string(&mut buf).unwrap();
-        vm.load_module(&buf).unwrap();
+        let load_err = |e: io::Error| EvalError::Error(format!("cannot load module `{path}`: {e}"));
+        if full_path.extension().is_some_and(|ext| ext == "sltc") {
+            let bytecode = fs::read(&full_path).map_err(load_err)?;
+            vm.load_module_bytecode(&bytecode)?;
+        } else if let Some(bytecode) = fresh_bytecode(&full_path) {
+            // a broken `.sltc` is not an error as long as the source is there
+            if vm.load_module_bytecode(&bytecode).is_err() {
+                let buf = fs::read_to_string(&full_path).map_err(load_err)?;
+                vm.load_module(&buf, path.get_inner())?;
+            }
+        } else {
+            let buf = fs::read_to_string(&full_path).map_err(load_err)?;
+            vm.load_module(&buf, path.get_inner())?;
+        }
+        // errors of the module body are caught by `try import(..)`
+        unsafe { (*vm.get_current_fiber()).protected = protected };
+        vm.fiber_changed = true;
         // return and entering load_module fiber
         // returned module will be pushed to stack later.
     } else {
         vm.get_stack().pop();
-        vm.get_stack().push(Value::Nil);
+        vm.get_stack().push(Value::NIL);
+    }
+    Ok(())
+}
+
+/// content of the `.sltc` next to `src`, if it is up to date
+/// and of the version this interpreter understands.
+/// it is used as well when the source is missing.
+fn fresh_bytecode(src: &Path) -> Option<Vec<u8>> {
+    let bc_path = src.with_extension("sltc");
+    let bc_time = fs::metadata(&bc_path).and_then(|m| m.modified()).ok()?;
+    if let Ok(src_time) = fs::metadata(src).and_then(|m| m.modified()) {
+        if src_time > bc_time {
+            return None;
+        }
+    }
+    let bytecode = fs::read(&bc_path).ok()?;
+    if serialize::is_current_version(&bytecode) {
+        Some(bytecode)
+    } else {
+        None
     }
 }
 
-pub fn sloth_print_val(vm: &mut Vm, arg_num: usize, _protected: bool) {
+pub fn sloth_print_val(vm: &mut Vm, arg_num: usize, _protected: bool) -> NativeResult {
     let mut val_to_print = Vec::new();
     for _ in 0..arg_num {
         val_to_print.push(vm.get_stack().pop().unwrap());
@@ -95,105 +131,127 @@ pub fn sloth_print_val(vm: &mut Vm, arg_num: usize, _protected: bool) {
     // pop me
     let _ = vm.get_stack().pop();
     val_to_print.reverse();
+    let mut buffer = String::new();
     for v in val_to_print {
         let mut vis = HashSet::new();
-        print_val(&v, &mut vis);
-        print!(" ");
+        let _ = write_val(&mut buffer, &v, &mut vis);
+        buffer.push(' ');
     }
+    vm.write_output(&buffer);
     // Functions always have ONE return Value
-    vm.get_stack().push(Value::Nil);
+    vm.get_stack().push(Value::NIL);
+    Ok(())
 }
 
-pub fn sloth_input(vm: &mut Vm, _arg_num: usize, _protected: bool) {
+pub fn sloth_input(vm: &mut Vm, arg_num: usize, _protected: bool) -> NativeResult {
+    arity_assert!(0, arg_num);
+    // pop me
+    let _ = vm.get_stack().pop();
     let mut buffer = String::new();
     // blocking...
     let _ = io::stdin().read_line(&mut buffer);
     let istring = vm.make_managed_string(buffer.trim());
-    vm.get_stack().push(Value::String(istring));
+    vm.get_stack().push(Value::string(istring));
+    Ok(())
 }
 
-pub fn sloth_to_number(vm: &mut Vm, arg_num: usize, _protected: bool) {
+pub fn sloth_to_number(vm: &mut Vm, arg_num: usize, _protected: bool) -> NativeResult {
+    arity_assert!(1, arg_num);
     let val = vm.get_stack().pop().unwrap();
     let _ = vm.get_stack().pop();
 
-    let num = match val {
-        Value::Bool(b) => {
+    let num = match val.unbox() {
+        Unboxed::Bool(b) => {
             if b {
                 1.
             } else {
                 0.
             }
         }
-        Value::String(s) => {
+        Unboxed::String(s) => {
             let s1 = s.get_inner();
             if let Ok(num) = s1.parse::<f64>() {
                 num
             } else {
-                vm.get_stack().push(Value::Nil);
-                return;
+                vm.get_stack().push(Value::NIL);
+                return Ok(());
             }
         }
         _ => {
-            vm.get_stack().push(Value::Nil);
-            return;
+            vm.get_stack().push(Value::NIL);
+            return Ok(());
         }
     };
 
-    vm.get_stack().push(Value::Number(num));
+    vm.get_stack().push(Value::number(num));
+    Ok(())
 }
 
-pub fn sloth_to_bool(vm: &mut Vm, arg_num: usize, _protected: bool) {
+pub fn sloth_to_bool(vm: &mut Vm, arg_num: usize, _protected: bool) -> NativeResult {
+    arity_assert!(1, arg_num);
     let val = vm.get_stack().pop().unwrap();
     let _ = vm.get_stack().pop();
     vm.get_stack().push(val.to_bool_v());
+    Ok(())
 }
 
-pub fn sloth_to_string(vm: &mut Vm, arg_num: usize, _protected: bool) {
+pub fn sloth_to_string(vm: &mut Vm, arg_num: usize, _protected: bool) -> NativeResult {
+    arity_assert!(1, arg_num);
     let val = vm.get_stack().pop().unwrap();
     let _ = vm.get_stack().pop();
     let mut vis = HashSet::new();
     let mut buffer = String::new();
     if let Ok(_) = write_val(&mut buffer, &val, &mut vis) {
         let s = vm.make_managed_string(&buffer);
-        vm.get_stack().push(Value::String(s));
+        vm.get_stack().push(Value::string(s));
     } else {
-        vm.get_stack().push(Value::Nil);
+        vm.get_stack().push(Value::NIL);
     }
+    Ok(())
 }
 
-pub fn sloth_va_arg(vm: &mut Vm, arg_num: usize, _protected: bool) {
+pub fn sloth_va_arg(vm: &mut Vm, arg_num: usize, _protected: bool) -> NativeResult {
+    arity_assert!(0, arg_num);
     let _ = vm.get_stack().pop();
     let va_arg_vec = vm.get_call_frame().va_args.clone();
     let mut b_array = Box::new(Array{marked: false, array: va_arg_vec});
     let p_array = b_array.as_mut() as *mut Array;
     vm.add_object(b_array);
 
-    vm.get_stack().push(Value::Array(p_array));
+    vm.get_stack().push(Value::array(p_array));
+    Ok(())
 }
 
 
-pub fn sloth_ord(vm: &mut Vm, arg_num: usize, _protected: bool) {
+pub fn sloth_ord(vm: &mut Vm, arg_num: usize, _protected: bool) -> NativeResult {
     arity_assert!(1, arg_num);
-    let s = if let Value::String(s) = vm.get_stack().pop().unwrap() {
+    let s = if let Unboxed::String(s) = vm.get_stack().pop().unwrap().unbox() {
         s
     } else {
-        panic!("ord take 1 argument: s: String.");
+        return Err(EvalError::TypeError(
+            "ord take 1 argument: s: String.".to_owned(),
+        ));
     };
     let _ = vm.get_stack().pop();
 
-    if s.get_inner().len() != 1{
-        panic!("ord(s): s should contains exactly **1** char.")
+    if s.get_inner().chars().count() != 1 {
+        return Err(EvalError::Error(
+            "ord(s): s should contains exactly **1** char.".to_owned(),
+        ));
     }
     let val = s.get_inner().chars().next().unwrap();
-    vm.get_stack().push(Value::Number(val as usize as f64));
+    vm.get_stack().push(Value::number(val as usize as f64));
+    Ok(())
 }
 
-pub fn sloth_chr(vm: &mut Vm, arg_num: usize, _protected: bool) {
+pub fn sloth_chr(vm: &mut Vm, arg_num: usize, _protected: bool) -> NativeResult {
     arity_assert!(1, arg_num);
-    let v = if let Value::Number(v) = vm.get_stack().pop().unwrap() {
+    let v = if let Unboxed::Number(v) = vm.get_stack().pop().unwrap().unbox() {
         v
     } else {
-        panic!("chr take 1 argument: v: Number.");
+        return Err(EvalError::TypeError(
+            "chr take 1 argument: v: Number.".to_owned(),
+        ));
     };
     let _ = vm.get_stack().pop();
 
@@ -201,57 +259,66 @@ pub fn sloth_chr(vm: &mut Vm, arg_num: usize, _protected: bool) {
         let mut s = String::new();
         s.push(c);
         let s = vm.make_managed_string(&s);
-        vm.get_stack().push(Value::String(s));
+        vm.get_stack().push(Value::string(s));
     } else {
-        panic!("chr(): not a valid unicode codepoint.");
+        return Err(EvalError::Error(
+            "chr(): not a valid unicode codepoint.".to_owned(),
+        ));
     }
+    Ok(())
+}
+/// same as `string(val)` in sloth
+pub fn value_to_string(val: &Value) -> String {
+    let mut buffer = String::new();
+    let _ = write_val(&mut buffer, val, &mut HashSet::new());
+    buffer
 }
 fn write_val(buffer: &mut String, val: &Value, visited_loc: &mut HashSet<*mut u8>) -> fmt::Result {
-    match val {
-        Value::Nil => {
+    match val.unbox() {
+        Unboxed::Nil => {
             write!(buffer, "Nil")?;
         }
-        Value::Number(x) => {
+        Unboxed::Number(x) => {
             write!(buffer, "{x}")?;
         }
-        Value::Bool(b) => {
+        Unboxed::Bool(b) => {
             write!(buffer, "{b}")?;
         }
 
-        Value::String(s) => {
+        Unboxed::String(s) => {
             write!(buffer, "{s}")?;
         }
-        Value::Array(a) => {
-            if visited_loc.get(&(*a as *mut u8)).is_some() {
+        Unboxed::Array(a) => {
+            if visited_loc.get(&(a as *mut u8)).is_some() {
                 write!(buffer, "...")?;
             } else {
-                visited_loc.insert(*a as *mut u8);
-                write_array(buffer, *a, visited_loc)?;
-                visited_loc.remove(&(*a as *mut u8));
+                visited_loc.insert(a as *mut u8);
+                write_array(buffer, a, visited_loc)?;
+                visited_loc.remove(&(a as *mut u8));
             }
         }
-        Value::Dictionary(d) => {
-            if visited_loc.get(&(*d as *mut u8)).is_some() {
+        Unboxed::Dictionary(d) => {
+            if visited_loc.get(&(d as *mut u8)).is_some() {
                 write!(buffer, "...")?;
             } else {
-                visited_loc.insert(*d as *mut u8);
-                write_dict(buffer, *d, visited_loc)?;
-                visited_loc.remove(&(*d as *mut u8));
+                visited_loc.insert(d as *mut u8);
+                write_dict(buffer, d, visited_loc)?;
+                visited_loc.remove(&(d as *mut u8));
             }
         }
-        Value::Error(d) => {
-            if visited_loc.get(&(*d as *mut u8)).is_some() {
+        Unboxed::Error(d) => {
+            if visited_loc.get(&(d as *mut u8)).is_some() {
                 write!(buffer, "...")?;
             } else {
-                visited_loc.insert(*d as *mut u8);
-                write_dict(buffer, *d, visited_loc)?;
-                visited_loc.remove(&(*d as *mut u8));
+                visited_loc.insert(d as *mut u8);
+                write_dict(buffer, d, visited_loc)?;
+                visited_loc.remove(&(d as *mut u8));
             }
         }
-        Value::Closure(p) => {
+        Unboxed::Closure(p) => {
             write!(buffer, "Closure@{p:?}")?;
         }
-        Value::NativeFunction(p) => {
+        Unboxed::NativeFunction(p) => {
             write!(buffer, "NativeFunc@{p:?}")?;
         }
         v => {
@@ -260,68 +327,6 @@ fn write_val(buffer: &mut String, val: &Value, visited_loc: &mut HashSet<*mut u8
     }
     Ok(())
 }
-fn print_val(val: &Value, visited_loc: &mut HashSet<*mut u8>) {
-    match val {
-        Value::Nil => {
-            print!("Nil")
-        }
-        Value::Number(x) => {
-            print!("{x}")
-        }
-        Value::Bool(b) => {
-            print!("{b}")
-        }
-
-        Value::String(s) => {
-            print!("{s}")
-        }
-        Value::Array(a) => {
-            if visited_loc.get(&(*a as *mut u8)).is_some() {
-                print!("...");
-            } else {
-                visited_loc.insert(*a as *mut u8);
-                print_array(*a, visited_loc);
-                visited_loc.remove(&(*a as *mut u8));
-            }
-        }
-        Value::Dictionary(d) => {
-            if visited_loc.get(&(*d as *mut u8)).is_some() {
-                print!("...");
-            } else {
-                visited_loc.insert(*d as *mut u8);
-                print_dict(*d, visited_loc);
-                visited_loc.remove(&(*d as *mut u8));
-            }
-        }
-        Value::Error(d) => {
-            if visited_loc.get(&(*d as *mut u8)).is_some() {
-                print!("...");
-            } else {
-                visited_loc.insert(*d as *mut u8);
-                print_dict(*d, visited_loc);
-                visited_loc.remove(&(*d as *mut u8));
-            }
-        }
-        Value::Closure(p) => {
-            print!("Closure@{p:?}")
-        }
-        Value::NativeFunction(p) => {
-            print!("NativeFunc@{p:?}")
-        }
-        v => {
-            print!("{v:?}")
-        }
-    }
-}
-fn print_array(arr: *mut Array, visited_loc: &mut HashSet<*mut u8>) {
-    let arr = unsafe { &*arr };
-    print!("[");
-    for val in arr.array.iter() {
-        print_val(val, visited_loc);
-        print!(",");
-    }
-    print!("]");
-}
 
 fn write_array(
     buffer: &mut String,
@@ -337,16 +342,6 @@ fn write_array(
     write!(buffer, "]")?;
     Ok(())
 }
-fn print_dict(dict: *mut Dict, visited_loc: &mut HashSet<*mut u8>) {
-    let dict = unsafe { &*dict };
-    print!("@(");
-    for (key, val) in dict.dict.iter() {
-        print!("{key}:");
-        print_val(val, visited_loc);
-        print!(",");
-    }
-    print!(")");
-}
 
 fn write_dict(
     buffer: &mut String,
@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+
+use crate::compiler::scanner::ScannerCtx;
+use crate::compiler::Token;
+use crate::interned_string::StringPool;
+use crate::native::value_to_string;
+use crate::vm::Vm;
+use crate::{build_vm, disassemble, eval_err_diagnostic, Chunk, Diagnostic, Unboxed, Value};
+
+/// file name of code typed into the REPL
+pub const REPL_FILE: &str = "<repl>";
+
+/// one `Vm` kept alive across inputs, so globals defined by earlier inputs
+/// and loaded modules stay visible.
+pub struct Repl {
+    vm: Box<Vm>,
+    /// globals from the prelude and native modules, hidden by `:globals`
+    builtins: HashMap<String, Value>,
+}
+
+impl Repl {
+    pub fn new() -> Repl {
+        let vm = build_vm(Chunk::default(), StringPool::new(), false);
+        let builtins = vm
+            .globals()
+            .map(|(k, v)| (k.get_inner().to_owned(), *v))
+            .collect();
+        Repl { vm, builtins }
+    }
+
+    /// run one input, returns the value of the last expression statement
+    /// for echoing, unless it is `nil`
+    pub fn eval(&mut self, src: &str) -> Result<Option<String>, Diagnostic> {
+        self.eval_file(src, REPL_FILE)
+    }
+
+    fn eval_file(&mut self, src: &str, file: &str) -> Result<Option<String>, Diagnostic> {
+        let chunk = self.vm.compile_incremental(src, file)?;
+        match self.vm.run_chunk(chunk).map(|val| (val, val.unbox())) {
+            Ok((_, Unboxed::Nil)) => Ok(None),
+            Ok((_, Unboxed::String(s))) => Ok(Some(format!("{:?}", s.get_inner()))),
+            Ok((val, _)) => Ok(Some(value_to_string(&val))),
+            Err(err) => Err(eval_err_diagnostic(&self.vm, err)),
+        }
+    }
+
+    /// `:dis`, `:globals` and `:load`, `line` starts with `:`
+    pub fn run_command(&mut self, line: &str) -> Result<Option<String>, Diagnostic> {
+        let line = line.trim();
+        let (cmd, arg) = line.split_once(' ').unwrap_or((line, ""));
+        let arg = arg.trim();
+        match cmd {
+            ":dis" => {
+                let chunk = self.vm.compile_incremental(arg, REPL_FILE)?;
+                Ok(Some(disassemble(&chunk)))
+            }
+            ":globals" => {
+                let mut globals: Vec<String> = self
+                    .vm
+                    .globals()
+                    .filter(|(k, v)| self.builtins.get(k.get_inner()) != Some(v))
+                    .map(|(k, v)| format!("{k} = {}", value_to_string(v)))
+                    .collect();
+                globals.sort();
+                Ok(Some(globals.join("\n")).filter(|s| !s.is_empty()))
+            }
+            ":load" => {
+                let path = self.vm.interpreter_cwd.join(arg);
+                let src = std::fs::read_to_string(&path).map_err(|e| {
+                    Diagnostic::error((0, 0), format!("cannot read `{arg}`: {e}")).with_file(arg)
+                })?;
+                self.eval_file(&src, arg)
+            }
+            _ => Err(
+                Diagnostic::error((0, 0), format!("unknown command `{cmd}`"))
+                    .with_file(REPL_FILE)
+                    .with_note("commands are :dis <code>, :globals and :load <file>"),
+            ),
+        }
+    }
+
+    /// whether `src` is worth compiling, or more lines are expected
+    /// because some bracket is not closed yet.
+    /// source that fails to scan is complete, the error is reported when compiled.
+    pub fn is_complete(src: &str) -> bool {
+        let mut string_pool = StringPool::new();
+        let mut scanner = ScannerCtx::new(src, &mut string_pool);
+        if scanner.parse().is_err() {
+            return true;
+        }
+        let mut depth = 0;
+        for tk in scanner.finish().tokens.iter() {
+            match tk {
+                Token::LParen | Token::LBrace | Token::LBracket => depth += 1,
+                Token::RParen | Token::RBrace | Token::RBracket => depth -= 1,
+                _ => {}
+            }
+        }
+        depth <= 0
+    }
+}
+
+impl Default for Repl {
+    fn default() -> Repl {
+        Repl::new()
+    }
+}
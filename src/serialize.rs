@@ -0,0 +1,408 @@
+//! `.sltc` bytecode file: a `Chunk` tree saved after compiling,
+//! string constants are re-interned when it is loaded.
+//!
+//! layout, integers are little-endian:
+//! ```text
+//! "SLTC" version:u32 chunk
+//! chunk := name:str file:str parameter_num:u64 num_locals:u64 is_va:u8
+//!          n:u64 (op:u8 operand)*n  (line:u32 col:u32)*n
+//!          n:u64 value*n  n:u64 upvalue*n  n:u64 local*n  n:u64 chunk*n
+//! str   := len:u64 utf8
+//! ```
+use crate::interned_string::StringPool;
+use crate::{Chunk, Instr, LocalVar, Unboxed, UpValueDecl, Value};
+
+const MAGIC: &[u8; 4] = b"SLTC";
+/// bump whenever `Instr` or the layout changes
+pub const FORMAT_VERSION: u32 = 9;
+
+/// opcodes are spelled out so that reordering `Instr` does not break old files
+macro_rules! instr_codec {
+    (
+        nullary { $($n_op:literal => $n_name:ident,)* }
+        index { $($u_op:literal => $u_name:ident,)* }
+        offset { $($j_op:literal => $j_name:ident,)* }
+        pair { $($p_op:literal => $p_name:ident,)* }
+    ) => {
+        fn write_instr(out: &mut Vec<u8>, instr: &Instr) {
+            match instr {
+                $(Instr::$n_name => out.push($n_op),)*
+                $(Instr::$u_name(x) => {
+                    out.push($u_op);
+                    write_u64(out, *x as u64);
+                })*
+                $(Instr::$j_name(x) => {
+                    out.push($j_op);
+                    out.extend_from_slice(&x.to_le_bytes());
+                })*
+                $(Instr::$p_name(x, y) => {
+                    out.push($p_op);
+                    out.extend_from_slice(&x.to_le_bytes());
+                    out.extend_from_slice(&y.to_le_bytes());
+                })*
+                _ => unreachable!("quickened instructions are saved as generic ones"),
+            }
+        }
+        fn read_instr(r: &mut Reader) -> Result<Instr, String> {
+            let op = r.u8()?;
+            let instr = match op {
+                $($n_op => Instr::$n_name,)*
+                $($u_op => Instr::$u_name(r.u64()? as usize),)*
+                $($j_op => Instr::$j_name(r.i32()?),)*
+                $($p_op => Instr::$p_name(r.u32()?, r.u32()?),)*
+                op => return Err(format!("unknown opcode {op}")),
+            };
+            Ok(instr)
+        }
+    };
+}
+
+instr_codec! {
+    nullary {
+        0 => Nop,
+        1 => PushNil,
+        2 => Add,
+        3 => Sub,
+        4 => Mul,
+        5 => Div,
+        6 => Mod,
+        7 => Negate,
+        8 => Not,
+        9 => And,
+        10 => Or,
+        11 => Gt,
+        12 => Lt,
+        13 => Ge,
+        14 => Le,
+        15 => Eq,
+        16 => Ne,
+        17 => LoadTrue,
+        18 => LoadFalse,
+        19 => ClassIs,
+        20 => Pop,
+        21 => Swap2,
+        22 => IsError,
+        23 => Return,
+        24 => Except,
+        25 => MakeRange,
+        26 => MakeRangeClosed,
+        27 => Iterator,
+        28 => Next,
+        29 => InitClass,
+        30 => AddMethod,
+        31 => ClassExtend,
+        32 => GetSuperMethod,
+        33 => GetThis,
+        34 => UnpackVA,
+        35 => MatchEq,
+        36 => PopHandler,
+        37 => MatchRange,
+        38 => MatchRangeClosed,
+    }
+    index {
+        64 => Load,
+        65 => LoadChunk,
+        66 => GetGlobal,
+        67 => SetGlobal,
+        68 => GetLocal,
+        69 => SetLocal,
+        70 => GetUpValue,
+        71 => SetUpValue,
+        72 => InitArray,
+        73 => InitDict,
+        74 => GetCollection,
+        75 => SetCollection,
+        76 => Call,
+        77 => TryCall,
+        78 => GetField,
+        79 => SetField,
+        80 => CloseUpValues,
+        81 => Dup,
+        82 => HasField,
+        83 => ArraySlice,
+    }
+    offset {
+        128 => JumpIfNot,
+        129 => JumpIfTrue,
+        130 => Jump,
+        131 => JumpIfNil,
+        132 => JumpIfNotNil,
+        133 => PushHandler,
+    }
+    pair {
+        192 => Invoke,
+        193 => MatchArray,
+    }
+}
+
+pub fn save_chunk(chunk: &Chunk) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    write_chunk(&mut out, chunk)?;
+    Ok(out)
+}
+
+pub fn load_chunk(bytes: &[u8], string_pool: &mut StringPool) -> Result<Chunk, String> {
+    let mut r = Reader { bytes, pos: 0 };
+    if r.take(4)? != MAGIC {
+        return Err("not a sloth bytecode file".to_owned());
+    }
+    let version = r.u32()?;
+    if version != FORMAT_VERSION {
+        return Err(format!(
+            "bytecode version {version} is not supported, expect {FORMAT_VERSION}"
+        ));
+    }
+    let chunk = read_chunk(&mut r, string_pool)?;
+    if r.pos != bytes.len() {
+        return Err("trailing bytes after chunk".to_owned());
+    }
+    if !chunk.upvalues.is_empty() {
+        return Err("corrupt bytecode file: upvalues of the top chunk".to_owned());
+    }
+    Ok(chunk)
+}
+
+/// whether `bytes` starts like a `.sltc` file of this version
+pub fn is_current_version(bytes: &[u8]) -> bool {
+    bytes.len() >= 8 && &bytes[..4] == MAGIC && bytes[4..8] == FORMAT_VERSION.to_le_bytes()
+}
+
+fn write_u64(out: &mut Vec<u8>, x: u64) {
+    out.extend_from_slice(&x.to_le_bytes());
+}
+fn write_str(out: &mut Vec<u8>, s: &str) {
+    write_u64(out, s.len() as u64);
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn write_chunk(out: &mut Vec<u8>, chunk: &Chunk) -> Result<(), String> {
+    write_str(out, &chunk.name);
+    write_str(out, &chunk.file);
+    write_u64(out, chunk.parameter_num as u64);
+    write_u64(out, chunk.num_locals as u64);
+    out.push(chunk.is_va as u8);
+
+    if chunk.cood.len() != chunk.bytecodes.len() {
+        return Err(format!("positions of `{}` are out of sync", chunk.name));
+    }
+    write_u64(out, chunk.bytecodes.len() as u64);
+    for instr in chunk.bytecodes.iter() {
+        write_instr(out, &instr.generic());
+    }
+    for (line, col) in chunk.cood.iter() {
+        out.extend_from_slice(&(*line as u32).to_le_bytes());
+        out.extend_from_slice(&(*col as u32).to_le_bytes());
+    }
+
+    write_u64(out, chunk.constants.len() as u64);
+    for val in chunk.constants.iter() {
+        match val.unbox() {
+            Unboxed::Nil => out.push(0),
+            Unboxed::Bool(b) => {
+                out.push(1);
+                out.push(b as u8);
+            }
+            Unboxed::Number(x) => {
+                out.push(2);
+                out.extend_from_slice(&x.to_le_bytes());
+            }
+            Unboxed::String(s) => {
+                out.push(3);
+                write_str(out, s.get_inner());
+            }
+            v => return Err(format!("constant {v:?} cannot be saved")),
+        }
+    }
+
+    write_u64(out, chunk.upvalues.len() as u64);
+    for upv in chunk.upvalues.iter() {
+        let (tag, idx, name) = match upv {
+            UpValueDecl::Ref(idx, name) => (0, idx, name),
+            UpValueDecl::RefUpValue(idx, name) => (1, idx, name),
+        };
+        out.push(tag);
+        write_u64(out, *idx as u64);
+        write_str(out, name.get_inner());
+    }
+
+    write_u64(out, chunk.locals.len() as u64);
+    for local in chunk.locals.iter() {
+        write_str(out, &local.name);
+        write_u64(out, local.slot as u64);
+        write_u64(out, local.start as u64);
+        write_u64(out, local.end as u64);
+    }
+
+    write_u64(out, chunk.chunks.len() as u64);
+    for sub_chunk in chunk.chunks.iter() {
+        write_chunk(out, sub_chunk)?;
+    }
+    Ok(())
+}
+
+fn read_chunk(r: &mut Reader, string_pool: &mut StringPool) -> Result<Chunk, String> {
+    let mut chunk = Chunk {
+        name: r.str()?.to_owned(),
+        file: r.str()?.to_owned(),
+        parameter_num: r.u64()? as usize,
+        num_locals: r.u64()? as usize,
+        is_va: r.u8()? != 0,
+        ..Chunk::default()
+    };
+
+    let n = r.len()?;
+    for _ in 0..n {
+        chunk.bytecodes.push(read_instr(r)?);
+    }
+    for _ in 0..n {
+        let line = r.u32()? as usize;
+        let col = r.u32()? as usize;
+        chunk.cood.push((line, col));
+    }
+
+    for _ in 0..r.len()? {
+        let val = match r.u8()? {
+            0 => Value::NIL,
+            1 => Value::bool(r.u8()? != 0),
+            2 => Value::number(f64::from_le_bytes(r.take(8)?.try_into().unwrap())),
+            3 => Value::string(string_pool.creat_istring(r.str()?)),
+            tag => return Err(format!("unknown constant tag {tag}")),
+        };
+        chunk.constants.push(val);
+    }
+
+    for _ in 0..r.len()? {
+        let tag = r.u8()?;
+        let idx = r.u64()? as usize;
+        let name = string_pool.creat_istring(r.str()?);
+        let upv = match tag {
+            0 => UpValueDecl::Ref(idx, name),
+            1 => UpValueDecl::RefUpValue(idx, name),
+            tag => return Err(format!("unknown upvalue tag {tag}")),
+        };
+        chunk.upvalues.push(upv);
+    }
+
+    for _ in 0..r.len()? {
+        let local = LocalVar {
+            name: r.str()?.to_owned(),
+            slot: r.u64()? as usize,
+            start: r.u64()? as usize,
+            end: r.u64()? as usize,
+        };
+        chunk.locals.push(local);
+    }
+
+    for _ in 0..r.len()? {
+        let sub_chunk = read_chunk(r, string_pool)?;
+        chunk.chunks.push(sub_chunk);
+    }
+    check_chunk(&chunk)?;
+    Ok(chunk)
+}
+
+/// the operands of `chunk` refer to what it has, so a corrupt or stale file
+/// is an error here rather than an index out of bounds in the vm.
+/// its sub chunks are already checked, but their upvalues refer to it
+fn check_chunk(chunk: &Chunk) -> Result<(), String> {
+    let corrupt = |what: String| Err(format!("corrupt bytecode file: {what} in `{}`", chunk.name));
+    if chunk.num_locals < chunk.parameter_num {
+        return corrupt(format!(
+            "{} locals for {} parameters",
+            chunk.num_locals, chunk.parameter_num
+        ));
+    }
+    let n = chunk.bytecodes.len();
+    if !matches!(
+        chunk.bytecodes.last(),
+        Some(Instr::Return | Instr::Except | Instr::Jump(_))
+    ) {
+        return corrupt("code running past its end".to_owned());
+    }
+    let is_string = |x: usize| matches!(chunk.constants.get(x).map(|v| v.unbox()), Some(Unboxed::String(_)));
+    for (pc, instr) in chunk.bytecodes.iter().enumerate() {
+        let ok = match *instr {
+            Instr::Load(x) => x < chunk.constants.len(),
+            Instr::GetGlobal(x)
+            | Instr::SetGlobal(x)
+            | Instr::GetField(x)
+            | Instr::SetField(x)
+            | Instr::HasField(x) => is_string(x),
+            Instr::Invoke(name, _) => is_string(name as usize),
+            Instr::LoadChunk(x) => x < chunk.chunks.len(),
+            Instr::GetLocal(x) | Instr::SetLocal(x) | Instr::CloseUpValues(x) => {
+                x < chunk.num_locals
+            }
+            Instr::GetUpValue(x) | Instr::SetUpValue(x) => x < chunk.upvalues.len(),
+            Instr::Jump(x)
+            | Instr::JumpIfNot(x)
+            | Instr::JumpIfTrue(x)
+            | Instr::JumpIfNil(x)
+            | Instr::JumpIfNotNil(x)
+            | Instr::PushHandler(x) => (0..n as i64).contains(&(pc as i64 + x as i64)),
+            _ => true,
+        };
+        if !ok {
+            return corrupt(format!("operand of {instr:?} at {pc} out of range"));
+        }
+    }
+    for local in chunk.locals.iter() {
+        if local.slot >= chunk.num_locals {
+            return corrupt(format!("slot of local `{}` out of range", local.name));
+        }
+    }
+    for sub_chunk in chunk.chunks.iter() {
+        for upv in sub_chunk.upvalues.iter() {
+            let (ok, name) = match upv {
+                UpValueDecl::Ref(idx, name) => (*idx < chunk.num_locals, name),
+                UpValueDecl::RefUpValue(idx, name) => (*idx < chunk.upvalues.len(), name),
+            };
+            if !ok {
+                return corrupt(format!("upvalue `{name}` of `{}` out of range", sub_chunk.name));
+            }
+        }
+    }
+    Ok(())
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() - self.pos < n {
+            return Err("unexpected end of bytecode file".to_owned());
+        }
+        let s = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(s)
+    }
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+    fn i32(&mut self) -> Result<i32, String> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+    /// element count, checked against the remaining bytes so a corrupt file
+    /// cannot make us allocate or loop forever
+    fn len(&mut self) -> Result<usize, String> {
+        let n = self.u64()?;
+        if n > (self.bytes.len() - self.pos) as u64 {
+            return Err("corrupt bytecode file".to_owned());
+        }
+        Ok(n as usize)
+    }
+    fn str(&mut self) -> Result<&'a str, String> {
+        let n = self.len()?;
+        std::str::from_utf8(self.take(n)?).map_err(|_| "invalid utf-8 in bytecode file".to_owned())
+    }
+}
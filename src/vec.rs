@@ -1,128 +1,166 @@
-use crate::{vm::Vm, Value};
+use crate::{
+    vm::{EvalError, Vm},
+    NativeResult, Unboxed, Value,
+};
 
 macro_rules! arity_assert {
     ($n:expr, $arg_num:expr) => {
         if $arg_num != $n {
-            panic!("arity check failed, passed {}, required {}", $n, $arg_num);
+            return Err(EvalError::CallError(format!(
+                "arity check failed, passed {}, required {}",
+                $arg_num, $n
+            )));
         }
     };
 }
 
 macro_rules! mf_entry {
     ($name:expr,$func:expr) => {
-        ($name.to_owned(), Value::NativeFunction($func as *mut u8))
+        ($name.to_owned(), Value::native_function($func as *mut u8))
     };
 }
 
-pub fn vec_u8_create(vm: &mut Vm, arg_num: usize, _protected: bool) {
+pub fn vec_u8_create(vm: &mut Vm, arg_num: usize, _protected: bool) -> NativeResult {
     arity_assert!(1, arg_num);
-    let length = if let Value::Number(length) = vm.get_stack().pop().unwrap() {
+    let length = if let Unboxed::Number(length) = vm.get_stack().pop().unwrap().unbox() {
         length
     } else {
-        panic!("vec_u8_create take 1 parameter: length:Number.")
+        return Err(EvalError::TypeError(
+            "vec_u8_create take 1 parameter: length:Number.".to_owned(),
+        ));
     };
     let _ = vm.get_stack().pop();
     let vec = vec![0u8; length as usize];
     let b_vec = Box::new(vec);
     // sloth guest program should manage it
     let p_vec = Box::into_raw(b_vec);
-    vm.get_stack().push(Value::OpaqueData(p_vec as *mut u8));
+    vm.get_stack().push(Value::opaque_data(p_vec as *mut u8));
+    Ok(())
 }
 
-pub fn vec_u8_set(vm: &mut Vm, arg_num: usize, _protected: bool) {
+pub fn vec_u8_set(vm: &mut Vm, arg_num: usize, _protected: bool) -> NativeResult {
     arity_assert!(3, arg_num);
-    let val = if let Value::Number(val) = vm.get_stack().pop().unwrap() {
+    let val = if let Unboxed::Number(val) = vm.get_stack().pop().unwrap().unbox() {
         val
     } else {
-        panic!("vec_u8_set take 3 parameter: clct:OpaqueData, idx:Number, val:Number.")
+        return Err(EvalError::TypeError(
+            "vec_u8_set take 3 parameter: clct:OpaqueData, idx:Number, val:Number.".to_owned(),
+        ));
     };
-    let idx = if let Value::Number(idx) = vm.get_stack().pop().unwrap() {
+    let idx = if let Unboxed::Number(idx) = vm.get_stack().pop().unwrap().unbox() {
         idx
     } else {
-        panic!("vec_u8_set take 3 parameter: clct:OpaqueData, idx:Number, val:Number.")
+        return Err(EvalError::TypeError(
+            "vec_u8_set take 3 parameter: clct:OpaqueData, idx:Number, val:Number.".to_owned(),
+        ));
     };
-    let clct = if let Value::OpaqueData(clct) = vm.get_stack().pop().unwrap() {
+    let clct = if let Unboxed::OpaqueData(clct) = vm.get_stack().pop().unwrap().unbox() {
         clct
     } else {
-        panic!("vec_u8_set take 3 parameter: clct:OpaqueData, idx:Number, val:Number.")
+        return Err(EvalError::TypeError(
+            "vec_u8_set take 3 parameter: clct:OpaqueData, idx:Number, val:Number.".to_owned(),
+        ));
     };
     let _ = vm.get_stack().pop();
     let clct = clct as *mut Vec<u8>;
     let idx = idx as usize;
     if val > 255. {
-        panic!("val > 255 CANNOT set vec_u8");
+        return Err(EvalError::TypeError(
+            "val > 255 CANNOT set vec_u8".to_owned(),
+        ));
     }
     unsafe {
         if idx >= (*clct).len() {
-            panic!("index out of range, index: {} len: {}", idx, (*clct).len());
+            return Err(EvalError::IndexOutOfBound(format!(
+                "index out of range, index: {} len: {}",
+                idx,
+                (*clct).len()
+            )));
         }
         (*clct)[idx] = val as u8;
     }
-    vm.get_stack().push(Value::Nil);
+    vm.get_stack().push(Value::NIL);
+    Ok(())
 }
 
-pub fn vec_u8_get(vm: &mut Vm, arg_num: usize, _protected: bool) {
+pub fn vec_u8_get(vm: &mut Vm, arg_num: usize, _protected: bool) -> NativeResult {
     arity_assert!(2, arg_num);
-    let idx = if let Value::Number(idx) = vm.get_stack().pop().unwrap() {
+    let idx = if let Unboxed::Number(idx) = vm.get_stack().pop().unwrap().unbox() {
         idx
     } else {
-        panic!("vec_u8_get take 2 parameter: clct:OpaqueData, idx:Number.")
+        return Err(EvalError::TypeError(
+            "vec_u8_get take 2 parameter: clct:OpaqueData, idx:Number.".to_owned(),
+        ));
     };
-    let clct = if let Value::OpaqueData(clct) = vm.get_stack().pop().unwrap() {
+    let clct = if let Unboxed::OpaqueData(clct) = vm.get_stack().pop().unwrap().unbox() {
         clct
     } else {
-        panic!("vec_u8_get take 2 parameter: clct:OpaqueData, idx:Number.")
+        return Err(EvalError::TypeError(
+            "vec_u8_get take 2 parameter: clct:OpaqueData, idx:Number.".to_owned(),
+        ));
     };
     let _ = vm.get_stack().pop();
     let clct = clct as *mut Vec<u8>;
     let idx = idx as usize;
     unsafe {
         if idx >= (*clct).len() {
-            panic!("index out of range, index: {} len: {}", idx, (*clct).len());
+            return Err(EvalError::IndexOutOfBound(format!(
+                "index out of range, index: {} len: {}",
+                idx,
+                (*clct).len()
+            )));
         }
         let val = (*clct)[idx];
-        vm.get_stack().push(Value::Number(val as f64));
+        vm.get_stack().push(Value::number(val as f64));
     }
+    Ok(())
 }
 
-pub fn vec_u8_len(vm: &mut Vm, arg_num: usize, _protected: bool) {
+pub fn vec_u8_len(vm: &mut Vm, arg_num: usize, _protected: bool) -> NativeResult {
     arity_assert!(1, arg_num);
-    let clct = if let Value::OpaqueData(clct) = vm.get_stack().pop().unwrap() {
+    let clct = if let Unboxed::OpaqueData(clct) = vm.get_stack().pop().unwrap().unbox() {
         clct
     } else {
-        panic!("vec_u8_len take 1 parameter: clct:OpaqueData.")
+        return Err(EvalError::TypeError(
+            "vec_u8_len take 1 parameter: clct:OpaqueData.".to_owned(),
+        ));
     };
     let _ = vm.get_stack().pop();
     let clct = clct as *mut Vec<u8>;
     unsafe {
-        
         let val = (*clct).len();
-        vm.get_stack().push(Value::Number(val as f64));
+        vm.get_stack().push(Value::number(val as f64));
     }
+    Ok(())
 }
-pub fn vec_u8_destroy(vm: &mut Vm, arg_num: usize, _protected: bool) {
+pub fn vec_u8_destroy(vm: &mut Vm, arg_num: usize, _protected: bool) -> NativeResult {
     arity_assert!(1, arg_num);
-    let clct = if let Value::OpaqueData(clct) = vm.get_stack().pop().unwrap() {
+    let clct = if let Unboxed::OpaqueData(clct) = vm.get_stack().pop().unwrap().unbox() {
         clct
     } else {
-        panic!("vec_u8_destory take 1 parameter: clct: OpaqueData.")
+        return Err(EvalError::TypeError(
+            "vec_u8_destory take 1 parameter: clct: OpaqueData.".to_owned(),
+        ));
     };
     let _ = vm.get_stack().pop();
-    
+
     let clct = clct as *mut Vec<u8>;
     unsafe {
         // drop here
         let _box = Box::from_raw(clct);
     }
-    vm.get_stack().push(Value::Nil);
+    vm.get_stack().push(Value::NIL);
+    Ok(())
 }
 
-pub fn vec_u8_from_ascii_string(vm: &mut Vm, arg_num: usize, _protected: bool) {
+pub fn vec_u8_from_ascii_string(vm: &mut Vm, arg_num: usize, _protected: bool) -> NativeResult {
     arity_assert!(1, arg_num);
-    let s = if let Value::String(s) = vm.get_stack().pop().unwrap() {
+    let s = if let Unboxed::String(s) = vm.get_stack().pop().unwrap().unbox() {
         s
     } else {
-        panic!("from_ascii_str take 1 argument: s: String.");
+        return Err(EvalError::TypeError(
+            "from_ascii_str take 1 argument: s: String.".to_owned(),
+        ));
     };
     let _ = vm.get_stack().pop();
     let mut vec = Vec::new();
@@ -130,15 +168,17 @@ pub fn vec_u8_from_ascii_string(vm: &mut Vm, arg_num: usize, _protected: bool) {
         if c.is_ascii() {
             vec.push(c as u8);
         } else {
-            panic!("String contain non-ascii characters.")
+            return Err(EvalError::TypeError(
+                "String contain non-ascii characters.".to_owned(),
+            ));
         }
     }
 
     let b_vec = Box::new(vec);
     // sloth guest program should manage it
     let p_vec = Box::into_raw(b_vec);
-    vm.get_stack().push(Value::OpaqueData(p_vec as *mut u8));
-
+    vm.get_stack().push(Value::opaque_data(p_vec as *mut u8));
+    Ok(())
 }
 pub fn module_export() -> (String, Vec<(String, Value)>) {
     let module_name = "vec_u8".to_owned();
@@ -152,4 +192,4 @@ pub fn module_export() -> (String, Vec<(String, Value)>) {
     ];
 
     (module_name, module_func)
-}
\ No newline at end of file
+}
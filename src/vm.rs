@@ -2,11 +2,15 @@ use fmt::format;
 use interned_string::{IString, StringPool};
 
 use crate::*;
+use debugger::{same_file, DebugState};
 use std::{
-    collections::btree_map::Range,
     fmt::Display,
     path::PathBuf,
     ptr::{self, null_mut},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
 };
 #[derive(Debug)]
 pub enum EvalError {
@@ -19,6 +23,25 @@ pub enum EvalError {
     VariableNotFound(String),
     KeyError(String),
     GCError,
+    /// uncaught error with the backtrace captured where it was raised
+    Traced(Box<EvalError>, Vec<TraceFrame>),
+}
+/// one call frame in a backtrace
+#[derive(Debug, Clone)]
+pub struct TraceFrame {
+    pub name: String,
+    pub file: String,
+    pub line: usize,
+}
+impl Display for TraceFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let file = if self.file.is_empty() {
+            "<input>"
+        } else {
+            &self.file
+        };
+        write!(f, "{} ({}:{})", self.name, file, self.line)
+    }
 }
 impl Display for EvalError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -31,35 +54,102 @@ impl From<EvalError> for String {
     }
 }
 
+impl From<Diagnostic> for EvalError {
+    fn from(value: Diagnostic) -> Self {
+        EvalError::Error(value.to_string())
+    }
+}
 impl From<String> for EvalError {
     fn from(value: String) -> Self {
         EvalError::Error(value)
     }
 }
+impl EvalError {
+    /// name of the variant, exposed as `kind` of the sloth error value
+    pub fn kind(&self) -> &'static str {
+        match self {
+            EvalError::Error(_) => "Error",
+            EvalError::Exception(_) => "Exception",
+            EvalError::ArithmError(_) => "ArithmError",
+            EvalError::TypeError(_) => "TypeError",
+            EvalError::IndexOutOfBound(_) => "IndexOutOfBound",
+            EvalError::CallError(_) => "CallError",
+            EvalError::VariableNotFound(_) => "VariableNotFound",
+            EvalError::KeyError(_) => "KeyError",
+            EvalError::GCError => "GCError",
+            EvalError::Traced(err, _) => err.kind(),
+        }
+    }
+    pub fn message(&self) -> String {
+        match self {
+            EvalError::Error(s)
+            | EvalError::ArithmError(s)
+            | EvalError::TypeError(s)
+            | EvalError::IndexOutOfBound(s)
+            | EvalError::CallError(s)
+            | EvalError::VariableNotFound(s)
+            | EvalError::KeyError(s) => s.clone(),
+            EvalError::Exception(info) => info
+                .get("info")
+                .map(native::value_to_string)
+                .unwrap_or_default(),
+            EvalError::GCError => "GC failed".to_owned(),
+            EvalError::Traced(err, _) => err.message(),
+        }
+    }
+    /// innermost frame first
+    pub fn backtrace(&self) -> Option<&[TraceFrame]> {
+        if let EvalError::Traced(_, trace) = self {
+            Some(trace)
+        } else {
+            None
+        }
+    }
+}
 type EvalResult = Result<(), EvalError>;
+/// receives what `print` writes
+type Output = Box<dyn FnMut(&str)>;
 
 pub struct Vm {
     executing_fiber: *mut Fiber,
-    upvalues: Vec<*mut UpValueObject>,
     objects: Vec<Box<dyn GCObject>>,
     // chunks: Vec<Chunk>,
     top_chunk: Box<Chunk>,     // no gc during running
     top_closure: Box<Closure>, // no gc during running
     main_fiber: Box<Fiber>,    // no gc during running
-    protected: bool,
-    /// should load other modules in seperated global namespace
-    global: Vec<HashMap<IString, Value>>,
+    /// should load other modules in seperated global namespace,
+    /// each maps names to `global_slots`
+    global: Vec<HashMap<IString, usize>>,
+    /// values of the globals of every module, `None` until defined
+    global_slots: Vec<Option<Value>>,
 
     /// modules other than ther main module
     loaded_chunk: Vec<Box<Chunk>>,
 
     string_pool: StringPool,
     debug: bool,
+    /// optimize imported modules
+    optimize: bool,
 
     pub interpreter_cwd: PathBuf,
     /// if fiber changed, pc should not be added
     pub fiber_changed: bool,
+
+    /// values held by native code between calls, treated as gc roots
+    native_roots: Vec<Value>,
+    /// collect when `objects` grows to this size
+    next_gc: usize,
+    /// collect on every allocation, for catching rooting bugs
+    gc_stress: bool,
+    /// breakpoints and stepping, `None` unless debugging
+    debugger: Option<Box<DebugState>>,
+    /// where `print` writes, stdout if `None`
+    output: Option<Output>,
 }
+/// objects allocated before the first collection
+const GC_INITIAL_THRESHOLD: usize = 1024;
+/// heap may grow to `live objects * GC_GROW_FACTOR` before next collection
+const GC_GROW_FACTOR: usize = 2;
 
 /// every fiber have its own stack
 
@@ -72,6 +162,15 @@ pub struct CallFrame {
     pub va_args: Vec<Value>,
 
     pub discard_return_value: bool,
+    /// entered by `TryCall`, errors raised above this frame unwind to here
+    pub protected: bool,
+    /// `(stack height, pc of the catch block)` of the `try` blocks the frame
+    /// is in, innermost last
+    pub handlers: Vec<(usize, usize)>,
+    /// line the debugger last saw in this frame, 0 before the first one
+    line: usize,
+    /// `this` of the closure, or the receiver of `Invoke`
+    pub this_ref: Option<*mut Instance>,
 }
 impl CallFrame {
     pub fn new(bottom: usize, closure: *mut Closure, va_args: Vec<Value>) -> CallFrame {
@@ -81,6 +180,10 @@ impl CallFrame {
             pc: 0,
             va_args,
             discard_return_value: false,
+            protected: false,
+            handlers: Vec::new(),
+            line: 0,
+            this_ref: unsafe { (*closure).this_ref },
         }
     }
     fn decode(&self) -> Instr {
@@ -117,35 +220,49 @@ impl Vm {
             closure.as_mut() as *mut Closure,
             Vec::new(),
         ));
+        let mut names = HashMap::new();
+        let mut global_slots = Vec::new();
+        for (name, val) in global {
+            names.insert(name, global_slots.len());
+            global_slots.push(Some(val));
+        }
+        link_chunk(&mut b_chunk, &mut names, None, &mut global_slots);
         let mut fiber = Box::new(Fiber {
             marked: false,
             call_frames: call_frames,
             stack: {
                 let mut vec = Vec::new();
                 for _ in 0..b_chunk.num_locals {
-                    vec.push(Value::Nil);
+                    vec.push(Value::NIL);
                 }
                 vec
             },
+            open_upvalues: Vec::new(),
             // main fiber is always waiting, which prevent other fibers from resuming it
             state: FiberState::Running,
             prev: null_mut() as *mut Fiber,
+            protected: false,
         });
 
         Vm {
             executing_fiber: fiber.as_mut() as *mut Fiber,
-            upvalues: Vec::new(),
             objects: Vec::new(),
             top_closure: closure,
             top_chunk: b_chunk,
             main_fiber: fiber,
-            protected: false,
-            global: vec![global],
+            global: vec![names],
+            global_slots,
             loaded_chunk: Vec::new(),
             string_pool,
             debug,
+            optimize: false,
             interpreter_cwd,
             fiber_changed: false,
+            native_roots: Vec::new(),
+            next_gc: GC_INITIAL_THRESHOLD,
+            gc_stress: cfg!(feature = "gc_stress"),
+            debugger: None,
+            output: None,
         }
     }
     pub fn get_stack<'a>(&'a self) -> &'a mut Vec<Value> {
@@ -157,6 +274,27 @@ impl Vm {
     }
     pub fn run(&mut self) -> EvalResult {
         loop {
+            match self.run_loop() {
+                Ok(()) => return Ok(()),
+                Err(err) => {
+                    let trace = self.backtrace();
+                    let err_val = self.new_error_value(&err, &trace);
+                    if !self.unwind_to_protected(err_val) {
+                        return Err(EvalError::Traced(Box::new(err), trace));
+                    }
+                }
+            }
+        }
+    }
+    fn run_loop(&mut self) -> EvalResult {
+        loop {
+            // every live value is reachable from roots between instructions
+            if self.objects.len() >= self.next_gc || self.string_pool.needs_sweep() {
+                self.run_gc()?;
+            }
+            if self.debugger.is_some() && self.debug_should_pause() {
+                return Ok(());
+            }
             let call_frame = unsafe { (*self.executing_fiber).call_frames.last_mut().unwrap() };
             let closure = call_frame.closure;
             let mut stack = unsafe { &mut (*self.executing_fiber).stack };
@@ -177,76 +315,93 @@ impl Vm {
                     num_locals
                 );
             }
+            // instructions counting their operands may come from a corrupt
+            // bytecode file, which must not take values of the locals or the caller
+            let takes = match instr {
+                Instr::Dup(n) | Instr::InitArray(n) => n,
+                Instr::InitDict(n) => n.saturating_mul(2),
+                Instr::Call(n) | Instr::TryCall(n) => n.saturating_add(1),
+                Instr::Invoke(_, n) => n as usize + 1,
+                _ => 0,
+            };
+            if stack.len().saturating_sub(call_frame.bottom + num_locals) < takes {
+                return Err(EvalError::Error(format!(
+                    "corrupt bytecode: {instr:?} takes more values than the stack holds"
+                )));
+            }
             match instr {
                 Instr::Add => {
+                    if self.quicken(pc, instr) {
+                        continue;
+                    }
                     let opr2 = stack.pop().unwrap();
                     let opr1 = stack.pop().unwrap();
-                    match opr1 {
-                        Value::Number(a) => {
-                            if let Value::Number(b) = opr2 {
+                    match opr1.unbox() {
+                        Unboxed::Number(a) => {
+                            if let Unboxed::Number(b) = opr2.unbox() {
                                 let res = a + b;
-                                stack.push(Value::Number(res));
+                                stack.push(Value::number(res));
                             } else {
                                 return Err(EvalError::TypeError(
-                                    self.eval_err_str("unsupported operation on `+`"),
+                                    "unsupported operation on `+`".to_owned(),
                                 ));
                             }
                             self.pc_add();
                         }
-                        Value::String(a) => {
-                            if let Value::String(b) = opr2 {
+                        Unboxed::String(a) => {
+                            if let Unboxed::String(b) = opr2.unbox() {
                                 let mut res = a.get_inner().to_owned();
                                 res += b.get_inner();
                                 let istring = self.string_pool.creat_istring(&res);
-                                stack.push(Value::String(istring));
+                                stack.push(Value::string(istring));
                             } else {
                                 return Err(EvalError::TypeError(
-                                    self.eval_err_str("unsupported operation on `+`"),
+                                    "unsupported operation on `+`".to_owned(),
                                 ));
                             }
                             self.pc_add();
                         }
-                        Value::Array(a) => {
-                            if let Value::Array(b) = opr2 {
+                        Unboxed::Array(a) => {
+                            if let Unboxed::Array(b) = opr2.unbox() {
                                 unsafe {
                                     let mut new_arr = Vec::new();
-                                    (*a).array.iter().for_each(|x| new_arr.push(x.clone()));
-                                    (*b).array.iter().for_each(|x| new_arr.push(x.clone()));
+                                    (*a).array.iter().for_each(|x| new_arr.push(*x));
+                                    (*b).array.iter().for_each(|x| new_arr.push(*x));
                                     let mut b_new_arr = Box::new(Array {
                                         marked: false,
                                         array: new_arr,
                                     });
                                     let p_new_arr = b_new_arr.as_mut() as *mut Array;
                                     self.objects.push(b_new_arr);
-                                    stack.push(Value::Array(p_new_arr));
+                                    stack.push(Value::array(p_new_arr));
                                 }
                                 self.pc_add();
                             } else {
                                 return Err(EvalError::TypeError(
-                                    self.eval_err_str("unsupported operation on `+`"),
+                                    "unsupported operation on `+`".to_owned(),
                                 ));
                             }
                         }
-                        Value::Instance(p_instance) => {
+                        Unboxed::Instance(p_instance) => {
                             // a + b => a.__add__(b)
                             let instance = unsafe { &mut *p_instance };
                             let protocol_func_name = self.string_pool.creat_istring("__add__");
-                            if let Some(v) = instance.fields.get(&protocol_func_name) {
+                            if let Some(_v) = instance.field(&protocol_func_name) {
                                 return Err(EvalError::CallError(
-                                    self.eval_err_str("`__add__` defined as field of Instance"),
+                                    "`__add__` defined as field of Instance".to_owned(),
                                 ));
                             } else {
                                 if let Some(method) =
                                     unsafe { (*instance.klass).methods.get(&protocol_func_name) }
                                 {
-                                    if let Value::Closure(method) = method {
-                                        let mut binded_closure = unsafe { (**method).clone() };
+                                    if let Unboxed::Closure(method) = method.unbox() {
+                                        let mut binded_closure = unsafe { (*method).clone() };
                                         binded_closure.this_ref = Some(p_instance);
                                         let mut b_binded_closure = Box::new(binded_closure);
                                         let p_binded_closure =
                                             b_binded_closure.as_mut() as *mut Closure;
                                         self.objects.push(b_binded_closure);
-                                        let f = Value::Closure(p_binded_closure);
+                                        let f = Value::closure(p_binded_closure);
                                         stack.push(f);
                                         stack.push(opr2);
                                         self.call_routine(1)?;
@@ -255,53 +410,56 @@ impl Vm {
                                     }
                                 } else {
                                     return Err(EvalError::VariableNotFound(
-                                        self.eval_err_str("`__add__` method not found"),
+                                        "`__add__` method not found".to_owned(),
                                     ));
                                 }
                             };
                         }
                         _ => {
                             return Err(EvalError::TypeError(
-                                self.eval_err_str("unsupported operation on `+`"),
+                                "unsupported operation on `+`".to_owned(),
                             ))
                         }
                     }
                 }
                 Instr::Sub => {
+                    if self.quicken(pc, instr) {
+                        continue;
+                    }
                     let opr2 = stack.pop().unwrap();
                     let opr1 = stack.pop().unwrap();
-                    match opr1 {
-                        Value::Number(a) => {
-                            if let Value::Number(b) = opr2 {
+                    match opr1.unbox() {
+                        Unboxed::Number(a) => {
+                            if let Unboxed::Number(b) = opr2.unbox() {
                                 let res = a - b;
-                                stack.push(Value::Number(res));
+                                stack.push(Value::number(res));
                             } else {
                                 return Err(EvalError::TypeError(
-                                    self.eval_err_str("unsupported operation on `-`"),
+                                    "unsupported operation on `-`".to_owned(),
                                 ));
                             }
                             self.pc_add();
                         }
-                        Value::Instance(p_instance) => {
+                        Unboxed::Instance(p_instance) => {
                             // a + b => a.__add__(b)
                             let instance = unsafe { &mut *p_instance };
                             let protocol_func_name = self.string_pool.creat_istring("__sub__");
-                            if let Some(v) = instance.fields.get(&protocol_func_name) {
+                            if let Some(_v) = instance.field(&protocol_func_name) {
                                 return Err(EvalError::CallError(
-                                    self.eval_err_str("`__sub__` defined as field of Instance"),
+                                    "`__sub__` defined as field of Instance".to_owned(),
                                 ));
                             } else {
                                 if let Some(method) =
                                     unsafe { (*instance.klass).methods.get(&protocol_func_name) }
                                 {
-                                    if let Value::Closure(method) = method {
-                                        let mut binded_closure = unsafe { (**method).clone() };
+                                    if let Unboxed::Closure(method) = method.unbox() {
+                                        let mut binded_closure = unsafe { (*method).clone() };
                                         binded_closure.this_ref = Some(p_instance);
                                         let mut b_binded_closure = Box::new(binded_closure);
                                         let p_binded_closure =
                                             b_binded_closure.as_mut() as *mut Closure;
                                         self.objects.push(b_binded_closure);
-                                        let f = Value::Closure(p_binded_closure);
+                                        let f = Value::closure(p_binded_closure);
                                         stack.push(f);
                                         stack.push(opr2);
                                         self.call_routine(1)?;
@@ -310,53 +468,56 @@ impl Vm {
                                     }
                                 } else {
                                     return Err(EvalError::VariableNotFound(
-                                        self.eval_err_str("`__sub__` method not found"),
+                                        "`__sub__` method not found".to_owned(),
                                     ));
                                 }
                             };
                         }
                         _ => {
                             return Err(EvalError::TypeError(
-                                self.eval_err_str("unsupported operation on `-`"),
+                                "unsupported operation on `-`".to_owned(),
                             ))
                         }
                     }
                 }
                 Instr::Mul => {
+                    if self.quicken(pc, instr) {
+                        continue;
+                    }
                     let opr2 = stack.pop().unwrap();
                     let opr1 = stack.pop().unwrap();
-                    match opr1 {
-                        Value::Number(a) => {
-                            if let Value::Number(b) = opr2 {
+                    match opr1.unbox() {
+                        Unboxed::Number(a) => {
+                            if let Unboxed::Number(b) = opr2.unbox() {
                                 let res = a * b;
-                                stack.push(Value::Number(res));
+                                stack.push(Value::number(res));
                             } else {
                                 return Err(EvalError::TypeError(
-                                    self.eval_err_str("unsupported operation on `*`"),
+                                    "unsupported operation on `*`".to_owned(),
                                 ));
                             }
                             self.pc_add();
                         }
-                        Value::Instance(p_instance) => {
+                        Unboxed::Instance(p_instance) => {
                             // a + b => a.__add__(b)
                             let instance = unsafe { &mut *p_instance };
                             let protocol_func_name = self.string_pool.creat_istring("__mul__");
-                            if let Some(v) = instance.fields.get(&protocol_func_name) {
+                            if let Some(_v) = instance.field(&protocol_func_name) {
                                 return Err(EvalError::CallError(
-                                    self.eval_err_str("`__mul__` defined as field of Instance"),
+                                    "`__mul__` defined as field of Instance".to_owned(),
                                 ));
                             } else {
                                 if let Some(method) =
                                     unsafe { (*instance.klass).methods.get(&protocol_func_name) }
                                 {
-                                    if let Value::Closure(method) = method {
-                                        let mut binded_closure = unsafe { (**method).clone() };
+                                    if let Unboxed::Closure(method) = method.unbox() {
+                                        let mut binded_closure = unsafe { (*method).clone() };
                                         binded_closure.this_ref = Some(p_instance);
                                         let mut b_binded_closure = Box::new(binded_closure);
                                         let p_binded_closure =
                                             b_binded_closure.as_mut() as *mut Closure;
                                         self.objects.push(b_binded_closure);
-                                        let f = Value::Closure(p_binded_closure);
+                                        let f = Value::closure(p_binded_closure);
                                         stack.push(f);
                                         stack.push(opr2);
                                         self.call_routine(1)?;
@@ -365,14 +526,14 @@ impl Vm {
                                     }
                                 } else {
                                     return Err(EvalError::VariableNotFound(
-                                        self.eval_err_str("`__mul__` method not found"),
+                                        "`__mul__` method not found".to_owned(),
                                     ));
                                 }
                             };
                         }
                         _ => {
                             return Err(EvalError::TypeError(
-                                self.eval_err_str("unsupported operation on `*`"),
+                                "unsupported operation on `*`".to_owned(),
                             ))
                         }
                     }
@@ -380,43 +541,41 @@ impl Vm {
                 Instr::Div => {
                     let opr2 = stack.pop().unwrap();
                     let opr1 = stack.pop().unwrap();
-                    match opr1 {
-                        Value::Number(a) => {
-                            if let Value::Number(b) = opr2 {
+                    match opr1.unbox() {
+                        Unboxed::Number(a) => {
+                            if let Unboxed::Number(b) = opr2.unbox() {
                                 if b < 1e-5 {
-                                    return Err(EvalError::ArithmError(
-                                        self.eval_err_str("div by 0"),
-                                    ));
+                                    return Err(EvalError::ArithmError("div by 0".to_owned()));
                                 }
                                 let res = a / b;
-                                stack.push(Value::Number(res));
+                                stack.push(Value::number(res));
                             } else {
                                 return Err(EvalError::TypeError(
-                                    self.eval_err_str("unsupported operation on `/`"),
+                                    "unsupported operation on `/`".to_owned(),
                                 ));
                             }
                             self.pc_add();
                         }
-                        Value::Instance(p_instance) => {
+                        Unboxed::Instance(p_instance) => {
                             // a + b => a.__add__(b)
                             let instance = unsafe { &mut *p_instance };
                             let protocol_func_name = self.string_pool.creat_istring("__div__");
-                            if let Some(v) = instance.fields.get(&protocol_func_name) {
+                            if let Some(_v) = instance.field(&protocol_func_name) {
                                 return Err(EvalError::CallError(
-                                    self.eval_err_str("`__div__` defined as field of Instance"),
+                                    "`__div__` defined as field of Instance".to_owned(),
                                 ));
                             } else {
                                 if let Some(method) =
                                     unsafe { (*instance.klass).methods.get(&protocol_func_name) }
                                 {
-                                    if let Value::Closure(method) = method {
-                                        let mut binded_closure = unsafe { (**method).clone() };
+                                    if let Unboxed::Closure(method) = method.unbox() {
+                                        let mut binded_closure = unsafe { (*method).clone() };
                                         binded_closure.this_ref = Some(p_instance);
                                         let mut b_binded_closure = Box::new(binded_closure);
                                         let p_binded_closure =
                                             b_binded_closure.as_mut() as *mut Closure;
                                         self.objects.push(b_binded_closure);
-                                        let f = Value::Closure(p_binded_closure);
+                                        let f = Value::closure(p_binded_closure);
                                         stack.push(f);
                                         stack.push(opr2);
                                         self.call_routine(1)?;
@@ -425,14 +584,14 @@ impl Vm {
                                     }
                                 } else {
                                     return Err(EvalError::VariableNotFound(
-                                        self.eval_err_str("`__div__` method not found"),
+                                        "`__div__` method not found".to_owned(),
                                     ));
                                 }
                             };
                         }
                         _ => {
                             return Err(EvalError::TypeError(
-                                self.eval_err_str("unsupported operation on `/`"),
+                                "unsupported operation on `/`".to_owned(),
                             ))
                         }
                     }
@@ -440,43 +599,41 @@ impl Vm {
                 Instr::Mod => {
                     let opr2 = stack.pop().unwrap();
                     let opr1 = stack.pop().unwrap();
-                    match opr1 {
-                        Value::Number(a) => {
-                            if let Value::Number(b) = opr2 {
+                    match opr1.unbox() {
+                        Unboxed::Number(a) => {
+                            if let Unboxed::Number(b) = opr2.unbox() {
                                 if b < 1e-5 {
-                                    return Err(EvalError::ArithmError(
-                                        self.eval_err_str("div by 0"),
-                                    ));
+                                    return Err(EvalError::ArithmError("div by 0".to_owned()));
                                 }
                                 let res = a % b;
-                                stack.push(Value::Number(res));
+                                stack.push(Value::number(res));
                             } else {
                                 return Err(EvalError::TypeError(
-                                    self.eval_err_str("unsupported operation on `%`"),
+                                    "unsupported operation on `%`".to_owned(),
                                 ));
                             }
                             self.pc_add();
                         }
-                        Value::Instance(p_instance) => {
+                        Unboxed::Instance(p_instance) => {
                             // a + b => a.__add__(b)
                             let instance = unsafe { &mut *p_instance };
                             let protocol_func_name = self.string_pool.creat_istring("__mod__");
-                            if let Some(v) = instance.fields.get(&protocol_func_name) {
+                            if let Some(_v) = instance.field(&protocol_func_name) {
                                 return Err(EvalError::CallError(
-                                    self.eval_err_str("`__mod__` defined as field of Instance"),
+                                    "`__mod__` defined as field of Instance".to_owned(),
                                 ));
                             } else {
                                 if let Some(method) =
                                     unsafe { (*instance.klass).methods.get(&protocol_func_name) }
                                 {
-                                    if let Value::Closure(method) = method {
-                                        let mut binded_closure = unsafe { (**method).clone() };
+                                    if let Unboxed::Closure(method) = method.unbox() {
+                                        let mut binded_closure = unsafe { (*method).clone() };
                                         binded_closure.this_ref = Some(p_instance);
                                         let mut b_binded_closure = Box::new(binded_closure);
                                         let p_binded_closure =
                                             b_binded_closure.as_mut() as *mut Closure;
                                         self.objects.push(b_binded_closure);
-                                        let f = Value::Closure(p_binded_closure);
+                                        let f = Value::closure(p_binded_closure);
                                         stack.push(f);
                                         stack.push(opr2);
                                         self.call_routine(1)?;
@@ -485,45 +642,45 @@ impl Vm {
                                     }
                                 } else {
                                     return Err(EvalError::VariableNotFound(
-                                        self.eval_err_str("`__mod__` method not found"),
+                                        "`__mod__` method not found".to_owned(),
                                     ));
                                 }
                             };
                         }
                         _ => {
                             return Err(EvalError::TypeError(
-                                self.eval_err_str("unsupported operation on `%`"),
+                                "unsupported operation on `%`".to_owned(),
                             ))
                         }
                     }
                 }
                 Instr::Negate => {
                     let opr1 = stack.pop().unwrap();
-                    match opr1 {
-                        Value::Number(a) => {
-                            stack.push(Value::Number(-a));
+                    match opr1.unbox() {
+                        Unboxed::Number(a) => {
+                            stack.push(Value::number(-a));
                             self.pc_add();
                         }
-                        Value::Instance(p_instance) => {
+                        Unboxed::Instance(p_instance) => {
                             // -a => a.__neg__()
                             let instance = unsafe { &mut *p_instance };
                             let protocol_func_name = self.string_pool.creat_istring("__neg__");
-                            if let Some(v) = instance.fields.get(&protocol_func_name) {
+                            if let Some(_v) = instance.field(&protocol_func_name) {
                                 return Err(EvalError::CallError(
-                                    self.eval_err_str("`__neg__` defined as field of Instance"),
+                                    "`__neg__` defined as field of Instance".to_owned(),
                                 ));
                             } else {
                                 if let Some(method) =
                                     unsafe { (*instance.klass).methods.get(&protocol_func_name) }
                                 {
-                                    if let Value::Closure(method) = method {
-                                        let mut binded_closure = unsafe { (**method).clone() };
+                                    if let Unboxed::Closure(method) = method.unbox() {
+                                        let mut binded_closure = unsafe { (*method).clone() };
                                         binded_closure.this_ref = Some(p_instance);
                                         let mut b_binded_closure = Box::new(binded_closure);
                                         let p_binded_closure =
                                             b_binded_closure.as_mut() as *mut Closure;
                                         self.objects.push(b_binded_closure);
-                                        let f = Value::Closure(p_binded_closure);
+                                        let f = Value::closure(p_binded_closure);
                                         stack.push(f);
                                         self.call_routine(0)?;
                                     } else {
@@ -531,28 +688,40 @@ impl Vm {
                                     }
                                 } else {
                                     return Err(EvalError::VariableNotFound(
-                                        self.eval_err_str("`__neg__` method not found"),
+                                        "`__neg__` method not found".to_owned(),
                                     ));
                                 }
                             };
                         }
                         _ => {
                             return Err(EvalError::TypeError(
-                                self.eval_err_str("unsupported operation on `-(pfx)`"),
+                                "unsupported operation on `-(pfx)`".to_owned(),
                             ))
                         }
                     }
                 }
                 Instr::Gt => {
+                    if self.quicken(pc, instr) {
+                        continue;
+                    }
                     self.binary_predicate_impl(|x, y| x > y, |s1, s2| s1 > s2, "__gt__")?;
                 }
                 Instr::Lt => {
+                    if self.quicken(pc, instr) {
+                        continue;
+                    }
                     self.binary_predicate_impl(|x, y| x < y, |s1, s2| s1 < s2, "__lt__")?;
                 }
                 Instr::Ge => {
+                    if self.quicken(pc, instr) {
+                        continue;
+                    }
                     self.binary_predicate_impl(|x, y| x >= y, |s1, s2| s1 >= s2, "__ge__")?;
                 }
                 Instr::Le => {
+                    if self.quicken(pc, instr) {
+                        continue;
+                    }
                     self.binary_predicate_impl(|x, y| x <= y, |s1, s2| s1 <= s2, "__le__")?;
                 }
                 Instr::Eq => {
@@ -569,34 +738,70 @@ impl Vm {
                         "__ne__",
                     )?;
                 }
+                Instr::AddNum => self.number_op(pc, instr, |a, b| Value::number(a + b)),
+                Instr::SubNum => self.number_op(pc, instr, |a, b| Value::number(a - b)),
+                Instr::MulNum => self.number_op(pc, instr, |a, b| Value::number(a * b)),
+                Instr::LtNum => self.number_op(pc, instr, |a, b| Value::bool(a < b)),
+                Instr::GtNum => self.number_op(pc, instr, |a, b| Value::bool(a > b)),
+                Instr::LeNum => self.number_op(pc, instr, |a, b| Value::bool(a <= b)),
+                Instr::GeNum => self.number_op(pc, instr, |a, b| Value::bool(a >= b)),
+                Instr::LtJumpIfNot(x) => self.compare_jump(pc, instr, x, |a, b| a < b),
+                Instr::GtJumpIfNot(x) => self.compare_jump(pc, instr, x, |a, b| a > b),
+                Instr::LeJumpIfNot(x) => self.compare_jump(pc, instr, x, |a, b| a <= b),
+                Instr::GeJumpIfNot(x) => self.compare_jump(pc, instr, x, |a, b| a >= b),
+                Instr::GetLocalAdd(x) => {
+                    let local = stack[call_frame.bottom + x];
+                    let top = *stack.last().unwrap();
+                    if let (Some(a), Some(b)) = (top.as_number(), local.as_number()) {
+                        *stack.last_mut().unwrap() = Value::number(a + b);
+                        call_frame.pc += 2;
+                    } else {
+                        self.rewrite_instr(pc, instr.generic());
+                    }
+                }
+                Instr::IncLocal(x) => {
+                    let chunk = unsafe { &*(*closure).chunk };
+                    let Instr::Load(c) = chunk.bytecodes[pc + 1] else {
+                        unreachable!("`IncLocal` is followed by the `Load` of the step")
+                    };
+                    let slot = call_frame.bottom + x;
+                    if let (Some(a), Some(b)) =
+                        (stack[slot].as_number(), chunk.constants[c].as_number())
+                    {
+                        stack[slot] = Value::number(a + b);
+                        call_frame.pc += 4;
+                    } else {
+                        self.rewrite_instr(pc, instr.generic());
+                    }
+                }
                 Instr::Not => {
                     let opr1 = self.get_stack().pop().unwrap();
-                    match opr1 {
-                        Value::Bool(a) => {
+                    match opr1.unbox() {
+                        Unboxed::Bool(a) => {
                             let res = !a;
-                            self.get_stack().push(Value::Bool(res));
+                            self.get_stack().push(Value::bool(res));
                             self.pc_add();
                         }
-                        Value::Instance(p_instance) => {
+                        Unboxed::Instance(p_instance) => {
                             // not a -> a.__not__()
                             let instance = unsafe { &mut *p_instance };
                             let protocol_func_name = self.string_pool.creat_istring("__not__");
-                            if let Some(_v) = instance.fields.get(&protocol_func_name) {
-                                return Err(EvalError::CallError(self.eval_err_str(
-                                    "`not` operation defined as field of Instance",
-                                )));
+                            if let Some(_v) = instance.field(&protocol_func_name) {
+                                return Err(EvalError::CallError(
+                                    "`not` operation defined as field of Instance".to_owned(),
+                                ));
                             } else {
                                 if let Some(method) =
                                     unsafe { (*instance.klass).methods.get(&protocol_func_name) }
                                 {
-                                    if let Value::Closure(method) = method {
-                                        let mut binded_closure = unsafe { (**method).clone() };
+                                    if let Unboxed::Closure(method) = method.unbox() {
+                                        let mut binded_closure = unsafe { (*method).clone() };
                                         binded_closure.this_ref = Some(p_instance);
                                         let mut b_binded_closure = Box::new(binded_closure);
                                         let p_binded_closure =
                                             b_binded_closure.as_mut() as *mut Closure;
                                         self.objects.push(b_binded_closure);
-                                        let f = Value::Closure(p_binded_closure);
+                                        let f = Value::closure(p_binded_closure);
                                         self.get_stack().push(f);
                                         self.call_routine(0)?;
                                     } else {
@@ -604,31 +809,31 @@ impl Vm {
                                     }
                                 } else {
                                     return Err(EvalError::VariableNotFound(
-                                        self.eval_err_str("`__not__` method not found"),
+                                        "`__not__` method not found".to_owned(),
                                     ));
                                 }
                             };
                         }
                         _ => {
                             return Err(EvalError::TypeError(
-                                self.eval_err_str("unsupported `not` operation"),
+                                "unsupported `not` operation".to_owned(),
                             ))
                         }
                     }
                 }
                 Instr::Or => {
                     let (opr1, opr2) = self.stack_get_bool()?;
-                    stack.push(Value::Bool(opr1 || opr2));
+                    stack.push(Value::bool(opr1 || opr2));
                     self.pc_add();
                 }
                 Instr::And => {
                     let (opr1, opr2) = self.stack_get_bool()?;
-                    stack.push(Value::Bool(opr1 && opr2));
+                    stack.push(Value::bool(opr1 && opr2));
                     self.pc_add();
                 }
                 Instr::ClassIs => {
-                    if let Value::Klass(klass) = stack.pop().unwrap() {
-                        if let Value::Instance(instance) = stack.pop().unwrap() {
+                    if let Unboxed::Klass(klass) = stack.pop().unwrap().unbox() {
+                        if let Unboxed::Instance(instance) = stack.pop().unwrap().unbox() {
                             unsafe {
                                 let mut p_klass = (*instance).klass;
                                 let mut is_class = false;
@@ -639,44 +844,50 @@ impl Vm {
                                     }
                                     p_klass = (*p_klass).super_klass;
                                 }
-                                stack.push(Value::Bool(is_class));
+                                stack.push(Value::bool(is_class));
                             }
                         } else {
-                            stack.push(Value::Bool(false));
+                            stack.push(Value::bool(false));
                         }
                         self.pc_add();
                     } else {
                         return Err(EvalError::TypeError(
-                            self.eval_err_str("`is` can ONLY check classes, r-hand must be Class"),
+                            "`is` can ONLY check classes, r-hand must be Class".to_owned(),
                         ));
                     }
                 }
                 Instr::MakeRange => {
                     let (opr1, opr2) = self.stack_get_number()?;
-                    stack.push(Value::Range(opr1, opr2));
+                    let range = self.new_range(opr1, opr2);
+                    stack.push(range);
                     self.pc_add();
                 }
                 Instr::MakeRangeClosed => {
                     let (opr1, opr2) = self.stack_get_number()?;
-                    stack.push(Value::Range(opr1, opr2 + 1.));
+                    let range = self.new_range(opr1, opr2 + 1.);
+                    stack.push(range);
                     self.pc_add();
                 }
                 Instr::PushNil => {
-                    stack.push(Value::Nil);
+                    stack.push(Value::NIL);
                     self.pc_add();
                 }
                 Instr::LoadTrue => {
-                    stack.push(Value::Bool(true));
+                    stack.push(Value::bool(true));
                     self.pc_add();
                 }
                 Instr::LoadFalse => {
-                    stack.push(Value::Bool(false));
+                    stack.push(Value::bool(false));
                     self.pc_add();
                 }
                 Instr::Pop => {
                     stack.pop();
                     self.pc_add();
                 }
+                Instr::Dup(n) => {
+                    stack.extend_from_within(stack.len() - n..);
+                    self.pc_add();
+                }
                 Instr::Swap2 => {
                     let t1 = stack.pop().unwrap();
                     let t2 = stack.pop().unwrap();
@@ -691,22 +902,8 @@ impl Vm {
                     for upval_decl in unsafe { &*chunk }.upvalues.iter() {
                         match upval_decl {
                             UpValueDecl::Ref(idx, _) => {
-                                let current_frame_bottom = call_frame.bottom;
-                                if let Some(x) =
-                                    self.upvalues.iter().position(|p| match unsafe { &**p } {
-                                        UpValueObject {
-                                            marked: _,
-                                            value: UpValue::Ref(idx2),
-                                        } => *idx2 == (*idx) + current_frame_bottom,
-                                        _ => false,
-                                    })
-                                {
-                                    upvalues.push(self.upvalues[x]);
-                                } else {
-                                    let upv =
-                                        self.new_upvalue_object((*idx) + current_frame_bottom);
-                                    upvalues.push(upv);
-                                }
+                                let upv = self.capture_upvalue(*idx + call_frame.bottom);
+                                upvalues.push(upv);
                             }
                             UpValueDecl::RefUpValue(idx, _) => {
                                 let current_closure = unsafe { &*call_frame.closure };
@@ -717,7 +914,7 @@ impl Vm {
                     }
                     // copy current this_ref to loaded closure
                     // for cases when lambda or functions are defined inside methods.
-                    let cur_this_ref = unsafe { (*closure).this_ref.clone() };
+                    let cur_this_ref = call_frame.this_ref;
                     let closure = Closure {
                         marked: false,
                         chunk,
@@ -727,42 +924,44 @@ impl Vm {
                     let mut boxed_closure = Box::new(closure);
                     let pointer = boxed_closure.as_mut() as *mut Closure;
                     self.objects.push(boxed_closure);
-                    stack.push(Value::Closure(pointer));
+                    stack.push(Value::closure(pointer));
                     self.pc_add();
                 }
                 Instr::Load(x) => {
                     let v = self.get_constant(x);
-                    let val = v.clone();
+                    let val = *v;
                     stack.push(val);
                     self.pc_add();
                 }
                 Instr::GetGlobal(x) => {
-                    let call_frame = call_frame;
-                    let idx = unsafe { (*(*call_frame.closure).chunk).constants[x].clone() };
-                    if let Value::String(idx) = idx {
-                        // dbg!(&idx);
-                        let val = self.global.last().unwrap()[&idx].clone();
-                        stack.push(val);
-                        self.pc_add();
-                    } else {
-                        unreachable!();
-                    }
+                    let chunk = unsafe { &*(*call_frame.closure).chunk };
+                    let (slot, main_slot) = chunk.global_slots[x];
+                    // not defined by the module, the main module may have it
+                    let val = match self.global_slots[slot].or(self.global_slots[main_slot]) {
+                        Some(val) => val,
+                        None => {
+                            let name = match chunk.constants[x].unbox() {
+                                Unboxed::String(name) => name,
+                                _ => unreachable!(),
+                            };
+                            return Err(EvalError::VariableNotFound(format!(
+                                "global variable `{name}` not found"
+                            )));
+                        }
+                    };
+                    stack.push(val);
+                    self.pc_add();
                 }
                 Instr::SetGlobal(x) => {
-                    let call_frame = call_frame;
-                    let idx = unsafe { (*(*call_frame.closure).chunk).constants[x].clone() };
+                    let chunk = unsafe { &*(*call_frame.closure).chunk };
                     let v = stack.pop().unwrap();
-                    if let Value::String(idx) = idx {
-                        self.global.last_mut().unwrap().insert(idx, v);
-                        self.pc_add();
-                    } else {
-                        unreachable!();
-                    }
+                    self.global_slots[chunk.global_slots[x].0] = Some(v);
+                    self.pc_add();
                 }
                 Instr::GetLocal(x) => {
                     let callframe = call_frame;
                     let bottom = callframe.bottom;
-                    let v = stack[x + bottom].clone();
+                    let v = stack[x + bottom];
                     stack.push(v);
                     self.pc_add();
                 }
@@ -783,188 +982,161 @@ impl Vm {
                     self.set_upvalue(x, opr);
                     self.pc_add();
                 }
+                Instr::CloseUpValues(x) => {
+                    self.close_upvalues(call_frame.bottom + x);
+                    self.pc_add();
+                }
                 Instr::InitArray(n) => {
-                    self.run_gc()?;
                     let p_array = self.new_array(n);
-                    stack.push(Value::Array(p_array));
+                    stack.push(Value::array(p_array));
                     self.pc_add();
                 }
                 Instr::InitDict(n) => {
-                    self.run_gc()?;
                     let p_dict = self.new_dict(n);
-                    stack.push(Value::Dictionary(p_dict));
+                    stack.push(Value::dictionary(p_dict));
+                    self.pc_add();
+                }
+                Instr::GetCollection(1) => {
+                    let name = stack.pop().unwrap();
+                    let target = stack.pop().unwrap();
+                    self.push_field(target, name)?;
                     self.pc_add();
                 }
-                Instr::GetCollection(va) => {
+                Instr::GetCollection(_) => {
                     let idx = stack.pop().unwrap();
                     let clct = stack.pop().unwrap();
-                    match clct {
-                        Value::Array(p_array) => {
-                            if va == 0 {
-                                if let Value::Number(i) = idx {
-                                    if i < 0. {
-                                        return Err(EvalError::IndexOutOfBound(self.eval_err_str(
-                                            "Array cannot be indexed by negative value",
-                                        )));
-                                    }
-                                    let i = i as usize;
-                                    let arr = unsafe { &mut *p_array };
-                                    if i >= arr.array.len() {
-                                        return Err(EvalError::IndexOutOfBound(
-                                            self.eval_err_str("index >= length of array"),
-                                        ));
-                                    } else {
-                                        let elem = arr.array.get(i).unwrap().clone();
-                                        stack.push(elem);
-                                        self.pc_add();
-                                    }
-                                } else {
-                                    return Err(EvalError::TypeError(
-                                        self.eval_err_str("Array can only be indexed by Number"),
+                    match clct.unbox() {
+                        Unboxed::Array(p_array) => {
+                            if let Unboxed::Number(i) = idx.unbox() {
+                                if i < 0. {
+                                    return Err(EvalError::IndexOutOfBound(
+                                        "Array cannot be indexed by negative value".to_owned(),
                                     ));
                                 }
-                            } else if va == 1 {
-                                // `methods` on Array
-                                stack.push(clct);
-                                if let Value::String(s) = idx {
-                                    let ext_name = self
-                                        .get_builtin_type_extension_name("Array", s.get_inner());
-                                    let ext_method = self.global.last().unwrap()[&ext_name].clone();
-                                    stack.push(ext_method);
-                                    self.pc_add();
+                                let i = i as usize;
+                                let arr = unsafe { &mut *p_array };
+                                if i >= arr.array.len() {
+                                    return Err(EvalError::IndexOutOfBound(
+                                        "index >= length of array".to_owned(),
+                                    ));
                                 } else {
-                                    return Err(EvalError::TypeError(self.eval_err_str(
-                                        "Array. syntax is used to call extension methods on it.",
-                                    )));
+                                    let elem = *arr.array.get(i).unwrap();
+                                    stack.push(elem);
+                                    self.pc_add();
                                 }
+                            } else {
+                                return Err(EvalError::TypeError(
+                                    "Array can only be indexed by Number".to_owned(),
+                                ));
                             }
                         }
-                        Value::Dictionary(p_dict) => {
-                            if let Value::String(s) = idx {
+                        Unboxed::Dictionary(p_dict) => {
+                            if let Unboxed::String(s) = idx.unbox() {
                                 let m = unsafe { &mut *p_dict };
                                 if let Some(v) = m.dict.get(&s) {
-                                    stack.push(v.clone());
+                                    stack.push(*v);
                                     self.pc_add();
                                 } else {
                                     return Err(EvalError::KeyError(
-                                        self.eval_err_str("unknown key to module"),
+                                        "unknown key to module".to_owned(),
                                     ));
                                 }
                             } else {
                                 return Err(EvalError::TypeError(
-                                    self.eval_err_str("Dict can only be indexed by String"),
+                                    "Dict can only be indexed by String".to_owned(),
                                 ));
                             }
                         }
-                        Value::Error(p_dict) => {
-                            if let Value::String(i) = idx {
+                        Unboxed::Error(p_dict) => {
+                            if let Unboxed::String(i) = idx.unbox() {
                                 let dict = unsafe { &mut *p_dict };
-                                let elem = dict.dict.get(&i).unwrap_or(&Value::Nil).clone();
+                                let elem = *dict.dict.get(&i).unwrap_or(&Value::NIL);
                                 stack.push(elem);
                                 self.pc_add();
                             } else {
                                 return Err(EvalError::TypeError(
-                                    self.eval_err_str("Error can only be indexed by String"),
+                                    "Error can only be indexed by String".to_owned(),
                                 ));
                             }
                         }
-                        Value::Instance(p_instance) => {
-                            if va == 0 {
-                                let protocol_name = self.string_pool.creat_istring("__index__");
-                                if let Some(method) =
-                                    unsafe { (*(*p_instance).klass).methods.get(&protocol_name) }
-                                {
-                                    if let Value::Closure(method) = method {
-                                        let mut binded_closure = unsafe { (**method).clone() };
-                                        binded_closure.this_ref = Some(p_instance);
-                                        let mut b_binded_closure = Box::new(binded_closure);
-                                        let p_binded_closure =
-                                            b_binded_closure.as_mut() as *mut Closure;
-                                        self.objects.push(b_binded_closure);
-                                        let f = Value::Closure(p_binded_closure);
-                                        stack.push(f);
-                                        stack.push(idx);
-                                        self.call_routine(1)?;
-                                    } else {
-                                        unreachable!()
-                                    }
+                        Unboxed::Instance(p_instance) => {
+                            let protocol_name = self.string_pool.creat_istring("__index__");
+                            if let Some(method) =
+                                unsafe { (*(*p_instance).klass).methods.get(&protocol_name) }
+                            {
+                                if let Unboxed::Closure(method) = method.unbox() {
+                                    let mut binded_closure = unsafe { (*method).clone() };
+                                    binded_closure.this_ref = Some(p_instance);
+                                    let mut b_binded_closure = Box::new(binded_closure);
+                                    let p_binded_closure =
+                                        b_binded_closure.as_mut() as *mut Closure;
+                                    self.objects.push(b_binded_closure);
+                                    let f = Value::closure(p_binded_closure);
+                                    stack.push(f);
+                                    stack.push(idx);
+                                    self.call_routine(1)?;
                                 } else {
-                                    return Err(EvalError::VariableNotFound(
-                                        self.eval_err_str("`__index__` method not found"),
-                                    ));
+                                    unreachable!()
                                 }
                             } else {
-                                if let Value::String(i) = idx {
-                                    let instance = unsafe { &mut *p_instance };
-                                    if let Some(v) = instance.fields.get(&i) {
-                                        stack.push(v.clone());
-                                        self.pc_add();
-                                    } else {
-                                        if let Some(method) =
-                                            unsafe { (*instance.klass).methods.get(&i) }
-                                        {
-                                            if let Value::Closure(method) = method {
-                                                let mut binded_closure =
-                                                    unsafe { (**method).clone() };
-                                                binded_closure.this_ref = Some(p_instance);
-                                                let mut b_binded_closure = Box::new(binded_closure);
-                                                let p_binded_closure =
-                                                    b_binded_closure.as_mut() as *mut Closure;
-                                                self.objects.push(b_binded_closure);
-                                                let v = Value::Closure(p_binded_closure);
-                                                stack.push(v);
-                                                self.pc_add();
-                                            } else {
-                                                unreachable!()
-                                            }
-                                        } else {
-                                            return Err(EvalError::VariableNotFound(
-                                                self.eval_err_str("method not found"),
-                                            ));
-                                        }
-                                    }
-                                } else {
-                                    return Err(EvalError::TypeError(
-                                        self.eval_err_str("Instance can only be indexed by String"),
-                                    ));
-                                }
+                                return Err(EvalError::VariableNotFound(
+                                    "`__index__` method not found".to_owned(),
+                                ));
                             }
                         }
-                        Value::Module(p_module) => {
-                            if let Value::String(s) = idx {
+                        Unboxed::Module(p_module) => {
+                            if let Unboxed::String(s) = idx.unbox() {
                                 let m = unsafe { &mut *p_module };
                                 if let Some(v) = m.dict.get(&s) {
-                                    stack.push(v.clone());
+                                    stack.push(*v);
                                     self.pc_add();
                                 } else {
                                     return Err(EvalError::KeyError(
-                                        self.eval_err_str("unknown key to module"),
+                                        "unknown key to module".to_owned(),
                                     ));
                                 }
                             } else {
                                 return Err(EvalError::TypeError(
-                                    self.eval_err_str("Module can only be indexed by String"),
+                                    "Module can only be indexed by String".to_owned(),
                                 ));
                             }
                         }
                         v => {
-                            return Err(EvalError::TypeError(
-                                self.eval_err_str(format!("{:?} can not be indexed", v).as_ref()),
-                            ));
+                            return Err(EvalError::TypeError(format!(
+                                "{:?} can not be indexed",
+                                v
+                            )));
                         }
                     };
                 }
+                Instr::GetField(x) => {
+                    let target = stack.pop().unwrap();
+                    let name = *self.get_constant(x);
+                    self.push_field(target, name)?;
+                    self.pc_add();
+                }
+                Instr::SetField(x) => {
+                    let val = stack.pop().unwrap();
+                    let target = stack.pop().unwrap();
+                    let name = *self.get_constant(x);
+                    self.set_field(target, name, val)?;
+                    self.pc_add();
+                }
+                Instr::Invoke(x, n) => {
+                    let name = *self.get_constant(x as usize);
+                    self.invoke(name, n as usize)?;
+                }
                 Instr::SetCollection(va) => {
                     let val = stack.pop().unwrap();
                     let idx = stack.pop().unwrap();
                     let clct = stack.pop().unwrap();
-                    match clct {
-                        Value::Array(p_array) => {
-                            if let Value::Number(i) = idx {
+                    match clct.unbox() {
+                        Unboxed::Array(p_array) => {
+                            if let Unboxed::Number(i) = idx.unbox() {
                                 if i < 0. {
-                                    return Err(EvalError::IndexOutOfBound(self.eval_err_str(
-                                        "Array cannot be indexed by negative value",
-                                    )));
+                                    return Err(EvalError::IndexOutOfBound(
+                                        "Array cannot be indexed by negative value".to_owned(),
+                                    ));
                                 }
                                 let i = i as usize;
                                 let arr = unsafe { &mut *p_array };
@@ -972,18 +1144,18 @@ impl Vm {
                                     *elem = val;
                                 } else {
                                     return Err(EvalError::IndexOutOfBound(
-                                        self.eval_err_str("Array index out of bound"),
+                                        "Array index out of bound".to_owned(),
                                     ));
                                 }
                             } else {
                                 return Err(EvalError::TypeError(
-                                    self.eval_err_str("Array can only be indexed by Number"),
+                                    "Array can only be indexed by Number".to_owned(),
                                 ));
                             }
                             self.pc_add();
                         }
-                        Value::Dictionary(p_dict) => {
-                            if let Value::String(i) = idx {
+                        Unboxed::Dictionary(p_dict) => {
+                            if let Unboxed::String(i) = idx.unbox() {
                                 let dict = unsafe { &mut *p_dict };
                                 if let Some(elem) = dict.dict.get_mut(&i) {
                                     *elem = val;
@@ -992,13 +1164,13 @@ impl Vm {
                                 }
                             } else {
                                 return Err(EvalError::TypeError(
-                                    self.eval_err_str("Dict can only be indexed by String"),
+                                    "Dict can only be indexed by String".to_owned(),
                                 ));
                             }
                             self.pc_add();
                         }
-                        Value::Error(p_dict) => {
-                            if let Value::String(i) = idx {
+                        Unboxed::Error(p_dict) => {
+                            if let Unboxed::String(i) = idx.unbox() {
                                 let dict = unsafe { &mut *p_dict };
                                 if let Some(elem) = dict.dict.get_mut(&i) {
                                     *elem = val;
@@ -1007,26 +1179,26 @@ impl Vm {
                                 }
                             } else {
                                 return Err(EvalError::TypeError(
-                                    self.eval_err_str("Error can only be indexed by String"),
+                                    "Error can only be indexed by String".to_owned(),
                                 ));
                             }
                             self.pc_add();
                         }
-                        Value::Instance(p_instance) => {
+                        Unboxed::Instance(p_instance) => {
                             if va == 0 {
                                 // this.__assign__(idx, val)
                                 let protocol_name = self.string_pool.creat_istring("__assign__");
                                 if let Some(method) =
                                     unsafe { (*(*p_instance).klass).methods.get(&protocol_name) }
                                 {
-                                    if let Value::Closure(method) = method {
-                                        let mut binded_closure = unsafe { (**method).clone() };
+                                    if let Unboxed::Closure(method) = method.unbox() {
+                                        let mut binded_closure = unsafe { (*method).clone() };
                                         binded_closure.this_ref = Some(p_instance);
                                         let mut b_binded_closure = Box::new(binded_closure);
                                         let p_binded_closure =
                                             b_binded_closure.as_mut() as *mut Closure;
                                         self.objects.push(b_binded_closure);
-                                        let f = Value::Closure(p_binded_closure);
+                                        let f = Value::closure(p_binded_closure);
                                         stack.push(f);
                                         stack.push(idx);
                                         stack.push(val);
@@ -1036,24 +1208,18 @@ impl Vm {
                                     }
                                 } else {
                                     return Err(EvalError::VariableNotFound(
-                                        self.eval_err_str("`__assign__` method not found"),
+                                        "`__assign__` method not found".to_owned(),
                                     ));
                                 }
                             } else {
-                                if let Value::String(i) = idx {
-                                    let instance = unsafe { &mut *p_instance };
-                                    instance.fields.insert(i.clone(), val);
-                                } else {
-                                    return Err(EvalError::TypeError(
-                                        self.eval_err_str("Instance can only be indexed by String"),
-                                    ));
-                                }
+                                self.set_field(clct, idx, val)?;
                                 self.pc_add();
                             }
                         }
                         v => {
-                            return Err(EvalError::TypeError(self.eval_err_str(
-                                format!("{:?} cannot be indexed and assigned to", v).as_ref(),
+                            return Err(EvalError::TypeError(format!(
+                                "{:?} cannot be indexed and assigned to",
+                                v
                             )));
                         }
                     }
@@ -1083,38 +1249,47 @@ impl Vm {
                         self.pc_add();
                     }
                 }
+                Instr::JumpIfNil(x) | Instr::JumpIfNotNil(x) => {
+                    let nil = stack.last().unwrap().is_nil();
+                    if nil == matches!(instr, Instr::JumpIfNil(_)) {
+                        call_frame.pc = (call_frame.pc as i32 + x) as usize;
+                    } else {
+                        self.pc_add();
+                    }
+                }
 
                 Instr::Iterator => {
-                    match stack.pop().unwrap() {
-                        Value::Range(l, r) => {
-                            let v = Value::Range(l, r);
+                    match stack.pop().unwrap().unbox() {
+                        Unboxed::Range(range) => {
+                            let (l, r) = unsafe { ((*range).start, (*range).end) };
+                            let v = self.new_iter(IterState::Range(l, r));
                             stack.push(v);
                             self.pc_add();
                         }
-                        Value::String(istring) => {
-                            let v = Value::StringIter(istring.clone(), 0);
+                        Unboxed::String(istring) => {
+                            let v = self.new_iter(IterState::String(istring, 0));
                             stack.push(v);
                             self.pc_add();
                         }
-                        Value::Array(array) => {
-                            let v = Value::ArrayIter(array, 0);
+                        Unboxed::Array(array) => {
+                            let v = self.new_iter(IterState::Array(array, 0));
                             stack.push(v);
                             self.pc_add();
                         }
-                        Value::Dictionary(dict) => unsafe {
+                        Unboxed::Dictionary(dict) => unsafe {
                             // [[k,v], [k,v], [k,v],...]
                             let kv_arr = (*dict)
                                 .dict
                                 .iter()
                                 .map(|(k, v)| {
-                                    let entry = vec![Value::String(k.clone()), v.clone()];
+                                    let entry = vec![Value::string(k.clone()), *v];
                                     let mut b_entry = Box::new(Array {
                                         marked: false,
                                         array: entry,
                                     });
                                     let p_entry = b_entry.as_mut() as *mut Array;
                                     self.objects.push(b_entry);
-                                    Value::Array(p_entry)
+                                    Value::array(p_entry)
                                 })
                                 .collect();
                             let mut b_array = Box::new(Array {
@@ -1123,29 +1298,28 @@ impl Vm {
                             });
                             let p_array = b_array.as_mut() as *mut Array;
                             self.objects.push(b_array);
-                            let v = Value::ArrayIter(p_array, 0);
+                            let v = self.new_iter(IterState::Array(p_array, 0));
                             stack.push(v);
                             self.pc_add();
                         },
-                        Value::Instance(p_instance) => {
+                        Unboxed::Instance(p_instance) => {
                             let instance = unsafe { &mut *p_instance };
                             let protocol_func_name = self.string_pool.creat_istring("__iter__");
-                            if let Some(v) = instance.fields.get(&protocol_func_name) {
-                                let v = v.clone();
+                            if let Some(v) = instance.field(&protocol_func_name) {
                                 stack.push(v);
                                 self.pc_add();
                             } else {
                                 if let Some(method) =
                                     unsafe { (*instance.klass).methods.get(&protocol_func_name) }
                                 {
-                                    if let Value::Closure(method) = method {
-                                        let mut binded_closure = unsafe { (**method).clone() };
+                                    if let Unboxed::Closure(method) = method.unbox() {
+                                        let mut binded_closure = unsafe { (*method).clone() };
                                         binded_closure.this_ref = Some(p_instance);
                                         let mut b_binded_closure = Box::new(binded_closure);
                                         let p_binded_closure =
                                             b_binded_closure.as_mut() as *mut Closure;
                                         self.objects.push(b_binded_closure);
-                                        let f = Value::Closure(p_binded_closure);
+                                        let f = Value::closure(p_binded_closure);
                                         stack.push(f);
                                         self.call_routine(0)?;
                                     } else {
@@ -1153,78 +1327,72 @@ impl Vm {
                                     }
                                 } else {
                                     return Err(EvalError::VariableNotFound(
-                                        self.eval_err_str("method not found"),
+                                        "method not found".to_owned(),
                                     ));
                                 }
                             };
                         }
                         _ => {
-                            return Err(EvalError::TypeError(
-                                self.eval_err_str("not subscriptable"),
-                            ));
+                            return Err(EvalError::TypeError("not subscriptable".to_owned()));
                         }
                     };
                 }
 
-                Instr::Next => match stack.last_mut().unwrap() {
-                    Value::Range(l, r) => {
-                        let v = if (*l - *r).abs() < f64::EPSILON {
-                            Value::Nil
-                        } else if l < r {
-                            *l += 1.;
-                            Value::Number(*l - 1.)
-                        } else if l > r {
-                            *l -= 1.;
-                            Value::Number(*l + 1.)
-                        } else {
-                            unreachable!()
+                Instr::Next => match stack.last().unwrap().unbox() {
+                    Unboxed::Iter(iter) => {
+                        let v = match unsafe { &mut (*iter).state } {
+                            IterState::Range(l, r) => {
+                                if (*l - *r).abs() < f64::EPSILON {
+                                    Value::NIL
+                                } else if l < r {
+                                    *l += 1.;
+                                    Value::number(*l - 1.)
+                                } else if l > r {
+                                    *l -= 1.;
+                                    Value::number(*l + 1.)
+                                } else {
+                                    unreachable!()
+                                }
+                            }
+                            IterState::Array(arr, i) => unsafe {
+                                if *i >= (**arr).array.len() {
+                                    Value::NIL
+                                } else {
+                                    *i += 1;
+                                    (**arr).array[*i - 1]
+                                }
+                            },
+                            IterState::String(s, i) => match s.get_inner()[*i..].chars().next() {
+                                Some(c) => {
+                                    *i += c.len_utf8();
+                                    let c =
+                                        self.string_pool.creat_istring(c.encode_utf8(&mut [0; 4]));
+                                    Value::string(c)
+                                }
+                                None => Value::NIL,
+                            },
                         };
                         stack.push(v);
                         self.pc_add();
                     }
-                    Value::ArrayIter(arr, i) => unsafe {
-                        if *i >= (**arr).array.len() {
-                            stack.push(Value::Nil);
-                            self.pc_add();
-                        } else {
-                            let v = (**arr).array[*i].clone();
-                            *i += 1;
-                            stack.push(v);
-                            self.pc_add();
-                        }
-                    },
-                    Value::StringIter(s, i) => {
-                        // performance?
-                        let c = s.get_inner().chars().skip(*i).next();
-                        if let Some(c) = c {
-                            *i += 1;
-                            let s = format!("{c}");
-                            stack.push(Value::String(self.string_pool.creat_istring(&s)));
-                            self.pc_add();
-                        } else {
-                            stack.push(Value::Nil);
-                            self.pc_add();
-                        }
-                    }
-                    Value::Instance(p_instance) => {
-                        let instance = unsafe { &mut **p_instance };
+                    Unboxed::Instance(p_instance) => {
+                        let instance = unsafe { &mut *p_instance };
                         let protocol_func_name = self.string_pool.creat_istring("__next__");
-                        if let Some(v) = instance.fields.get(&protocol_func_name) {
-                            let v = v.clone();
+                        if let Some(v) = instance.field(&protocol_func_name) {
                             stack.push(v);
                             self.pc_add();
                         } else {
                             if let Some(method) =
                                 unsafe { (*instance.klass).methods.get(&protocol_func_name) }
                             {
-                                if let Value::Closure(method) = method {
-                                    let mut binded_closure = unsafe { (**method).clone() };
-                                    binded_closure.this_ref = Some(*p_instance);
+                                if let Unboxed::Closure(method) = method.unbox() {
+                                    let mut binded_closure = unsafe { (*method).clone() };
+                                    binded_closure.this_ref = Some(p_instance);
                                     let mut b_binded_closure = Box::new(binded_closure);
                                     let p_binded_closure =
                                         b_binded_closure.as_mut() as *mut Closure;
                                     self.objects.push(b_binded_closure);
-                                    let f = Value::Closure(p_binded_closure);
+                                    let f = Value::closure(p_binded_closure);
                                     stack.push(f);
                                     self.call_routine(0)?;
                                 } else {
@@ -1232,178 +1400,114 @@ impl Vm {
                                 }
                             } else {
                                 return Err(EvalError::VariableNotFound(
-                                    self.eval_err_str("method not found"),
+                                    "method not found".to_owned(),
                                 ));
                             }
                         };
                     }
                     _ => {
                         return Err(EvalError::VariableNotFound(
-                            self.eval_err_str("not a Iterator Object"),
+                            "not a Iterator Object".to_owned(),
                         ));
                     }
                 },
-                Instr::Call(x) => {
-                    let val = &stack[stack.len() - x - 1];
-                    if let Value::Closure(p_closure) = val {
-                        let mut packed_va_list = Vec::new();
-                        let chunk = unsafe { &*((**p_closure).chunk) };
-                        if chunk.parameter_num != x {
-                            if chunk.parameter_num < x && chunk.is_va {
-                                for idx in (stack.len() - x + chunk.parameter_num)..stack.len() {
-                                    packed_va_list.push(stack[idx].clone());
-                                }
-                            } else {
-                                return Err(EvalError::CallError(
-                                    self.eval_err_str(
-                                        format!(
-                                            "wrong number of argument {x}/{}",
-                                            chunk.parameter_num
-                                        )
-                                        .as_ref(),
-                                    ),
-                                ));
-                            }
-                        }
-                        let call_frame =
-                            CallFrame::new(stack.len() - x, *p_closure, packed_va_list);
-                        // va_list arg should be pop
-                        for _ in 0..x - chunk.parameter_num {
-                            stack.pop();
-                        }
-                        self.pc_add();
-                        self.protected = false;
-                        self.reserve_local(chunk.num_locals - chunk.parameter_num);
-                        unsafe {
-                            (*self.executing_fiber).call_frames.push(call_frame);
-                        }
-                    } else if let Value::Klass(klass) = val {
-                        let class_idx = stack.len() - x - 1;
-
-                        let mut b_instace = Box::new(Instance {
-                            marked: false,
-                            klass: *klass,
-                            fields: HashMap::new(),
-                        });
-                        let p_instance = b_instace.as_mut() as *mut Instance;
-                        self.objects.push(b_instace);
-                        let idx = self.string_pool.creat_istring("__init__");
-                        if let Some(method) = unsafe { (**klass).methods.get(&idx) } {
-                            if let Value::Closure(method) = method {
-                                let mut binded_closure = unsafe { (**method).clone() };
-                                binded_closure.this_ref = Some(p_instance);
-                                let mut b_binded_closure = Box::new(binded_closure);
-                                let p_binded_closure = b_binded_closure.as_mut() as *mut Closure;
-                                self.objects.push(b_binded_closure);
-                                let init_method = Value::Closure(p_binded_closure);
-                                stack[class_idx] = init_method;
-                                self.call_routine(x)?;
-                                // constructor evaluate to Nil
-                            } else {
-                                unreachable!()
-                            }
-                        } else {
-                            // no __init__() definded
-                            for _ in 0..x {
-                                stack.pop();
-                            }
-                            stack.pop();
-                            stack.push(Value::Instance(p_instance));
-                            self.pc_add();
-                        }
-                    } else if let Value::NativeFunction(f) = val {
-                        let f = unsafe { std::mem::transmute::<*mut u8, NativeFunction>(*f) };
-                        //println!("{:?}", native::sloth_print as *mut u8);
-
-                        f(self, x, false);
-                        if self.fiber_changed {
-                            self.fiber_changed = false;
-                        } else {
-                            self.pc_add();
+                Instr::Call(x) | Instr::TryCall(x) => {
+                    self.call_value(x, matches!(instr, Instr::TryCall(_)))?;
+                }
+                Instr::PushHandler(x) => {
+                    let catch = (pc as i32 + x) as usize;
+                    call_frame.handlers.push((stack.len(), catch));
+                    self.pc_add();
+                }
+                Instr::PopHandler => {
+                    call_frame.handlers.pop();
+                    self.pc_add();
+                }
+                Instr::IsError => {
+                    let is_error =
+                        matches!(stack.last().map(|v| v.unbox()), Some(Unboxed::Error(_)));
+                    stack.push(Value::bool(is_error));
+                    self.pc_add();
+                }
+                Instr::MatchEq => {
+                    let pattern = stack.pop().unwrap();
+                    let matched = pattern_eq(*stack.last().unwrap(), pattern);
+                    stack.push(Value::bool(matched));
+                    self.pc_add();
+                }
+                Instr::MatchRange | Instr::MatchRangeClosed => {
+                    let end = stack.pop().unwrap().as_number();
+                    let start = stack.pop().unwrap().as_number();
+                    let matched = match (stack.last().unwrap().as_number(), start, end) {
+                        (Some(x), Some(start), Some(end)) if matches!(instr, Instr::MatchRange) => {
+                            start <= x && x < end
+                        }
+                        (Some(x), Some(start), Some(end)) => start <= x && x <= end,
+                        _ => false,
+                    };
+                    stack.push(Value::bool(matched));
+                    self.pc_add();
+                }
+                Instr::MatchArray(len, rest) => {
+                    let matched = match stack.last().unwrap().unbox() {
+                        Unboxed::Array(p_array) => {
+                            let n = unsafe { (*p_array).array.len() };
+                            n == len as usize || (rest == 1 && n > len as usize)
                         }
-                    } else {
-                        return Err(EvalError::CallError(
-                            self.eval_err_str("calling object which is not Callable"),
-                        ));
-                    }
+                        _ => false,
+                    };
+                    stack.push(Value::bool(matched));
+                    self.pc_add();
+                }
+                Instr::HasField(x) => {
+                    let Unboxed::String(name) = self.get_constant(x).unbox() else {
+                        unreachable!("field names are string constants")
+                    };
+                    let found = match stack.last().unwrap().unbox() {
+                        Unboxed::Dictionary(p_dict) | Unboxed::Error(p_dict) => unsafe {
+                            (*p_dict).dict.contains_key(&name)
+                        },
+                        Unboxed::Instance(p_instance) => unsafe {
+                            (*p_instance).field(&name).is_some()
+                        },
+                        _ => false,
+                    };
+                    stack.push(Value::bool(found));
+                    self.pc_add();
+                }
+                Instr::ArraySlice(x) => {
+                    let Unboxed::Array(p_array) = stack.pop().unwrap().unbox() else {
+                        unreachable!("`ArraySlice` is only emitted after `MatchArray`")
+                    };
+                    let Some(rest) = (unsafe { (*p_array).array.get(x..) }) else {
+                        return Err(EvalError::Error(format!(
+                            "corrupt bytecode: {instr:?} past the end of the array"
+                        )));
+                    };
+                    let n = rest.len();
+                    stack.extend_from_slice(rest);
+                    let p_rest = self.new_array(n);
+                    stack.push(Value::array(p_rest));
+                    self.pc_add();
                 }
 
                 Instr::Except => {
-                    let callframe = unsafe { (*self.executing_fiber).call_frames.pop().unwrap() };
-                    let chunk = unsafe { &*(*callframe.closure).chunk };
-                    let mut new_upvalues = Vec::new();
-                    for upv in self.upvalues.iter_mut() {
-                        let mut escape = false;
-                        let mut idx = 0;
-                        if let UpValue::Ref(x) = unsafe { &(**upv).value } {
-                            if *x >= callframe.bottom {
-                                escape = true;
-                                idx = *x;
-                            }
-                        }
-                        if escape {
-                            unsafe {
-                                (**upv).value = UpValue::Closed(stack[idx].clone());
-                            }
-                        } else {
-                            new_upvalues.push(*upv);
-                        }
-                    }
-                    self.upvalues = new_upvalues;
-
-                    if callframe.bottom + chunk.num_locals == stack.len() {
-                        for _ in callframe.bottom..stack.len() {
-                            stack.pop();
-                        }
-                        stack.pop(); //pop closure
-                        let err = Value::Error(self.new_dict(0));
-                        stack.push(err);
-                    } else {
-                        let val = stack.pop().unwrap(); // closure ret_vall <- get it
-                        for _ in callframe.bottom..stack.len() {
-                            stack.pop();
-                        }
-                        stack.pop(); // pop closure
-                        stack.push(Value::String(self.string_pool.creat_istring("info")));
-                        stack.push(val); // push return value
-                        let err = Value::Error(self.new_dict(1));
-                        stack.push(err);
-                    }
-
-                    if !self.protected || unsafe { (*self.executing_fiber).call_frames.is_empty() }
-                    {
-                        if let Value::Error(p_dict) = stack.pop().unwrap() {
-                            let mut hash_map = unsafe { (*p_dict).dict.clone() };
-                            return Err(EvalError::Exception(HashMap::from_iter(
-                                hash_map.iter().map(|(k, v)| (k.to_string(), v.clone())),
-                            )));
-                        } else {
-                            return Err(EvalError::Exception(HashMap::new()));
-                        }
-                    }
+                    // `except val;` always leaves exactly one value on the stack
+                    let info = stack.pop().unwrap();
+                    let line = self.current_cood().0;
+                    return Err(EvalError::Exception(HashMap::from([
+                        (
+                            "kind".to_owned(),
+                            Value::string(self.string_pool.creat_istring("Exception")),
+                        ),
+                        ("info".to_owned(), info),
+                        ("line".to_owned(), Value::number(line as f64)),
+                    ])));
                 }
                 Instr::Return => {
                     let callframe = unsafe { (*self.executing_fiber).call_frames.pop().unwrap() };
                     let chunk = unsafe { &*(*callframe.closure).chunk };
-                    let mut new_upvalues = Vec::new();
-                    for upv in self.upvalues.iter_mut() {
-                        let mut escape = false;
-                        let mut idx = 0;
-                        if let UpValue::Ref(x) = unsafe { &(**upv).value } {
-                            if *x >= callframe.bottom {
-                                escape = true;
-                                idx = *x;
-                            }
-                        }
-                        if escape {
-                            unsafe {
-                                (**upv).value = UpValue::Closed(stack[idx].clone());
-                            }
-                        } else {
-                            new_upvalues.push(*upv);
-                        }
-                    }
-                    self.upvalues = new_upvalues;
+                    self.close_upvalues(callframe.bottom);
 
                     if callframe.bottom + chunk.num_locals == stack.len() {
                         for _ in callframe.bottom..stack.len() {
@@ -1411,7 +1515,7 @@ impl Vm {
                         }
                         stack.pop(); //pop closure
                         if !call_frame.discard_return_value {
-                            stack.push(Value::Nil); // functions always return exactly ONE value, unless SetCollection
+                            stack.push(Value::NIL); // functions always return exactly ONE value, unless SetCollection
                         }
                     } else {
                         let val = stack.pop().unwrap(); // closure ret_vall <- get it
@@ -1434,19 +1538,25 @@ impl Vm {
                                     // the instruction lead to fiber transfering should be skipped
                                     self.pc_add();
                                     let module_namespace = self.global.pop().unwrap();
+                                    let dict = module_namespace
+                                        .into_iter()
+                                        .filter_map(|(name, slot)| {
+                                            self.global_slots[slot].map(|val| (name, val))
+                                        })
+                                        .collect();
                                     let mut b_module_namespace_dict = Box::new(Dict {
                                         marked: false,
-                                        dict: module_namespace,
+                                        dict,
                                     });
                                     let p_module_namespace_dict =
                                         b_module_namespace_dict.as_mut() as *mut Dict;
                                     self.objects.push(b_module_namespace_dict);
                                     self.get_stack()
-                                        .push(Value::Dictionary(p_module_namespace_dict));
+                                        .push(Value::dictionary(p_module_namespace_dict));
                                 } else {
                                     (*ret_from).state = FiberState::Finished;
                                     (*prev).state = FiberState::Running;
-                                    self.get_stack().push(Value::Nil);
+                                    self.get_stack().push(Value::NIL);
                                 }
                             } else {
                                 return Ok(());
@@ -1457,21 +1567,21 @@ impl Vm {
                 Instr::GetSuperMethod => {
                     let idx = stack.pop().unwrap();
                     let clct = stack.pop().unwrap();
-                    if let Value::Instance(p_instance) = clct {
-                        if let Value::String(i) = idx {
+                    if let Unboxed::Instance(p_instance) = clct.unbox() {
+                        if let Unboxed::String(i) = idx.unbox() {
                             let instance = unsafe { &mut *p_instance };
                             let mut super_class = unsafe { (*instance.klass).super_klass };
                             let mut ok = false;
                             while super_class != ptr::null_mut() {
                                 if let Some(method) = unsafe { (*super_class).methods.get(&i) } {
-                                    if let Value::Closure(method) = method {
-                                        let mut binded_closure = unsafe { (**method).clone() };
+                                    if let Unboxed::Closure(method) = method.unbox() {
+                                        let mut binded_closure = unsafe { (*method).clone() };
                                         binded_closure.this_ref = Some(p_instance);
                                         let mut b_binded_closure = Box::new(binded_closure);
                                         let p_binded_closure =
                                             b_binded_closure.as_mut() as *mut Closure;
                                         self.objects.push(b_binded_closure);
-                                        stack.push(Value::Closure(p_binded_closure));
+                                        stack.push(Value::closure(p_binded_closure));
                                         self.pc_add();
                                         ok = true;
                                         break;
@@ -1482,12 +1592,12 @@ impl Vm {
                             }
                             if !ok {
                                 return Err(EvalError::VariableNotFound(
-                                    self.eval_err_str("method not found"),
+                                    "method not found".to_owned(),
                                 ));
                             }
                         } else {
                             return Err(EvalError::TypeError(
-                                self.eval_err_str("Instance can only be indexed by String"),
+                                "Instance can only be indexed by String".to_owned(),
                             ));
                         }
                     } else {
@@ -1495,33 +1605,37 @@ impl Vm {
                     }
                 }
                 Instr::InitClass => {
+                    let mut shape = Box::<Shape>::default();
+                    let p_shape = shape.as_mut() as *mut Shape;
+                    self.objects.push(shape);
                     let mut b_class = Box::new(Klass {
                         marked: false,
                         super_klass: null_mut(),
                         methods: HashMap::new(),
+                        shape: p_shape,
                     });
                     let p_class = b_class.as_mut() as *mut Klass;
                     self.objects.push(b_class);
-                    stack.push(Value::Klass(p_class));
+                    stack.push(Value::klass(p_class));
                     self.pc_add();
                 }
                 Instr::ClassExtend => {
                     let super_klass = stack.pop().unwrap();
                     let klass = stack.pop().unwrap();
-                    if let Value::Klass(klass) = klass {
-                        if let Value::Klass(super_klass) = super_klass {
+                    if let Unboxed::Klass(klass) = klass.unbox() {
+                        if let Unboxed::Klass(super_klass) = super_klass.unbox() {
                             unsafe {
                                 (*klass).super_klass = super_klass;
                                 // copy down methods
                                 for (name, closure) in &(*super_klass).methods {
-                                    (*klass).methods.insert(name.clone(), closure.clone());
+                                    (*klass).methods.insert(name.clone(), *closure);
                                 }
                             }
-                            stack.push(Value::Klass(klass));
+                            stack.push(Value::klass(klass));
                             self.pc_add();
                         } else {
                             return Err(EvalError::TypeError(
-                                self.eval_err_str("Class can only extend Class"),
+                                "Class can only extend Class".to_owned(),
                             ));
                         }
                     } else {
@@ -1532,16 +1646,16 @@ impl Vm {
                     let method = stack.pop().unwrap();
                     let name = stack.pop().unwrap();
                     let klass = stack.pop().unwrap();
-                    if let Value::Klass(klass) = klass {
-                        if let Value::String(name) = name {
-                            if let Value::Closure(method) = method {
+                    if let Unboxed::Klass(klass) = klass.unbox() {
+                        if let Unboxed::String(name) = name.unbox() {
+                            if let Unboxed::Closure(method) = method.unbox() {
                                 unsafe {
-                                    (*klass).methods.insert(name, Value::Closure(method));
+                                    (*klass).methods.insert(name, Value::closure(method));
                                 }
                             } else {
                                 unreachable!()
                             }
-                            stack.push(Value::Klass(klass));
+                            stack.push(Value::klass(klass));
                             self.pc_add();
                         } else {
                             unreachable!()
@@ -1551,8 +1665,8 @@ impl Vm {
                     }
                 }
                 Instr::GetThis => {
-                    let t = unsafe { (*closure).this_ref.unwrap() };
-                    stack.push(Value::Instance(t));
+                    let t = call_frame.this_ref.unwrap();
+                    stack.push(Value::instance(t));
                     self.pc_add();
                 }
                 Instr::Nop => {
@@ -1560,69 +1674,153 @@ impl Vm {
                 }
                 Instr::UnpackVA => {
                     for elem in call_frame.va_args.iter() {
-                        stack.push(elem.clone());
+                        stack.push(*elem);
                     }
                     self.pc_add();
                 }
-                i => {
-                    return Err(EvalError::Error(
-                        self.eval_err_str(format!("unknown instruction {i:?}").as_ref()),
-                    ))
-                }
             }
         }
     }
 
-    fn binary_predicate_impl(
-        &mut self,
-        op: fn(f64, f64) -> bool,
-        op_str: fn(&str, &str) -> bool,
-        op_name: &str,
-    ) -> Result<(), EvalError> {
-        let opr2 = self.get_stack().pop().unwrap();
-        let opr1 = self.get_stack().pop().unwrap();
-        match opr1 {
-            Value::Number(a) => {
-                if let Value::Number(b) = opr2 {
-                    let res = op(a, b);
-                    self.get_stack().push(Value::Bool(res));
+    /// write a specialized instruction over the generic `instr` at `pc` once
+    /// its operands are numbers, fusing it with its neighbours when they allow.
+    /// the caller dispatches again if it did
+    fn quicken(&self, pc: usize, instr: Instr) -> bool {
+        // the debugger steps through every instruction
+        if self.debugger.is_some() || number_pair(self.get_stack()).is_none() {
+            return false;
+        }
+        let chunk = unsafe { &*self.get_call_frame().closure }.chunk;
+        let code = unsafe { &(*chunk).bytecodes };
+        let next = code.get(pc + 1).copied();
+        let quick = match (instr, next) {
+            (Instr::Add, _) => {
+                self.fuse_add(pc);
+                Instr::AddNum
+            }
+            (Instr::Sub, _) => Instr::SubNum,
+            (Instr::Mul, _) => Instr::MulNum,
+            (Instr::Lt, Some(Instr::JumpIfNot(x))) => Instr::LtJumpIfNot(x),
+            (Instr::Gt, Some(Instr::JumpIfNot(x))) => Instr::GtJumpIfNot(x),
+            (Instr::Le, Some(Instr::JumpIfNot(x))) => Instr::LeJumpIfNot(x),
+            (Instr::Ge, Some(Instr::JumpIfNot(x))) => Instr::GeJumpIfNot(x),
+            (Instr::Lt, _) => Instr::LtNum,
+            (Instr::Gt, _) => Instr::GtNum,
+            (Instr::Le, _) => Instr::LeNum,
+            (Instr::Ge, _) => Instr::GeNum,
+            _ => return false,
+        };
+        self.rewrite_instr(pc, quick);
+        true
+    }
+    /// the `Add` at `pc` adds numbers, fuse the local read before it.
+    /// what runs after a jump landing between the fused instructions is
+    /// left as it is
+    fn fuse_add(&self, pc: usize) {
+        let chunk = unsafe { &*(*self.get_call_frame().closure).chunk };
+        let code = &chunk.bytecodes;
+        let before = |n: usize| pc.checked_sub(n).map(|i| code[i]);
+        match (before(2), before(1), code.get(pc + 1)) {
+            (Some(Instr::GetLocal(x)), Some(Instr::Load(c)), Some(&Instr::SetLocal(y)))
+                if x == y && chunk.constants[c].as_number().is_some() =>
+            {
+                self.rewrite_instr(pc - 2, Instr::IncLocal(x));
+            }
+            (_, Some(Instr::GetLocal(x)), _) => self.rewrite_instr(pc - 1, Instr::GetLocalAdd(x)),
+            _ => {}
+        }
+    }
+    /// write `instr` at `pc` of the running function
+    fn rewrite_instr(&self, pc: usize, instr: Instr) {
+        let closure = self.get_call_frame().closure;
+        // chunks are read only otherwise, and a quickened instruction does
+        // what the generic one does
+        unsafe { (*((*closure).chunk as *mut Chunk)).bytecodes[pc] = instr };
+    }
+    /// quickened arithmetic or comparison, back to the generic instruction
+    /// when the operands are not numbers
+    #[inline(always)]
+    fn number_op(&mut self, pc: usize, instr: Instr, op: impl Fn(f64, f64) -> Value) {
+        let stack = self.get_stack();
+        match number_pair(stack) {
+            Some((a, b)) => {
+                stack.pop();
+                *stack.last_mut().unwrap() = op(a, b);
+                self.pc_add();
+            }
+            None => self.rewrite_instr(pc, instr.generic()),
+        }
+    }
+    /// like `number_op` for a comparison fused with the `JumpIfNot(x)` after it,
+    /// the condition is left on the stack
+    #[inline(always)]
+    fn compare_jump(&mut self, pc: usize, instr: Instr, x: i32, op: impl Fn(f64, f64) -> bool) {
+        let stack = self.get_stack();
+        match number_pair(stack) {
+            Some((a, b)) => {
+                let res = op(a, b);
+                stack.pop();
+                *stack.last_mut().unwrap() = Value::bool(res);
+                let call_frame = self.get_call_frame();
+                call_frame.pc = if res {
+                    pc + 2
+                } else {
+                    (pc as i32 + 1 + x) as usize
+                };
+            }
+            None => self.rewrite_instr(pc, instr.generic()),
+        }
+    }
+    fn binary_predicate_impl(
+        &mut self,
+        op: fn(f64, f64) -> bool,
+        op_str: fn(&str, &str) -> bool,
+        op_name: &str,
+    ) -> Result<(), EvalError> {
+        let opr2 = self.get_stack().pop().unwrap();
+        let opr1 = self.get_stack().pop().unwrap();
+        match opr1.unbox() {
+            Unboxed::Number(a) => {
+                if let Unboxed::Number(b) = opr2.unbox() {
+                    let res = op(a, b);
+                    self.get_stack().push(Value::bool(res));
                 } else {
                     return Err(EvalError::TypeError(
-                        self.eval_err_str("unsupported comparing operation"),
+                        "unsupported comparing operation".to_owned(),
                     ));
                 }
                 self.pc_add();
             }
-            Value::String(s1) => {
-                if let Value::String(s2) = opr2 {
+            Unboxed::String(s1) => {
+                if let Unboxed::String(s2) = opr2.unbox() {
                     let res = op_str(s1.get_inner(), s2.get_inner());
-                    self.get_stack().push(Value::Bool(res));
+                    self.get_stack().push(Value::bool(res));
                 } else {
                     return Err(EvalError::TypeError(
-                        self.eval_err_str("unsupported comparing operation"),
+                        "unsupported comparing operation".to_owned(),
                     ));
                 }
                 self.pc_add();
             }
-            Value::Instance(p_instance) => {
+            Unboxed::Instance(p_instance) => {
                 // a op b => a.__op__(b)
                 let instance = unsafe { &mut *p_instance };
                 let protocol_func_name = self.string_pool.creat_istring(op_name);
-                if let Some(v) = instance.fields.get(&protocol_func_name) {
+                if let Some(_v) = instance.field(&protocol_func_name) {
                     return Err(EvalError::CallError(
-                        self.eval_err_str("coparing operation defined as field of Instance"),
+                        "coparing operation defined as field of Instance".to_owned(),
                     ));
                 } else {
                     if let Some(method) =
                         unsafe { (*instance.klass).methods.get(&protocol_func_name) }
                     {
-                        if let Value::Closure(method) = method {
-                            let mut binded_closure = unsafe { (**method).clone() };
+                        if let Unboxed::Closure(method) = method.unbox() {
+                            let mut binded_closure = unsafe { (*method).clone() };
                             binded_closure.this_ref = Some(p_instance);
                             let mut b_binded_closure = Box::new(binded_closure);
                             let p_binded_closure = b_binded_closure.as_mut() as *mut Closure;
                             self.objects.push(b_binded_closure);
-                            let f = Value::Closure(p_binded_closure);
+                            let f = Value::closure(p_binded_closure);
                             self.get_stack().push(f);
                             self.get_stack().push(opr2);
                             self.call_routine(1)?;
@@ -1631,19 +1829,313 @@ impl Vm {
                         }
                     } else {
                         return Err(EvalError::VariableNotFound(
-                            self.eval_err_str("comparing method not found"),
+                            "comparing method not found".to_owned(),
                         ));
                     }
                 };
             }
             _ => {
                 return Err(EvalError::TypeError(
-                    self.eval_err_str("unsupported comparing operation"),
+                    "unsupported comparing operation".to_owned(),
                 ))
             }
         }
         Ok(())
     }
+    /// cache of the running instruction, if its chunk has them
+    fn inline_cache(&mut self) -> Option<&mut InlineCache> {
+        let frame = self.get_call_frame();
+        // written while the chunk runs, like quickened instructions
+        let chunk = unsafe { &mut *((*frame.closure).chunk as *mut Chunk) };
+        chunk.inline_caches.get_mut(frame.pc)
+    }
+    /// field or method `name` of the instance, the cache of the running
+    /// instruction answers if the instance has the shape it saw last time
+    fn instance_member(
+        &mut self,
+        p_instance: *mut Instance,
+        name: Value,
+    ) -> Result<Member, EvalError> {
+        let instance = unsafe { &*p_instance };
+        let shape = instance.shape as *const Shape;
+        match self.inline_cache().copied() {
+            Some(InlineCache::Field(s, slot)) if s == shape => {
+                return Ok(Member::Field(instance.fields[slot]));
+            }
+            Some(InlineCache::Method(s, method)) if s == shape => {
+                return Ok(Member::Method(method));
+            }
+            _ => {}
+        }
+        let Unboxed::String(name) = name.unbox() else {
+            return Err(EvalError::TypeError(
+                "Instance can only be indexed by String".to_owned(),
+            ));
+        };
+        let (member, cache) = if let Some(&slot) = unsafe { (*shape).slots.get(&name) } {
+            (
+                Member::Field(instance.fields[slot]),
+                InlineCache::Field(shape, slot),
+            )
+        } else if let Some(method) = unsafe { (*instance.klass).methods.get(&name) } {
+            let Unboxed::Closure(method) = method.unbox() else {
+                unreachable!()
+            };
+            (Member::Method(method), InlineCache::Method(shape, method))
+        } else {
+            return Err(EvalError::VariableNotFound("method not found".to_owned()));
+        };
+        if let Some(c) = self.inline_cache() {
+            *c = cache;
+        }
+        Ok(member)
+    }
+    /// `method` with `this` bound to the instance
+    fn bind_method(&mut self, method: *mut Closure, p_instance: *mut Instance) -> Value {
+        let mut binded_closure = unsafe { (*method).clone() };
+        binded_closure.this_ref = Some(p_instance);
+        let mut b_binded_closure = Box::new(binded_closure);
+        let p_binded_closure = b_binded_closure.as_mut() as *mut Closure;
+        self.objects.push(b_binded_closure);
+        Value::closure(p_binded_closure)
+    }
+    /// `target.name`, methods of instances are bound to them and
+    /// arrays have extension methods
+    fn field_value(&mut self, target: Value, name: Value) -> Result<Value, EvalError> {
+        if let Unboxed::Instance(p_instance) = target.unbox() {
+            return match self.instance_member(p_instance, name)? {
+                Member::Field(val) => Ok(val),
+                Member::Method(method) => Ok(self.bind_method(method, p_instance)),
+            };
+        }
+        let Unboxed::String(s) = name.unbox() else {
+            return Err(EvalError::TypeError(
+                "field name must be a String".to_owned(),
+            ));
+        };
+        match target.unbox() {
+            Unboxed::Array(_) => {
+                let ext_name = self.get_builtin_type_extension_name("Array", s.get_inner());
+                self.lookup_global(&ext_name).ok_or_else(|| {
+                    EvalError::VariableNotFound(format!("Array has no method `{s}`"))
+                })
+            }
+            Unboxed::Dictionary(p_dict) | Unboxed::Module(p_dict) => {
+                unsafe { (*p_dict).dict.get(&s).copied() }
+                    .ok_or_else(|| EvalError::KeyError("unknown key to module".to_owned()))
+            }
+            Unboxed::Error(p_dict) => {
+                Ok(unsafe { (*p_dict).dict.get(&s).copied() }.unwrap_or(Value::NIL))
+            }
+            v => Err(EvalError::TypeError(format!("{:?} can not be indexed", v))),
+        }
+    }
+    /// push `target.name`, an array stays below its extension method
+    fn push_field(&mut self, target: Value, name: Value) -> EvalResult {
+        let val = self.field_value(target, name)?;
+        let stack = self.get_stack();
+        if let Unboxed::Array(_) = target.unbox() {
+            stack.push(target);
+        }
+        stack.push(val);
+        Ok(())
+    }
+    /// `target.name = val`, an instance getting a new field moves to the
+    /// next shape
+    fn set_field(&mut self, target: Value, name: Value, val: Value) -> EvalResult {
+        match target.unbox() {
+            Unboxed::Instance(p_instance) => {
+                let instance = unsafe { &mut *p_instance };
+                let shape = instance.shape;
+                match self.inline_cache().copied() {
+                    Some(InlineCache::Field(s, slot)) if ptr::eq(s, shape) => {
+                        instance.fields[slot] = val;
+                        return Ok(());
+                    }
+                    Some(InlineCache::Transition(s, next)) if ptr::eq(s, shape) => {
+                        instance.shape = next;
+                        instance.fields.push(val);
+                        return Ok(());
+                    }
+                    _ => {}
+                }
+                let Unboxed::String(name) = name.unbox() else {
+                    return Err(EvalError::TypeError(
+                        "Instance can only be indexed by String".to_owned(),
+                    ));
+                };
+                let cache = match unsafe { (*shape).slots.get(&name) } {
+                    Some(&slot) => {
+                        instance.fields[slot] = val;
+                        InlineCache::Field(shape, slot)
+                    }
+                    None => {
+                        let next = self.shape_with(shape, name);
+                        instance.shape = next;
+                        instance.fields.push(val);
+                        InlineCache::Transition(shape, next)
+                    }
+                };
+                if let Some(c) = self.inline_cache() {
+                    *c = cache;
+                }
+            }
+            Unboxed::Dictionary(p_dict) | Unboxed::Error(p_dict) => {
+                let Unboxed::String(name) = name.unbox() else {
+                    return Err(EvalError::TypeError(
+                        "Dict can only be indexed by String".to_owned(),
+                    ));
+                };
+                unsafe { (*p_dict).dict.insert(name, val) };
+            }
+            v => {
+                return Err(EvalError::TypeError(format!(
+                    "{:?} cannot be indexed and assigned to",
+                    v
+                )));
+            }
+        }
+        Ok(())
+    }
+    /// shape of instances of `shape` with the field `name` added
+    fn shape_with(&mut self, shape: *mut Shape, name: IString) -> *mut Shape {
+        if let Some(next) = unsafe { (*shape).transitions.get(&name) } {
+            return *next;
+        }
+        let mut slots = unsafe { (*shape).slots.clone() };
+        slots.insert(name.clone(), slots.len());
+        let mut next = Box::new(Shape {
+            marked: false,
+            slots,
+            transitions: HashMap::new(),
+        });
+        let p_next = next.as_mut() as *mut Shape;
+        self.objects.push(next);
+        unsafe { (*shape).transitions.insert(name, p_next) };
+        p_next
+    }
+    /// `receiver.name(args)`, methods of instances get `this` from the
+    /// call frame instead of being bound
+    fn invoke(&mut self, name: Value, n: usize) -> EvalResult {
+        let recv_idx = self.get_stack().len() - n - 1;
+        let receiver = self.get_stack()[recv_idx];
+        match receiver.unbox() {
+            Unboxed::Instance(p_instance) => match self.instance_member(p_instance, name)? {
+                Member::Field(val) => {
+                    self.get_stack()[recv_idx] = val;
+                    self.call_value(n, false)
+                }
+                Member::Method(method) => {
+                    self.get_stack()[recv_idx] = Value::closure(method);
+                    self.call_value(n, false)?;
+                    self.get_call_frame().this_ref = Some(p_instance);
+                    Ok(())
+                }
+            },
+            Unboxed::Array(_) => {
+                let method = self.field_value(receiver, name)?;
+                self.get_stack().insert(recv_idx + 1, method);
+                self.call_value(n, false)
+            }
+            _ => {
+                let val = self.field_value(receiver, name)?;
+                self.get_stack()[recv_idx] = val;
+                self.call_value(n, false)
+            }
+        }
+    }
+    /// call the callee below the `x` arguments on the stack top
+    fn call_value(&mut self, x: usize, protected: bool) -> EvalResult {
+        let stack = unsafe { &mut (*self.executing_fiber).stack };
+        let val = stack[stack.len() - x - 1].unbox();
+        if let Unboxed::Closure(p_closure) = val {
+            let mut packed_va_list = Vec::new();
+            let chunk = unsafe { &*((*p_closure).chunk) };
+            if chunk.parameter_num != x {
+                if chunk.parameter_num < x && chunk.is_va {
+                    for idx in (stack.len() - x + chunk.parameter_num)..stack.len() {
+                        packed_va_list.push(stack[idx]);
+                    }
+                } else {
+                    return Err(EvalError::CallError(format!(
+                        "wrong number of argument {x}/{}",
+                        chunk.parameter_num
+                    )));
+                }
+            }
+            let mut call_frame = CallFrame::new(stack.len() - x, p_closure, packed_va_list);
+            call_frame.protected = protected;
+            // va_list arg should be pop
+            for _ in 0..x - chunk.parameter_num {
+                stack.pop();
+            }
+            self.pc_add();
+            self.reserve_local(chunk.num_locals - chunk.parameter_num);
+            unsafe {
+                (*self.executing_fiber).call_frames.push(call_frame);
+            }
+        } else if let Unboxed::Klass(klass) = val {
+            let class_idx = stack.len() - x - 1;
+
+            let mut b_instace = Box::new(Instance {
+                marked: false,
+                klass,
+                shape: unsafe { (*klass).shape },
+                fields: Vec::new(),
+            });
+            let p_instance = b_instace.as_mut() as *mut Instance;
+            self.objects.push(b_instace);
+            let idx = self.string_pool.creat_istring("__init__");
+            if let Some(method) = unsafe { (*klass).methods.get(&idx) } {
+                if let Unboxed::Closure(method) = method.unbox() {
+                    stack[class_idx] = Value::closure(method);
+                    self.call_routine(x)?;
+                    // frame of `__init__` is on the top now
+                    let frame = self.get_call_frame();
+                    frame.this_ref = Some(p_instance);
+                    frame.protected = protected;
+                    // constructor evaluate to Nil
+                } else {
+                    unreachable!()
+                }
+            } else {
+                // no __init__() definded
+                for _ in 0..x {
+                    stack.pop();
+                }
+                stack.pop();
+                stack.push(Value::instance(p_instance));
+                self.pc_add();
+            }
+        } else if let Unboxed::NativeFunction(f) = val {
+            let f = unsafe { std::mem::transmute::<*mut u8, NativeFunction>(f) };
+            //println!("{:?}", native::sloth_print as *mut u8);
+
+            let callee_idx = stack.len() - x - 1;
+            if let Err(err) = f(self, x, protected) {
+                if !protected {
+                    return Err(err);
+                }
+                // natives have no call frame, so `try` is handled right here
+                let trace = self.backtrace();
+                let err_val = self.new_error_value(&err, &trace);
+                let stack = self.get_stack();
+                stack.truncate(callee_idx);
+                stack.push(err_val);
+                self.fiber_changed = false;
+            }
+            if self.fiber_changed {
+                self.fiber_changed = false;
+            } else {
+                self.pc_add();
+            }
+        } else {
+            return Err(EvalError::CallError(
+                "calling object which is not Callable".to_owned(),
+            ));
+        }
+        Ok(())
+    }
     fn call_routine(&mut self, arg_cnt: usize) -> Result<(), EvalError> {
         self.call_routine2(arg_cnt, false)
     }
@@ -1654,38 +2146,38 @@ impl Vm {
     ) -> Result<(), EvalError> {
         let stack = self.get_stack();
         let x = arg_cnt;
-        let val = &stack[stack.len() - x - 1];
-        if let Value::Closure(p_closure) = val {
+        let val = stack[stack.len() - x - 1].unbox();
+        if let Unboxed::Closure(p_closure) = val {
             let mut packed_va_list = Vec::new();
-            let chunk = unsafe { &*((**p_closure).chunk) };
+            let chunk = unsafe { &*((*p_closure).chunk) };
             if chunk.parameter_num != x {
                 if chunk.parameter_num < x && chunk.is_va {
                     for idx in (stack.len() - x + chunk.parameter_num)..stack.len() {
-                        packed_va_list.push(stack[idx].clone());
+                        packed_va_list.push(stack[idx]);
                     }
                 } else {
-                    return Err(EvalError::CallError(self.eval_err_str(
-                        format!("wrong number of argument {x}/{}", chunk.parameter_num).as_ref(),
+                    return Err(EvalError::CallError(format!(
+                        "wrong number of argument {x}/{}",
+                        chunk.parameter_num
                     )));
                 }
             }
-            let mut call_frame = CallFrame::new(stack.len() - x, *p_closure, packed_va_list);
+            let mut call_frame = CallFrame::new(stack.len() - x, p_closure, packed_va_list);
             call_frame.discard_return_value = discard_return_value;
             // va_list arg should be pop
             for _ in 0..x - chunk.parameter_num {
                 stack.pop();
             }
             self.pc_add();
-            self.protected = false;
             self.reserve_local(chunk.num_locals - chunk.parameter_num);
             unsafe {
                 (*self.executing_fiber).call_frames.push(call_frame);
             }
             return Ok(());
-        } else if let Value::NativeFunction(f) = val {
-            let f = unsafe { std::mem::transmute::<*mut u8, NativeFunction>(*f) };
+        } else if let Unboxed::NativeFunction(f) = val {
+            let f = unsafe { std::mem::transmute::<*mut u8, NativeFunction>(f) };
             //println!("{:?}", native::sloth_print as *mut u8);
-            f(self, x, false);
+            f(self, x, false)?;
             if self.fiber_changed {
                 self.fiber_changed = false;
             } else {
@@ -1694,20 +2186,156 @@ impl Vm {
             return Ok(());
         } else {
             return Err(EvalError::CallError(
-                self.eval_err_str("calling object which is not Callable"),
+                "calling object which is not Callable".to_owned(),
             ));
         }
     }
-    fn new_upvalue_object(&mut self, idx: usize) -> *mut UpValueObject {
+    /// close every open upvalue refering to stack slot at or above `bottom`
+    fn close_upvalues(&mut self, bottom: usize) {
+        unsafe { close_fiber_upvalues(&mut *self.executing_fiber, bottom) };
+    }
+    /// Unwind to the innermost `try` block, or call frame entered by `TryCall`,
+    /// and push `err` where it is caught.
+    /// Fibers catching nothing are left in `Error` state and the search goes
+    /// on in the fiber which resumed (or imported) them, which catches it as
+    /// the result of that call if it was protected.
+    fn unwind_to_protected(&mut self, err: Value) -> bool {
+        let mut fiber = self.executing_fiber;
+        // frame catching it, `None` if caught as the result of `resume` or `import`
+        let frame_idx = loop {
+            if fiber.is_null() {
+                return false;
+            }
+            unsafe {
+                let idx = (*fiber)
+                    .call_frames
+                    .iter()
+                    .rposition(|frame| frame.protected || !frame.handlers.is_empty());
+                if idx.is_some() {
+                    break idx;
+                }
+                if (*fiber).protected && !(*fiber).prev.is_null() {
+                    fiber = (*fiber).prev;
+                    break None;
+                }
+                fiber = (*fiber).prev;
+            }
+        };
+        unsafe {
+            while self.executing_fiber != fiber {
+                let abandoned = self.executing_fiber;
+                if (*abandoned).state == FiberState::Loader {
+                    self.global.pop();
+                }
+                (*abandoned).state = FiberState::Error;
+                close_fiber_upvalues(&mut *abandoned, 0);
+                self.executing_fiber = (*abandoned).prev;
+                (*self.executing_fiber).state = FiberState::Running;
+                // pc is still on the `resume` or `import` call which entered the abandoned fiber
+                self.pc_add();
+            }
+            let Some(idx) = frame_idx else {
+                // `resume` and `import` already popped the callee and arguments
+                (*fiber).stack.push(err);
+                return true;
+            };
+            let frame = &mut (*fiber).call_frames[idx];
+            if let Some((height, catch)) = frame.handlers.pop() {
+                frame.pc = catch;
+                (*fiber).call_frames.truncate(idx + 1);
+                self.close_upvalues(height);
+                (*fiber).stack.truncate(height);
+                (*fiber).stack.push(err);
+                return true;
+            }
+            let bottom = frame.bottom;
+            (*fiber).call_frames.truncate(idx);
+            self.close_upvalues(bottom);
+            // drop locals, arguments and the callee
+            (*fiber).stack.truncate(bottom - 1);
+            (*fiber).stack.push(err);
+        }
+        true
+    }
+    /// error value handed to sloth code when `err` is caught
+    fn new_error_value(&mut self, err: &EvalError, trace: &[TraceFrame]) -> Value {
+        let mut dict = HashMap::new();
+        if let EvalError::Exception(info) = err {
+            for (k, v) in info.iter() {
+                dict.insert(self.string_pool.creat_istring(k), *v);
+            }
+        } else {
+            let line = self.current_cood().0;
+            dict.insert(
+                self.string_pool.creat_istring("kind"),
+                Value::string(self.string_pool.creat_istring(err.kind())),
+            );
+            dict.insert(
+                self.string_pool.creat_istring("msg"),
+                Value::string(self.string_pool.creat_istring(&err.message())),
+            );
+            dict.insert(
+                self.string_pool.creat_istring("line"),
+                Value::number(line as f64),
+            );
+        }
+        let trace = trace
+            .iter()
+            .map(|frame| Value::string(self.string_pool.creat_istring(&frame.to_string())))
+            .collect();
+        let mut b_trace = Box::new(Array {
+            marked: false,
+            array: trace,
+        });
+        dict.insert(
+            self.string_pool.creat_istring("trace"),
+            Value::array(b_trace.as_mut() as *mut Array),
+        );
+        self.objects.push(b_trace);
+        let mut b_dict = Box::new(Dict {
+            marked: false,
+            dict,
+        });
+        let p_dict = b_dict.as_mut() as *mut Dict;
+        self.objects.push(b_dict);
+        Value::error(p_dict)
+    }
+    /// the open upvalue of stack slot `idx` of the executing fiber,
+    /// shared by every closure capturing that slot
+    fn capture_upvalue(&mut self, idx: usize) -> *mut UpValueObject {
+        let open = unsafe { &mut (*self.executing_fiber).open_upvalues };
+        let pos = match open.binary_search_by_key(&idx, |upv| open_slot(*upv)) {
+            Ok(pos) => return open[pos],
+            Err(pos) => pos,
+        };
         let mut ret = Box::new(UpValueObject {
             marked: false,
             value: UpValue::Ref(idx),
         });
         let pointer = ret.as_mut() as *mut UpValueObject;
         self.objects.push(ret);
-        self.upvalues.push(pointer);
+        open.insert(pos, pointer);
         pointer
     }
+    fn new_range(&mut self, start: f64, end: f64) -> Value {
+        let mut ret = Box::new(Range {
+            marked: false,
+            start,
+            end,
+        });
+        let pointer = ret.as_mut() as *mut Range;
+        self.objects.push(ret);
+        Value::range(pointer)
+    }
+    fn new_iter(&mut self, state: IterState) -> Value {
+        let mut ret = Box::new(Iter {
+            marked: false,
+            state,
+        });
+        let pointer = ret.as_mut() as *mut Iter;
+        self.objects.push(ret);
+        Value::iter(pointer)
+    }
     fn new_array(&mut self, n: usize) -> *mut Array {
         let mut vec = Vec::new();
         for _ in 0..n {
@@ -1729,7 +2357,7 @@ impl Vm {
             let v = self.get_stack().pop().unwrap();
             let k_wrap = self.get_stack().pop().unwrap();
             let k;
-            if let Value::String(s) = k_wrap {
+            if let Unboxed::String(s) = k_wrap.unbox() {
                 k = s;
             } else {
                 panic!("dict key is supposed to be String, maybe wrong bytecodes emitted");
@@ -1747,45 +2375,45 @@ impl Vm {
     #[inline]
     fn reserve_local(&mut self, n: usize) {
         for _ in 0..n {
-            self.get_stack().push(Value::Nil);
+            self.get_stack().push(Value::NIL);
         }
     }
     #[inline]
     fn stack_get_number(&mut self) -> Result<(f64, f64), EvalError> {
         let (opr1, opr2);
-        if let Value::Number(x) = self.get_stack().pop().unwrap_or(Value::Nil) {
+        if let Unboxed::Number(x) = self.get_stack().pop().unwrap_or(Value::NIL).unbox() {
             opr2 = x;
         } else {
-            return Err(EvalError::TypeError(self.eval_err_str("")));
+            return Err(EvalError::TypeError("".to_owned()));
         }
-        if let Value::Number(x) = self.get_stack().pop().unwrap_or(Value::Nil) {
+        if let Unboxed::Number(x) = self.get_stack().pop().unwrap_or(Value::NIL).unbox() {
             opr1 = x;
         } else {
-            return Err(EvalError::TypeError(self.eval_err_str("")));
+            return Err(EvalError::TypeError("".to_owned()));
         }
         Ok((opr1, opr2))
     }
     #[inline]
     fn stack_get_bool(&mut self) -> Result<(bool, bool), EvalError> {
         let (opr1, opr2);
-        if let Value::Bool(x) = self.get_stack().pop().unwrap_or(Value::Nil) {
+        if let Unboxed::Bool(x) = self.get_stack().pop().unwrap_or(Value::NIL).unbox() {
             opr2 = x;
         } else {
-            return Err(EvalError::TypeError(self.eval_err_str("")));
+            return Err(EvalError::TypeError("".to_owned()));
         }
-        if let Value::Bool(x) = self.get_stack().pop().unwrap_or(Value::Nil) {
+        if let Unboxed::Bool(x) = self.get_stack().pop().unwrap_or(Value::NIL).unbox() {
             opr1 = x;
         } else {
-            return Err(EvalError::TypeError(self.eval_err_str("")));
+            return Err(EvalError::TypeError("".to_owned()));
         }
         Ok((opr1, opr2))
     }
     #[inline]
     fn stack_get_number1(&mut self) -> Result<f64, EvalError> {
-        if let Value::Number(x) = self.get_stack().pop().unwrap_or(Value::Nil) {
+        if let Unboxed::Number(x) = self.get_stack().pop().unwrap_or(Value::NIL).unbox() {
             Ok(x)
         } else {
-            Err(EvalError::TypeError(self.eval_err_str("")))
+            Err(EvalError::TypeError("".to_owned()))
         }
     }
     #[inline]
@@ -1809,8 +2437,8 @@ impl Vm {
         let upv_obj = closure.upvalues[idx];
         let upv = unsafe { &(*upv_obj).value };
         match upv {
-            UpValue::Ref(idx) => self.get_stack()[*idx].clone(),
-            UpValue::Closed(value) => value.clone(),
+            UpValue::Ref(idx) => self.get_stack()[*idx],
+            UpValue::Closed(value) => *value,
         }
     }
     #[inline]
@@ -1831,98 +2459,122 @@ impl Vm {
         let callframe = self.get_call_frame();
         callframe.nxt()
     }
-    #[inline]
-    fn eval_err_str(&self, s: &str) -> String {
+    /// call frames of the executing fiber and every fiber waiting on it
+    /// (resumer or importer), innermost first
+    fn backtrace(&self) -> Vec<TraceFrame> {
+        let mut trace = Vec::new();
+        let mut fiber = self.executing_fiber;
+        while !fiber.is_null() {
+            let frames = unsafe { &(*fiber).call_frames };
+            for (i, frame) in frames.iter().enumerate().rev() {
+                let chunk = unsafe { &*(*frame.closure).chunk };
+                // callers have already stepped past the `Call`, except the top frame
+                // of a waiting fiber which stays on `resume` or `import`
+                let pc = if i + 1 == frames.len() {
+                    frame.pc
+                } else {
+                    frame.pc.saturating_sub(1)
+                };
+                trace.push(TraceFrame {
+                    name: chunk.name.clone(),
+                    file: chunk.file.clone(),
+                    line: chunk.cood[pc.min(chunk.cood.len() - 1)].0,
+                });
+            }
+            fiber = unsafe { (*fiber).prev };
+        }
+        trace
+    }
+    pub fn current_file(&self) -> &str {
         let callframe = self.get_call_frame();
-        let line = unsafe { (*(*callframe.closure).chunk).lines[callframe.pc] };
-        format!("{s} in {line}")
+        unsafe { &(*(*callframe.closure).chunk).file }
+    }
+    /// (line, col) of the instruction being executed in the current fiber
+    pub fn current_cood(&self) -> (usize, usize) {
+        let callframe = self.get_call_frame();
+        let cood = unsafe { &(*(*callframe.closure).chunk).cood };
+        // pc may already point past the last instruction
+        cood[callframe.pc.min(cood.len() - 1)]
     }
     fn run_gc(&mut self) -> EvalResult {
-        return Ok(());
-        if self.objects.len() < 128 {
-            return Ok(());
+        // mark
+        let mut gray = Vec::new();
+        mark_object(self.main_fiber.as_mut(), &mut gray);
+        // executing fiber and every fiber waiting for it
+        let mut fiber = self.executing_fiber;
+        while !fiber.is_null() {
+            mark_object(fiber, &mut gray);
+            fiber = unsafe { (*fiber).prev };
         }
-        macro_rules! mark_val {
-            ($v:expr) => {
-                match $v {
-                    Value::Array(p_arr) => {
-                        unsafe {
-                            let arr = &mut **p_arr;
-                            arr.mark();
-                            arr.mark_children();
-                        };
-                    }
-                    Value::Dictionary(p_dict) => {
-                        unsafe {
-                            let dict = &mut **p_dict;
-                            dict.mark();
-                            dict.mark_children();
-                        };
-                    }
-                    Value::Closure(p_closure) => {
-                        unsafe {
-                            let closure = &mut **p_closure;
-                            closure.mark();
-                            closure.mark_children();
-                        };
-                    }
-                    Value::Fiber(p_fiber) => unsafe {
-                        let fiber = &mut **p_fiber;
-                        fiber.mark();
-                        fiber.mark_children();
-                    },
-                    Value::Klass(p) => unsafe {
-                        let p = &mut **p;
-                        p.mark();
-                        p.mark_children();
-                    },
-                    Value::Instance(p) => unsafe {
-                        let p = &mut **p;
-                        p.mark();
-                        p.mark_children();
-                    },
-                    Value::Module(p) => unsafe {
-                        let p = &mut **p;
-                        p.mark();
-                        p.mark_children();
-                    },
-                    Value::ArrayIter(p, _) => unsafe {
-                        let p = &mut **p;
-                        p.mark();
-                        p.mark_children();
-                    },
-                    _ => {}
-                }
-            };
+        // functions of loaded modules keep using their slots
+        for v in self.global_slots.iter().flatten() {
+            mark_value(v, &mut gray);
         }
-        for val in self.main_fiber.stack.iter_mut() {
-            mark_val!(val);
+        for v in self.native_roots.iter() {
+            mark_value(v, &mut gray);
         }
-        for g in self.global.iter_mut() {
-            for (_s, v) in g.iter_mut() {
-                mark_val!(v);
-            }
+        mark_chunk(&self.top_chunk, &mut gray);
+        for chunk in self.loaded_chunk.iter() {
+            mark_chunk(chunk, &mut gray);
         }
-        unsafe {
-            (*self.executing_fiber).mark();
-            (*self.executing_fiber).mark_children();
+        while let Some(obj) = gray.pop() {
+            unsafe { (*obj).mark_children(&mut gray) };
         }
-        let mut new_obj_vec: Vec<Box<dyn GCObject>> = self
-            .objects
-            .drain(0..)
-            .filter(|obj| obj.is_marked())
-            .collect();
-        new_obj_vec.iter_mut().for_each(|obj| obj.demark());
-        self.objects = new_obj_vec;
+        // sweep
+        self.objects.retain_mut(|obj| {
+            if obj.is_marked() {
+                obj.demark();
+                true
+            } else {
+                false
+            }
+        });
+        // roots owned by the vm rather than `objects`
+        self.main_fiber.demark();
+        self.top_closure.demark();
+        self.string_pool.sweep();
+        self.next_gc = if self.gc_stress {
+            self.objects.len() + 1
+        } else {
+            (self.objects.len() * GC_GROW_FACTOR).max(GC_INITIAL_THRESHOLD)
+        };
         Ok(())
     }
+    /// the chunk of the main script, quickened as it runs
+    pub(crate) fn main_chunk(&self) -> &Chunk {
+        &self.top_chunk
+    }
+    /// run the optimizer over modules loaded from source
+    pub fn set_optimize(&mut self, optimize: bool) {
+        self.optimize = optimize;
+    }
+    /// collect on every allocation, also enabled by feature `gc_stress`
+    pub fn set_gc_stress(&mut self, stress: bool) {
+        self.gc_stress = stress;
+        if stress {
+            self.next_gc = self.objects.len() + 1;
+        }
+    }
+    /// number of objects managed by gc
+    pub fn gc_object_count(&self) -> usize {
+        self.objects.len()
+    }
+    /// keep `val` alive while native code holds it outside the vm stack
+    pub fn add_native_root(&mut self, val: Value) {
+        self.native_roots.push(val);
+    }
+    pub fn remove_native_root(&mut self, val: &Value) {
+        if let Some(idx) = self.native_roots.iter().position(|v| v == val) {
+            self.native_roots.swap_remove(idx);
+        }
+    }
     /// Value passed to this function should not
     /// be GC-managed, or memory would leak.
     pub fn load_native_module(&mut self, module_name: Option<&str>, kv: Vec<(String, Value)>) {
         if let Some(module_name) = module_name {
             let module: HashMap<IString, Value> = HashMap::from_iter(
                 kv.iter()
-                    .map(|(k, v)| (self.string_pool.creat_istring(&k), v.clone())),
+                    .map(|(k, v)| (self.string_pool.creat_istring(&k), *v)),
             );
             let dict = Dict {
                 marked: false,
@@ -1931,31 +2583,52 @@ impl Vm {
             let mut managed_module = Box::new(dict);
             let p_module = managed_module.as_mut() as *mut Dict;
             self.objects.push(managed_module);
-            let module_value = Value::Module(p_module);
+            let module_value = Value::module(p_module);
 
-            self.global
-                .last_mut()
-                .unwrap()
-                .insert(self.string_pool.creat_istring(module_name), module_value);
+            let name = self.string_pool.creat_istring(module_name);
+            self.set_global(name, module_value);
         } else {
             // insert into current Global namespace
             for (k, v) in kv.iter() {
-                self.global
-                    .last_mut()
-                    .unwrap()
-                    .insert(self.string_pool.creat_istring(&k), v.clone());
+                let name = self.string_pool.creat_istring(k);
+                self.set_global(name, *v);
             }
         }
     }
-    pub fn load_module(&mut self, src: &str) -> EvalResult {
+    pub fn load_module(&mut self, src: &str, file: &str) -> EvalResult {
         let mut scanner = ScannerCtx::new(src, &mut self.string_pool);
-        scanner.parse()?;
-        let mut parser = ParserCtx::new(scanner.finish(), HashMap::new(), &mut self.string_pool);
-        parser.parse_prog()?;
-        let res = parser.finish();
-        dbg!(&res.chunk);
+        scanner.parse().map_err(|diag| diag.with_file(file))?;
+        let prog = ParserCtx::new(scanner.finish())
+            .parse_prog()
+            .map_err(|diag| diag.with_file(file))?;
+        let mut codegen = CodeGen::new(&mut self.string_pool);
+        codegen.set_file(file);
+        codegen.set_optimize(self.optimize);
+        codegen
+            .gen_prog(&prog)
+            .map_err(|diag| diag.with_file(file))?;
+        let mut res = codegen.finish();
+        res.chunk.name = "<module>".to_owned();
+        if self.debug {
+            eprint!("{}", disassemble(&res.chunk));
+        }
+        self.load_module_chunk(res.chunk);
+        Ok(())
+    }
+
+    /// like `load_module`, `bytecode` is the content of a `.sltc` file
+    pub fn load_module_bytecode(&mut self, bytecode: &[u8]) -> EvalResult {
+        let mut chunk =
+            serialize::load_chunk(bytecode, &mut self.string_pool).map_err(EvalError::Error)?;
+        chunk.name = "<module>".to_owned();
+        self.load_module_chunk(chunk);
+        Ok(())
+    }
+
+    /// like `load_module`, for a chunk compiled ahead of time
+    pub fn load_module_chunk(&mut self, chunk: Chunk) {
         let mut call_frames = Vec::<CallFrame>::new();
-        let mut b_chunk = Box::new(res.chunk);
+        let mut b_chunk = Box::new(chunk);
         let mut closure = Box::new(Closure {
             marked: false,
             chunk: b_chunk.as_mut() as *const Chunk,
@@ -1974,20 +2647,425 @@ impl Vm {
             stack: {
                 let mut vec = Vec::new();
                 for _ in 0..b_chunk.num_locals {
-                    vec.push(Value::Nil);
+                    vec.push(Value::NIL);
                 }
                 vec
             },
+            open_upvalues: Vec::new(),
             state: FiberState::Loader,
             prev: self.executing_fiber,
+            protected: false,
         });
         // run module code in fresh env
         self.global.push(HashMap::new());
+        self.link_module_chunk(&mut b_chunk);
         self.executing_fiber = fiber.as_mut() as *mut Fiber;
         self.loaded_chunk.push(b_chunk);
         self.objects.push(closure);
         self.objects.push(fiber);
-        Ok(())
+    }
+
+    /// compile one REPL input, see `CodeGen::gen_incremental`
+    pub fn compile_incremental(&mut self, src: &str, file: &str) -> Result<Chunk, Diagnostic> {
+        let mut scanner = ScannerCtx::new(src, &mut self.string_pool);
+        scanner.parse().map_err(|diag| diag.with_file(file))?;
+        let prog = ParserCtx::new(scanner.finish())
+            .parse_incremental()
+            .map_err(|diag| diag.with_file(file))?;
+        let mut codegen = CodeGen::new(&mut self.string_pool);
+        codegen.set_file(file);
+        codegen
+            .gen_incremental(&prog)
+            .map_err(|diag| diag.with_file(file))?;
+        Ok(codegen.finish().chunk)
+    }
+
+    /// run `chunk` as the new main chunk, globals are kept.
+    /// returns what the chunk returns.
+    pub fn run_chunk(&mut self, chunk: Chunk) -> Result<Value, EvalError> {
+        self.reset_main_fiber();
+        let mut b_chunk = Box::new(chunk);
+        link_chunk(&mut b_chunk, &mut self.global[0], None, &mut self.global_slots);
+        let mut closure = Box::new(Closure {
+            marked: false,
+            chunk: b_chunk.as_mut() as *const Chunk,
+            upvalues: Vec::new(),
+            this_ref: None,
+        });
+        self.main_fiber.call_frames.push(CallFrame::new(
+            0,
+            closure.as_mut() as *mut Closure,
+            Vec::new(),
+        ));
+        for _ in 0..b_chunk.num_locals {
+            self.main_fiber.stack.push(Value::NIL);
+        }
+        // functions defined by earlier chunks still point into them
+        let prev_chunk = std::mem::replace(&mut self.top_chunk, b_chunk);
+        self.loaded_chunk.push(prev_chunk);
+        self.top_closure = closure;
+        self.run()?;
+        Ok(self.main_fiber.stack.pop().unwrap_or(Value::NIL))
+    }
+
+    /// get the main fiber ready for a new chunk, even if the last run failed halfway
+    fn reset_main_fiber(&mut self) {
+        // open upvalues are left behind by a failed run,
+        // in the main fiber and every fiber it was waiting for
+        let mut fiber = self.executing_fiber;
+        while !fiber.is_null() {
+            unsafe {
+                close_fiber_upvalues(&mut *fiber, 0);
+                fiber = (*fiber).prev;
+            }
+        }
+        self.main_fiber.stack.clear();
+        self.main_fiber.call_frames.clear();
+        self.main_fiber.state = FiberState::Running;
+        self.executing_fiber = self.main_fiber.as_mut() as *mut Fiber;
+        self.global.truncate(1);
+        self.fiber_changed = false;
+    }
+
+    /// check breakpoints and stepping before executing an instruction,
+    /// only the first instruction of a line may pause, unless interrupted.
+    fn debug_should_pause(&mut self) -> bool {
+        let fiber = self.executing_fiber;
+        let frames = unsafe { &mut (*fiber).call_frames };
+        let depth = frames.len();
+        let frame = frames.last_mut().unwrap();
+        let chunk = unsafe { &*(*frame.closure).chunk };
+        let line = match chunk.cood.get(frame.pc) {
+            Some(&(line, _)) if line > 0 => line,
+            _ => return false,
+        };
+        let dbg = self.debugger.as_mut().unwrap();
+        if dbg.interrupt.swap(false, Ordering::Relaxed) {
+            frame.line = line;
+            dbg.paused = Some(PauseReason::Interrupt);
+            dbg.step = StepMode::Continue;
+            return true;
+        }
+        if frame.line == line {
+            return false;
+        }
+        frame.line = line;
+        let reason = if dbg
+            .breakpoints
+            .iter()
+            .any(|(file, l)| *l == line && same_file(file, &chunk.file))
+        {
+            Some(PauseReason::Breakpoint)
+        } else {
+            let same_fiber = fiber == dbg.step_fiber;
+            let pause = match dbg.step {
+                StepMode::Continue => false,
+                StepMode::StepIn => true,
+                StepMode::StepOver => same_fiber && depth <= dbg.step_depth,
+                StepMode::StepOut => same_fiber && depth < dbg.step_depth,
+            };
+            pause.then_some(PauseReason::Step)
+        };
+        if reason.is_some() {
+            dbg.paused = reason;
+            dbg.step = StepMode::Continue;
+        }
+        reason.is_some()
+    }
+
+    /// pause at breakpoints from now on, see `debug_run`
+    pub fn enable_debugger(&mut self, stop_on_entry: bool) {
+        self.debugger = Some(Box::new(DebugState::new(stop_on_entry)));
+    }
+
+    /// pause as soon as `flag` is set, it is cleared then.
+    /// the flag may be set by another thread while `debug_run` is running
+    pub fn set_debug_interrupt(&mut self, flag: Arc<AtomicBool>) {
+        if self.debugger.is_none() {
+            self.enable_debugger(false);
+        }
+        self.debugger.as_mut().unwrap().interrupt = flag;
+    }
+
+    /// replace breakpoints in `file`.
+    /// every line is moved to the next line with code, if the file is loaded,
+    /// returns where the breakpoints are set then.
+    pub fn set_breakpoints(&mut self, file: &str, lines: &[usize]) -> Vec<Option<usize>> {
+        let resolved: Vec<Option<usize>> = lines
+            .iter()
+            .map(|line| {
+                let mut best = None;
+                resolve_line(&self.top_chunk, file, *line, &mut best);
+                for chunk in self.loaded_chunk.iter() {
+                    resolve_line(chunk, file, *line, &mut best);
+                }
+                best
+            })
+            .collect();
+        if self.debugger.is_none() {
+            self.enable_debugger(false);
+        }
+        let dbg = self.debugger.as_mut().unwrap();
+        dbg.breakpoints.retain(|(f, _)| !same_file(f, file));
+        for (line, res) in lines.iter().zip(resolved.iter()) {
+            dbg.breakpoints
+                .push((file.to_owned(), res.unwrap_or(*line)));
+        }
+        resolved
+    }
+
+    /// run till paused or finished.
+    /// stepping counts from where the vm is paused now.
+    pub fn debug_run(&mut self, mode: StepMode) -> Result<DebugEvent, EvalError> {
+        if self.debugger.is_none() {
+            self.enable_debugger(false);
+        }
+        let fiber = self.executing_fiber;
+        let dbg = self.debugger.as_mut().unwrap();
+        dbg.step = mode;
+        dbg.step_fiber = fiber;
+        dbg.step_depth = unsafe { (*fiber).call_frames.len() };
+        dbg.paused = None;
+        self.run()?;
+        match self.debugger.as_mut().and_then(|dbg| dbg.paused.take()) {
+            Some(reason) => Ok(DebugEvent::Paused(reason)),
+            None => Ok(DebugEvent::Finished),
+        }
+    }
+
+    /// frames of the executing fiber and those waiting for it, innermost first
+    pub fn debug_frames(&self) -> Vec<TraceFrame> {
+        self.backtrace()
+    }
+
+    /// fiber and index in its call frames of the `n`th frame of `debug_frames`
+    fn debug_frame(&self, n: usize) -> Option<(*mut Fiber, usize)> {
+        let mut fiber = self.executing_fiber;
+        let mut n = n;
+        while !fiber.is_null() {
+            let len = unsafe { (*fiber).call_frames.len() };
+            if n < len {
+                return Some((fiber, len - 1 - n));
+            }
+            n -= len;
+            fiber = unsafe { (*fiber).prev };
+        }
+        None
+    }
+
+    /// locals in scope at where the frame is paused, innermost declaration wins
+    fn visible_locals(&self, fiber: *mut Fiber, idx: usize) -> Vec<LocalVar> {
+        let frames = unsafe { &(*fiber).call_frames };
+        let frame = &frames[idx];
+        let chunk = unsafe { &*(*frame.closure).chunk };
+        // callers have already stepped past the `Call`
+        let pc = if idx + 1 == frames.len() {
+            frame.pc
+        } else {
+            frame.pc.saturating_sub(1)
+        };
+        let mut visible: Vec<LocalVar> = Vec::new();
+        for local in chunk.locals.iter() {
+            if local.start <= pc && pc < local.end {
+                visible.retain(|v| v.name != local.name);
+                visible.push(local.clone());
+            }
+        }
+        visible.sort_by_key(|v| v.slot);
+        visible
+    }
+
+    pub fn debug_locals(&self, frame: usize) -> Vec<(String, Value)> {
+        let Some((fiber, idx)) = self.debug_frame(frame) else {
+            return Vec::new();
+        };
+        let stack = unsafe { &(*fiber).stack };
+        let bottom = unsafe { (*fiber).call_frames[idx].bottom };
+        self.visible_locals(fiber, idx)
+            .into_iter()
+            .map(|v| {
+                let val = stack.get(bottom + v.slot).cloned().unwrap_or(Value::NIL);
+                (v.name, val)
+            })
+            .collect()
+    }
+
+    pub fn debug_upvalues(&self, frame: usize) -> Vec<(String, Value)> {
+        let Some((fiber, idx)) = self.debug_frame(frame) else {
+            return Vec::new();
+        };
+        let closure = unsafe { &*(*fiber).call_frames[idx].closure };
+        let chunk = unsafe { &*closure.chunk };
+        let stack = unsafe { &(*fiber).stack };
+        chunk
+            .upvalues
+            .iter()
+            .zip(closure.upvalues.iter())
+            .map(|(decl, upv)| {
+                let (UpValueDecl::Ref(_, name) | UpValueDecl::RefUpValue(_, name)) = decl;
+                let val = match unsafe { &(**upv).value } {
+                    UpValue::Ref(idx) => stack.get(*idx).cloned().unwrap_or(Value::NIL),
+                    UpValue::Closed(val) => *val,
+                };
+                (name.get_inner().to_owned(), val)
+            })
+            .collect()
+    }
+
+    /// run `src` as if it were written where the `frame`th frame is paused.
+    /// locals and upvalues may be assigned, the value of the last expression
+    /// statement is returned. breakpoints are ignored meanwhile.
+    pub fn debug_eval(&mut self, frame: usize, src: &str) -> Result<Value, EvalError> {
+        let (fiber, idx) = self
+            .debug_frame(frame)
+            .ok_or_else(|| EvalError::Error(format!("no frame {frame}")))?;
+        let frame_closure = unsafe { (*fiber).call_frames[idx].closure };
+        let bottom = unsafe { (*fiber).call_frames[idx].bottom };
+        let locals = self.visible_locals(fiber, idx);
+        let mut names: Vec<String> = locals.iter().map(|v| v.name.clone()).collect();
+        let mut values: Vec<Value> = self
+            .debug_locals(frame)
+            .into_iter()
+            .map(|(_, val)| val)
+            .collect();
+        // upvalues shadowed by locals are not reachable
+        let mut upvalue_idx = Vec::new();
+        for (i, (name, val)) in self.debug_upvalues(frame).into_iter().enumerate() {
+            if !names.contains(&name) {
+                names.push(name);
+                values.push(val);
+                upvalue_idx.push(i);
+            }
+        }
+        let scope: Vec<IString> = names
+            .iter()
+            .map(|name| self.string_pool.creat_istring(name))
+            .collect();
+        let this_ref = unsafe { (*fiber).call_frames[idx].this_ref };
+        let file = unsafe { (*(*frame_closure).chunk).file.clone() };
+
+        let mut scanner = ScannerCtx::new(src, &mut self.string_pool);
+        scanner.parse().map_err(|diag| diag.with_file(&file))?;
+        let prog = ParserCtx::new(scanner.finish())
+            .parse_incremental()
+            .map_err(|diag| diag.with_file(&file))?;
+        let mut codegen = CodeGen::new(&mut self.string_pool);
+        codegen.set_file(&file);
+        let chunk = codegen
+            .gen_debug_eval(&prog, &scope, this_ref.is_some())
+            .map_err(|diag| diag.with_file(&file))?;
+
+        // the code sees copies, written back when it is done
+        let mut b_chunk = Box::new(chunk);
+        self.link_module_chunk(&mut b_chunk);
+        let mut upvalues = Vec::new();
+        for val in values {
+            let mut b_upv = Box::new(UpValueObject {
+                marked: false,
+                value: UpValue::Closed(val),
+            });
+            upvalues.push(b_upv.as_mut() as *mut UpValueObject);
+            self.objects.push(b_upv);
+        }
+        let mut closure = Box::new(Closure {
+            marked: false,
+            chunk: b_chunk.as_mut() as *const Chunk,
+            upvalues: upvalues.clone(),
+            this_ref,
+        });
+        let mut eval_fiber = Box::new(Fiber {
+            marked: false,
+            call_frames: vec![CallFrame::new(
+                0,
+                closure.as_mut() as *mut Closure,
+                Vec::new(),
+            )],
+            stack: vec![Value::NIL; b_chunk.num_locals],
+            open_upvalues: Vec::new(),
+            state: FiberState::Running,
+            prev: null_mut(),
+            protected: false,
+        });
+        let p_eval_fiber = eval_fiber.as_mut() as *mut Fiber;
+        self.loaded_chunk.push(b_chunk);
+        self.objects.push(closure);
+        self.objects.push(eval_fiber);
+
+        // paused fibers are not reachable from the eval fiber
+        let roots_len = self.native_roots.len();
+        let mut paused = self.executing_fiber;
+        while !paused.is_null() {
+            self.native_roots.push(Value::fiber(paused));
+            paused = unsafe { (*paused).prev };
+        }
+        let saved_fiber = self.executing_fiber;
+        let saved_debugger = self.debugger.take();
+        self.executing_fiber = p_eval_fiber;
+
+        let res = self.run();
+
+        // close what a failed run left open
+        unsafe { close_fiber_upvalues(&mut *p_eval_fiber, 0) };
+        let ret = unsafe { (*p_eval_fiber).stack.pop().unwrap_or(Value::NIL) };
+        self.debugger = saved_debugger;
+        self.executing_fiber = saved_fiber;
+        self.native_roots.truncate(roots_len);
+        self.fiber_changed = false;
+        res?;
+
+        let copied = |i: usize| match unsafe { &(*upvalues[i]).value } {
+            UpValue::Closed(val) => *val,
+            UpValue::Ref(_) => unreachable!(),
+        };
+        let stack = unsafe { &mut (*fiber).stack };
+        for (i, local) in locals.iter().enumerate() {
+            stack[bottom + local.slot] = copied(i);
+        }
+        for (i, upv_idx) in upvalue_idx.into_iter().enumerate() {
+            let val = copied(locals.len() + i);
+            let upv = unsafe { (*frame_closure).upvalues[upv_idx] };
+            match unsafe { &mut (*upv).value } {
+                UpValue::Ref(idx) => stack[*idx] = val,
+                UpValue::Closed(closed) => *closed = val,
+            }
+        }
+        Ok(ret)
+    }
+
+    /// global variables of the main module
+    pub fn globals(&self) -> impl Iterator<Item = (&IString, &Value)> {
+        self.global[0]
+            .iter()
+            .filter_map(|(name, slot)| self.global_slots[*slot].as_ref().map(|val| (name, val)))
+    }
+
+    /// global of the main module, the fallback of other modules
+    fn main_global(&self, name: &IString) -> Option<Value> {
+        self.global[0]
+            .get(name)
+            .and_then(|slot| self.global_slots[*slot])
+    }
+
+    /// global of the module being loaded, or of the main module
+    fn lookup_global(&self, name: &IString) -> Option<Value> {
+        self.global
+            .last()
+            .unwrap()
+            .get(name)
+            .and_then(|slot| self.global_slots[*slot])
+            .or_else(|| self.main_global(name))
+    }
+
+    /// send output of `print` to `output` instead of stdout
+    pub fn set_output(&mut self, output: Output) {
+        self.output = Some(output);
+    }
+
+    pub fn write_output(&mut self, s: &str) {
+        match self.output.as_mut() {
+            Some(output) => output(s),
+            None => print!("{s}"),
+        }
     }
 
     pub fn make_managed_string(&mut self, s: &str) -> IString {
@@ -2011,32 +3089,160 @@ impl Vm {
         self.string_pool.creat_istring(&s)
     }
 
-    pub fn get_current_glob(&mut self) -> &mut HashMap<IString, Value> {
-        self.global.last_mut().unwrap()
+    /// resolve the globals of `chunk` in the namespace of the module being loaded
+    fn link_module_chunk(&mut self, chunk: &mut Chunk) {
+        let (main, modules) = self.global.split_first_mut().unwrap();
+        match modules.last_mut() {
+            Some(names) => link_chunk(chunk, names, Some(main), &mut self.global_slots),
+            None => link_chunk(chunk, main, None, &mut self.global_slots),
+        }
+    }
+
+    /// define a global in the namespace of the module being loaded,
+    /// code already loaded sees it through its slot
+    pub fn set_global(&mut self, name: IString, val: Value) {
+        let slot = global_slot(
+            self.global.last_mut().unwrap(),
+            &mut self.global_slots,
+            name,
+        );
+        self.global_slots[slot] = Some(val);
     }
 
     // stack utils
-    pub fn gets_number(&mut self) -> f64 {
-        if let Value::Number(v) = self.get_stack().pop().unwrap() {
-            v
+    pub fn gets_number(&mut self) -> Result<f64, EvalError> {
+        if let Some(v) = self.get_stack().pop().and_then(Value::as_number) {
+            Ok(v)
         } else {
-            panic!("not a Number");
+            Err(EvalError::TypeError("not a Number".to_owned()))
         }
     }
 
-    pub fn gets_string(&mut self) -> IString {
-        if let Value::String(v) = self.get_stack().pop().unwrap() {
-            v.clone()
+    pub fn gets_string(&mut self) -> Result<IString, EvalError> {
+        if let Some(Unboxed::String(v)) = self.get_stack().pop().map(Value::unbox) {
+            Ok(v)
         } else {
-            panic!("not a Number");
+            Err(EvalError::TypeError("not a String".to_owned()))
         }
     }
 
-    pub fn gets_opaque(&mut self) -> *mut u8 {
-        if let Value::OpaqueData(v) = self.get_stack().pop().unwrap() {
-            v
+    pub fn gets_opaque(&mut self) -> Result<*mut u8, EvalError> {
+        if let Some(Unboxed::OpaqueData(v)) = self.get_stack().pop().map(Value::unbox) {
+            Ok(v)
         } else {
-            panic!("not an OpaqueData");
+            Err(EvalError::TypeError("not an OpaqueData".to_owned()))
+        }
+    }
+}
+
+/// what `instance.name` is
+enum Member {
+    Field(Value),
+    Method(*mut Closure),
+}
+
+/// stack slot an open upvalue refers to
+fn open_slot(upv: *mut UpValueObject) -> usize {
+    match unsafe { &(*upv).value } {
+        UpValue::Ref(idx) => *idx,
+        UpValue::Closed(_) => unreachable!("closed upvalue in open list"),
+    }
+}
+
+/// close the open upvalues of `fiber` at or above stack slot `bottom`,
+/// they are at the end of its sorted list
+fn close_fiber_upvalues(fiber: &mut Fiber, bottom: usize) {
+    while let Some(&upv) = fiber.open_upvalues.last() {
+        let idx = open_slot(upv);
+        if idx < bottom {
+            break;
+        }
+        let val = fiber.stack.get(idx).copied().unwrap_or(Value::NIL);
+        unsafe { (*upv).value = UpValue::Closed(val) };
+        fiber.open_upvalues.pop();
+    }
+}
+
+/// `value` is the literal `pattern`,
+/// like `==` without raising for values of different types
+fn pattern_eq(value: Value, pattern: Value) -> bool {
+    match (value.unbox(), pattern.unbox()) {
+        (Unboxed::Number(x), Unboxed::Number(y)) => (x - y).abs() < 1e-5,
+        (Unboxed::String(s1), Unboxed::String(s2)) => s1 == s2,
+        (Unboxed::Bool(a), Unboxed::Bool(b)) => a == b,
+        (Unboxed::Nil, Unboxed::Nil) => true,
+        _ => false,
+    }
+}
+
+/// slot of `name` in `names`, a new undefined one if missing
+fn global_slot(
+    names: &mut HashMap<IString, usize>,
+    global_slots: &mut Vec<Option<Value>>,
+    name: IString,
+) -> usize {
+    *names.entry(name).or_insert_with(|| {
+        global_slots.push(None);
+        global_slots.len() - 1
+    })
+}
+
+/// resolve the globals used by `chunk` and its functions to slots of `names`,
+/// and those read to slots of `main` too, where undefined ones are looked up
+fn link_chunk(
+    chunk: &mut Chunk,
+    names: &mut HashMap<IString, usize>,
+    mut main: Option<&mut HashMap<IString, usize>>,
+    global_slots: &mut Vec<Option<Value>>,
+) {
+    chunk.global_slots = vec![(0, 0); chunk.constants.len()];
+    let cached = |instr: &Instr| {
+        matches!(
+            instr,
+            Instr::GetField(_) | Instr::SetField(_) | Instr::Invoke(..)
+        )
+    };
+    if chunk.bytecodes.iter().any(cached) {
+        chunk.inline_caches = vec![InlineCache::Empty; chunk.bytecodes.len()];
+    }
+    for instr in chunk.bytecodes.iter() {
+        if let Instr::GetGlobal(x) | Instr::SetGlobal(x) = *instr {
+            if let Unboxed::String(name) = chunk.constants[x].unbox() {
+                let slot = global_slot(names, global_slots, name.clone());
+                let main_slot = match main.as_deref_mut() {
+                    Some(main) if matches!(instr, Instr::GetGlobal(_)) => {
+                        global_slot(main, global_slots, name)
+                    }
+                    _ => slot,
+                };
+                chunk.global_slots[x] = (slot, main_slot);
+            }
         }
     }
+    for sub in chunk.chunks.iter_mut() {
+        link_chunk(sub, names, main.as_deref_mut(), global_slots);
+    }
+}
+
+/// smallest line `>= line` with code in `file`
+fn resolve_line(chunk: &Chunk, file: &str, line: usize, best: &mut Option<usize>) {
+    if same_file(&chunk.file, file) {
+        for (l, _) in chunk.cood.iter() {
+            if *l >= line && best.is_none_or(|b| *l < b) {
+                *best = Some(*l);
+            }
+        }
+    }
+    for sub_chunk in chunk.chunks.iter() {
+        resolve_line(sub_chunk, file, line, best);
+    }
+}
+
+/// the two numbers on top of `stack`
+#[inline(always)]
+fn number_pair(stack: &[Value]) -> Option<(f64, f64)> {
+    match stack {
+        [.., a, b] => Some((a.as_number()?, b.as_number()?)),
+        _ => None,
+    }
 }
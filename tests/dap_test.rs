@@ -0,0 +1,229 @@
+extern crate sloth_lang_core;
+use std::{
+    io::{BufReader, Write},
+    path::PathBuf,
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+};
+
+use serde_json::{json, Value};
+use sloth_lang_core::{read_message, write_message};
+
+/// drives `sloth dap` like an editor would
+struct Client {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    seq: i64,
+    /// events not waited for yet
+    events: Vec<Value>,
+}
+
+impl Client {
+    fn start() -> Client {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_sloth-lang-core"))
+            .arg("dap")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        Client {
+            child,
+            stdin,
+            stdout,
+            seq: 0,
+            events: Vec::new(),
+        }
+    }
+
+    fn send(&mut self, command: &str, arguments: Value) -> i64 {
+        self.seq += 1;
+        let req = json!({
+            "seq": self.seq,
+            "type": "request",
+            "command": command,
+            "arguments": arguments,
+        });
+        write_message(&mut self.stdin, &req).unwrap();
+        self.seq
+    }
+
+    fn read(&mut self) -> Value {
+        read_message(&mut self.stdout)
+            .unwrap()
+            .expect("adapter closed the connection")
+    }
+
+    /// send a request and wait for its response
+    fn request(&mut self, command: &str, arguments: Value) -> Value {
+        let seq = self.send(command, arguments);
+        loop {
+            let msg = self.read();
+            if msg["type"] == "event" {
+                self.events.push(msg);
+            } else if msg["request_seq"] == seq {
+                assert_eq!(msg["command"], command);
+                return msg;
+            }
+        }
+    }
+
+    fn event(&mut self, event: &str) -> Value {
+        if let Some(idx) = self.events.iter().position(|e| e["event"] == event) {
+            return self.events.remove(idx);
+        }
+        loop {
+            let msg = self.read();
+            if msg["event"] == event {
+                return msg;
+            }
+            self.events.push(msg);
+        }
+    }
+
+    fn stopped(&mut self) -> String {
+        let stopped = self.event("stopped");
+        assert_eq!(stopped["body"]["threadId"], 1);
+        stopped["body"]["reason"].as_str().unwrap().to_owned()
+    }
+
+    fn top_line(&mut self) -> u64 {
+        let trace = self.request("stackTrace", json!({"threadId": 1}));
+        trace["body"]["stackFrames"][0]["line"].as_u64().unwrap()
+    }
+
+    fn variables(&mut self, reference: u64) -> Vec<(String, String)> {
+        let res = self.request("variables", json!({"variablesReference": reference}));
+        res["body"]["variables"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|v| {
+                let name = v["name"].as_str().unwrap().to_owned();
+                (name, v["value"].as_str().unwrap().to_owned())
+            })
+            .collect()
+    }
+
+    fn launch(&mut self, program: &PathBuf, breakpoints: &[u64]) {
+        let init = self.request("initialize", json!({"adapterID": "sloth"}));
+        assert_eq!(init["success"], true);
+        self.event("initialized");
+        let launch = self.request("launch", json!({"program": program}));
+        assert_eq!(launch["success"], true, "{launch}");
+        let lines: Vec<Value> = breakpoints.iter().map(|l| json!({"line": l})).collect();
+        let bps = self.request(
+            "setBreakpoints",
+            json!({"source": {"path": program}, "breakpoints": lines}),
+        );
+        for (bp, line) in bps["body"]["breakpoints"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .zip(breakpoints)
+        {
+            assert_eq!(bp["verified"], true);
+            assert_eq!(bp["line"], *line);
+        }
+        self.request("configurationDone", json!({}));
+    }
+
+    fn disconnect(mut self) {
+        self.request("disconnect", json!({}));
+        assert!(self.child.wait().unwrap().success());
+    }
+}
+
+fn write_script(name: &str, src: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("sloth_dap_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    let mut file = std::fs::File::create(&path).unwrap();
+    file.write_all(src.as_bytes()).unwrap();
+    path
+}
+
+#[test]
+fn breakpoint_and_step() {
+    let src = "func add(a, b) {\n    var c = a + b;\n    return c;\n}\nvar x = 1;\nvar y = add(x, 2);\nprint(y);\n";
+    let program = write_script("add.slt", src);
+    let mut client = Client::start();
+    client.launch(&program, &[2]);
+    assert_eq!(client.stopped(), "breakpoint");
+
+    let trace = client.request("stackTrace", json!({"threadId": 1}));
+    let frames = trace["body"]["stackFrames"].as_array().unwrap().clone();
+    assert_eq!(frames[0]["name"], "add");
+    assert_eq!(frames[0]["line"], 2);
+    assert_eq!(frames[1]["line"], 6);
+    assert_eq!(frames[0]["source"]["name"], "add.slt");
+
+    let scopes = client.request("scopes", json!({"frameId": 0}));
+    let locals = scopes["body"]["scopes"][0]["variablesReference"]
+        .as_u64()
+        .unwrap();
+    let globals = scopes["body"]["scopes"][2]["variablesReference"]
+        .as_u64()
+        .unwrap();
+    let locals = client.variables(locals);
+    assert!(locals.contains(&("a".to_owned(), "1".to_owned())));
+    assert!(locals.contains(&("b".to_owned(), "2".to_owned())));
+    let globals = client.variables(globals);
+    assert!(globals.contains(&("x".to_owned(), "1".to_owned())));
+    assert!(!globals.iter().any(|(name, _)| name == "print"));
+
+    let res = client.request("evaluate", json!({"expression": "a + b", "frameId": 0}));
+    assert_eq!(res["body"]["result"], "3");
+    let res = client.request("evaluate", json!({"expression": "nope", "frameId": 0}));
+    assert_eq!(res["success"], false);
+
+    client.request("next", json!({"threadId": 1}));
+    assert_eq!(client.stopped(), "step");
+    assert_eq!(client.top_line(), 3);
+    // the call has returned and `y` is assigned by then
+    client.request("stepOut", json!({"threadId": 1}));
+    assert_eq!(client.stopped(), "step");
+    assert_eq!(client.top_line(), 7);
+
+    client.request("continue", json!({"threadId": 1}));
+    let output = client.event("output");
+    assert_eq!(output["body"]["category"], "stdout");
+    assert_eq!(output["body"]["output"], "3 ");
+    let exited = client.event("exited");
+    assert_eq!(exited["body"]["exitCode"], 0);
+    client.event("terminated");
+    let res = client.request("stackTrace", json!({"threadId": 1}));
+    assert_eq!(res["success"], false);
+    client.disconnect();
+}
+
+#[test]
+fn pause_running_program() {
+    // the loop prints and runs on one line, whichever instruction pauses is on it
+    let src = "var i = 0;\nwhile (true) { i = i + 1; if (i == 1) { print(\"running\"); } }\n";
+    let program = write_script("forever.slt", src);
+    let mut client = Client::start();
+    client.launch(&program, &[]);
+    // the loop is running once something is printed
+    client.event("output");
+    client.request("pause", json!({"threadId": 1}));
+    assert_eq!(client.stopped(), "pause");
+    assert_eq!(client.top_line(), 2);
+    let res = client.request("evaluate", json!({"expression": "i >= 1", "frameId": 0}));
+    assert_eq!(res["body"]["result"], "true");
+    client.disconnect();
+}
+
+#[test]
+fn runtime_error() {
+    let src = "var a = 1;\nvar b = a + nil;\n";
+    let program = write_script("error.slt", src);
+    let mut client = Client::start();
+    client.launch(&program, &[]);
+    let output = client.event("output");
+    assert_eq!(output["body"]["category"], "stderr");
+    let exited = client.event("exited");
+    assert_eq!(exited["body"]["exitCode"], 1);
+    client.disconnect();
+}
@@ -0,0 +1,199 @@
+extern crate sloth_lang_core;
+use std::io::Cursor;
+
+use serde_json::{json, Value};
+use sloth_lang_core::{read_message, run_lsp, write_message};
+
+const URI: &str = "file:///work/main.slt";
+
+/// run a whole session, returns everything the server sent
+fn session(requests: &[Value]) -> Vec<Value> {
+    let mut input = Vec::new();
+    for req in requests {
+        write_message(&mut input, req).unwrap();
+    }
+    let mut output = Vec::new();
+    run_lsp(Cursor::new(input), &mut output).unwrap();
+    let mut output = Cursor::new(output);
+    let mut messages = Vec::new();
+    while let Some(msg) = read_message(&mut output).unwrap() {
+        messages.push(msg);
+    }
+    messages
+}
+
+fn open(text: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {"textDocument": {"uri": URI, "languageId": "sloth", "version": 1, "text": text}},
+    })
+}
+
+fn request(id: i64, method: &str, line: usize, character: usize) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "method": method,
+        "params": {
+            "textDocument": {"uri": URI},
+            "position": {"line": line, "character": character},
+            "context": {"includeDeclaration": true},
+        },
+    })
+}
+
+fn response(messages: &[Value], id: i64) -> Value {
+    messages
+        .iter()
+        .find(|msg| msg["id"] == id)
+        .map(|msg| msg["result"].clone())
+        .unwrap()
+}
+
+fn diagnostics(messages: &[Value]) -> Vec<Value> {
+    messages
+        .iter()
+        .filter(|msg| msg["method"] == "textDocument/publishDiagnostics")
+        .map(|msg| msg["params"]["diagnostics"].clone())
+        .collect()
+}
+
+const SRC: &str = r#"var total = 0;
+func add(a, b) {
+    var sum = a + b;
+    return sum;
+}
+class Counter {
+    func __init__() {
+        this.value = 0;
+    }
+    func inc() {
+        this.value = add(this.value, 1);
+    }
+}
+total = add(total, 2);
+print(total);
+"#;
+
+#[test]
+fn definition_and_references() {
+    let messages = session(&[
+        json!({"jsonrpc": "2.0", "id": 0, "method": "initialize", "params": {}}),
+        open(SRC),
+        // `sum` in `return sum;`
+        request(1, "textDocument/definition", 3, 12),
+        // `add` called in `inc`
+        request(2, "textDocument/definition", 10, 22),
+        request(3, "textDocument/references", 1, 5),
+        // `total` used before the declaration is looked up
+        request(4, "textDocument/references", 13, 0),
+        json!({"jsonrpc": "2.0", "id": 5, "method": "shutdown"}),
+        json!({"jsonrpc": "2.0", "method": "exit"}),
+    ]);
+    let caps = response(&messages, 0);
+    assert_eq!(caps["capabilities"]["definitionProvider"], true);
+    assert_eq!(diagnostics(&messages), vec![json!([])]);
+
+    let def = response(&messages, 1);
+    assert_eq!(def["uri"], URI);
+    assert_eq!(def["range"]["start"], json!({"line": 2, "character": 8}));
+    let def = response(&messages, 2);
+    assert_eq!(def["range"]["start"], json!({"line": 1, "character": 5}));
+
+    let refs = response(&messages, 3);
+    let lines: Vec<&Value> = refs
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|loc| &loc["range"]["start"]["line"])
+        .collect();
+    assert_eq!(lines, vec![1, 10, 13]);
+    let refs = response(&messages, 4);
+    assert_eq!(refs.as_array().unwrap().len(), 4);
+}
+
+#[test]
+fn document_symbols() {
+    let messages = session(&[
+        open(SRC),
+        json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "textDocument/documentSymbol",
+            "params": {"textDocument": {"uri": URI}},
+        }),
+    ]);
+    let symbols = response(&messages, 1);
+    let symbols = symbols.as_array().unwrap();
+    assert_eq!(symbols.len(), 2);
+    assert_eq!(symbols[0]["name"], "add");
+    assert_eq!(symbols[0]["range"]["end"]["line"], 4);
+    assert_eq!(symbols[1]["name"], "Counter");
+    let methods: Vec<&Value> = symbols[1]["children"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|m| &m["name"])
+        .collect();
+    assert_eq!(methods, vec!["__init__", "inc"]);
+}
+
+#[test]
+fn diagnostics_while_typing() {
+    let messages = session(&[
+        open("var a = 1;\nvar b = (a;\n"),
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didChange",
+            "params": {
+                "textDocument": {"uri": URI, "version": 2},
+                "contentChanges": [{"text": "var a = 1;\nvar b = (a);\n"}],
+            },
+        }),
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didChange",
+            "params": {
+                "textDocument": {"uri": URI, "version": 3},
+                "contentChanges": [{"text": "var a = 1;\nvar s = \"oops;\n"}],
+            },
+        }),
+    ]);
+    let published = diagnostics(&messages);
+    assert_eq!(published.len(), 3);
+    assert_eq!(published[0][0]["range"]["start"]["line"], 1);
+    assert_eq!(published[0][0]["severity"], 1);
+    assert_eq!(published[1], json!([]));
+    assert_eq!(published[2].as_array().unwrap().len(), 1);
+}
+
+#[test]
+fn module_completion() {
+    let text = "var x = 1;\nvar m = math.floor;\n";
+    let messages = session(&[
+        open(text),
+        request(1, "textDocument/completion", 1, 13),
+        request(2, "textDocument/completion", 1, 8),
+    ]);
+    let members = response(&messages, 1);
+    let labels: Vec<&str> = members
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item["label"].as_str().unwrap())
+        .collect();
+    assert!(labels.contains(&"floor"), "{labels:?}");
+    assert!(!labels.contains(&"print"));
+
+    let globals = response(&messages, 2);
+    let labels: Vec<&str> = globals
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item["label"].as_str().unwrap())
+        .collect();
+    assert!(labels.contains(&"print"));
+    assert!(labels.contains(&"math"));
+    assert!(labels.contains(&"x"));
+}
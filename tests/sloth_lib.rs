@@ -4,7 +4,9 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use sloth_lang_core::{run_string, run_string_debug};
+use sloth_lang_core::{
+    compile_to_bytecode, format_program, parse_program, run_string, run_string_debug,
+};
 
 #[test]
 fn func_tool() {
@@ -56,6 +58,25 @@ fn game_of_life() {
 fn snake() {
     run_file("sloth/sloth_examples/snake.slt".into(), false, false);
 }
+#[test]
+fn format_examples() {
+    for dir in ["sloth/sloth_lib", "sloth/sloth_examples"] {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|ext| ext != "slt") {
+                continue;
+            }
+            let file = path.to_string_lossy();
+            let src = std::fs::read_to_string(&path).unwrap();
+            let formatted = format_program(&src, &file).unwrap();
+            assert_eq!(format_program(&formatted, &file).unwrap(), formatted, "{file}");
+            assert!(compile_to_bytecode(&formatted, &file, false).is_ok(), "{file}");
+            for comment in parse_program(&src, &file).unwrap().comments {
+                assert!(formatted.contains(&comment.text), "{file}");
+            }
+        }
+    }
+}
 
 fn run_file(path: PathBuf, only_compile: bool, debug: bool) {
     let cwd = std::env::current_dir().unwrap();